@@ -1,3 +1,67 @@
+use std::{env, fs::File, path::PathBuf, process::Command};
+
+use passthrough_core::sha256;
+
+/// 编译 sub_dll 并放到 `OUT_DIR/sub_dll.dll`，`hook_sub` 用 `include_bytes!` 嵌入。
+/// 设置了 `SUB_DLL_PATH` 时直接用那个文件，不再编译
+fn build_sub_dll() -> PathBuf {
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let output = out_dir.join("sub_dll.dll");
+    println!("cargo:rerun-if-env-changed=SUB_DLL_PATH");
+    println!("cargo:rerun-if-changed=sub_dll/src");
+    println!("cargo:rerun-if-changed=sub_dll/Cargo.toml");
+    println!("cargo:rerun-if-changed=passthrough_core/src");
+
+    if let Ok(path) = env::var("SUB_DLL_PATH") {
+        std::fs::copy(&path, &output).expect("copy SUB_DLL_PATH");
+        return output;
+    }
+    // 只有 Windows 上才能注入，其他平台放一个空文件让 include_bytes! 能编译
+    if env::var("CARGO_CFG_TARGET_OS").as_deref() != Ok("windows") {
+        std::fs::write(&output, []).unwrap();
+        return output;
+    }
+
+    // 用单独的 target 目录，和外面的 cargo 抢同一个目录的锁会卡住
+    let target = env::var("TARGET").unwrap();
+    let target_dir = out_dir.join("sub_dll-target");
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let status = Command::new(cargo)
+        .args([
+            "build",
+            "--release",
+            "--manifest-path",
+            "sub_dll/Cargo.toml",
+        ])
+        .arg("--target")
+        .arg(&target)
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .env_remove("CARGO_TARGET_DIR")
+        .env_remove("RUSTFLAGS")
+        .env_remove("CARGO_ENCODED_RUSTFLAGS")
+        .status()
+        .expect("run cargo for sub_dll");
+    assert!(status.success(), "building sub_dll failed");
+    let built = target_dir.join(target).join("release").join("sub_dll.dll");
+    std::fs::copy(&built, &output).expect("copy sub_dll.dll");
+    output
+}
+
 fn main() {
-    tauri_build::build()
+    let dll = build_sub_dll();
+    // 注入前用它校验缓存目录里的文件
+    let digest = sha256::digest_reader(File::open(&dll).unwrap()).unwrap();
+    println!("cargo:rustc-env=SUB_DLL_SHA256={}", sha256::to_hex(&digest));
+
+    // `--features elevated` 时换成要求管理员权限的清单，默认用 tauri 自带的
+    println!("cargo:rerun-if-changed=elevated-manifest.xml");
+    let mut attributes = tauri_build::Attributes::new();
+    if env::var_os("CARGO_FEATURE_ELEVATED").is_some() {
+        attributes = attributes.windows_attributes(
+            tauri_build::WindowsAttributes::new()
+                .app_manifest(include_str!("elevated-manifest.xml")),
+        );
+    }
+    tauri_build::try_build(attributes).expect("failed to run tauri build script")
 }
@@ -0,0 +1,259 @@
+//! sub_dll 导出函数的返回值，远程线程的退出码就是这些值
+
+pub const SUBCLASS_OK: u32 = 0;
+/// 窗口没有窗口过程
+pub const SUBCLASS_NO_WND_PROC: u32 = 102;
+/// 窗口已经被子类化过了
+pub const SUBCLASS_ALREADY_SET: u32 = 103;
+/// 窗口没有被子类化，无需恢复
+pub const SUBCLASS_NOT_SET: u32 = 104;
+/// `SetWindowLongPtrW` 失败
+pub const SUBCLASS_SET_FAILED: u32 = 105;
+pub const FILTER_OK: u32 = 0;
+/// `set_message_filter` 的参数无法解析
+pub const FILTER_INVALID: u32 = 106;
+/// 使用 `marshal` 编码参数的导出函数成功
+pub const MARSHAL_OK: u32 = 0;
+/// 参数块无法解析，见 `marshal::MarshalError`
+pub const MARSHAL_INVALID: u32 = 107;
+/// 结果比 host 留出的结果区大
+pub const MARSHAL_RESULT_TOO_LARGE: u32 = 108;
+pub const COMMAND_HOOK_OK: u32 = 0;
+/// `SetWindowsHookExW` 失败，host 改用远程线程调用导出函数
+pub const COMMAND_HOOK_FAILED: u32 = 109;
+/// 命令无法解析，见 `command::CommandError`
+pub const COMMAND_INVALID: u32 = 110;
+/// 命令没有带上 host 在握手时设置的令牌，没有执行
+pub const COMMAND_UNAUTHORIZED: u32 = 111;
+
+/// host 和 sub_dll 约定的接口版本，导出函数的参数、返回值或内存布局变化时加一。
+/// 缓存目录按版本区分，忘了加一时旧的 DLL 会被当成新的继续用
+///
+/// 3：区域表、遥测共享内存和带令牌的窗口消息命令
+pub const ABI_VERSION: u16 = 3;
+
+/// 支持 `set_subclass` / `remove_subclass` / `subclass_count`
+pub const CAP_SUBCLASS: u16 = 1 << 0;
+/// 支持 `set_message_filter`
+pub const CAP_MESSAGE_FILTER: u16 = 1 << 1;
+/// 支持用 `marshal` 编码参数和结果的导出函数
+pub const CAP_MARSHAL: u16 = 1 << 2;
+/// 子类化过程里从 `region_table` 共享内存读可交互区域
+pub const CAP_REGION_TABLE: u16 = 1 << 3;
+/// 往 `telemetry` 共享内存写计数器和事件
+pub const CAP_TELEMETRY: u16 = 1 << 4;
+/// 支持 `install_command_hook` 和 `command` 里的窗口消息命令
+pub const CAP_COMMANDS: u16 = 1 << 5;
+/// 当前版本的 sub_dll 提供的全部能力
+pub const CAPABILITIES: u16 = CAP_SUBCLASS
+    | CAP_MESSAGE_FILTER
+    | CAP_MARSHAL
+    | CAP_REGION_TABLE
+    | CAP_TELEMETRY
+    | CAP_COMMANDS;
+/// host 正常工作需要的能力
+pub const REQUIRED_CAPABILITIES: u16 = CAP_SUBCLASS | CAP_MESSAGE_FILTER | CAP_MARSHAL;
+
+const CAPABILITY_NAMES: [(u16, &str); 6] = [
+    (CAP_SUBCLASS, "subclass"),
+    (CAP_MESSAGE_FILTER, "messageFilter"),
+    (CAP_MARSHAL, "marshal"),
+    (CAP_REGION_TABLE, "regionTable"),
+    (CAP_TELEMETRY, "telemetry"),
+    (CAP_COMMANDS, "commands"),
+];
+
+/// `sub_dll_abi` 的返回值，高 16 位是版本，低 16 位是能力
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AbiInfo {
+    pub version: u16,
+    pub capabilities: u16,
+}
+
+impl AbiInfo {
+    /// 没有 `sub_dll_abi` 导出的旧版本 DLL
+    pub const LEGACY: AbiInfo = AbiInfo {
+        version: 0,
+        capabilities: 0,
+    };
+
+    pub const fn current() -> Self {
+        Self {
+            version: ABI_VERSION,
+            capabilities: CAPABILITIES,
+        }
+    }
+
+    pub const fn pack(self) -> u32 {
+        (self.version as u32) << 16 | self.capabilities as u32
+    }
+
+    pub const fn unpack(value: u32) -> Self {
+        Self {
+            version: (value >> 16) as u16,
+            capabilities: value as u16,
+        }
+    }
+
+    pub fn capability_names(self) -> Vec<&'static str> {
+        CAPABILITY_NAMES
+            .iter()
+            .filter(|(bit, _)| self.capabilities & bit != 0)
+            .map(|(_, name)| *name)
+            .collect()
+    }
+
+    /// 检查已经加载的 DLL 能不能给当前 host 使用
+    pub fn check(self) -> Result<(), AbiMismatch> {
+        if self.version != ABI_VERSION {
+            return Err(AbiMismatch::Version {
+                expected: ABI_VERSION,
+                found: self.version,
+            });
+        }
+        let missing = REQUIRED_CAPABILITIES & !self.capabilities;
+        if missing != 0 {
+            return Err(AbiMismatch::Capabilities { missing });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AbiMismatch {
+    Version { expected: u16, found: u16 },
+    Capabilities { missing: u16 },
+}
+
+impl std::fmt::Display for AbiMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AbiMismatch::Version { expected, found: 0 } => write!(
+                f,
+                "sub_dll predates the ABI handshake, expected version {}",
+                expected
+            ),
+            AbiMismatch::Version { expected, found } => write!(
+                f,
+                "sub_dll ABI version {} does not match host version {}",
+                found, expected
+            ),
+            AbiMismatch::Capabilities { missing } => write!(
+                f,
+                "sub_dll is missing capabilities {:?}",
+                AbiInfo {
+                    version: ABI_VERSION,
+                    capabilities: *missing,
+                }
+                .capability_names()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AbiMismatch {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pack_puts_the_version_in_the_high_word() {
+        let info = AbiInfo {
+            version: 3,
+            capabilities: CAP_SUBCLASS | CAP_TELEMETRY,
+        };
+        assert_eq!(info.pack(), 0x0003_0011);
+        assert_eq!(AbiInfo::unpack(0x0003_0011), info);
+    }
+
+    #[test]
+    fn pack_and_unpack_round_trip() {
+        for info in [
+            AbiInfo::LEGACY,
+            AbiInfo::current(),
+            AbiInfo {
+                version: u16::MAX,
+                capabilities: u16::MAX,
+            },
+        ] {
+            assert_eq!(AbiInfo::unpack(info.pack()), info);
+        }
+        assert_eq!(AbiInfo::unpack(0), AbiInfo::LEGACY);
+    }
+
+    #[test]
+    fn current_passes_the_check() {
+        assert_eq!(AbiInfo::current().check(), Ok(()));
+        let required = AbiInfo {
+            version: ABI_VERSION,
+            capabilities: REQUIRED_CAPABILITIES,
+        };
+        assert_eq!(required.check(), Ok(()));
+    }
+
+    #[test]
+    fn check_rejects_other_versions() {
+        for version in [0, ABI_VERSION - 1, ABI_VERSION + 1] {
+            let info = AbiInfo {
+                version,
+                capabilities: CAPABILITIES,
+            };
+            assert_eq!(
+                info.check(),
+                Err(AbiMismatch::Version {
+                    expected: ABI_VERSION,
+                    found: version,
+                })
+            );
+        }
+        assert_eq!(
+            AbiInfo::LEGACY.check().unwrap_err().to_string(),
+            format!(
+                "sub_dll predates the ABI handshake, expected version {}",
+                ABI_VERSION
+            )
+        );
+    }
+
+    #[test]
+    fn check_reports_missing_required_capabilities() {
+        let info = AbiInfo {
+            version: ABI_VERSION,
+            capabilities: CAPABILITIES & !CAP_MESSAGE_FILTER & !CAP_MARSHAL,
+        };
+        let err = info.check().unwrap_err();
+        assert_eq!(
+            err,
+            AbiMismatch::Capabilities {
+                missing: CAP_MESSAGE_FILTER | CAP_MARSHAL,
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            r#"sub_dll is missing capabilities ["messageFilter", "marshal"]"#
+        );
+        // 可选能力缺了也能用
+        let info = AbiInfo {
+            version: ABI_VERSION,
+            capabilities: REQUIRED_CAPABILITIES | CAP_COMMANDS,
+        };
+        assert_eq!(info.check(), Ok(()));
+    }
+
+    #[test]
+    fn capability_names_follow_the_bit_order() {
+        assert_eq!(
+            AbiInfo::current().capability_names(),
+            [
+                "subclass",
+                "messageFilter",
+                "marshal",
+                "regionTable",
+                "telemetry",
+                "commands"
+            ]
+        );
+        assert!(AbiInfo::LEGACY.capability_names().is_empty());
+    }
+}
@@ -0,0 +1,292 @@
+//! `export_passthrough_report` 导出的问题报告：最近的日志和鼠标事件、窗口树，
+//! 以及隐藏其他程序的窗口标题。
+//!
+//! 这里只有数据和整理数据的逻辑，收集和写 zip 在 host 里。
+
+use std::{collections::VecDeque, fmt};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    clock::UtcTime,
+    inspect::{Diagnostics, WindowNode},
+};
+
+/// 代替被隐藏的窗口标题
+pub const REDACTED: &str = "<redacted>";
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+pub const STATE_FILE: &str = "state.json";
+pub const METRICS_FILE: &str = "metrics.json";
+pub const DIAGNOSTICS_FILE: &str = "diagnostics.json";
+pub const LOG_FILE: &str = "log.txt";
+pub const POINTER_EVENTS_FILE: &str = "pointer-events.json";
+
+/// 带时间的环形缓冲，满了丢掉最旧的。时间是从 1970-01-01 起的毫秒数
+pub struct Recent<T> {
+    items: VecDeque<(u64, T)>,
+    capacity: usize,
+}
+
+impl<T: Clone> Recent<T> {
+    pub const fn new(capacity: usize) -> Self {
+        Self {
+            items: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, time_ms: u64, item: T) {
+        if self.items.len() == self.capacity {
+            self.items.pop_front();
+        }
+        self.items.push_back((time_ms, item));
+    }
+
+    /// `since_ms` 以及之后的，按时间顺序
+    pub fn since(&self, since_ms: u64) -> Vec<T> {
+        // 时间来自系统时钟，可能被调回去，不能假设有序
+        self.items
+            .iter()
+            .filter(|(time_ms, _)| *time_ms >= since_ms)
+            .map(|(_, item)| item.clone())
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+impl<T: Copy> Recent<T> {
+    /// 整块拷贝一份，拿着锁的时候用：只有两次内存拷贝，过滤和序列化留到放开锁之后
+    pub fn copy_out(&self) -> Self {
+        let (front, back) = self.items.as_slices();
+        let mut items = Vec::with_capacity(self.items.len());
+        items.extend_from_slice(front);
+        items.extend_from_slice(back);
+        Self {
+            items: items.into(),
+            capacity: self.capacity,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogLine {
+    pub time_ms: u64,
+    pub level: String,
+    pub target: String,
+    pub message: String,
+}
+
+/// `2024-05-01T08:30:00.250Z WARN passthrough::telemetry: message`
+impl fmt::Display for LogLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let time = UtcTime::from_unix(self.time_ms / 1000).to_string();
+        write!(
+            f,
+            "{}.{:03}Z {:5} {}: {}",
+            time.trim_end_matches('Z'),
+            self.time_ms % 1000,
+            self.level,
+            self.target,
+            self.message
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PointerKind {
+    Move,
+    Wheel,
+}
+
+/// 鼠标钩子收到的一个事件
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PointerEvent {
+    pub time_ms: u64,
+    pub kind: PointerKind,
+    /// 屏幕坐标，物理像素
+    pub x: i32,
+    pub y: i32,
+    /// 滚轮的滚动量，移动时是 0
+    pub delta: i16,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    pub app_version: String,
+    /// UTC，ISO 8601
+    pub created: String,
+    pub host_pid: u32,
+    /// 日志和鼠标事件覆盖的秒数
+    pub seconds: u64,
+    /// 隐藏了多少个窗口标题，没有隐藏时是 `None`
+    pub redacted_titles: Option<usize>,
+    /// 收集失败的部分，其他文件照常导出
+    pub errors: Vec<String>,
+    pub files: Vec<String>,
+}
+
+fn redact_tree(node: &mut WindowNode, own_pids: &[u32]) -> usize {
+    let mut count = 0;
+    if !own_pids.contains(&node.pid) && !node.title.is_empty() {
+        node.title = REDACTED.to_string();
+        count += 1;
+    }
+    for child in &mut node.children {
+        count += redact_tree(child, own_pids);
+    }
+    count
+}
+
+/// 隐藏不属于 host 和它的 WebView2 进程的窗口的标题，返回隐藏的数量。
+/// 其他程序的窗口可能嵌进 host 的窗口里，标题里可能有用户的隐私
+pub fn redact_titles(diagnostics: &mut Diagnostics) -> usize {
+    let mut own_pids = vec![diagnostics.host_pid];
+    own_pids.extend(diagnostics.processes.iter().map(|process| process.pid));
+    let mut count = 0;
+    for window in &mut diagnostics.windows {
+        count += redact_tree(&mut window.tree, &own_pids);
+    }
+    for process in &mut diagnostics.processes {
+        for window in &mut process.windows {
+            count += redact_tree(window, &own_pids);
+        }
+    }
+    count
+}
+
+/// 日志文件的内容，每行一条
+pub fn log_text(lines: &[LogLine]) -> String {
+    let mut text = String::new();
+    for line in lines {
+        text.push_str(&line.to_string());
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inspect::{HostWindow, ProcessInfo};
+
+    const HOST: u32 = 100;
+    const WEBVIEW: u32 = 200;
+    const OTHER: u32 = 300;
+
+    fn node(pid: u32, title: &str, children: Vec<WindowNode>) -> WindowNode {
+        WindowNode {
+            pid,
+            title: title.to_string(),
+            children,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn recent_keeps_the_newest() {
+        let mut recent = Recent::new(3);
+        assert!(recent.is_empty());
+        for i in 0..5 {
+            recent.push(i * 10, i);
+        }
+        assert_eq!(recent.len(), 3);
+        assert_eq!(recent.since(25), vec![3, 4]);
+        assert_eq!(recent.since(0), vec![2, 3, 4]);
+        assert_eq!(recent.since(41), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn copy_out_keeps_the_order_after_wrapping() {
+        let mut recent = Recent::new(4);
+        for i in 0..7 {
+            recent.push(i * 10, i);
+        }
+        let copy = recent.copy_out();
+        recent.push(70, 7);
+        assert_eq!(copy.len(), 4);
+        assert_eq!(copy.since(0), vec![3, 4, 5, 6]);
+        assert_eq!(copy.since(45), vec![5, 6]);
+        assert!(Recent::<u8>::new(2).copy_out().is_empty());
+    }
+
+    #[test]
+    fn recent_tolerates_clock_going_back() {
+        let mut recent = Recent::new(4);
+        recent.push(100, "a");
+        recent.push(50, "b");
+        recent.push(120, "c");
+        assert_eq!(recent.since(60), vec!["a", "c"]);
+    }
+
+    #[test]
+    fn only_other_process_titles_are_redacted() {
+        let mut diagnostics = Diagnostics {
+            host_pid: HOST,
+            windows: vec![HostWindow {
+                label: "main".to_string(),
+                tree: node(
+                    HOST,
+                    "My App",
+                    vec![
+                        node(WEBVIEW, "Chrome Legacy Window", vec![]),
+                        node(OTHER, "secret.docx - Word", vec![node(OTHER, "", vec![])]),
+                    ],
+                ),
+                ..Default::default()
+            }],
+            processes: vec![ProcessInfo {
+                pid: WEBVIEW,
+                windows: vec![node(WEBVIEW, "webview", vec![node(OTHER, "inbox", vec![])])],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert_eq!(redact_titles(&mut diagnostics), 2);
+
+        let tree = &diagnostics.windows[0].tree;
+        assert_eq!(tree.title, "My App");
+        assert_eq!(tree.children[0].title, "Chrome Legacy Window");
+        assert_eq!(tree.children[1].title, REDACTED);
+        // 空标题不算隐藏
+        assert_eq!(tree.children[1].children[0].title, "");
+        let webview = &diagnostics.processes[0].windows[0];
+        assert_eq!(webview.title, "webview");
+        assert_eq!(webview.children[0].title, REDACTED);
+    }
+
+    #[test]
+    fn log_lines() {
+        let lines = [
+            LogLine {
+                time_ms: 1714552200250,
+                level: "WARN".to_string(),
+                target: "passthrough::telemetry".to_string(),
+                message: "message".to_string(),
+            },
+            LogLine {
+                time_ms: 1714552201000,
+                level: "INFO".to_string(),
+                target: "app".to_string(),
+                message: "done".to_string(),
+            },
+        ];
+        assert_eq!(
+            log_text(&lines),
+            "2024-05-01T08:30:00.250Z WARN  passthrough::telemetry: message\n\
+             2024-05-01T08:30:01.000Z INFO  app: done\n"
+        );
+        assert_eq!(log_text(&[]), "");
+    }
+}
@@ -0,0 +1,63 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::clock::Clock;
+
+/// 远程操作的取消标记，克隆出来的共享同一个状态
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// 是否是同一个标记克隆出来的
+    pub fn same(&self, other: &CancelToken) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WaitOutcome {
+    Done,
+    TimedOut,
+    Cancelled,
+}
+
+/// 把一次长等待拆成 `slice` 长的小段，每段之间检查取消标记和总超时。
+/// `wait_once(d)` 最多等待 `d`，等到了返回 true
+pub fn wait_sliced<C: Clock>(
+    clock: &C,
+    timeout: Duration,
+    slice: Duration,
+    cancel: &CancelToken,
+    mut wait_once: impl FnMut(Duration) -> bool,
+) -> WaitOutcome {
+    let deadline = clock.now() + timeout;
+    loop {
+        if cancel.is_cancelled() {
+            return WaitOutcome::Cancelled;
+        }
+        let left = deadline.saturating_duration_since(clock.now());
+        if wait_once(left.min(slice)) {
+            return WaitOutcome::Done;
+        }
+        if clock.now() >= deadline {
+            return WaitOutcome::TimedOut;
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// 时间来源，测试时可以换成 `MockClock`
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// 只有调用 `advance` 时才会走的时钟
+pub struct MockClock {
+    now: Mutex<Instant>,
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        Self {
+            now: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+}
+
+/// UTC 的日期和时间，不依赖外部 crate
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UtcTime {
+    pub year: u32,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl UtcTime {
+    /// 从 1970-01-01 起的秒数换算
+    pub fn from_unix(secs: u64) -> Self {
+        let days = (secs / 86400) as i64;
+        let time = (secs % 86400) as u32;
+        // 从 0000-03-01 算起，每 400 年 146097 天，闰日在每年的最后
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = (yoe + era * 400) as u32 + u32::from(month <= 2);
+        Self {
+            year,
+            month,
+            day,
+            hour: time / 3600,
+            minute: time / 60 % 60,
+            second: time % 60,
+        }
+    }
+}
+
+/// ISO 8601，比如 `2024-05-01T08:30:00Z`
+impl std::fmt::Display for UtcTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(secs: u64) -> String {
+        UtcTime::from_unix(secs).to_string()
+    }
+
+    #[test]
+    fn epoch() {
+        assert_eq!(utc(0), "1970-01-01T00:00:00Z");
+        assert_eq!(utc(86399), "1970-01-01T23:59:59Z");
+    }
+
+    #[test]
+    fn leap_days() {
+        assert_eq!(utc(951782400), "2000-02-29T00:00:00Z");
+        assert_eq!(utc(1709251199), "2024-02-29T23:59:59Z");
+        assert_eq!(utc(1709251200), "2024-03-01T00:00:00Z");
+        // 2100 年不是闰年
+        assert_eq!(utc(4107456000), "2100-02-28T00:00:00Z");
+        assert_eq!(utc(4107542400), "2100-03-01T00:00:00Z");
+    }
+
+    #[test]
+    fn year_boundaries() {
+        assert_eq!(utc(946684799), "1999-12-31T23:59:59Z");
+        assert_eq!(utc(4102444800), "2100-01-01T00:00:00Z");
+        assert_eq!(utc(253402300799), "9999-12-31T23:59:59Z");
+    }
+
+    #[test]
+    fn fields() {
+        assert_eq!(
+            UtcTime::from_unix(1714552259),
+            UtcTime {
+                year: 2024,
+                month: 5,
+                day: 1,
+                hour: 8,
+                minute: 30,
+                second: 59,
+            }
+        );
+    }
+
+    #[test]
+    fn mock_clock_only_moves_when_advanced() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        assert_eq!(clock.now(), start);
+        clock.advance(Duration::from_millis(1500));
+        assert_eq!(clock.now() - start, Duration::from_millis(1500));
+    }
+}
@@ -0,0 +1,205 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+use crate::clock::Clock;
+
+pub enum Submit<R> {
+    /// 距离上次生效已经超过合并窗口，立即生效
+    Apply(R),
+    /// 合并窗口内的请求，等窗口结束后再生效
+    Deferred {
+        /// 是否替换掉了还没生效的请求
+        replaced: bool,
+    },
+}
+
+struct Entry<R> {
+    last_applied: Instant,
+    pending: Option<R>,
+}
+
+/// 合并短时间内的重复请求：窗口内第一个请求立即生效，
+/// 之后的只保留最后一个，窗口结束时再生效
+pub struct Coalescer<K, R, C> {
+    clock: C,
+    window: Duration,
+    entries: HashMap<K, Entry<R>>,
+}
+
+impl<K: Eq + Hash + Clone, R, C: Clock> Coalescer<K, R, C> {
+    pub fn new(clock: C, window: Duration) -> Self {
+        Self {
+            clock,
+            window,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    pub fn set_window(&mut self, window: Duration) {
+        self.window = window;
+    }
+
+    pub fn submit(&mut self, key: K, request: R) -> Submit<R> {
+        let now = self.clock.now();
+        match self.entries.get_mut(&key) {
+            Some(entry) if now < entry.last_applied + self.window => Submit::Deferred {
+                replaced: entry.pending.replace(request).is_some(),
+            },
+            _ => {
+                self.entries.insert(
+                    key,
+                    Entry {
+                        last_applied: now,
+                        pending: None,
+                    },
+                );
+                Submit::Apply(request)
+            }
+        }
+    }
+
+    /// 丢弃还没生效的请求
+    pub fn discard(&mut self, key: &K) -> bool {
+        self.entries
+            .get_mut(key)
+            .is_some_and(|entry| entry.pending.take().is_some())
+    }
+
+    /// 取出窗口已经结束的请求
+    pub fn poll(&mut self) -> Vec<(K, R)> {
+        let now = self.clock.now();
+        let window = self.window;
+        let mut due = Vec::new();
+        for (key, entry) in self.entries.iter_mut() {
+            if entry.pending.is_some() && now >= entry.last_applied + window {
+                entry.last_applied = now;
+                due.extend(entry.pending.take().map(|request| (key.clone(), request)));
+            }
+        }
+        due
+    }
+
+    pub fn next_timeout(&self) -> Option<Duration> {
+        let now = self.clock.now();
+        self.entries
+            .values()
+            .filter(|entry| entry.pending.is_some())
+            .map(|entry| (entry.last_applied + self.window).saturating_duration_since(now))
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    const WINDOW: Duration = Duration::from_millis(16);
+
+    fn coalescer() -> Coalescer<&'static str, u32, MockClock> {
+        Coalescer::new(MockClock::new(), WINDOW)
+    }
+
+    fn applied(submit: Submit<u32>) -> Option<u32> {
+        match submit {
+            Submit::Apply(request) => Some(request),
+            Submit::Deferred { .. } => None,
+        }
+    }
+
+    fn replaced(submit: Submit<u32>) -> bool {
+        match submit {
+            Submit::Apply(_) => panic!("expected the request to be deferred"),
+            Submit::Deferred { replaced } => replaced,
+        }
+    }
+
+    #[test]
+    fn first_request_applies_immediately() {
+        let mut coalescer = coalescer();
+        assert_eq!(applied(coalescer.submit("main", 1)), Some(1));
+        assert_eq!(coalescer.next_timeout(), None);
+        assert!(coalescer.poll().is_empty());
+    }
+
+    #[test]
+    fn burst_collapses_to_the_last_request() {
+        let mut coalescer = coalescer();
+        assert_eq!(applied(coalescer.submit("main", 1)), Some(1));
+        assert!(!replaced(coalescer.submit("main", 2)));
+        assert!(replaced(coalescer.submit("main", 3)));
+        assert!(replaced(coalescer.submit("main", 4)));
+
+        coalescer.clock().advance(WINDOW);
+        assert_eq!(coalescer.poll(), vec![("main", 4)]);
+        assert!(coalescer.poll().is_empty());
+    }
+
+    #[test]
+    fn deferred_request_flushes_after_the_window() {
+        let mut coalescer = coalescer();
+        coalescer.submit("main", 1);
+        coalescer.clock().advance(Duration::from_millis(6));
+        coalescer.submit("main", 2);
+        assert_eq!(coalescer.next_timeout(), Some(Duration::from_millis(10)));
+
+        coalescer.clock().advance(Duration::from_millis(9));
+        assert!(coalescer.poll().is_empty());
+        coalescer.clock().advance(Duration::from_millis(1));
+        assert_eq!(coalescer.poll(), vec![("main", 2)]);
+        assert_eq!(coalescer.next_timeout(), None);
+
+        // 刚刚的刷新也算一次生效，紧接着的请求还要再等一个窗口
+        assert!(!replaced(coalescer.submit("main", 3)));
+        assert_eq!(coalescer.next_timeout(), Some(WINDOW));
+    }
+
+    #[test]
+    fn requests_after_the_window_apply_immediately() {
+        let mut coalescer = coalescer();
+        coalescer.submit("main", 1);
+        coalescer.clock().advance(WINDOW);
+        assert_eq!(applied(coalescer.submit("main", 2)), Some(2));
+    }
+
+    #[test]
+    fn windows_are_tracked_per_key() {
+        let mut coalescer = coalescer();
+        assert_eq!(applied(coalescer.submit("main", 1)), Some(1));
+        assert_eq!(applied(coalescer.submit("overlay", 2)), Some(2));
+        coalescer.submit("overlay", 3);
+        coalescer.clock().advance(WINDOW);
+        assert_eq!(coalescer.poll(), vec![("overlay", 3)]);
+    }
+
+    #[test]
+    fn discard_cancels_the_pending_request() {
+        let mut coalescer = coalescer();
+        coalescer.submit("main", 1);
+        coalescer.submit("main", 2);
+        assert!(coalescer.discard(&"main"));
+        assert!(!coalescer.discard(&"main"));
+        assert_eq!(coalescer.next_timeout(), None);
+
+        coalescer.clock().advance(WINDOW);
+        assert!(coalescer.poll().is_empty());
+        assert!(!coalescer.discard(&"unknown"));
+    }
+
+    #[test]
+    fn set_window_applies_to_pending_requests() {
+        let mut coalescer = coalescer();
+        coalescer.submit("main", 1);
+        coalescer.submit("main", 2);
+        coalescer.set_window(Duration::from_millis(4));
+        coalescer.clock().advance(Duration::from_millis(4));
+        assert_eq!(coalescer.poll(), vec![("main", 2)]);
+    }
+}
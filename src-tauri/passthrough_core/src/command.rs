@@ -0,0 +1,346 @@
+//! host 用注册的窗口消息给 sub_dll 发的命令。
+//!
+//! sub_dll 在 WebView2 窗口的线程上装一个 `WH_CALLWNDPROC` 钩子，收到 [`MESSAGE_NAME`]
+//! 消息时在窗口自己的线程里执行命令，用 `ReplyMessage` 把结果交给 `SendMessage` 的调用者。
+//! 这样每个命令只是一次跨进程的 `SendMessage`，不用再 `CreateRemoteThread`。
+//!
+//! `wParam`（只用低 32 位，32 位进程也能用）：
+//!
+//! | 位    | 内容 |
+//! | ----- | ---- |
+//! | 31-16 | [`COMMAND_TAG`] |
+//! | 15-8  | 协议版本 [`PROTOCOL_VERSION`] |
+//! | 7-0   | 命令 |
+//!
+//! `lParam` 的低 32 位是 host 握手时用 `set_command_token` 交给 sub_dll 的令牌。同一个用户的
+//! 任何进程都能给窗口发消息，令牌不对（或者还没设置）的命令不执行，只回复
+//! `COMMAND_UNAUTHORIZED`。命令不带指针，`UpdateFilter` 的参数由 host 写进
+//! [`command_args`](crate::command_args) 共享内存，sub_dll 自己映射了去读。
+//!
+//! 回复（`LRESULT` 的低 32 位）：
+//!
+//! | 位    | 内容 |
+//! | ----- | ---- |
+//! | 31-24 | [`REPLY_TAG`] |
+//! | 23-16 | 回复的命令 |
+//! | 15-0  | 结果码（见 `abi`） |
+//!
+//! 钩子没装上时窗口过程按默认处理返回 0，不是合法的回复。
+
+use std::{
+    fmt,
+    hash::{BuildHasher, Hasher, RandomState},
+};
+
+/// `RegisterWindowMessageW` 用的名字
+pub const MESSAGE_NAME: &str = "passthrough-sub-dll-command";
+/// `"PC"`
+pub const COMMAND_TAG: u16 = u16::from_be_bytes(*b"PC");
+/// `'R'`
+pub const REPLY_TAG: u8 = b'R';
+/// 命令格式变化时加一
+pub const PROTOCOL_VERSION: u8 = 2;
+
+const OP_PING: u8 = 1;
+const OP_SUBCLASS: u8 = 2;
+const OP_UNSUBCLASS: u8 = 3;
+const OP_UPDATE_FILTER: u8 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// 检查钩子还在
+    Ping,
+    /// 子类化收到消息的窗口
+    Subclass,
+    /// 恢复收到消息的窗口的原始过程
+    Unsubclass,
+    /// 设置过滤规则，参数在 `command_args` 共享内存里
+    UpdateFilter,
+}
+
+impl Command {
+    fn opcode(self) -> u8 {
+        match self {
+            Command::Ping => OP_PING,
+            Command::Subclass => OP_SUBCLASS,
+            Command::Unsubclass => OP_UNSUBCLASS,
+            Command::UpdateFilter => OP_UPDATE_FILTER,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Command::Ping => "ping",
+            Command::Subclass => "subclass",
+            Command::Unsubclass => "unsubclass",
+            Command::UpdateFilter => "updateFilter",
+        }
+    }
+
+    /// 编码成 `(wParam, lParam)`
+    pub fn encode(self, token: u32) -> (usize, isize) {
+        let wparam = (COMMAND_TAG as usize) << 16
+            | (PROTOCOL_VERSION as usize) << 8
+            | self.opcode() as usize;
+        (wparam, token as isize)
+    }
+
+    /// `token` 是 sub_dll 收到的令牌，0 表示还没设置，所有命令都不执行
+    pub fn decode(wparam: usize, lparam: isize, token: u32) -> Result<Self, CommandError> {
+        let wparam = wparam as u32;
+        if (wparam >> 16) as u16 != COMMAND_TAG {
+            return Err(CommandError::NotACommand);
+        }
+        let version = (wparam >> 8) as u8;
+        let opcode = wparam as u8;
+        if version != PROTOCOL_VERSION {
+            return Err(CommandError::Version { opcode, version });
+        }
+        if token == 0 || lparam as u32 != token {
+            return Err(CommandError::Unauthorized { opcode });
+        }
+        match opcode {
+            OP_PING => Ok(Command::Ping),
+            OP_SUBCLASS => Ok(Command::Subclass),
+            OP_UNSUBCLASS => Ok(Command::Unsubclass),
+            OP_UPDATE_FILTER => Ok(Command::UpdateFilter),
+            _ => Err(CommandError::Invalid { opcode }),
+        }
+    }
+
+    /// sub_dll 回复这个命令
+    pub fn reply(self, value: u16) -> isize {
+        reply(self.opcode(), value)
+    }
+
+    /// host 解析这个命令的回复
+    pub fn parse_reply(self, result: isize) -> Result<u16, CommandError> {
+        let result = result as u32;
+        if (result >> 24) as u8 != REPLY_TAG || (result >> 16) as u8 != self.opcode() {
+            return Err(CommandError::NoReply);
+        }
+        Ok(result as u16)
+    }
+}
+
+fn reply(opcode: u8, value: u16) -> isize {
+    ((REPLY_TAG as u32) << 24 | (opcode as u32) << 16 | value as u32) as isize
+}
+
+/// host 每次启动生成一个，不会是 0
+pub fn new_token() -> u32 {
+    loop {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u32(std::process::id());
+        let token = hasher.finish() as u32;
+        if token != 0 {
+            return token;
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommandError {
+    /// 不是 host 发的命令，sub_dll 不回复
+    NotACommand,
+    /// 协议版本不一致
+    Version { opcode: u8, version: u8 },
+    /// 不认识的命令
+    Invalid { opcode: u8 },
+    /// 令牌不对，命令没有执行
+    Unauthorized { opcode: u8 },
+    /// 没有合法的回复，通常是钩子没装上
+    NoReply,
+}
+
+impl CommandError {
+    /// sub_dll 对解析不了的命令的回复，`NotACommand` 不回复
+    pub fn reply(self, code: u16) -> Option<isize> {
+        match self {
+            CommandError::Version { opcode, .. }
+            | CommandError::Invalid { opcode }
+            | CommandError::Unauthorized { opcode } => Some(reply(opcode, code)),
+            CommandError::NotACommand | CommandError::NoReply => None,
+        }
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::NotACommand => write!(f, "message is not a sub_dll command"),
+            CommandError::Version { version, .. } => write!(
+                f,
+                "command protocol version {} does not match version {}",
+                version, PROTOCOL_VERSION
+            ),
+            CommandError::Invalid { opcode } => write!(f, "invalid command {}", opcode),
+            CommandError::Unauthorized { opcode } => {
+                write!(f, "command {} has the wrong session token", opcode)
+            }
+            CommandError::NoReply => write!(f, "sub_dll did not reply to the command"),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMMANDS: [Command; 4] = [
+        Command::Ping,
+        Command::Subclass,
+        Command::Unsubclass,
+        Command::UpdateFilter,
+    ];
+
+    const TOKEN: u32 = 0x8765_4321;
+
+    #[test]
+    fn encode_decode_round_trip() {
+        for command in COMMANDS {
+            let (wparam, lparam) = command.encode(TOKEN);
+            assert_eq!(
+                Command::decode(wparam, lparam, TOKEN),
+                Ok(command),
+                "{:?}",
+                command
+            );
+        }
+    }
+
+    #[test]
+    fn wparam_layout() {
+        assert_eq!(Command::Ping.encode(7), (0x5043_0201, 7));
+        assert_eq!(Command::Subclass.encode(7), (0x5043_0202, 7));
+        assert_eq!(Command::Unsubclass.encode(7), (0x5043_0203, 7));
+        assert_eq!(Command::UpdateFilter.encode(7), (0x5043_0204, 7));
+        // 令牌原样放进 lParam，32 位进程里也能完整传过去
+        assert_eq!(Command::Ping.encode(u32::MAX).1 as u32, u32::MAX);
+    }
+
+    #[test]
+    fn reply_round_trip() {
+        for command in COMMANDS {
+            for value in [0, 1, 0x8000, u16::MAX] {
+                assert_eq!(command.parse_reply(command.reply(value)), Ok(value));
+            }
+        }
+        assert_eq!(Command::Subclass.reply(3), 0x5202_0003);
+    }
+
+    #[test]
+    fn garbage_replies_are_rejected() {
+        for command in COMMANDS {
+            // 钩子没装上时默认窗口过程返回 0
+            for result in [0, 1, -1, 0x5200_0000, 0x4102_0000, isize::MIN] {
+                assert_eq!(
+                    command.parse_reply(result),
+                    Err(CommandError::NoReply),
+                    "{:?} {:#x}",
+                    command,
+                    result
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn reply_to_another_command_is_rejected() {
+        let reply = Command::Subclass.reply(0);
+        assert_eq!(Command::Subclass.parse_reply(reply), Ok(0));
+        assert_eq!(
+            Command::Unsubclass.parse_reply(reply),
+            Err(CommandError::NoReply)
+        );
+        assert_eq!(Command::Ping.parse_reply(reply), Err(CommandError::NoReply));
+    }
+
+    #[test]
+    fn other_messages_are_not_commands() {
+        for wparam in [0, 0x5043, 0x4350_0201, 0xffff_0201] {
+            assert_eq!(
+                Command::decode(wparam, TOKEN as isize, TOKEN),
+                Err(CommandError::NotACommand),
+                "{:#x}",
+                wparam
+            );
+        }
+    }
+
+    #[test]
+    fn high_bits_of_wparam_are_ignored() {
+        let (wparam, lparam) = Command::Subclass.encode(TOKEN);
+        assert_eq!(
+            Command::decode(wparam | 0xdead_0000_0000, lparam, TOKEN),
+            Ok(Command::Subclass)
+        );
+    }
+
+    #[test]
+    fn version_mismatch() {
+        let wparam = (COMMAND_TAG as usize) << 16 | 1 << 8 | OP_SUBCLASS as usize;
+        let err = Command::decode(wparam, TOKEN as isize, TOKEN).unwrap_err();
+        assert_eq!(
+            err,
+            CommandError::Version {
+                opcode: OP_SUBCLASS,
+                version: 1,
+            }
+        );
+        assert_eq!(err.reply(9), Some(Command::Subclass.reply(9)));
+    }
+
+    #[test]
+    fn unknown_commands_are_invalid() {
+        for opcode in [0, 5, 0xff] {
+            let wparam = (COMMAND_TAG as usize) << 16 | (PROTOCOL_VERSION as usize) << 8 | opcode;
+            let err = Command::decode(wparam, TOKEN as isize, TOKEN).unwrap_err();
+            assert_eq!(
+                err,
+                CommandError::Invalid {
+                    opcode: opcode as u8
+                }
+            );
+            assert_eq!(err.reply(1), Some(reply(opcode as u8, 1)));
+        }
+    }
+
+    #[test]
+    fn commands_with_the_wrong_token_are_unauthorized() {
+        for command in COMMANDS {
+            let opcode = command.opcode();
+            for (sent, expected) in [(0, TOKEN), (TOKEN + 1, TOKEN), (TOKEN, 0), (0, 0)] {
+                let (wparam, lparam) = command.encode(sent);
+                let err = Command::decode(wparam, lparam, expected).unwrap_err();
+                assert_eq!(err, CommandError::Unauthorized { opcode }, "{:?}", command);
+                // 回复里只有结果码，调用者能知道钩子还在，但命令没有执行
+                assert_eq!(err.reply(2), Some(command.reply(2)));
+            }
+        }
+    }
+
+    #[test]
+    fn high_bits_of_lparam_are_not_part_of_the_token() {
+        let (wparam, lparam) = Command::Subclass.encode(TOKEN);
+        assert_eq!(
+            Command::decode(wparam, lparam | 0x7fff_0000_0000, TOKEN),
+            Ok(Command::Subclass)
+        );
+    }
+
+    #[test]
+    fn new_tokens_are_nonzero_and_differ() {
+        let tokens: Vec<u32> = (0..8).map(|_| new_token()).collect();
+        assert!(tokens.iter().all(|&token| token != 0));
+        assert!(tokens.windows(2).any(|pair| pair[0] != pair[1]));
+    }
+
+    #[test]
+    fn errors_without_a_reply() {
+        assert_eq!(CommandError::NotACommand.reply(1), None);
+        assert_eq!(CommandError::NoReply.reply(1), None);
+    }
+}
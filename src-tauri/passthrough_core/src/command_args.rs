@@ -0,0 +1,309 @@
+//! 窗口消息命令的参数，放在 host 建的命名共享内存里，每个 WebView2 进程一个。
+//!
+//! 命令本身只有操作码和令牌，sub_dll 不会去解引用 `lParam` 里的地址；需要参数的命令
+//! （现在只有 `UpdateFilter`）由 host 先写到这里，sub_dll 收到命令时自己映射这块内存读出来，
+//! 再按 `filter` 的格式检查。和 `region_table` 一样用 seqlock：host 是唯一的写者，
+//! 写之前 `seq` 变成奇数，写完变成下一个偶数
+
+use std::{
+    fmt, hint,
+    sync::atomic::{fence, AtomicU32, AtomicU64, Ordering},
+};
+
+use crate::filter;
+
+/// `"PTCA"`
+pub const MAGIC: u32 = u32::from_le_bytes(*b"PTCA");
+/// 内存布局版本，字段变化时加一
+pub const LAYOUT_VERSION: u32 = 1;
+/// 最多能放下 `filter::MAX_RULES` 条规则的参数块
+pub const CAPACITY: usize = filter::HEADER_SIZE + filter::MAX_RULES * filter::RULE_SIZE;
+const WORDS: usize = CAPACITY.div_ceil(8);
+/// 共享内存段至少要这么大
+pub const SECTION_SIZE: usize = std::mem::size_of::<CommandArgs>();
+/// 读者最多重试的次数
+pub const READ_ATTEMPTS: usize = 128;
+
+/// 共享内存的名字，每个 WebView2 进程一个
+pub fn section_name(pid: u32) -> String {
+    format!("Local\\passthrough-command-args-{}", pid)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArgsError {
+    /// host 还没有初始化这块内存
+    Uninitialized,
+    Version {
+        expected: u32,
+        found: u32,
+    },
+    TooLarge {
+        len: usize,
+    },
+    /// 重试了 `READ_ATTEMPTS` 次都碰上写者
+    Busy,
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgsError::Uninitialized => write!(f, "command arguments are not initialized"),
+            ArgsError::Version { expected, found } => write!(
+                f,
+                "command arguments layout version is {}, expected {}",
+                found, expected
+            ),
+            ArgsError::TooLarge { len } => write!(
+                f,
+                "{} bytes of command arguments do not fit (max {})",
+                len, CAPACITY
+            ),
+            ArgsError::Busy => write!(f, "command arguments are being written"),
+        }
+    }
+}
+
+impl std::error::Error for ArgsError {}
+
+/// 共享内存里的布局，改了要加 `LAYOUT_VERSION`
+#[repr(C)]
+pub struct CommandArgs {
+    magic: AtomicU32,
+    version: AtomicU32,
+    seq: AtomicU32,
+    len: AtomicU32,
+    /// 参数按小端序每 8 字节一个字
+    data: [AtomicU64; WORDS],
+}
+
+impl CommandArgs {
+    /// 进程内使用，共享内存用 `from_ptr`
+    pub const fn new() -> Self {
+        Self {
+            magic: AtomicU32::new(0),
+            version: AtomicU32::new(0),
+            seq: AtomicU32::new(0),
+            len: AtomicU32::new(0),
+            data: [const { AtomicU64::new(0) }; WORDS],
+        }
+    }
+
+    /// # Safety
+    /// `ptr` 按 8 字节对齐，至少有 `SECTION_SIZE` 字节，在返回的引用用完之前一直有效。
+    /// 新建的共享内存全是 0，和 `new()` 一样
+    pub unsafe fn from_ptr<'a>(ptr: *const u8) -> &'a CommandArgs {
+        &*(ptr as *const CommandArgs)
+    }
+
+    /// 写者在第一次写之前调用。已经初始化过的保持不变
+    pub fn init(&self) {
+        if self.magic.load(Ordering::Acquire) == MAGIC {
+            return;
+        }
+        self.version.store(LAYOUT_VERSION, Ordering::Relaxed);
+        self.seq.store(0, Ordering::Relaxed);
+        self.len.store(0, Ordering::Relaxed);
+        self.magic.store(MAGIC, Ordering::Release);
+    }
+
+    fn check_header(&self) -> Result<(), ArgsError> {
+        if self.magic.load(Ordering::Acquire) != MAGIC {
+            return Err(ArgsError::Uninitialized);
+        }
+        let version = self.version.load(Ordering::Relaxed);
+        if version != LAYOUT_VERSION {
+            return Err(ArgsError::Version {
+                expected: LAYOUT_VERSION,
+                found: version,
+            });
+        }
+        Ok(())
+    }
+
+    /// 替换参数。只能有一个写者
+    pub fn write(&self, args: &[u8]) -> Result<(), ArgsError> {
+        self.check_header()?;
+        if args.len() > CAPACITY {
+            return Err(ArgsError::TooLarge { len: args.len() });
+        }
+        // 上一个写者写到一半退出时 seq 还是奇数，直接接着用
+        let start = self.seq.load(Ordering::Relaxed) | 1;
+        self.seq.store(start, Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        self.len.store(args.len() as u32, Ordering::Relaxed);
+        for (word, chunk) in self.data.iter().zip(args.chunks(8)) {
+            let mut bytes = [0; 8];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            word.store(u64::from_le_bytes(bytes), Ordering::Relaxed);
+        }
+
+        self.seq.store(start.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// 读一份完整的参数，碰上写者时重试
+    pub fn read(&self) -> Result<Vec<u8>, ArgsError> {
+        self.check_header()?;
+        for _ in 0..READ_ATTEMPTS {
+            let before = self.seq.load(Ordering::Acquire);
+            if before & 1 == 1 {
+                hint::spin_loop();
+                continue;
+            }
+            let args = self.load();
+            fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) == before {
+                return Ok(args);
+            }
+            hint::spin_loop();
+        }
+        Err(ArgsError::Busy)
+    }
+
+    /// 可能读到写了一半的内容，由 `read` 判断要不要丢掉
+    fn load(&self) -> Vec<u8> {
+        // 写了一半的 len 可能是任何值
+        let len = (self.len.load(Ordering::Relaxed) as usize).min(CAPACITY);
+        let mut args: Vec<u8> = self.data[..len.div_ceil(8)]
+            .iter()
+            .flat_map(|word| word.load(Ordering::Relaxed).to_le_bytes())
+            .collect();
+        args.truncate(len);
+        args
+    }
+}
+
+impl Default for CommandArgs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{atomic::AtomicBool, Arc},
+        thread,
+    };
+
+    use super::*;
+    use crate::filter::{Action, Rule};
+
+    fn initialized() -> CommandArgs {
+        let args = CommandArgs::new();
+        args.init();
+        args
+    }
+
+    #[test]
+    fn uninitialized_section_is_rejected() {
+        let args = CommandArgs::new();
+        assert_eq!(args.read(), Err(ArgsError::Uninitialized));
+        assert_eq!(args.write(&[1]), Err(ArgsError::Uninitialized));
+    }
+
+    #[test]
+    fn other_layout_version_is_rejected() {
+        let args = initialized();
+        args.version.store(LAYOUT_VERSION + 1, Ordering::Relaxed);
+        assert_eq!(
+            args.read(),
+            Err(ArgsError::Version {
+                expected: LAYOUT_VERSION,
+                found: LAYOUT_VERSION + 1,
+            })
+        );
+    }
+
+    #[test]
+    fn write_then_read_keeps_the_exact_length() {
+        let args = initialized();
+        assert_eq!(args.read(), Ok(Vec::new()));
+        for len in [1, 7, 8, 9, CAPACITY - 1, CAPACITY, 3] {
+            let bytes: Vec<u8> = (0..len).map(|i| i as u8 ^ 0x5a).collect();
+            args.write(&bytes).unwrap();
+            assert_eq!(args.read(), Ok(bytes), "{}", len);
+        }
+    }
+
+    #[test]
+    fn the_largest_filter_fits() {
+        let args = initialized();
+        let rules = vec![
+            Rule {
+                msg: filter::WM_MOUSELEAVE,
+                action: Action::Swallow { result: -1 },
+            };
+            filter::MAX_RULES
+        ];
+        let encoded = filter::encode(0x1234, &rules);
+        assert_eq!(encoded.len(), CAPACITY);
+        args.write(&encoded).unwrap();
+        assert_eq!(filter::decode(&args.read().unwrap()), Ok((0x1234, rules)));
+    }
+
+    #[test]
+    fn too_large_arguments_are_rejected() {
+        let args = initialized();
+        args.write(&[1, 2, 3]).unwrap();
+        assert_eq!(
+            args.write(&vec![0; CAPACITY + 1]),
+            Err(ArgsError::TooLarge { len: CAPACITY + 1 })
+        );
+        assert_eq!(args.read(), Ok(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn corrupt_length_is_clamped() {
+        let args = initialized();
+        args.len.store(u32::MAX, Ordering::Relaxed);
+        assert_eq!(args.read().unwrap().len(), CAPACITY);
+    }
+
+    #[test]
+    fn reader_gives_up_while_writing() {
+        let args = initialized();
+        args.seq.store(1, Ordering::Relaxed);
+        assert_eq!(args.read(), Err(ArgsError::Busy));
+        // 写到一半退出的写者留下的奇数 seq 由下一个写者接着用
+        args.write(&[4]).unwrap();
+        assert_eq!(args.read(), Ok(vec![4]));
+    }
+
+    /// 第 `i` 次写入 `i % CAPACITY` 个值为 `i` 的字节，读到混合了两次写入的内容就能发现
+    #[test]
+    fn concurrent_readers_never_see_torn_writes() {
+        const WRITES: usize = 5_000;
+
+        let args = Arc::new(initialized());
+        let done = Arc::new(AtomicBool::new(false));
+        let reader = {
+            let (args, done) = (args.clone(), done.clone());
+            thread::spawn(move || loop {
+                let finished = done.load(Ordering::Relaxed);
+                match args.read() {
+                    Ok(bytes) => {
+                        if let Some(&first) = bytes.first() {
+                            assert!(bytes.iter().all(|&byte| byte == first));
+                            assert_eq!(bytes.len() % 256, first as usize);
+                        }
+                    }
+                    Err(ArgsError::Busy) => {}
+                    Err(err) => panic!("{}", err),
+                }
+                if finished {
+                    break;
+                }
+                thread::yield_now();
+            })
+        };
+        for i in 0..WRITES {
+            // 长度和内容都由 `i` 决定，长度对 256 取余就是字节的值
+            let len = i % (CAPACITY / 256) * 256 + i % 256;
+            args.write(&vec![(i % 256) as u8; len]).unwrap();
+        }
+        done.store(true, Ordering::Relaxed);
+        reader.join().unwrap();
+    }
+}
@@ -0,0 +1,276 @@
+use std::{
+    fmt, fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use crate::sha256;
+
+/// 缓存目录下放 DLL 的子目录
+pub const CACHE_DIR: &str = "sub_dll";
+/// sub_dll 的文件名，也是它在 WebView2 进程里的模块名
+pub const DLL_NAME: &str = "sub_dll.dll";
+
+/// `root/sub_dll/<version>/<file_name>`，不同版本的 DLL 互不覆盖：
+/// 旧版本可能还被 WebView2 进程加载着，文件是锁住的
+pub fn cache_path(root: &Path, version: &str, file_name: &str) -> PathBuf {
+    root.join(CACHE_DIR).join(version).join(file_name)
+}
+
+/// 文件内容是否和 `bytes` 一样，文件不存在时返回 false
+pub fn matches(path: &Path, bytes: &[u8]) -> io::Result<bool> {
+    match fs::read(path) {
+        Ok(existing) => Ok(existing == bytes),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// 把 DLL 写到缓存目录，已经是同样内容时不再写。
+/// 先写临时文件再改名，中途退出不会留下半个 DLL。旁边用 `sha256_path` 记下它的 SHA-256
+pub fn extract(root: &Path, version: &str, file_name: &str, bytes: &[u8]) -> io::Result<PathBuf> {
+    let path = cache_path(root, version, file_name);
+    if !matches(&path, bytes)? {
+        let dir = path.parent().unwrap_or(root);
+        fs::create_dir_all(dir)?;
+        let temp = dir.join(format!("{}.{}.tmp", file_name, std::process::id()));
+        fs::write(&temp, bytes)?;
+        if let Err(err) = fs::rename(&temp, &path) {
+            let _ = fs::remove_file(&temp);
+            return Err(err);
+        }
+    }
+    let digest = sha256::to_hex(&sha256::digest(bytes));
+    if recorded_sha256(&path).as_deref() != Some(digest.as_str()) {
+        fs::write(sha256_path(&path), digest)?;
+    }
+    Ok(path)
+}
+
+/// `extract` 在 DLL 旁边记下的 SHA-256，就是 host 编译时嵌入的那个。
+/// passthrough-doctor 不知道 host 的 `SUB_DLL_SHA256`，用它检查加载着的文件有没有被换掉
+pub fn sha256_path(dll_path: &Path) -> PathBuf {
+    let mut path = dll_path.as_os_str().to_owned();
+    path.push(".sha256");
+    PathBuf::from(path)
+}
+
+/// 读 `sha256_path` 里的记录（小写十六进制），没有记录时返回 `None`
+pub fn recorded_sha256(dll_path: &Path) -> Option<String> {
+    let text = fs::read_to_string(sha256_path(dll_path)).ok()?;
+    Some(text.trim().to_ascii_lowercase())
+}
+
+/// 从加载着的 DLL 的路径认出它是哪个版本，路径不在缓存目录里时返回 `None`。
+/// 路径可能来自另一台 Windows 机器的记录，`\\` 和 `/` 都当作分隔符
+pub fn version_of(path: &str) -> Option<&str> {
+    let mut parts = path.rsplit(['\\', '/']).skip(1);
+    let version = parts.next()?;
+    let dir = parts.next()?;
+    (dir.eq_ignore_ascii_case(CACHE_DIR) && !version.is_empty()).then_some(version)
+}
+
+/// 版本里的接口版本，见 `hook_sub::sub_dll_version`
+pub fn abi_of_version(version: &str) -> Option<u16> {
+    version.rsplit_once("-abi")?.1.parse().ok()
+}
+
+/// 删除其他版本的目录，返回删除的数量。还被加载着的删不掉，下次启动再试
+pub fn remove_stale(root: &Path, keep_version: &str) -> usize {
+    let Ok(entries) = fs::read_dir(root.join(CACHE_DIR)) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .filter(|entry| entry.file_name() != keep_version)
+        .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()))
+        .filter(|entry| fs::remove_dir_all(entry.path()).is_ok())
+        .count()
+}
+
+#[derive(Debug)]
+pub enum IntegrityError {
+    Io(io::Error),
+    /// 文件的 SHA-256 和编译时记录的不一致
+    Mismatch {
+        expected: String,
+        found: String,
+    },
+}
+
+impl fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntegrityError::Io(err) => write!(f, "read dll: {}", err),
+            IntegrityError::Mismatch { expected, found } => {
+                write!(f, "sha256 is {}, expected {}", found, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for IntegrityError {}
+
+/// 计算文件内容的 SHA-256 并和 `expected`（十六进制，不区分大小写）比较。
+/// 传入已经打开的文件，调用方可以在校验之后继续锁着同一个文件
+pub fn verify(file: impl Read, expected: &str) -> Result<(), IntegrityError> {
+    let found = sha256::to_hex(&sha256::digest_reader(file).map_err(IntegrityError::Io)?);
+    if found.eq_ignore_ascii_case(expected) {
+        Ok(())
+    } else {
+        Err(IntegrityError::Mismatch {
+            expected: expected.to_ascii_lowercase(),
+            found,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 测试用的临时目录，drop 时删除
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "passthrough-dll-cache-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn versions(root: &Path) -> Vec<String> {
+        let mut versions: Vec<String> = fs::read_dir(root.join(CACHE_DIR))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        versions.sort();
+        versions
+    }
+
+    #[test]
+    fn extract_writes_into_the_version_directory() {
+        let dir = TempDir::new("extract");
+        let path = extract(&dir.0, "0.1.0-abi2", "sub_dll.dll", b"dll").unwrap();
+        assert_eq!(
+            path,
+            dir.0.join("sub_dll").join("0.1.0-abi2").join("sub_dll.dll")
+        );
+        assert_eq!(fs::read(&path).unwrap(), b"dll");
+        // 只有 DLL 和它的 SHA-256，没有留下临时文件
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn extract_records_the_sha256_next_to_the_dll() {
+        let dir = TempDir::new("sha256");
+        let abc = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        let path = extract(&dir.0, "1", "sub_dll.dll", b"abc").unwrap();
+        assert_eq!(
+            sha256_path(&path),
+            dir.0.join("sub_dll").join("1").join("sub_dll.dll.sha256")
+        );
+        assert_eq!(recorded_sha256(&path).as_deref(), Some(abc));
+
+        // 之前的版本解压时没有记录，DLL 不变也要补上
+        fs::remove_file(sha256_path(&path)).unwrap();
+        assert_eq!(recorded_sha256(&path), None);
+        extract(&dir.0, "1", "sub_dll.dll", b"abc").unwrap();
+        assert_eq!(recorded_sha256(&path).as_deref(), Some(abc));
+
+        // 内容换了，记录跟着换
+        extract(&dir.0, "1", "sub_dll.dll", b"new").unwrap();
+        assert_eq!(
+            recorded_sha256(&path),
+            Some(sha256::to_hex(&sha256::digest(b"new")))
+        );
+    }
+
+    #[test]
+    fn extract_skips_identical_and_replaces_different_content() {
+        let dir = TempDir::new("replace");
+        let path = extract(&dir.0, "1", "sub_dll.dll", b"old").unwrap();
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        assert_eq!(extract(&dir.0, "1", "sub_dll.dll", b"old").unwrap(), path);
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), modified);
+
+        extract(&dir.0, "1", "sub_dll.dll", b"new").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert!(matches(&path, b"new").unwrap());
+        assert!(!matches(&path, b"old").unwrap());
+        assert!(!matches(&dir.0.join("missing.dll"), b"new").unwrap());
+    }
+
+    #[test]
+    fn remove_stale_keeps_the_current_version() {
+        let dir = TempDir::new("stale");
+        extract(&dir.0, "0.1.0-abi1", "sub_dll.dll", b"1").unwrap();
+        extract(&dir.0, "0.1.0-abi2", "sub_dll.dll", b"2").unwrap();
+        extract(&dir.0, "0.2.0-abi2", "sub_dll.dll", b"3").unwrap();
+        // 缓存目录里的文件不是版本目录，不删
+        fs::write(dir.0.join(CACHE_DIR).join("notes.txt"), b"").unwrap();
+
+        assert_eq!(remove_stale(&dir.0, "0.2.0-abi2"), 2);
+        assert_eq!(versions(&dir.0), ["0.2.0-abi2", "notes.txt"]);
+        assert_eq!(remove_stale(&dir.0, "0.2.0-abi2"), 0);
+    }
+
+    #[test]
+    fn remove_stale_without_cache_directory() {
+        let dir = TempDir::new("empty");
+        assert_eq!(remove_stale(&dir.0, "1"), 0);
+    }
+
+    #[test]
+    fn version_of_cached_paths() {
+        assert_eq!(
+            version_of(r"C:\Users\demo\AppData\Local\app\sub_dll\0.1.0-abi2\sub_dll.dll"),
+            Some("0.1.0-abi2")
+        );
+        assert_eq!(
+            version_of("/home/demo/.cache/app/SUB_DLL/0.1.0-abi2/sub_dll.dll"),
+            Some("0.1.0-abi2")
+        );
+        assert_eq!(version_of(r"C:\Windows\System32\sub_dll.dll"), None);
+        assert_eq!(version_of(r"sub_dll\\sub_dll.dll"), None);
+        assert_eq!(version_of("sub_dll.dll"), None);
+        assert_eq!(version_of(""), None);
+    }
+
+    #[test]
+    fn abi_of_versions() {
+        assert_eq!(abi_of_version("0.1.0-abi2"), Some(2));
+        assert_eq!(abi_of_version("0.1.0-rc-abi12"), Some(12));
+        assert_eq!(abi_of_version("0.1.0"), None);
+        assert_eq!(abi_of_version("0.1.0-abix"), None);
+    }
+
+    #[test]
+    fn verify_compares_sha256() {
+        let abc = "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD";
+        assert!(verify(&b"abc"[..], abc).is_ok());
+        match verify(&b"abc"[..], &"0".repeat(64)) {
+            Err(IntegrityError::Mismatch { expected, found }) => {
+                assert_eq!(expected, "0".repeat(64));
+                assert_eq!(found, abc.to_ascii_lowercase());
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let dir = TempDir::new("verify");
+        let path = extract(&dir.0, "1", "sub_dll.dll", b"abc").unwrap();
+        assert!(verify(fs::File::open(path).unwrap(), abc).is_ok());
+    }
+}
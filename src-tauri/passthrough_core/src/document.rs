@@ -0,0 +1,271 @@
+use std::{fmt, fs, io, path::Path};
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+/// 把第 n 版的文档升级到第 n + 1 版
+pub type Migration = fn(Value) -> Result<Value, String>;
+
+/// 带 `version` 字段的 JSON 文档：当前版本和从旧版本升级的步骤
+pub struct Schema {
+    pub version: u64,
+    /// `migrations[n]` 把第 n 版升级到第 n + 1 版，不支持的旧版本留空位置
+    pub migrations: &'static [Option<Migration>],
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReadError {
+    /// 更新版本的程序写的文件，不认识也不能覆盖
+    Newer(u64),
+    /// 读不出来的文件，`load` 已经把它改名成 `.json.corrupt`
+    Corrupt(String),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Newer(version) => write!(f, "written by a newer version ({})", version),
+            Self::Corrupt(err) => f.write_str(err),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+impl From<String> for ReadError {
+    fn from(err: String) -> Self {
+        ReadError::Corrupt(err)
+    }
+}
+
+impl Schema {
+    pub fn migrate(&self, mut value: Value) -> Result<Value, ReadError> {
+        loop {
+            let version = value
+                .get("version")
+                .ok_or_else(|| "missing version".to_string())?
+                .as_u64()
+                .ok_or_else(|| format!("invalid version: {}", value["version"]))?;
+            if version == self.version {
+                return Ok(value);
+            }
+            if version > self.version {
+                return Err(ReadError::Newer(version));
+            }
+            let migration = self
+                .migrations
+                .get(version as usize)
+                .copied()
+                .flatten()
+                .ok_or_else(|| format!("unsupported version {}", version))?;
+            value = migration(value)?;
+        }
+    }
+
+    pub fn parse<T: DeserializeOwned>(&self, text: &str) -> Result<T, ReadError> {
+        let value = serde_json::from_str(text).map_err(|err| err.to_string())?;
+        serde_json::from_value(self.migrate(value)?)
+            .map_err(|err| ReadError::Corrupt(err.to_string()))
+    }
+
+    /// 读取并升级到当前版本，文件不存在时返回 `None`。
+    /// 文件损坏时备份成 `.json.corrupt`，调用方从空状态开始；
+    /// 更新版本的文件原样保留，调用方只能只读使用
+    pub fn load<T: DeserializeOwned>(&self, path: &Path) -> Result<Option<T>, ReadError> {
+        let document = match fs::read_to_string(path) {
+            Ok(text) => self.parse(&text),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => Err(ReadError::Corrupt(err.to_string())),
+        };
+        if let Err(ReadError::Corrupt(_)) = &document {
+            let _ = fs::rename(path, path.with_extension("json.corrupt"));
+        }
+        document.map(Some)
+    }
+}
+
+/// 先写临时文件再替换，避免写到一半退出把文件写坏
+pub fn write<T: Serialize>(path: &Path, document: &T) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_vec_pretty(document)?)?;
+    fs::rename(tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, path::PathBuf};
+
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use super::*;
+
+    /// 第 1 版的 `names` 是数组，第 2 版改成 name -> 次数，第 3 版加了 `enabled`
+    fn v1_to_v2(mut value: Value) -> Result<Value, String> {
+        let names = value["names"].as_array().ok_or("names is not an array")?;
+        let counts: HashMap<String, u64> = names
+            .iter()
+            .filter_map(|name| Some((name.as_str()?.to_string(), 1)))
+            .collect();
+        value["names"] = json!(counts);
+        value["version"] = json!(2);
+        Ok(value)
+    }
+
+    fn v2_to_v3(mut value: Value) -> Result<Value, String> {
+        value["enabled"] = json!(true);
+        value["version"] = json!(3);
+        Ok(value)
+    }
+
+    const SCHEMA: Schema = Schema {
+        version: 3,
+        migrations: &[None, Some(v1_to_v2), Some(v2_to_v3)],
+    };
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Document {
+        version: u64,
+        names: HashMap<String, u64>,
+        enabled: bool,
+    }
+
+    fn document() -> Document {
+        Document {
+            version: 3,
+            names: HashMap::from([("a".to_string(), 2)]),
+            enabled: false,
+        }
+    }
+
+    /// 测试用的临时目录，drop 时删除
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "passthrough-document-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn file(&self) -> PathBuf {
+            self.0.join("state.json")
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn current_version_is_returned_unchanged() {
+        let value = json!({ "version": 3, "names": {}, "enabled": false });
+        assert_eq!(SCHEMA.migrate(value.clone()), Ok(value));
+    }
+
+    #[test]
+    fn old_versions_run_every_migration_in_order() {
+        let document: Document = SCHEMA
+            .parse(r#"{ "version": 1, "names": ["a", "b"] }"#)
+            .unwrap();
+        assert_eq!(
+            document,
+            Document {
+                version: 3,
+                names: HashMap::from([("a".to_string(), 1), ("b".to_string(), 1)]),
+                enabled: true,
+            }
+        );
+        let document: Document = SCHEMA
+            .parse(r#"{ "version": 2, "names": { "a": 5 } }"#)
+            .unwrap();
+        assert_eq!(document.names["a"], 5);
+        assert!(document.enabled);
+    }
+
+    #[test]
+    fn newer_versions_are_reported_not_migrated() {
+        assert_eq!(
+            SCHEMA.migrate(json!({ "version": 4 })),
+            Err(ReadError::Newer(4))
+        );
+    }
+
+    #[test]
+    fn unsupported_missing_and_invalid_versions_are_corrupt() {
+        for value in [
+            json!({ "version": 0 }),
+            json!({ "names": [] }),
+            json!({ "version": "1" }),
+            json!({ "version": -1 }),
+        ] {
+            assert!(
+                matches!(SCHEMA.migrate(value.clone()), Err(ReadError::Corrupt(_))),
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn failed_migrations_are_corrupt() {
+        assert_eq!(
+            SCHEMA.migrate(json!({ "version": 1, "names": 1 })),
+            Err(ReadError::Corrupt("names is not an array".to_string()))
+        );
+    }
+
+    #[test]
+    fn load_returns_none_for_a_missing_file() {
+        let dir = TempDir::new("missing");
+        assert_eq!(SCHEMA.load::<Document>(&dir.file()), Ok(None));
+    }
+
+    #[test]
+    fn write_then_load_round_trips_without_leaving_a_temp_file() {
+        let dir = TempDir::new("round-trip");
+        write(&dir.file(), &document()).unwrap();
+        assert_eq!(SCHEMA.load(&dir.file()), Ok(Some(document())));
+        assert_eq!(fs::read_dir(&dir.0).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn load_leaves_newer_files_in_place() {
+        let dir = TempDir::new("newer");
+        let text = r#"{ "version": 9, "unknown": true }"#;
+        fs::write(dir.file(), text).unwrap();
+        assert_eq!(
+            SCHEMA.load::<Document>(&dir.file()),
+            Err(ReadError::Newer(9))
+        );
+        assert_eq!(fs::read_to_string(dir.file()).unwrap(), text);
+        assert!(!dir.file().with_extension("json.corrupt").exists());
+    }
+
+    #[test]
+    fn load_backs_up_corrupt_files() {
+        let dir = TempDir::new("corrupt");
+        for text in ["{ not json", r#"{ "version": 3 }"#, r#"{ "version": 0 }"#] {
+            fs::write(dir.file(), text).unwrap();
+            assert!(matches!(
+                SCHEMA.load::<Document>(&dir.file()),
+                Err(ReadError::Corrupt(_))
+            ));
+            assert!(!dir.file().exists());
+            let backup = dir.file().with_extension("json.corrupt");
+            assert_eq!(fs::read_to_string(&backup).unwrap(), text);
+            // 下次启动从空状态开始
+            assert_eq!(SCHEMA.load::<Document>(&dir.file()), Ok(None));
+        }
+    }
+}
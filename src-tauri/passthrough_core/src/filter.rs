@@ -0,0 +1,409 @@
+//! 注入到 WebView2 进程里的子类窗口过程用的消息过滤规则
+//!
+//! 规则按顺序匹配，第一条 `msg` 相同的规则生效，没有匹配的消息原样交给原始窗口过程。
+//!
+//! `set_message_filter` 的参数布局（小端）：
+//!
+//! | 偏移 | 大小 | 内容 |
+//! | ---- | ---- | ---- |
+//! | 0    | 8    | 目标窗口 HWND |
+//! | 8    | 4    | 规则数量 n，不超过 [`MAX_RULES`] |
+//! | 12   | 4    | 保留，0 |
+//! | 16   | 32n  | 规则，见下表 |
+//!
+//! 每条规则：
+//!
+//! | 偏移 | 大小 | 内容 |
+//! | ---- | ---- | ---- |
+//! | 0    | 4    | 匹配的消息 |
+//! | 4    | 4    | 动作：0 放行，1 吞掉，2 改写 |
+//! | 8    | 4    | 改写标记：bit0 改写 wparam，bit1 改写 lparam |
+//! | 12   | 4    | 改写后的消息 |
+//! | 16   | 8    | 吞掉时的返回值，或改写后的 wparam |
+//! | 24   | 8    | 改写后的 lparam |
+
+use std::fmt;
+
+pub const WM_SETCURSOR: u32 = 0x0020;
+pub const WM_NCHITTEST: u32 = 0x0084;
+pub const WM_MOUSELEAVE: u32 = 0x02A3;
+
+pub const HEADER_SIZE: usize = 16;
+pub const RULE_SIZE: usize = 32;
+pub const MAX_RULES: usize = 64;
+
+const ACTION_PASS: u32 = 0;
+const ACTION_SWALLOW: u32 = 1;
+const ACTION_REWRITE: u32 = 2;
+
+const REWRITE_WPARAM: u32 = 1;
+const REWRITE_LPARAM: u32 = 1 << 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// 交给原始窗口过程
+    Pass,
+    /// 不交给原始窗口过程，直接返回 `result`
+    Swallow { result: isize },
+    /// 改写之后再交给原始窗口过程
+    Rewrite {
+        msg: u32,
+        wparam: Option<usize>,
+        lparam: Option<isize>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rule {
+    pub msg: u32,
+    pub action: Action,
+}
+
+/// 没有设置过滤规则的窗口使用的默认规则：吞掉 `WM_MOUSELEAVE`
+pub const DEFAULT_RULES: &[Rule] = &[Rule {
+    msg: WM_MOUSELEAVE,
+    action: Action::Swallow { result: 0 },
+}];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision {
+    /// 用这些参数调用原始窗口过程
+    Forward {
+        msg: u32,
+        wparam: usize,
+        lparam: isize,
+    },
+    /// 直接返回
+    Return(isize),
+}
+
+pub fn evaluate(rules: &[Rule], msg: u32, wparam: usize, lparam: isize) -> Decision {
+    let forward = Decision::Forward {
+        msg,
+        wparam,
+        lparam,
+    };
+    let Some(rule) = rules.iter().find(|rule| rule.msg == msg) else {
+        return forward;
+    };
+    match rule.action {
+        Action::Pass => forward,
+        Action::Swallow { result } => Decision::Return(result),
+        Action::Rewrite {
+            msg,
+            wparam: new_wparam,
+            lparam: new_lparam,
+        } => Decision::Forward {
+            msg,
+            wparam: new_wparam.unwrap_or(wparam),
+            lparam: new_lparam.unwrap_or(lparam),
+        },
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    TooShort { expected: usize, actual: usize },
+    TooManyRules(usize),
+    UnknownAction(u32),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::TooShort { expected, actual } => {
+                write!(f, "expected {} bytes, got {}", expected, actual)
+            }
+            DecodeError::TooManyRules(count) => {
+                write!(f, "{} rules exceeds the limit of {}", count, MAX_RULES)
+            }
+            DecodeError::UnknownAction(action) => write!(f, "unknown action {}", action),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+pub fn encode(hwnd: u64, rules: &[Rule]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(HEADER_SIZE + rules.len() * RULE_SIZE);
+    buf.extend_from_slice(&hwnd.to_le_bytes());
+    buf.extend_from_slice(&(rules.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&0u32.to_le_bytes());
+    for rule in rules {
+        let (action, flags, msg, a, b) = match rule.action {
+            Action::Pass => (ACTION_PASS, 0, 0, 0, 0),
+            Action::Swallow { result } => (ACTION_SWALLOW, 0, 0, result as u64, 0),
+            Action::Rewrite {
+                msg,
+                wparam,
+                lparam,
+            } => {
+                let mut flags = 0;
+                if wparam.is_some() {
+                    flags |= REWRITE_WPARAM;
+                }
+                if lparam.is_some() {
+                    flags |= REWRITE_LPARAM;
+                }
+                (
+                    ACTION_REWRITE,
+                    flags,
+                    msg,
+                    wparam.unwrap_or(0) as u64,
+                    lparam.unwrap_or(0) as u64,
+                )
+            }
+        };
+        buf.extend_from_slice(&rule.msg.to_le_bytes());
+        buf.extend_from_slice(&action.to_le_bytes());
+        buf.extend_from_slice(&flags.to_le_bytes());
+        buf.extend_from_slice(&msg.to_le_bytes());
+        buf.extend_from_slice(&a.to_le_bytes());
+        buf.extend_from_slice(&b.to_le_bytes());
+    }
+    buf
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+/// 从头部读出整个参数的字节数
+pub fn encoded_len(header: &[u8]) -> Result<usize, DecodeError> {
+    if header.len() < HEADER_SIZE {
+        return Err(DecodeError::TooShort {
+            expected: HEADER_SIZE,
+            actual: header.len(),
+        });
+    }
+    let count = read_u32(header, 8) as usize;
+    if count > MAX_RULES {
+        return Err(DecodeError::TooManyRules(count));
+    }
+    Ok(HEADER_SIZE + count * RULE_SIZE)
+}
+
+pub fn decode(buf: &[u8]) -> Result<(u64, Vec<Rule>), DecodeError> {
+    let len = encoded_len(buf)?;
+    if buf.len() < len {
+        return Err(DecodeError::TooShort {
+            expected: len,
+            actual: buf.len(),
+        });
+    }
+    let hwnd = read_u64(buf, 0);
+    let rules = buf[HEADER_SIZE..len]
+        .chunks_exact(RULE_SIZE)
+        .map(|rule| {
+            let flags = read_u32(rule, 8);
+            let (a, b) = (read_u64(rule, 16), read_u64(rule, 24));
+            let action = match read_u32(rule, 4) {
+                ACTION_PASS => Action::Pass,
+                ACTION_SWALLOW => Action::Swallow { result: a as isize },
+                ACTION_REWRITE => Action::Rewrite {
+                    msg: read_u32(rule, 12),
+                    wparam: (flags & REWRITE_WPARAM != 0).then_some(a as usize),
+                    lparam: (flags & REWRITE_LPARAM != 0).then_some(b as isize),
+                },
+                action => return Err(DecodeError::UnknownAction(action)),
+            };
+            Ok(Rule {
+                msg: read_u32(rule, 0),
+                action,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((hwnd, rules))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WM_MOUSEMOVE: u32 = 0x0200;
+
+    fn rewrite(msg: u32, wparam: Option<usize>, lparam: Option<isize>) -> Action {
+        Action::Rewrite {
+            msg,
+            wparam,
+            lparam,
+        }
+    }
+
+    #[test]
+    fn unmatched_message_is_forwarded() {
+        assert_eq!(
+            evaluate(DEFAULT_RULES, WM_MOUSEMOVE, 1, 2),
+            Decision::Forward {
+                msg: WM_MOUSEMOVE,
+                wparam: 1,
+                lparam: 2
+            }
+        );
+        assert_eq!(
+            evaluate(&[], WM_MOUSELEAVE, 0, 0),
+            Decision::Forward {
+                msg: WM_MOUSELEAVE,
+                wparam: 0,
+                lparam: 0
+            }
+        );
+    }
+
+    #[test]
+    fn default_rules_swallow_mouse_leave() {
+        assert_eq!(
+            evaluate(DEFAULT_RULES, WM_MOUSELEAVE, 0, 0),
+            Decision::Return(0)
+        );
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules = [
+            Rule {
+                msg: WM_NCHITTEST,
+                action: Action::Pass,
+            },
+            Rule {
+                msg: WM_NCHITTEST,
+                action: Action::Swallow { result: -1 },
+            },
+            Rule {
+                msg: WM_SETCURSOR,
+                action: Action::Swallow { result: 1 },
+            },
+        ];
+        assert_eq!(
+            evaluate(&rules, WM_NCHITTEST, 3, 4),
+            Decision::Forward {
+                msg: WM_NCHITTEST,
+                wparam: 3,
+                lparam: 4
+            }
+        );
+        assert_eq!(evaluate(&rules, WM_SETCURSOR, 0, 0), Decision::Return(1));
+    }
+
+    #[test]
+    fn rewrite_keeps_unmasked_params() {
+        let rules = [
+            Rule {
+                msg: WM_MOUSELEAVE,
+                action: rewrite(WM_MOUSEMOVE, None, None),
+            },
+            Rule {
+                msg: WM_SETCURSOR,
+                action: rewrite(WM_SETCURSOR, Some(10), None),
+            },
+            Rule {
+                msg: WM_NCHITTEST,
+                action: rewrite(WM_NCHITTEST, None, Some(-20)),
+            },
+        ];
+        assert_eq!(
+            evaluate(&rules, WM_MOUSELEAVE, 1, 2),
+            Decision::Forward {
+                msg: WM_MOUSEMOVE,
+                wparam: 1,
+                lparam: 2
+            }
+        );
+        assert_eq!(
+            evaluate(&rules, WM_SETCURSOR, 1, 2),
+            Decision::Forward {
+                msg: WM_SETCURSOR,
+                wparam: 10,
+                lparam: 2
+            }
+        );
+        assert_eq!(
+            evaluate(&rules, WM_NCHITTEST, 1, 2),
+            Decision::Forward {
+                msg: WM_NCHITTEST,
+                wparam: 1,
+                lparam: -20
+            }
+        );
+    }
+
+    #[test]
+    fn encode_writes_rewrite_masks() {
+        let rules = [
+            Rule {
+                msg: WM_SETCURSOR,
+                action: rewrite(WM_MOUSEMOVE, Some(1), None),
+            },
+            Rule {
+                msg: WM_NCHITTEST,
+                action: rewrite(WM_NCHITTEST, Some(0), Some(0)),
+            },
+        ];
+        let buf = encode(0x1234, &rules);
+        assert_eq!(buf.len(), HEADER_SIZE + 2 * RULE_SIZE);
+        assert_eq!(read_u32(&buf, HEADER_SIZE + 4), ACTION_REWRITE);
+        assert_eq!(read_u32(&buf, HEADER_SIZE + 8), REWRITE_WPARAM);
+        assert_eq!(
+            read_u32(&buf, HEADER_SIZE + RULE_SIZE + 8),
+            REWRITE_WPARAM | REWRITE_LPARAM
+        );
+        // 改写成 0 和不改写要能区分开
+        assert_eq!(decode(&buf).unwrap(), (0x1234, rules.to_vec()));
+    }
+
+    #[test]
+    fn round_trip() {
+        let rules = [
+            Rule {
+                msg: WM_MOUSELEAVE,
+                action: Action::Swallow { result: -1 },
+            },
+            Rule {
+                msg: WM_SETCURSOR,
+                action: Action::Pass,
+            },
+            Rule {
+                msg: WM_NCHITTEST,
+                action: rewrite(WM_NCHITTEST, None, Some(isize::MIN)),
+            },
+        ];
+        let buf = encode(u64::MAX, &rules);
+        assert_eq!(encoded_len(&buf), Ok(buf.len()));
+        assert_eq!(decode(&buf).unwrap(), (u64::MAX, rules.to_vec()));
+        assert_eq!(decode(&encode(0, &[])).unwrap(), (0, Vec::new()));
+    }
+
+    #[test]
+    fn decode_rejects_bad_input() {
+        let buf = encode(1, DEFAULT_RULES);
+        assert_eq!(
+            decode(&buf[..HEADER_SIZE - 1]),
+            Err(DecodeError::TooShort {
+                expected: HEADER_SIZE,
+                actual: HEADER_SIZE - 1
+            })
+        );
+        assert_eq!(
+            decode(&buf[..buf.len() - 1]),
+            Err(DecodeError::TooShort {
+                expected: buf.len(),
+                actual: buf.len() - 1
+            })
+        );
+
+        let mut unknown = buf.clone();
+        unknown[HEADER_SIZE + 4] = 9;
+        assert_eq!(decode(&unknown), Err(DecodeError::UnknownAction(9)));
+
+        let mut too_many = buf;
+        too_many[8..12].copy_from_slice(&(MAX_RULES as u32 + 1).to_le_bytes());
+        assert_eq!(
+            decode(&too_many),
+            Err(DecodeError::TooManyRules(MAX_RULES + 1))
+        );
+    }
+}
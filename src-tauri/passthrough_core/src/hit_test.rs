@@ -0,0 +1,241 @@
+//! 鼠标是否落在可交互区域。`Regions` 策略和 `WM_NCHITTEST` 子类化共用这里的判断
+
+pub const HTNOWHERE: isize = 0;
+pub const HTCLIENT: isize = 1;
+pub const HTTRANSPARENT: isize = -1;
+
+/// 客户区内的矩形，单位为逻辑像素
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Rect {
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.width && y < self.y + self.height
+    }
+}
+
+/// 客户区内、可交互区域以外的点怎么回答 `WM_NCHITTEST`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Miss {
+    /// `HTTRANSPARENT`，交给下面的窗口
+    #[default]
+    Transparent,
+    /// `HTNOWHERE`，当作不在任何窗口上
+    Nowhere,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HitTest {
+    /// 客户区以外（标题栏、边框），交给默认的窗口过程
+    Default,
+    Client,
+    Transparent,
+    Nowhere,
+}
+
+impl HitTest {
+    /// `WM_NCHITTEST` 的返回值，`Default` 没有
+    pub fn code(self) -> Option<isize> {
+        match self {
+            HitTest::Default => None,
+            HitTest::Client => Some(HTCLIENT),
+            HitTest::Transparent => Some(HTTRANSPARENT),
+            HitTest::Nowhere => Some(HTNOWHERE),
+        }
+    }
+}
+
+/// 客户区坐标（物理像素）的点是否在某个可交互区域里，`scale` 是物理像素 / 逻辑像素
+pub fn is_interactive(regions: &[Rect], scale: f64, x: i32, y: i32) -> bool {
+    let (x, y) = (x as f64 / scale, y as f64 / scale);
+    regions.iter().any(|region| region.contains(x, y))
+}
+
+/// `x`/`y` 是相对客户区的物理像素坐标，`width`/`height` 是客户区大小
+pub fn hit_test(
+    regions: &[Rect],
+    scale: f64,
+    miss: Miss,
+    (x, y): (i32, i32),
+    (width, height): (i32, i32),
+) -> HitTest {
+    if x < 0 || y < 0 || x >= width || y >= height {
+        return HitTest::Default;
+    }
+    if is_interactive(regions, scale, x, y) {
+        return HitTest::Client;
+    }
+    match miss {
+        Miss::Transparent => HitTest::Transparent,
+        Miss::Nowhere => HitTest::Nowhere,
+    }
+}
+
+/// 缩放比例无效时按 1 处理，不能让除法得到 NaN 之后所有点都穿透
+pub fn sanitize_scale(scale: f64) -> f64 {
+    if scale.is_finite() && scale > 0.0 {
+        scale
+    } else {
+        1.0
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HitTester {
+    pub regions: Vec<Rect>,
+    /// 物理像素 / 逻辑像素
+    pub scale: f64,
+    pub miss: Miss,
+}
+
+impl HitTester {
+    pub fn new(regions: Vec<Rect>, scale: f64, miss: Miss) -> Self {
+        Self {
+            regions,
+            scale: sanitize_scale(scale),
+            miss,
+        }
+    }
+
+    pub fn is_interactive(&self, x: i32, y: i32) -> bool {
+        is_interactive(&self.regions, self.scale, x, y)
+    }
+
+    pub fn hit_test(&self, x: i32, y: i32, width: i32, height: i32) -> HitTest {
+        hit_test(
+            &self.regions,
+            self.scale,
+            self.miss,
+            (x, y),
+            (width, height),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: (i32, i32) = (800, 600);
+
+    fn rect(x: f64, y: f64, width: f64, height: f64) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn rect_is_half_open() {
+        let r = rect(10.0, 20.0, 30.0, 40.0);
+        assert!(r.contains(10.0, 20.0));
+        assert!(r.contains(39.9, 59.9));
+        assert!(!r.contains(40.0, 30.0));
+        assert!(!r.contains(20.0, 60.0));
+        assert!(!r.contains(9.9, 30.0));
+        assert!(!r.contains(20.0, 19.9));
+    }
+
+    #[test]
+    fn empty_rect_contains_nothing() {
+        assert!(!rect(10.0, 10.0, 0.0, 0.0).contains(10.0, 10.0));
+    }
+
+    #[test]
+    fn any_region_is_interactive() {
+        let regions = [rect(0.0, 0.0, 10.0, 10.0), rect(100.0, 100.0, 10.0, 10.0)];
+        assert!(is_interactive(&regions, 1.0, 5, 5));
+        assert!(is_interactive(&regions, 1.0, 105, 105));
+        assert!(!is_interactive(&regions, 1.0, 50, 50));
+        assert!(!is_interactive(&[], 1.0, 0, 0));
+    }
+
+    #[test]
+    fn overlapping_regions() {
+        let regions = [rect(0.0, 0.0, 20.0, 20.0), rect(10.0, 10.0, 20.0, 20.0)];
+        assert!(is_interactive(&regions, 1.0, 15, 15));
+        assert!(is_interactive(&regions, 1.0, 25, 25));
+        assert!(!is_interactive(&regions, 1.0, 25, 5));
+    }
+
+    #[test]
+    fn physical_pixels_are_scaled_to_logical() {
+        let regions = [rect(100.0, 100.0, 50.0, 50.0)];
+        // 150% 缩放时逻辑像素 (100, 100) 在物理像素 (150, 150)
+        assert!(!is_interactive(&regions, 1.5, 149, 149));
+        assert!(is_interactive(&regions, 1.5, 150, 150));
+        assert!(is_interactive(&regions, 1.5, 224, 224));
+        assert!(!is_interactive(&regions, 1.5, 225, 225));
+    }
+
+    #[test]
+    fn outside_client_area_is_default() {
+        let regions = [rect(0.0, 0.0, 1000.0, 1000.0)];
+        for point in [(-1, 0), (0, -1), (800, 0), (0, 600), (i32::MIN, i32::MAX)] {
+            assert_eq!(
+                hit_test(&regions, 1.0, Miss::Transparent, point, SIZE),
+                HitTest::Default,
+                "{:?}",
+                point
+            );
+        }
+        assert_eq!(
+            hit_test(&regions, 1.0, Miss::Transparent, (799, 599), SIZE),
+            HitTest::Client
+        );
+    }
+
+    #[test]
+    fn miss_follows_the_configured_answer() {
+        let regions = [rect(0.0, 0.0, 10.0, 10.0)];
+        assert_eq!(
+            hit_test(&regions, 1.0, Miss::Transparent, (50, 50), SIZE),
+            HitTest::Transparent
+        );
+        assert_eq!(
+            hit_test(&regions, 1.0, Miss::Nowhere, (50, 50), SIZE),
+            HitTest::Nowhere
+        );
+        assert_eq!(
+            hit_test(&regions, 1.0, Miss::Nowhere, (5, 5), SIZE),
+            HitTest::Client
+        );
+    }
+
+    #[test]
+    fn empty_client_area_is_default() {
+        assert_eq!(
+            hit_test(&[], 1.0, Miss::Transparent, (0, 0), (0, 0)),
+            HitTest::Default
+        );
+    }
+
+    #[test]
+    fn codes() {
+        assert_eq!(HitTest::Default.code(), None);
+        assert_eq!(HitTest::Client.code(), Some(HTCLIENT));
+        assert_eq!(HitTest::Transparent.code(), Some(HTTRANSPARENT));
+        assert_eq!(HitTest::Nowhere.code(), Some(HTNOWHERE));
+    }
+
+    #[test]
+    fn invalid_scale_is_one() {
+        for scale in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert_eq!(sanitize_scale(scale), 1.0);
+        }
+        assert_eq!(sanitize_scale(1.25), 1.25);
+
+        let tester = HitTester::new(vec![rect(0.0, 0.0, 10.0, 10.0)], f64::NAN, Miss::default());
+        assert_eq!(tester.scale, 1.0);
+        assert!(tester.is_interactive(5, 5));
+        assert_eq!(tester.hit_test(5, 5, 100, 100), HitTest::Client);
+        assert_eq!(tester.hit_test(50, 50, 100, 100), HitTest::Transparent);
+    }
+}
@@ -0,0 +1,740 @@
+use std::fmt;
+
+/// 远程内存的访问权限
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protection {
+    ReadWrite,
+    ExecuteReadWrite,
+}
+
+/// 注入过程中的一步。`buffer` 是计划里第几块远程内存，执行时换成实际地址
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Step {
+    Alloc {
+        buffer: usize,
+        size: usize,
+        protection: Protection,
+    },
+    Write {
+        buffer: usize,
+        data: Vec<u8>,
+    },
+    /// 在本进程找函数地址，系统模块在每个进程里的基址相同
+    Resolve {
+        module: String,
+        function: String,
+    },
+    /// 用上一次 `Resolve` 的函数创建远程线程并等待结束，`require_nonzero` 时退出码为 0 算失败
+    RunThread {
+        param: Option<usize>,
+        require_nonzero: bool,
+    },
+    Free {
+        buffer: usize,
+    },
+    FindModule {
+        name: String,
+    },
+}
+
+impl Step {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Step::Alloc { .. } => "alloc",
+            Step::Write { .. } => "write",
+            Step::Resolve { .. } => "resolve",
+            Step::RunThread { .. } => "runThread",
+            Step::Free { .. } => "free",
+            Step::FindModule { .. } => "findModule",
+        }
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Step::Alloc {
+                buffer,
+                size,
+                protection,
+            } => write!(
+                f,
+                "alloc buffer #{} ({} bytes, {:?})",
+                buffer, size, protection
+            ),
+            Step::Write { buffer, data } => {
+                write!(f, "write {} bytes to buffer #{}", data.len(), buffer)
+            }
+            Step::Resolve { module, function } => write!(f, "resolve {}!{}", module, function),
+            Step::RunThread {
+                param,
+                require_nonzero,
+            } => {
+                match param {
+                    Some(buffer) => write!(f, "run remote thread with buffer #{}", buffer)?,
+                    None => write!(f, "run remote thread")?,
+                }
+                if *require_nonzero {
+                    write!(f, ", exit code must be nonzero")?;
+                }
+                Ok(())
+            }
+            Step::Free { buffer } => write!(f, "free buffer #{}", buffer),
+            Step::FindModule { name } => write!(f, "find module {}", name),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Plan {
+    pub steps: Vec<Step>,
+}
+
+impl Plan {
+    /// 把 DLL 加载到目标进程：写入 UTF-16 的路径，远程调用 `LoadLibraryW`，再从模块列表里找到它。
+    /// `dll_path` 不带结尾的 0，用户目录里有非 ASCII 字符时 `LoadLibraryA` 会按代码页解释而找不到文件
+    pub fn load_library(dll_path: &[u16], module_name: &str) -> Self {
+        let path: Vec<u8> = dll_path
+            .iter()
+            .chain([&0])
+            .flat_map(|unit| unit.to_le_bytes())
+            .collect();
+        Self {
+            steps: vec![
+                Step::Alloc {
+                    buffer: 0,
+                    size: path.len(),
+                    // 只放路径，远程线程不会执行这块内存
+                    protection: Protection::ReadWrite,
+                },
+                Step::Write {
+                    buffer: 0,
+                    data: path,
+                },
+                Step::Resolve {
+                    module: "kernel32.dll".to_string(),
+                    function: "LoadLibraryW".to_string(),
+                },
+                // 模块句柄可能超过 32 位，退出码只用来判断是否成功
+                Step::RunThread {
+                    param: Some(0),
+                    require_nonzero: true,
+                },
+                Step::Free { buffer: 0 },
+                Step::FindModule {
+                    name: module_name.to_string(),
+                },
+            ],
+        }
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, step) in self.steps.iter().enumerate() {
+            writeln!(f, "{}. {}", index + 1, step)?;
+        }
+        Ok(())
+    }
+}
+
+/// 实际执行每一步的操作，Win32 实现在宿主里，这里还有一个只记录调用的实现
+pub trait Executor {
+    type Error;
+
+    fn alloc(&mut self, size: usize, protection: Protection) -> Result<usize, Self::Error>;
+    fn write(&mut self, address: usize, data: &[u8]) -> Result<(), Self::Error>;
+    fn resolve(&mut self, module: &str, function: &str) -> Result<usize, Self::Error>;
+    fn run_thread(&mut self, function: usize, param: Option<usize>) -> Result<u32, Self::Error>;
+    fn free(&mut self, address: usize) -> Result<(), Self::Error>;
+    /// 远程线程可能还在用这块内存，不能马上释放
+    fn abandon(&mut self, address: usize);
+    fn find_module(&mut self, name: &str) -> Result<usize, Self::Error>;
+    /// 错误发生时远程线程是否可能还在运行（超时、取消）
+    fn is_abandoned(&self, err: &Self::Error) -> bool;
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Outcome {
+    /// 最后一次远程线程的退出码
+    pub exit_code: Option<u32>,
+    /// 最后一次 `FindModule` 找到的模块基址
+    pub module: Option<usize>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PlanError<E> {
+    /// 执行器在第 `step` 步返回了错误
+    Step {
+        step: usize,
+        name: &'static str,
+        error: E,
+    },
+    /// 远程线程退出码为 0
+    ZeroExitCode { step: usize },
+    /// 计划本身不对，比如用了没分配的内存、没有先 `Resolve`
+    Invalid { step: usize, reason: &'static str },
+}
+
+impl<E> PlanError<E> {
+    pub fn step(&self) -> usize {
+        match self {
+            PlanError::Step { step, .. }
+            | PlanError::ZeroExitCode { step }
+            | PlanError::Invalid { step, .. } => *step,
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for PlanError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlanError::Step { step, name, error } => {
+                write!(f, "step {} ({}) failed: {}", step + 1, name, error)
+            }
+            PlanError::ZeroExitCode { step } => {
+                write!(f, "step {} (runThread) exited with code 0", step + 1)
+            }
+            PlanError::Invalid { step, reason } => {
+                write!(f, "step {} is invalid: {}", step + 1, reason)
+            }
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for PlanError<E> {}
+
+/// 按顺序执行计划。失败时把还没释放的内存释放掉，
+/// 如果失败的是还可能在运行的远程线程，内存交给执行器 `abandon`
+pub fn execute<X: Executor>(plan: &Plan, executor: &mut X) -> Result<Outcome, PlanError<X::Error>> {
+    let mut buffers: Vec<Option<usize>> = Vec::new();
+    let mut function = None;
+    let mut outcome = Outcome::default();
+
+    let result = (|| {
+        for (step, item) in plan.steps.iter().enumerate() {
+            let fail = |error| PlanError::Step {
+                step,
+                name: item.name(),
+                error,
+            };
+            let buffer = |buffers: &[Option<usize>], index: usize| {
+                buffers
+                    .get(index)
+                    .copied()
+                    .flatten()
+                    .ok_or(PlanError::Invalid {
+                        step,
+                        reason: "buffer is not allocated",
+                    })
+            };
+            match item {
+                Step::Alloc {
+                    buffer: index,
+                    size,
+                    protection,
+                } => {
+                    if buffers.get(*index).is_some_and(Option::is_some) {
+                        return Err(PlanError::Invalid {
+                            step,
+                            reason: "buffer is already allocated",
+                        });
+                    }
+                    let address = executor.alloc(*size, *protection).map_err(fail)?;
+                    if buffers.len() <= *index {
+                        buffers.resize(*index + 1, None);
+                    }
+                    buffers[*index] = Some(address);
+                }
+                Step::Write {
+                    buffer: index,
+                    data,
+                } => {
+                    let address = buffer(&buffers, *index)?;
+                    executor.write(address, data).map_err(fail)?;
+                }
+                Step::Resolve {
+                    module,
+                    function: name,
+                } => {
+                    function = Some(executor.resolve(module, name).map_err(fail)?);
+                }
+                Step::RunThread {
+                    param,
+                    require_nonzero,
+                } => {
+                    let Some(function) = function else {
+                        return Err(PlanError::Invalid {
+                            step,
+                            reason: "no function resolved",
+                        });
+                    };
+                    let param = param.map(|index| buffer(&buffers, index)).transpose()?;
+                    match executor.run_thread(function, param) {
+                        Ok(code) => {
+                            outcome.exit_code = Some(code);
+                            if *require_nonzero && code == 0 {
+                                return Err(PlanError::ZeroExitCode { step });
+                            }
+                        }
+                        Err(err) => {
+                            if executor.is_abandoned(&err) {
+                                // 线程还可能在读写这些内存，全部交给执行器
+                                for address in buffers.iter_mut().filter_map(Option::take) {
+                                    executor.abandon(address);
+                                }
+                            }
+                            return Err(fail(err));
+                        }
+                    }
+                }
+                Step::Free { buffer: index } => {
+                    let address = buffer(&buffers, *index)?;
+                    buffers[*index] = None;
+                    executor.free(address).map_err(fail)?;
+                }
+                Step::FindModule { name } => {
+                    outcome.module = Some(executor.find_module(name).map_err(fail)?);
+                }
+            }
+        }
+        Ok(())
+    })();
+
+    // 成功时计划应该自己释放，这里兜底
+    for address in buffers.into_iter().flatten() {
+        let _ = executor.free(address);
+    }
+    result.map(|()| outcome)
+}
+
+/// 执行器收到的一次调用
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Call {
+    Alloc {
+        size: usize,
+        protection: Protection,
+    },
+    Write {
+        address: usize,
+        len: usize,
+    },
+    Resolve {
+        module: String,
+        function: String,
+    },
+    RunThread {
+        function: usize,
+        param: Option<usize>,
+    },
+    Free {
+        address: usize,
+    },
+    Abandon {
+        address: usize,
+    },
+    FindModule {
+        name: String,
+    },
+}
+
+/// 不碰任何进程，只记录调用，返回假的地址。可以在第几次调用时注入错误，
+/// dry run 时用它打印计划会做的事
+#[derive(Debug)]
+pub struct RecordingExecutor<E> {
+    pub calls: Vec<Call>,
+    /// 第几次调用（从 0 开始）返回这个错误
+    pub fail_at: Option<(usize, E)>,
+    /// 远程线程的退出码
+    pub exit_code: u32,
+    /// 哪些错误算作线程还在运行
+    pub abandoned: fn(&E) -> bool,
+    next_address: usize,
+    live: Vec<usize>,
+}
+
+/// 假地址从这里开始，每次分配往后挪一页
+const FAKE_BASE: usize = 0x1000_0000;
+const FAKE_PAGE: usize = 0x1000;
+
+impl<E> RecordingExecutor<E> {
+    pub fn new() -> Self {
+        Self {
+            calls: Vec::new(),
+            fail_at: None,
+            exit_code: 1,
+            abandoned: |_| false,
+            next_address: FAKE_BASE,
+            live: Vec::new(),
+        }
+    }
+
+    pub fn failing_at(mut self, call: usize, error: E) -> Self {
+        self.fail_at = Some((call, error));
+        self
+    }
+
+    fn record(&mut self, call: Call) -> Result<(), E> {
+        let index = self.calls.len();
+        self.calls.push(call);
+        match self.fail_at.take() {
+            Some((at, error)) if at == index => Err(error),
+            other => {
+                self.fail_at = other;
+                Ok(())
+            }
+        }
+    }
+
+    fn fake_address(&mut self) -> usize {
+        let address = self.next_address;
+        self.next_address += FAKE_PAGE;
+        address
+    }
+
+    /// 分配了但没有释放也没有交出去的地址
+    pub fn leaked(&self) -> &[usize] {
+        &self.live
+    }
+}
+
+impl<E> Default for RecordingExecutor<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E> Executor for RecordingExecutor<E> {
+    type Error = E;
+
+    fn alloc(&mut self, size: usize, protection: Protection) -> Result<usize, E> {
+        self.record(Call::Alloc { size, protection })?;
+        let address = self.fake_address();
+        self.live.push(address);
+        Ok(address)
+    }
+
+    fn write(&mut self, address: usize, data: &[u8]) -> Result<(), E> {
+        self.record(Call::Write {
+            address,
+            len: data.len(),
+        })
+    }
+
+    fn resolve(&mut self, module: &str, function: &str) -> Result<usize, E> {
+        self.record(Call::Resolve {
+            module: module.to_string(),
+            function: function.to_string(),
+        })?;
+        Ok(self.fake_address())
+    }
+
+    fn run_thread(&mut self, function: usize, param: Option<usize>) -> Result<u32, E> {
+        self.record(Call::RunThread { function, param })?;
+        Ok(self.exit_code)
+    }
+
+    fn free(&mut self, address: usize) -> Result<(), E> {
+        self.record(Call::Free { address })?;
+        self.live.retain(|&live| live != address);
+        Ok(())
+    }
+
+    fn abandon(&mut self, address: usize) {
+        self.calls.push(Call::Abandon { address });
+        self.live.retain(|&live| live != address);
+    }
+
+    fn find_module(&mut self, name: &str) -> Result<usize, E> {
+        self.record(Call::FindModule {
+            name: name.to_string(),
+        })?;
+        Ok(self.fake_address())
+    }
+
+    fn is_abandoned(&self, err: &E) -> bool {
+        (self.abandoned)(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUFFER: usize = FAKE_BASE;
+    const FUNCTION: usize = FAKE_BASE + FAKE_PAGE;
+    const MODULE: usize = FAKE_BASE + 2 * FAKE_PAGE;
+
+    const PATH: &str = r"C:\Users\张三\AppData\Local\app\sub_dll.dll";
+
+    fn plan() -> Plan {
+        let path: Vec<u16> = PATH.encode_utf16().collect();
+        Plan::load_library(&path, "sub_dll.dll")
+    }
+
+    fn recorder() -> RecordingExecutor<&'static str> {
+        RecordingExecutor::new()
+    }
+
+    fn run(
+        executor: &mut RecordingExecutor<&'static str>,
+    ) -> Result<Outcome, PlanError<&'static str>> {
+        execute(&plan(), executor)
+    }
+
+    /// 调用的名字，比较时不用关心参数
+    fn names(executor: &RecordingExecutor<&'static str>) -> Vec<&'static str> {
+        executor
+            .calls
+            .iter()
+            .map(|call| match call {
+                Call::Alloc { .. } => "alloc",
+                Call::Write { .. } => "write",
+                Call::Resolve { .. } => "resolve",
+                Call::RunThread { .. } => "runThread",
+                Call::Free { .. } => "free",
+                Call::Abandon { .. } => "abandon",
+                Call::FindModule { .. } => "findModule",
+            })
+            .collect()
+    }
+
+    fn step_error(step: usize, name: &'static str) -> PlanError<&'static str> {
+        PlanError::Step {
+            step,
+            name,
+            error: "boom",
+        }
+    }
+
+    #[test]
+    fn success_frees_the_path() {
+        let mut executor = recorder();
+        let outcome = run(&mut executor).unwrap();
+        assert_eq!(
+            outcome,
+            Outcome {
+                exit_code: Some(1),
+                module: Some(MODULE),
+            }
+        );
+        assert_eq!(
+            executor.calls[3],
+            Call::RunThread {
+                function: FUNCTION,
+                param: Some(BUFFER),
+            }
+        );
+        assert_eq!(executor.calls[4], Call::Free { address: BUFFER });
+        assert_eq!(
+            names(&executor),
+            [
+                "alloc",
+                "write",
+                "resolve",
+                "runThread",
+                "free",
+                "findModule"
+            ]
+        );
+        assert!(executor.leaked().is_empty());
+    }
+
+    #[test]
+    fn load_library_writes_a_wide_path() {
+        let plan = plan();
+        let Step::Write { data, .. } = &plan.steps[1] else {
+            panic!("{:?}", plan.steps[1]);
+        };
+        let units: Vec<u16> = data
+            .chunks_exact(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .collect();
+        assert_eq!(units.last(), Some(&0));
+        assert_eq!(String::from_utf16(&units[..units.len() - 1]).unwrap(), PATH);
+        assert_eq!(
+            plan.steps[0],
+            Step::Alloc {
+                buffer: 0,
+                size: data.len(),
+                protection: Protection::ReadWrite,
+            }
+        );
+        assert_eq!(
+            plan.steps[2],
+            Step::Resolve {
+                module: "kernel32.dll".to_string(),
+                function: "LoadLibraryW".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn alloc_failure_has_nothing_to_free() {
+        let mut executor = recorder().failing_at(0, "boom");
+        assert_eq!(run(&mut executor), Err(step_error(0, "alloc")));
+        assert_eq!(names(&executor), ["alloc"]);
+        assert!(executor.leaked().is_empty());
+    }
+
+    #[test]
+    fn failures_before_the_thread_free_the_buffer() {
+        let cases = [
+            (1, "write", vec!["alloc", "write", "free"]),
+            (2, "resolve", vec!["alloc", "write", "resolve", "free"]),
+        ];
+        for (call, name, calls) in cases {
+            let mut executor = recorder().failing_at(call, "boom");
+            assert_eq!(run(&mut executor), Err(step_error(call, name)));
+            assert_eq!(names(&executor), calls);
+            assert_eq!(executor.calls.last(), Some(&Call::Free { address: BUFFER }));
+            assert!(executor.leaked().is_empty());
+        }
+    }
+
+    #[test]
+    fn thread_failure_frees_the_buffer() {
+        let mut executor = recorder().failing_at(3, "boom");
+        assert_eq!(run(&mut executor), Err(step_error(3, "runThread")));
+        assert_eq!(
+            names(&executor),
+            ["alloc", "write", "resolve", "runThread", "free"]
+        );
+        assert!(executor.leaked().is_empty());
+    }
+
+    #[test]
+    fn abandoned_thread_keeps_the_buffer() {
+        let mut executor = recorder().failing_at(3, "timeout");
+        executor.abandoned = |err| *err == "timeout";
+        assert_eq!(
+            run(&mut executor),
+            Err(PlanError::Step {
+                step: 3,
+                name: "runThread",
+                error: "timeout",
+            })
+        );
+        assert_eq!(
+            names(&executor),
+            ["alloc", "write", "resolve", "runThread", "abandon"]
+        );
+        assert_eq!(executor.calls[4], Call::Abandon { address: BUFFER });
+        assert!(executor.leaked().is_empty());
+    }
+
+    #[test]
+    fn zero_exit_code_fails_and_frees() {
+        let mut executor = recorder();
+        executor.exit_code = 0;
+        assert_eq!(run(&mut executor), Err(PlanError::ZeroExitCode { step: 3 }));
+        assert_eq!(
+            names(&executor),
+            ["alloc", "write", "resolve", "runThread", "free"]
+        );
+        assert!(executor.leaked().is_empty());
+    }
+
+    #[test]
+    fn failed_free_is_not_retried() {
+        let mut executor = recorder().failing_at(4, "boom");
+        assert_eq!(run(&mut executor), Err(step_error(4, "free")));
+        assert_eq!(
+            names(&executor),
+            ["alloc", "write", "resolve", "runThread", "free"]
+        );
+        assert_eq!(executor.leaked(), [BUFFER]);
+    }
+
+    #[test]
+    fn find_module_failure_after_free() {
+        let mut executor = recorder().failing_at(5, "boom");
+        assert_eq!(run(&mut executor), Err(step_error(5, "findModule")));
+        assert_eq!(
+            names(&executor),
+            [
+                "alloc",
+                "write",
+                "resolve",
+                "runThread",
+                "free",
+                "findModule"
+            ]
+        );
+        assert!(executor.leaked().is_empty());
+    }
+
+    #[test]
+    fn invalid_plans_free_allocated_buffers() {
+        let write_unallocated = Plan {
+            steps: vec![
+                Step::Alloc {
+                    buffer: 0,
+                    size: 4,
+                    protection: Protection::ReadWrite,
+                },
+                Step::Write {
+                    buffer: 1,
+                    data: vec![0; 4],
+                },
+            ],
+        };
+        let mut executor = recorder();
+        assert_eq!(
+            execute(&write_unallocated, &mut executor),
+            Err(PlanError::Invalid {
+                step: 1,
+                reason: "buffer is not allocated",
+            })
+        );
+        assert_eq!(names(&executor), ["alloc", "free"]);
+
+        let run_unresolved = Plan {
+            steps: vec![
+                Step::Alloc {
+                    buffer: 0,
+                    size: 4,
+                    protection: Protection::ReadWrite,
+                },
+                Step::RunThread {
+                    param: Some(0),
+                    require_nonzero: false,
+                },
+            ],
+        };
+        let mut executor = recorder();
+        assert_eq!(
+            execute(&run_unresolved, &mut executor),
+            Err(PlanError::Invalid {
+                step: 1,
+                reason: "no function resolved",
+            })
+        );
+        assert_eq!(names(&executor), ["alloc", "free"]);
+
+        let alloc_twice = Plan {
+            steps: vec![
+                Step::Alloc {
+                    buffer: 0,
+                    size: 4,
+                    protection: Protection::ReadWrite,
+                },
+                Step::Alloc {
+                    buffer: 0,
+                    size: 4,
+                    protection: Protection::ReadWrite,
+                },
+            ],
+        };
+        let mut executor = recorder();
+        assert_eq!(
+            execute(&alloc_twice, &mut executor),
+            Err(PlanError::Invalid {
+                step: 1,
+                reason: "buffer is already allocated",
+            })
+        );
+        assert_eq!(names(&executor), ["alloc", "free"]);
+        assert!(executor.leaked().is_empty());
+    }
+}
@@ -0,0 +1,196 @@
+//! `passthrough_diagnostics` 返回的诊断信息：WebView2 子进程、它们加载的模块、窗口树，
+//! 以及 sub_dll 和子类化是否还在。
+//!
+//! 这里只有数据和整理数据的逻辑，枚举进程、模块和窗口的 Win32 调用在 host 里。
+//! 结构可以序列化成 JSON 再读回来，离线分析用户发来的诊断信息时也用它。
+
+use std::collections::{BTreeMap, HashSet, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::abi::AbiInfo;
+
+/// WebView2 运行时的进程名
+pub const WEBVIEW2_PROCESS: &str = "msedgewebview2.exe";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Diagnostics {
+    pub host_pid: u32,
+    /// host 期望的 sub_dll 版本，见 `hook_sub::sub_dll_version`
+    pub sub_dll_version: String,
+    pub windows: Vec<HostWindow>,
+    pub processes: Vec<ProcessInfo>,
+}
+
+/// host 的一个 Tauri 窗口
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostWindow {
+    pub label: String,
+    pub hwnd: u64,
+    /// 接收鼠标消息的 WebView2 窗口，找不到时是 `None`
+    pub webview: Option<u64>,
+    /// 注入了 sub_dll 的 WebView2 进程
+    pub injected_pid: Option<u32>,
+    /// 包括其他进程的子窗口
+    pub tree: WindowNode,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WindowNode {
+    pub hwnd: u64,
+    pub pid: u32,
+    pub thread_id: u32,
+    pub class_name: String,
+    pub title: String,
+    pub visible: bool,
+    /// 屏幕坐标 `[left, top, right, bottom]`
+    pub rect: [i32; 4],
+    /// `GWLP_WNDPROC`，读不到时是 0
+    pub wnd_proc: u64,
+    /// 窗口过程在 sub_dll 里
+    pub subclassed: bool,
+    pub children: Vec<WindowNode>,
+}
+
+impl WindowNode {
+    /// 先序遍历这个窗口和它所有的子窗口
+    pub fn walk(&self) -> Vec<&WindowNode> {
+        let mut nodes = Vec::new();
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            nodes.push(node);
+            stack.extend(node.children.iter().rev());
+        }
+        nodes
+    }
+
+    /// 窗口过程在 `module` 里的窗口标成已子类化
+    pub fn mark_subclassed(&mut self, pid: u32, module: &ModuleInfo) {
+        if self.pid == pid {
+            self.subclassed = module.contains(self.wnd_proc);
+        }
+        for child in &mut self.children {
+            child.mark_subclassed(pid, module);
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModuleInfo {
+    pub name: String,
+    pub path: String,
+    pub base: u64,
+    pub size: u32,
+}
+
+impl ModuleInfo {
+    pub fn contains(&self, address: u64) -> bool {
+        address >= self.base && address - self.base < self.size as u64
+    }
+}
+
+/// 按文件名找模块，不区分大小写
+pub fn find_module<'a>(modules: &'a [ModuleInfo], name: &str) -> Option<&'a ModuleInfo> {
+    modules
+        .iter()
+        .find(|module| module.name.eq_ignore_ascii_case(name))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AbiSummary {
+    pub version: u16,
+    pub capabilities: Vec<String>,
+}
+
+impl From<AbiInfo> for AbiSummary {
+    fn from(abi: AbiInfo) -> Self {
+        Self {
+            version: abi.version,
+            capabilities: abi
+                .capability_names()
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubDllStatus {
+    pub module: ModuleInfo,
+    /// 握手得到的版本，host 没有注入过这个进程时是 `None`
+    pub abi: Option<AbiSummary>,
+    /// 窗口过程在 sub_dll 里的窗口
+    pub subclassed_windows: Vec<u64>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub parent_pid: u32,
+    pub name: String,
+    pub path: Option<String>,
+    pub modules: Vec<ModuleInfo>,
+    /// 这个进程的顶层窗口，和它嵌在 host 窗口里的子窗口树
+    pub windows: Vec<WindowNode>,
+    pub sub_dll: Option<SubDllStatus>,
+    /// 打不开进程或者读不到模块时的错误
+    pub error: Option<String>,
+}
+
+/// 系统里的一个进程，来自进程快照
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProcessEntry {
+    pub pid: u32,
+    pub parent_pid: u32,
+    pub name: String,
+}
+
+/// `root` 的所有后代进程，按广度优先的顺序。父进程退出后 pid 可能被复用成环，
+/// 每个进程只收一次
+pub fn descendants(entries: &[ProcessEntry], root: u32) -> Vec<&ProcessEntry> {
+    let mut found = Vec::new();
+    let mut seen = HashSet::from([root]);
+    let mut queue = VecDeque::from([root]);
+    while let Some(parent) = queue.pop_front() {
+        for entry in entries {
+            if entry.parent_pid == parent && seen.insert(entry.pid) {
+                found.push(entry);
+                queue.push_back(entry.pid);
+            }
+        }
+    }
+    found
+}
+
+/// `root` 的后代里的 WebView2 进程
+pub fn webview2_processes(entries: &[ProcessEntry], root: u32) -> Vec<&ProcessEntry> {
+    descendants(entries, root)
+        .into_iter()
+        .filter(|entry| entry.name.eq_ignore_ascii_case(WEBVIEW2_PROCESS))
+        .collect()
+}
+
+/// 把窗口树按所属进程拆开：每个进程得到父窗口属于别的进程（或者没有父窗口）的那些子树
+pub fn split_by_process(roots: &[WindowNode]) -> BTreeMap<u32, Vec<WindowNode>> {
+    fn visit(node: &WindowNode, parent_pid: Option<u32>, out: &mut BTreeMap<u32, Vec<WindowNode>>) {
+        if parent_pid != Some(node.pid) {
+            out.entry(node.pid).or_default().push(node.clone());
+        }
+        for child in &node.children {
+            visit(child, Some(node.pid), out);
+        }
+    }
+    let mut out = BTreeMap::new();
+    for root in roots {
+        visit(root, None, &mut out);
+    }
+    out
+}
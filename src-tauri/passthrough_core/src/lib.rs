@@ -0,0 +1,24 @@
+pub mod abi;
+pub mod bug_report;
+pub mod cancel;
+pub mod clock;
+pub mod coalesce;
+pub mod command;
+pub mod command_args;
+pub mod dll_cache;
+pub mod document;
+pub mod filter;
+pub mod hit_test;
+pub mod inject;
+pub mod inspect;
+pub mod marshal;
+pub mod pe;
+pub mod preflight;
+pub mod region_table;
+pub mod release;
+pub mod resources;
+pub mod sha256;
+pub mod telemetry;
+#[cfg(all(windows, feature = "win32"))]
+pub mod win32;
+pub mod zip;
@@ -0,0 +1,586 @@
+//! 远程调用的参数和返回值编码
+//!
+//! host 把参数编码进一块内存写到远程进程，远程线程的参数就是这块内存的地址。
+//! 远程函数把返回值写回同一块内存的结果区，host 再用 `ReadProcessMemory` 读回来，
+//! 所以返回值不受线程退出码 32 位的限制。
+//!
+//! 整块内存的布局（小端）：
+//!
+//! | 偏移 | 大小 | 内容 |
+//! | ---- | ---- | ---- |
+//! | 0    | 4    | [`MAGIC`] |
+//! | 4    | 4    | 整块内存的长度 |
+//! | 8    | 4    | 参数长度 a |
+//! | 12   | 4    | 结果区容量 c |
+//! | 16   | 4    | 结果实际长度，远程写入 |
+//! | 20   | 4    | 保留，0 |
+//! | 24   | a    | 参数 |
+//! | 24+a | c    | 结果 |
+//!
+//! 参数和结果里的值按 [`Encode`] 依次排列，没有对齐：
+//!
+//! - 整数、`f64`：固定长度小端
+//! - `bool`：1 字节，0 或 1
+//! - `Option<T>`：1 字节标记，1 时后面跟着 `T`
+//! - `str` / `String`：4 字节长度 + UTF-8
+//! - `[T]` / `Vec<T>`：4 字节数量 + 每个元素
+//! - 元组：每个字段依次排列
+
+use std::fmt;
+
+/// `"PTRC"`
+pub const MAGIC: u32 = u32::from_le_bytes(*b"PTRC");
+pub const FRAME_HEADER_SIZE: usize = 24;
+/// 单块内存的上限，避免错误的长度字段让远程进程读写越界太多
+pub const MAX_FRAME_SIZE: usize = 1 << 20;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MarshalError {
+    /// 数据在读完一个值之前就结束了
+    UnexpectedEnd {
+        needed: usize,
+        remaining: usize,
+    },
+    InvalidBool(u8),
+    InvalidTag(u8),
+    InvalidUtf8,
+    /// 值读完之后还有多余的字节
+    TrailingBytes(usize),
+    BadMagic(u32),
+    /// 头部的长度字段互相矛盾或超过 [`MAX_FRAME_SIZE`]
+    BadLength,
+    /// 结果比结果区大
+    ResultTooLarge {
+        len: usize,
+        capacity: usize,
+    },
+}
+
+impl fmt::Display for MarshalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MarshalError::UnexpectedEnd { needed, remaining } => write!(
+                f,
+                "unexpected end of data: needed {} bytes, {} remaining",
+                needed, remaining
+            ),
+            MarshalError::InvalidBool(v) => write!(f, "invalid bool byte {}", v),
+            MarshalError::InvalidTag(v) => write!(f, "invalid option tag {}", v),
+            MarshalError::InvalidUtf8 => write!(f, "string is not valid UTF-8"),
+            MarshalError::TrailingBytes(n) => write!(f, "{} trailing bytes after value", n),
+            MarshalError::BadMagic(v) => write!(f, "bad frame magic {:#x}", v),
+            MarshalError::BadLength => write!(f, "inconsistent frame lengths"),
+            MarshalError::ResultTooLarge { len, capacity } => write!(
+                f,
+                "result of {} bytes does not fit the {} byte result area",
+                len, capacity
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MarshalError {}
+
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Self { buf: Vec::new() }
+    }
+
+    pub fn put(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+impl Default for Writer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    pub fn take(&mut self, n: usize) -> Result<&'a [u8], MarshalError> {
+        if self.buf.len() < n {
+            return Err(MarshalError::UnexpectedEnd {
+                needed: n,
+                remaining: self.buf.len(),
+            });
+        }
+        let (head, rest) = self.buf.split_at(n);
+        self.buf = rest;
+        Ok(head)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], MarshalError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.buf.len()
+    }
+}
+
+pub trait Encode {
+    fn encode(&self, w: &mut Writer);
+}
+
+pub trait Decode: Sized {
+    fn decode(r: &mut Reader<'_>) -> Result<Self, MarshalError>;
+}
+
+pub fn to_bytes<T: Encode + ?Sized>(value: &T) -> Vec<u8> {
+    let mut w = Writer::new();
+    value.encode(&mut w);
+    w.into_bytes()
+}
+
+/// 解码整段数据，多出来的字节算错误
+pub fn from_bytes<T: Decode>(buf: &[u8]) -> Result<T, MarshalError> {
+    let mut r = Reader::new(buf);
+    let value = T::decode(&mut r)?;
+    match r.remaining() {
+        0 => Ok(value),
+        n => Err(MarshalError::TrailingBytes(n)),
+    }
+}
+
+macro_rules! impl_number {
+    ($($t:ty),*) => {$(
+        impl Encode for $t {
+            fn encode(&self, w: &mut Writer) {
+                w.put(&self.to_le_bytes());
+            }
+        }
+
+        impl Decode for $t {
+            fn decode(r: &mut Reader<'_>) -> Result<Self, MarshalError> {
+                Ok(<$t>::from_le_bytes(r.take_array()?))
+            }
+        }
+    )*};
+}
+
+impl_number!(u8, u16, u32, u64, i8, i16, i32, i64, f64);
+
+impl Encode for () {
+    fn encode(&self, _: &mut Writer) {}
+}
+
+impl Decode for () {
+    fn decode(_: &mut Reader<'_>) -> Result<Self, MarshalError> {
+        Ok(())
+    }
+}
+
+impl Encode for bool {
+    fn encode(&self, w: &mut Writer) {
+        w.put(&[*self as u8]);
+    }
+}
+
+impl Decode for bool {
+    fn decode(r: &mut Reader<'_>) -> Result<Self, MarshalError> {
+        match u8::decode(r)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            v => Err(MarshalError::InvalidBool(v)),
+        }
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, w: &mut Writer) {
+        match self {
+            None => w.put(&[0]),
+            Some(value) => {
+                w.put(&[1]);
+                value.encode(w);
+            }
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(r: &mut Reader<'_>) -> Result<Self, MarshalError> {
+        match u8::decode(r)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(r)?)),
+            v => Err(MarshalError::InvalidTag(v)),
+        }
+    }
+}
+
+impl Encode for str {
+    fn encode(&self, w: &mut Writer) {
+        (self.len() as u32).encode(w);
+        w.put(self.as_bytes());
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, w: &mut Writer) {
+        self.as_str().encode(w);
+    }
+}
+
+impl Decode for String {
+    fn decode(r: &mut Reader<'_>) -> Result<Self, MarshalError> {
+        let len = u32::decode(r)? as usize;
+        let bytes = r.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| MarshalError::InvalidUtf8)
+    }
+}
+
+impl<T: Encode> Encode for [T] {
+    fn encode(&self, w: &mut Writer) {
+        (self.len() as u32).encode(w);
+        for item in self {
+            item.encode(w);
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, w: &mut Writer) {
+        self.as_slice().encode(w);
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(r: &mut Reader<'_>) -> Result<Self, MarshalError> {
+        let count = u32::decode(r)? as usize;
+        // 数量来自不可信的数据，不能直接按它预分配
+        let mut items = Vec::with_capacity(count.min(r.remaining()));
+        for _ in 0..count {
+            items.push(T::decode(r)?);
+        }
+        Ok(items)
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+    fn encode(&self, w: &mut Writer) {
+        (**self).encode(w);
+    }
+}
+
+macro_rules! impl_tuple {
+    ($($name:ident)+) => {
+        impl<$($name: Encode),+> Encode for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn encode(&self, w: &mut Writer) {
+                let ($($name,)+) = self;
+                $($name.encode(w);)+
+            }
+        }
+
+        impl<$($name: Decode),+> Decode for ($($name,)+) {
+            fn decode(r: &mut Reader<'_>) -> Result<Self, MarshalError> {
+                Ok(($($name::decode(r)?,)+))
+            }
+        }
+    };
+}
+
+impl_tuple!(A);
+impl_tuple!(A B);
+impl_tuple!(A B C);
+impl_tuple!(A B C D);
+impl_tuple!(A B C D E);
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// host 端：编码参数并留出 `result_capacity` 字节的结果区
+pub fn encode_frame<A: Encode + ?Sized>(args: &A, result_capacity: usize) -> Vec<u8> {
+    let args = to_bytes(args);
+    let total = FRAME_HEADER_SIZE + args.len() + result_capacity;
+    let mut buf = Vec::with_capacity(total);
+    buf.extend_from_slice(&MAGIC.to_le_bytes());
+    buf.extend_from_slice(&(total as u32).to_le_bytes());
+    buf.extend_from_slice(&(args.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&(result_capacity as u32).to_le_bytes());
+    buf.extend_from_slice(&0u32.to_le_bytes());
+    buf.extend_from_slice(&0u32.to_le_bytes());
+    buf.extend_from_slice(&args);
+    buf.resize(total, 0);
+    buf
+}
+
+/// 远程端拿到的只是一个指针，先读头部得到整块内存的长度
+pub fn frame_len(header: &[u8]) -> Result<usize, MarshalError> {
+    if header.len() < FRAME_HEADER_SIZE {
+        return Err(MarshalError::UnexpectedEnd {
+            needed: FRAME_HEADER_SIZE,
+            remaining: header.len(),
+        });
+    }
+    let magic = read_u32(header, 0);
+    if magic != MAGIC {
+        return Err(MarshalError::BadMagic(magic));
+    }
+    let total = read_u32(header, 4) as usize;
+    let args = read_u32(header, 8) as usize;
+    let capacity = read_u32(header, 12) as usize;
+    if total > MAX_FRAME_SIZE || FRAME_HEADER_SIZE + args + capacity != total {
+        return Err(MarshalError::BadLength);
+    }
+    Ok(total)
+}
+
+/// 远程端：解码参数
+pub fn frame_args<A: Decode>(frame: &[u8]) -> Result<A, MarshalError> {
+    let total = frame_len(frame)?;
+    if frame.len() < total {
+        return Err(MarshalError::BadLength);
+    }
+    let args = read_u32(frame, 8) as usize;
+    from_bytes(&frame[FRAME_HEADER_SIZE..FRAME_HEADER_SIZE + args])
+}
+
+/// 远程端：把结果写进结果区
+pub fn write_result<R: Encode + ?Sized>(frame: &mut [u8], result: &R) -> Result<(), MarshalError> {
+    let total = frame_len(frame)?;
+    if frame.len() < total {
+        return Err(MarshalError::BadLength);
+    }
+    let args = read_u32(frame, 8) as usize;
+    let capacity = read_u32(frame, 12) as usize;
+    let bytes = to_bytes(result);
+    if bytes.len() > capacity {
+        return Err(MarshalError::ResultTooLarge {
+            len: bytes.len(),
+            capacity,
+        });
+    }
+    let start = FRAME_HEADER_SIZE + args;
+    frame[start..start + bytes.len()].copy_from_slice(&bytes);
+    write_u32(frame, 16, bytes.len() as u32);
+    Ok(())
+}
+
+/// host 端：从读回来的整块内存里解码结果
+pub fn read_result<R: Decode>(frame: &[u8]) -> Result<R, MarshalError> {
+    let total = frame_len(frame)?;
+    if frame.len() < total {
+        return Err(MarshalError::BadLength);
+    }
+    let args = read_u32(frame, 8) as usize;
+    let capacity = read_u32(frame, 12) as usize;
+    let len = read_u32(frame, 16) as usize;
+    if len > capacity {
+        return Err(MarshalError::ResultTooLarge { len, capacity });
+    }
+    let start = FRAME_HEADER_SIZE + args;
+    from_bytes(&frame[start..start + len])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T: Encode + Decode + PartialEq + fmt::Debug>(value: T) {
+        assert_eq!(from_bytes::<T>(&to_bytes(&value)), Ok(value));
+    }
+
+    #[test]
+    fn values_round_trip() {
+        round_trip(0u8);
+        round_trip(u16::MAX);
+        round_trip(0xdead_beefu32);
+        round_trip(u64::MAX);
+        round_trip(i8::MIN);
+        round_trip(-2i16);
+        round_trip(i32::MIN);
+        round_trip(i64::MAX);
+        round_trip(-1.5f64);
+        round_trip(());
+        round_trip(true);
+        round_trip(false);
+        round_trip(None::<u32>);
+        round_trip(Some(7u32));
+        round_trip(Some(None::<u8>));
+        round_trip(String::new());
+        round_trip("窗口 title".to_string());
+        round_trip(Vec::<u64>::new());
+        round_trip(vec![Some("a".to_string()), None]);
+        round_trip((1u8,));
+        round_trip((1u8, -2i64, "three".to_string(), vec![4u16], Some(true)));
+    }
+
+    #[test]
+    fn layout_is_little_endian_without_padding() {
+        assert_eq!(
+            to_bytes(&(1u8, 0x0203u16, "ab", Some(true))),
+            [1, 3, 2, 2, 0, 0, 0, b'a', b'b', 1, 1]
+        );
+        // str 和 String、切片和 Vec 的编码相同
+        assert_eq!(to_bytes("ab"), to_bytes(&"ab".to_string()));
+        assert_eq!(to_bytes(&[1u8, 2][..]), to_bytes(&vec![1u8, 2]));
+    }
+
+    #[test]
+    fn truncated_values_are_rejected() {
+        assert_eq!(
+            from_bytes::<u32>(&[1, 2, 3]),
+            Err(MarshalError::UnexpectedEnd {
+                needed: 4,
+                remaining: 3
+            })
+        );
+        let bytes = to_bytes("hello");
+        assert_eq!(
+            from_bytes::<String>(&bytes[..bytes.len() - 1]),
+            Err(MarshalError::UnexpectedEnd {
+                needed: 5,
+                remaining: 4
+            })
+        );
+        let bytes = to_bytes(&vec![1u32, 2, 3]);
+        for len in 0..bytes.len() {
+            assert!(from_bytes::<Vec<u32>>(&bytes[..len]).is_err(), "{}", len);
+        }
+        // 很大的数量不会按数量预分配
+        assert!(from_bytes::<Vec<u64>>(&u32::MAX.to_le_bytes()).is_err());
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        assert_eq!(from_bytes::<bool>(&[2]), Err(MarshalError::InvalidBool(2)));
+        assert_eq!(
+            from_bytes::<Option<u8>>(&[3, 0]),
+            Err(MarshalError::InvalidTag(3))
+        );
+        assert_eq!(
+            from_bytes::<String>(&[2, 0, 0, 0, 0xff, 0xfe]),
+            Err(MarshalError::InvalidUtf8)
+        );
+        assert_eq!(
+            from_bytes::<u8>(&[1, 2, 3]),
+            Err(MarshalError::TrailingBytes(2))
+        );
+    }
+
+    #[test]
+    fn frame_round_trip() {
+        let args = (42u64, "remove_subclass".to_string());
+        let mut frame = encode_frame(&args, 16);
+        assert_eq!(frame.len(), FRAME_HEADER_SIZE + to_bytes(&args).len() + 16);
+        assert_eq!(frame_len(&frame[..FRAME_HEADER_SIZE]), Ok(frame.len()));
+        assert_eq!(frame_args::<(u64, String)>(&frame), Ok(args));
+        // 远程还没写结果时读到的是空结果
+        assert_eq!(read_result::<()>(&frame), Ok(()));
+
+        write_result(&mut frame, &(true, 7u32)).unwrap();
+        assert_eq!(read_result::<(bool, u32)>(&frame), Ok((true, 7)));
+        // 写结果不会动参数
+        assert_eq!(
+            frame_args::<(u64, String)>(&frame),
+            Ok((42, "remove_subclass".to_string()))
+        );
+    }
+
+    #[test]
+    fn result_exactly_filling_capacity() {
+        let mut frame = encode_frame(&(), 8);
+        write_result(&mut frame, &u64::MAX).unwrap();
+        assert_eq!(read_result::<u64>(&frame), Ok(u64::MAX));
+    }
+
+    #[test]
+    fn oversized_results_are_rejected() {
+        let mut frame = encode_frame(&1u32, 4);
+        assert_eq!(
+            write_result(&mut frame, &1u64),
+            Err(MarshalError::ResultTooLarge {
+                len: 8,
+                capacity: 4
+            })
+        );
+        assert_eq!(read_result::<()>(&frame), Ok(()));
+
+        // 远程写了超过容量的长度
+        write_u32(&mut frame, 16, 5);
+        assert_eq!(
+            read_result::<u32>(&frame),
+            Err(MarshalError::ResultTooLarge {
+                len: 5,
+                capacity: 4
+            })
+        );
+    }
+
+    #[test]
+    fn truncated_frames_are_rejected() {
+        let frame = encode_frame(&"args", 8);
+        for len in 0..FRAME_HEADER_SIZE {
+            assert_eq!(
+                frame_len(&frame[..len]),
+                Err(MarshalError::UnexpectedEnd {
+                    needed: FRAME_HEADER_SIZE,
+                    remaining: len
+                })
+            );
+        }
+        let short = &frame[..frame.len() - 1];
+        assert_eq!(frame_args::<String>(short), Err(MarshalError::BadLength));
+        assert_eq!(read_result::<()>(short), Err(MarshalError::BadLength));
+        assert_eq!(
+            write_result(&mut short.to_vec(), &()),
+            Err(MarshalError::BadLength)
+        );
+    }
+
+    #[test]
+    fn bad_headers_are_rejected() {
+        let frame = encode_frame(&1u32, 4);
+
+        let mut bad = frame.clone();
+        bad[0] ^= 0xff;
+        assert!(matches!(frame_len(&bad), Err(MarshalError::BadMagic(_))));
+
+        let mut bad = frame.clone();
+        write_u32(&mut bad, 12, 5);
+        assert_eq!(frame_len(&bad), Err(MarshalError::BadLength));
+
+        let mut huge = encode_frame(&(), 0);
+        write_u32(&mut huge, 4, (MAX_FRAME_SIZE + 1) as u32);
+        write_u32(
+            &mut huge,
+            12,
+            (MAX_FRAME_SIZE + 1 - FRAME_HEADER_SIZE) as u32,
+        );
+        assert_eq!(frame_len(&huge), Err(MarshalError::BadLength));
+
+        let mut trailing = frame;
+        write_u32(&mut trailing, 8, 3);
+        write_u32(&mut trailing, 12, 5);
+        assert_eq!(
+            frame_args::<u32>(&trailing),
+            Err(MarshalError::UnexpectedEnd {
+                needed: 4,
+                remaining: 3
+            })
+        );
+    }
+}
@@ -0,0 +1,477 @@
+use std::fmt;
+
+/// COFF 头里的目标机器类型
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Machine(pub u16);
+
+impl Machine {
+    pub const I386: Self = Self(0x014c);
+    pub const AMD64: Self = Self(0x8664);
+    pub const ARM64: Self = Self(0xaa64);
+    /// ARM64 上和 x64 代码混合运行的进程
+    pub const ARM64EC: Self = Self(0xa641);
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::I386 => "x86",
+            Self::AMD64 => "x64",
+            Self::ARM64 => "arm64",
+            Self::ARM64EC => "arm64ec",
+            _ => "unknown",
+        }
+    }
+
+    /// 指针宽度，不认识的机器类型返回 None
+    pub fn pointer_bits(self) -> Option<u32> {
+        match self {
+            Self::I386 => Some(32),
+            Self::AMD64 | Self::ARM64 | Self::ARM64EC => Some(64),
+            _ => None,
+        }
+    }
+}
+
+impl Machine {
+    /// 这个机器类型的进程能不能加载 `dll` 机器类型的 DLL。
+    /// 只看指针宽度不够，x64 和 ARM64 的 DLL 不能互相加载；ARM64EC 进程可以加载 x64 的 DLL
+    pub fn can_load(self, dll: Machine) -> bool {
+        self == dll || (self == Self::ARM64EC && dll == Self::AMD64)
+    }
+}
+
+impl fmt::Display for Machine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({:#06x})", self.name(), self.0)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExportTarget {
+    /// 函数在模块里的 RVA，加上模块基址就是函数地址
+    Rva(u32),
+    /// 转发到别的 DLL，比如 `NTDLL.RtlAllocateHeap`
+    Forwarded(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Export {
+    pub name: String,
+    pub ordinal: u16,
+    pub target: ExportTarget,
+}
+
+/// 从 DLL 文件里读出来的信息，不需要加载 DLL
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeImage {
+    pub machine: Machine,
+    /// 可选头是 PE32+
+    pub pe32_plus: bool,
+    pub exports: Vec<Export>,
+}
+
+impl PeImage {
+    /// 按名字查找导出函数的 RVA，转发的导出返回 None
+    pub fn export_rva(&self, name: &str) -> Option<u32> {
+        self.exports
+            .iter()
+            .find(|export| export.name == name)
+            .and_then(|export| match export.target {
+                ExportTarget::Rva(rva) => Some(rva),
+                ExportTarget::Forwarded(_) => None,
+            })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PeError {
+    /// 读取 `what` 时超出了文件末尾
+    Truncated {
+        what: &'static str,
+    },
+    BadDosSignature,
+    BadPeSignature,
+    BadOptionalMagic(u16),
+    /// RVA 不在任何节里
+    BadRva(u32),
+    InvalidName,
+}
+
+impl fmt::Display for PeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeError::Truncated { what } => write!(f, "file is truncated while reading {}", what),
+            PeError::BadDosSignature => write!(f, "missing MZ signature"),
+            PeError::BadPeSignature => write!(f, "missing PE signature"),
+            PeError::BadOptionalMagic(magic) => {
+                write!(f, "unknown optional header magic {:#06x}", magic)
+            }
+            PeError::BadRva(rva) => write!(f, "rva {:#x} is outside every section", rva),
+            PeError::InvalidName => write!(f, "export name is not valid UTF-8"),
+        }
+    }
+}
+
+impl std::error::Error for PeError {}
+
+const PE32_MAGIC: u16 = 0x10b;
+const PE32_PLUS_MAGIC: u16 = 0x20b;
+const SECTION_HEADER_SIZE: usize = 40;
+const EXPORT_DIRECTORY_SIZE: usize = 40;
+
+struct Section {
+    virtual_address: u32,
+    virtual_size: u32,
+    raw_size: u32,
+    raw_offset: u32,
+}
+
+struct Image<'a> {
+    bytes: &'a [u8],
+    sections: Vec<Section>,
+}
+
+impl<'a> Image<'a> {
+    fn slice(&self, offset: usize, len: usize, what: &'static str) -> Result<&'a [u8], PeError> {
+        offset
+            .checked_add(len)
+            .and_then(|end| self.bytes.get(offset..end))
+            .ok_or(PeError::Truncated { what })
+    }
+
+    fn u16(&self, offset: usize, what: &'static str) -> Result<u16, PeError> {
+        let bytes = self.slice(offset, 2, what)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&self, offset: usize, what: &'static str) -> Result<u32, PeError> {
+        let bytes = self.slice(offset, 4, what)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// RVA 转换成文件偏移，只接受落在节的文件数据里的 RVA
+    fn offset(&self, rva: u32) -> Result<usize, PeError> {
+        self.sections
+            .iter()
+            .find_map(|section| {
+                let size = section.virtual_size.max(section.raw_size);
+                let delta = rva.checked_sub(section.virtual_address)?;
+                (delta < size && delta < section.raw_size)
+                    .then(|| section.raw_offset as usize + delta as usize)
+            })
+            .ok_or(PeError::BadRva(rva))
+    }
+
+    fn c_str(&self, rva: u32) -> Result<String, PeError> {
+        let tail = self
+            .bytes
+            .get(self.offset(rva)?..)
+            .ok_or(PeError::Truncated { what: "string" })?;
+        let len = tail
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or(PeError::Truncated { what: "string" })?;
+        String::from_utf8(tail[..len].to_vec()).map_err(|_| PeError::InvalidName)
+    }
+}
+
+/// 解析 DLL 文件的机器类型和导出表
+pub fn parse(bytes: &[u8]) -> Result<PeImage, PeError> {
+    let mut image = Image {
+        bytes,
+        sections: Vec::new(),
+    };
+    if image.slice(0, 2, "DOS header")? != b"MZ" {
+        return Err(PeError::BadDosSignature);
+    }
+    let pe = image.u32(0x3c, "DOS header")? as usize;
+    if image.slice(pe, 4, "PE signature")? != b"PE\0\0" {
+        return Err(PeError::BadPeSignature);
+    }
+
+    let coff = pe + 4;
+    let machine = Machine(image.u16(coff, "COFF header")?);
+    let section_count = image.u16(coff + 2, "COFF header")? as usize;
+    let optional_size = image.u16(coff + 16, "COFF header")? as usize;
+
+    let optional = coff + 20;
+    let magic = image.u16(optional, "optional header")?;
+    let (rva_count_offset, directories) = match magic {
+        PE32_MAGIC => (92, 96),
+        PE32_PLUS_MAGIC => (108, 112),
+        other => return Err(PeError::BadOptionalMagic(other)),
+    };
+
+    let section_table = optional + optional_size;
+    for index in 0..section_count {
+        let header = section_table + index * SECTION_HEADER_SIZE;
+        image.slice(header, SECTION_HEADER_SIZE, "section table")?;
+        let section = Section {
+            virtual_size: image.u32(header + 8, "section table")?,
+            virtual_address: image.u32(header + 12, "section table")?,
+            raw_size: image.u32(header + 16, "section table")?,
+            raw_offset: image.u32(header + 20, "section table")?,
+        };
+        image.sections.push(section);
+    }
+
+    let mut exports = Vec::new();
+    let rva_count = image.u32(optional + rva_count_offset, "optional header")?;
+    let (export_rva, export_size) = if rva_count > 0 {
+        (
+            image.u32(optional + directories, "data directories")?,
+            image.u32(optional + directories + 4, "data directories")?,
+        )
+    } else {
+        (0, 0)
+    };
+    if export_rva != 0 {
+        let directory = image.offset(export_rva)?;
+        image.slice(directory, EXPORT_DIRECTORY_SIZE, "export directory")?;
+        let ordinal_base = image.u32(directory + 16, "export directory")?;
+        let function_count = image.u32(directory + 20, "export directory")?;
+        let name_count = image.u32(directory + 24, "export directory")? as usize;
+        let functions = image.u32(directory + 28, "export directory")?;
+        let names = image.u32(directory + 32, "export directory")?;
+        let ordinals = image.u32(directory + 36, "export directory")?;
+
+        let forwarded = export_rva..export_rva.saturating_add(export_size);
+        if name_count > 0 {
+            let names = image.offset(names)?;
+            let ordinals = image.offset(ordinals)?;
+            let functions = image.offset(functions)?;
+            for index in 0..name_count {
+                let name = image.c_str(image.u32(names + index * 4, "export names")?)?;
+                let slot = image.u16(ordinals + index * 2, "export ordinals")?;
+                if u32::from(slot) >= function_count {
+                    return Err(PeError::Truncated {
+                        what: "export functions",
+                    });
+                }
+                let rva = image.u32(functions + slot as usize * 4, "export functions")?;
+                let target = if forwarded.contains(&rva) {
+                    ExportTarget::Forwarded(image.c_str(rva)?)
+                } else {
+                    ExportTarget::Rva(rva)
+                };
+                exports.push(Export {
+                    name,
+                    ordinal: (ordinal_base + u32::from(slot)) as u16,
+                    target,
+                });
+            }
+        }
+    }
+
+    Ok(PeImage {
+        machine,
+        pe32_plus: magic == PE32_PLUS_MAGIC,
+        exports,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 由 tests/fixtures/make_pe.py 生成
+    const X86: &[u8] = include_bytes!("../tests/fixtures/exports_x86.dll");
+    const X64: &[u8] = include_bytes!("../tests/fixtures/exports_x64.dll");
+    const ARM64: &[u8] = include_bytes!("../tests/fixtures/exports_arm64.dll");
+
+    const PE_OFFSET: usize = 0x40;
+    const OPTIONAL: usize = PE_OFFSET + 4 + 20;
+    /// PE32+ 的导出表数据目录
+    const EXPORT_DIRECTORY_ENTRY: usize = OPTIONAL + 112;
+    /// 导出目录在文件里的位置，.rdata 节的开头
+    const EXPORT_DIRECTORY: usize = 0x200;
+
+    fn patch_u16(bytes: &mut [u8], offset: usize, value: u16) {
+        bytes[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn patch_u32(bytes: &mut [u8], offset: usize, value: u32) {
+        bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn find(bytes: &[u8], needle: &[u8]) -> usize {
+        bytes
+            .windows(needle.len())
+            .position(|window| window == needle)
+            .unwrap()
+    }
+
+    fn export(name: &str, ordinal: u16, target: ExportTarget) -> Export {
+        Export {
+            name: name.to_string(),
+            ordinal,
+            target,
+        }
+    }
+
+    #[test]
+    fn fixtures_parse_for_every_machine() {
+        for (bytes, machine, pe32_plus, bits) in [
+            (X86, Machine::I386, false, 32),
+            (X64, Machine::AMD64, true, 64),
+            (ARM64, Machine::ARM64, true, 64),
+        ] {
+            let image = parse(bytes).unwrap();
+            assert_eq!(image.machine, machine);
+            assert_eq!(image.pe32_plus, pe32_plus);
+            assert_eq!(image.machine.pointer_bits(), Some(bits));
+            // 导出名按字母排序，序号是函数表里的位置加上基数 1
+            assert_eq!(
+                image.exports,
+                [
+                    export(
+                        "HeapAlloc",
+                        4,
+                        ExportTarget::Forwarded("NTDLL.RtlAllocateHeap".to_string())
+                    ),
+                    export("remove_subclass", 2, ExportTarget::Rva(0x2010)),
+                    export("set_subclass", 1, ExportTarget::Rva(0x2000)),
+                    export("sub_dll_abi", 3, ExportTarget::Rva(0x2020)),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn export_rva_skips_forwarded_exports() {
+        let image = parse(X64).unwrap();
+        assert_eq!(image.export_rva("sub_dll_abi"), Some(0x2020));
+        assert_eq!(image.export_rva("HeapAlloc"), None);
+        assert_eq!(image.export_rva("missing"), None);
+    }
+
+    #[test]
+    fn machines_load_only_their_own_dlls() {
+        let machines = [
+            Machine::I386,
+            Machine::AMD64,
+            Machine::ARM64,
+            Machine::ARM64EC,
+        ];
+        for process in machines {
+            for dll in machines {
+                let expected =
+                    process == dll || (process == Machine::ARM64EC && dll == Machine::AMD64);
+                assert_eq!(process.can_load(dll), expected, "{} {}", process, dll);
+            }
+        }
+        assert!(!Machine::AMD64.can_load(Machine::ARM64EC));
+        assert!(Machine(0x01c4).can_load(Machine(0x01c4)));
+    }
+
+    #[test]
+    fn machine_names() {
+        assert_eq!(Machine::ARM64.to_string(), "arm64 (0xaa64)");
+        assert_eq!(Machine(0x01c4).name(), "unknown");
+        assert_eq!(Machine(0x01c4).pointer_bits(), None);
+    }
+
+    #[test]
+    fn truncated_files_are_rejected() {
+        for len in [
+            0,
+            1,
+            0x3c,
+            PE_OFFSET + 2,
+            OPTIONAL,
+            OPTIONAL + 100,
+            0x180,
+            0x210,
+        ] {
+            assert!(
+                matches!(parse(&X64[..len]), Err(PeError::Truncated { .. })),
+                "{}",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn bad_signatures_are_rejected() {
+        let mut bytes = X64.to_vec();
+        bytes[0] = b'X';
+        assert_eq!(parse(&bytes), Err(PeError::BadDosSignature));
+
+        let mut bytes = X64.to_vec();
+        bytes[PE_OFFSET + 2] = b'X';
+        assert_eq!(parse(&bytes), Err(PeError::BadPeSignature));
+
+        let mut bytes = X64.to_vec();
+        patch_u16(&mut bytes, OPTIONAL, 0x107);
+        assert_eq!(parse(&bytes), Err(PeError::BadOptionalMagic(0x107)));
+    }
+
+    #[test]
+    fn pe_offset_past_the_end() {
+        let mut bytes = X64.to_vec();
+        patch_u32(&mut bytes, 0x3c, u32::MAX);
+        assert_eq!(
+            parse(&bytes),
+            Err(PeError::Truncated {
+                what: "PE signature"
+            })
+        );
+    }
+
+    #[test]
+    fn section_table_past_the_end() {
+        let mut bytes = X64.to_vec();
+        patch_u16(&mut bytes, PE_OFFSET + 4 + 2, 200);
+        assert_eq!(
+            parse(&bytes),
+            Err(PeError::Truncated {
+                what: "section table"
+            })
+        );
+    }
+
+    #[test]
+    fn missing_export_directory() {
+        let mut bytes = X64.to_vec();
+        patch_u32(&mut bytes, EXPORT_DIRECTORY_ENTRY, 0);
+        assert_eq!(parse(&bytes).unwrap().exports, []);
+
+        // 没有数据目录
+        let mut bytes = X64.to_vec();
+        patch_u32(&mut bytes, EXPORT_DIRECTORY_ENTRY - 4, 0);
+        assert_eq!(parse(&bytes).unwrap().exports, []);
+    }
+
+    #[test]
+    fn export_directory_outside_sections() {
+        let mut bytes = X64.to_vec();
+        patch_u32(&mut bytes, EXPORT_DIRECTORY_ENTRY, 0x9000);
+        assert_eq!(parse(&bytes), Err(PeError::BadRva(0x9000)));
+    }
+
+    #[test]
+    fn ordinal_outside_the_function_table() {
+        let mut bytes = X64.to_vec();
+        let ordinals = EXPORT_DIRECTORY + 0x48;
+        patch_u16(&mut bytes, ordinals, 4);
+        assert_eq!(
+            parse(&bytes),
+            Err(PeError::Truncated {
+                what: "export functions"
+            })
+        );
+    }
+
+    #[test]
+    fn bad_export_names() {
+        let mut bytes = X64.to_vec();
+        let name = find(&bytes, b"set_subclass\0");
+        bytes[name] = 0xff;
+        assert_eq!(parse(&bytes), Err(PeError::InvalidName));
+
+        // 最后一个字符串没有结尾的 0，一直读到文件末尾
+        let mut bytes = X64.to_vec();
+        let end = find(&bytes, b"NTDLL.RtlAllocateHeap\0") + "NTDLL.RtlAllocateHeap".len();
+        bytes[end..].fill(b'x');
+        assert_eq!(parse(&bytes), Err(PeError::Truncated { what: "string" }));
+    }
+}
@@ -0,0 +1,220 @@
+use std::{collections::HashMap, fmt};
+
+use crate::pe::Machine;
+
+/// WebView2 的进程文件名
+pub const WEBVIEW2_IMAGE: &str = "msedgewebview2.exe";
+
+/// 注入前的检查项，按执行顺序排列
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Check {
+    /// 目标是 WebView2 进程
+    WebView2Process,
+    /// 目标是本进程启动的（直接或间接）
+    ProcessTree,
+    /// 目标进程的机器类型能加载 DLL 的机器类型
+    Architecture,
+    /// 目标进程的完整性级别不高于本进程
+    IntegrityLevel,
+    /// 要注入的 DLL 文件和编译时记录的 SHA-256 一致
+    DllIntegrity,
+}
+
+impl Check {
+    pub fn name(self) -> &'static str {
+        match self {
+            Check::WebView2Process => "webview2Process",
+            Check::ProcessTree => "processTree",
+            Check::Architecture => "architecture",
+            Check::IntegrityLevel => "integrityLevel",
+            Check::DllIntegrity => "dllIntegrity",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PreflightError {
+    pub check: Check,
+    pub reason: String,
+}
+
+impl PreflightError {
+    pub fn new(check: Check, reason: impl Into<String>) -> Self {
+        Self {
+            check,
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for PreflightError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "preflight check `{}` failed: {}",
+            self.check.name(),
+            self.reason
+        )
+    }
+}
+
+impl std::error::Error for PreflightError {}
+
+/// 进程令牌里的强制完整性级别，值是 SID 最后一个子授权
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct IntegrityLevel(pub u32);
+
+impl IntegrityLevel {
+    pub const UNTRUSTED: Self = Self(0x0000);
+    pub const LOW: Self = Self(0x1000);
+    pub const MEDIUM: Self = Self(0x2000);
+    pub const HIGH: Self = Self(0x3000);
+    pub const SYSTEM: Self = Self(0x4000);
+
+    pub fn name(self) -> &'static str {
+        match self.0 {
+            level if level < Self::LOW.0 => "untrusted",
+            level if level < Self::MEDIUM.0 => "low",
+            level if level < Self::HIGH.0 => "medium",
+            level if level < Self::SYSTEM.0 => "high",
+            _ => "system",
+        }
+    }
+}
+
+impl fmt::Display for IntegrityLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({:#x})", self.name(), self.0)
+    }
+}
+
+/// 从目标进程查到的信息
+#[derive(Clone, Debug)]
+pub struct Target {
+    pub pid: u32,
+    /// 进程文件的完整路径或文件名
+    pub image: String,
+    pub machine: Machine,
+    pub integrity: IntegrityLevel,
+}
+
+#[derive(Clone, Debug)]
+pub struct Host {
+    pub pid: u32,
+    pub integrity: IntegrityLevel,
+}
+
+/// 沿着父进程往上找 `ancestor`。pid 会被复用，父子关系可能成环，走过的不再走
+pub fn is_descendant(parents: &HashMap<u32, u32>, pid: u32, ancestor: u32) -> bool {
+    let mut visited = Vec::new();
+    let mut current = pid;
+    while let Some(&parent) = parents.get(&current) {
+        if parent == ancestor {
+            return true;
+        }
+        if parent == current || visited.contains(&parent) {
+            return false;
+        }
+        visited.push(current);
+        current = parent;
+    }
+    false
+}
+
+fn file_name(image: &str) -> &str {
+    image.rsplit(['\\', '/']).next().unwrap_or(image)
+}
+
+/// 依次执行所有检查，返回第一个失败的。`parents` 是 pid -> 父进程 pid
+pub fn check(
+    host: &Host,
+    target: &Target,
+    parents: &HashMap<u32, u32>,
+    dll_machine: Machine,
+) -> Result<(), PreflightError> {
+    let name = file_name(&target.image);
+    if !name.eq_ignore_ascii_case(WEBVIEW2_IMAGE) {
+        return Err(PreflightError::new(
+            Check::WebView2Process,
+            format!("process {} is {}, not {}", target.pid, name, WEBVIEW2_IMAGE),
+        ));
+    }
+
+    if !is_descendant(parents, target.pid, host.pid) {
+        return Err(PreflightError::new(
+            Check::ProcessTree,
+            format!(
+                "process {} was not started by this app (pid {})",
+                target.pid, host.pid
+            ),
+        ));
+    }
+
+    if !target.machine.can_load(dll_machine) {
+        return Err(PreflightError::new(
+            Check::Architecture,
+            format!(
+                "process {} is {} but sub_dll is built for {}",
+                target.pid, target.machine, dll_machine
+            ),
+        ));
+    }
+
+    // 写不了完整性级别更高的进程的内存
+    if target.integrity > host.integrity {
+        return Err(PreflightError::new(
+            Check::IntegrityLevel,
+            format!(
+                "process {} runs at {} integrity, above this app's {}",
+                target.pid, target.integrity, host.integrity
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOST: Host = Host {
+        pid: 100,
+        integrity: IntegrityLevel::MEDIUM,
+    };
+
+    fn target(machine: Machine) -> Target {
+        Target {
+            pid: 200,
+            image: r"C:\Program Files\WebView2\msedgewebview2.exe".to_string(),
+            machine,
+            integrity: IntegrityLevel::LOW,
+        }
+    }
+
+    fn architecture(process: Machine, dll: Machine) -> Result<(), PreflightError> {
+        let parents = HashMap::from([(200, 100)]);
+        check(&HOST, &target(process), &parents, dll)
+    }
+
+    #[test]
+    fn architecture_must_match_exactly() {
+        assert_eq!(architecture(Machine::AMD64, Machine::AMD64), Ok(()));
+        assert_eq!(architecture(Machine::ARM64, Machine::ARM64), Ok(()));
+        assert_eq!(architecture(Machine::I386, Machine::I386), Ok(()));
+        // 位数相同也不行
+        let err = architecture(Machine::AMD64, Machine::ARM64).unwrap_err();
+        assert_eq!(err.check, Check::Architecture);
+        assert_eq!(
+            err.reason,
+            "process 200 is x64 (0x8664) but sub_dll is built for arm64 (0xaa64)"
+        );
+        assert!(architecture(Machine::ARM64, Machine::AMD64).is_err());
+        assert!(architecture(Machine::AMD64, Machine::I386).is_err());
+    }
+
+    #[test]
+    fn arm64ec_process_loads_x64_dll() {
+        assert_eq!(architecture(Machine::ARM64EC, Machine::AMD64), Ok(()));
+        assert!(architecture(Machine::ARM64EC, Machine::ARM64).is_err());
+    }
+}
@@ -0,0 +1,419 @@
+//! host 和 sub_dll 共享的可交互区域表，放在命名共享内存里。
+//!
+//! host 是唯一的写者，sub_dll 在子类化过程里读，用 seqlock 保证读到的是完整的一份：
+//! 写之前 `seq` 变成奇数，写完变成下一个偶数；读者前后两次读到同一个偶数才算成功。
+//! 所有字段都是原子类型，两个进程同时访问不是数据竞争
+
+use std::{
+    fmt, hint,
+    sync::atomic::{fence, AtomicU32, AtomicU64, Ordering},
+};
+
+use crate::hit_test::{self, HitTest, Miss, Rect};
+
+/// `"PTRT"`
+pub const MAGIC: u32 = u32::from_le_bytes(*b"PTRT");
+/// 表的内存布局版本，字段变化时加一
+pub const LAYOUT_VERSION: u32 = 1;
+pub const MAX_REGIONS: usize = 64;
+/// 共享内存段至少要这么大
+pub const TABLE_SIZE: usize = std::mem::size_of::<RegionTable>();
+/// 读者最多重试的次数，写者一直在写时放弃，交给默认处理
+pub const READ_ATTEMPTS: usize = 128;
+
+/// 共享内存的名字，每个 host 进程的每个顶层窗口一个
+pub fn segment_name(host_pid: u32, hwnd: usize) -> String {
+    format!("Local\\passthrough-regions-{}-{:x}", host_pid, hwnd)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TableError {
+    /// host 还没有初始化这块内存
+    Uninitialized,
+    Version {
+        expected: u32,
+        found: u32,
+    },
+    TooManyRegions {
+        count: usize,
+    },
+    /// 重试了 `READ_ATTEMPTS` 次都碰上写者
+    Busy,
+}
+
+impl fmt::Display for TableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TableError::Uninitialized => write!(f, "region table is not initialized"),
+            TableError::Version { expected, found } => write!(
+                f,
+                "region table layout version is {}, expected {}",
+                found, expected
+            ),
+            TableError::TooManyRegions { count } => write!(
+                f,
+                "{} regions do not fit in the region table (max {})",
+                count, MAX_REGIONS
+            ),
+            TableError::Busy => write!(f, "region table is being written"),
+        }
+    }
+}
+
+impl std::error::Error for TableError {}
+
+/// 共享内存里的布局，字段顺序和大小不能随便改，改了要加 `LAYOUT_VERSION`
+#[repr(C)]
+pub struct RegionTable {
+    magic: AtomicU32,
+    version: AtomicU32,
+    seq: AtomicU32,
+    count: AtomicU32,
+    /// 0 是 `Miss::Transparent`，1 是 `Miss::Nowhere`
+    miss: AtomicU32,
+    reserved: AtomicU32,
+    /// `f64::to_bits`
+    scale: AtomicU64,
+    /// 每个区域是 x、y、width、height 的 `f64::to_bits`
+    regions: [[AtomicU64; 4]; MAX_REGIONS],
+}
+
+/// 一次读到的完整内容
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    /// 第几次写入，每次写完加一
+    pub generation: u32,
+    pub scale: f64,
+    pub miss: Miss,
+    count: usize,
+    regions: [Rect; MAX_REGIONS],
+}
+
+impl Snapshot {
+    pub fn regions(&self) -> &[Rect] {
+        &self.regions[..self.count]
+    }
+
+    pub fn hit_test(&self, point: (i32, i32), size: (i32, i32)) -> HitTest {
+        let scale = hit_test::sanitize_scale(self.scale);
+        hit_test::hit_test(self.regions(), scale, self.miss, point, size)
+    }
+}
+
+const EMPTY: Rect = Rect {
+    x: 0.0,
+    y: 0.0,
+    width: 0.0,
+    height: 0.0,
+};
+
+impl RegionTable {
+    /// 进程内使用，共享内存用 `from_ptr`
+    pub const fn new() -> Self {
+        Self {
+            magic: AtomicU32::new(0),
+            version: AtomicU32::new(0),
+            seq: AtomicU32::new(0),
+            count: AtomicU32::new(0),
+            miss: AtomicU32::new(0),
+            reserved: AtomicU32::new(0),
+            scale: AtomicU64::new(0),
+            regions: [const { [const { AtomicU64::new(0) }; 4] }; MAX_REGIONS],
+        }
+    }
+
+    /// # Safety
+    /// `ptr` 按 8 字节对齐，至少有 `TABLE_SIZE` 字节，在返回的引用用完之前一直有效。
+    /// 新建的共享内存全是 0，和 `new()` 一样
+    pub unsafe fn from_ptr<'a>(ptr: *const u8) -> &'a RegionTable {
+        &*(ptr as *const RegionTable)
+    }
+
+    /// 写者在第一次写之前调用。已经初始化过的（比如 sub_dll 还开着旧的）保持不变
+    pub fn init(&self) {
+        if self.magic.load(Ordering::Acquire) == MAGIC {
+            return;
+        }
+        self.version.store(LAYOUT_VERSION, Ordering::Relaxed);
+        self.seq.store(0, Ordering::Relaxed);
+        self.count.store(0, Ordering::Relaxed);
+        self.magic.store(MAGIC, Ordering::Release);
+    }
+
+    fn check_header(&self) -> Result<(), TableError> {
+        if self.magic.load(Ordering::Acquire) != MAGIC {
+            return Err(TableError::Uninitialized);
+        }
+        let version = self.version.load(Ordering::Relaxed);
+        if version != LAYOUT_VERSION {
+            return Err(TableError::Version {
+                expected: LAYOUT_VERSION,
+                found: version,
+            });
+        }
+        Ok(())
+    }
+
+    /// 替换整张表，返回新的 generation。只能有一个写者
+    pub fn write(&self, regions: &[Rect], scale: f64, miss: Miss) -> Result<u32, TableError> {
+        self.check_header()?;
+        if regions.len() > MAX_REGIONS {
+            return Err(TableError::TooManyRegions {
+                count: regions.len(),
+            });
+        }
+        // 上一个写者写到一半退出时 seq 还是奇数，直接接着用
+        let start = self.seq.load(Ordering::Relaxed) | 1;
+        self.seq.store(start, Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        self.count.store(regions.len() as u32, Ordering::Relaxed);
+        self.miss.store(
+            match miss {
+                Miss::Transparent => 0,
+                Miss::Nowhere => 1,
+            },
+            Ordering::Relaxed,
+        );
+        self.scale.store(scale.to_bits(), Ordering::Relaxed);
+        for (slot, region) in self.regions.iter().zip(regions) {
+            let values = [region.x, region.y, region.width, region.height];
+            for (field, value) in slot.iter().zip(values) {
+                field.store(value.to_bits(), Ordering::Relaxed);
+            }
+        }
+
+        let end = start.wrapping_add(1);
+        self.seq.store(end, Ordering::Release);
+        Ok(end / 2)
+    }
+
+    /// 读一份完整的内容，碰上写者时重试
+    pub fn read(&self) -> Result<Snapshot, TableError> {
+        self.check_header()?;
+        for _ in 0..READ_ATTEMPTS {
+            let before = self.seq.load(Ordering::Acquire);
+            if before & 1 == 1 {
+                hint::spin_loop();
+                continue;
+            }
+            let snapshot = self.load(before);
+            fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) == before {
+                return Ok(snapshot);
+            }
+            hint::spin_loop();
+        }
+        Err(TableError::Busy)
+    }
+
+    /// 可能读到写了一半的内容，由 `read` 判断要不要丢掉
+    fn load(&self, seq: u32) -> Snapshot {
+        // 写了一半的 count 可能是任何值
+        let count = (self.count.load(Ordering::Relaxed) as usize).min(MAX_REGIONS);
+        let mut regions = [EMPTY; MAX_REGIONS];
+        for (region, slot) in regions.iter_mut().zip(&self.regions).take(count) {
+            let [x, y, width, height] =
+                [0, 1, 2, 3].map(|i| f64::from_bits(slot[i].load(Ordering::Relaxed)));
+            *region = Rect {
+                x,
+                y,
+                width,
+                height,
+            };
+        }
+        Snapshot {
+            generation: seq / 2,
+            scale: f64::from_bits(self.scale.load(Ordering::Relaxed)),
+            miss: match self.miss.load(Ordering::Relaxed) {
+                1 => Miss::Nowhere,
+                _ => Miss::Transparent,
+            },
+            count,
+            regions,
+        }
+    }
+}
+
+impl Default for RegionTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, AtomicUsize},
+            Arc,
+        },
+        thread,
+    };
+
+    use super::*;
+
+    fn rect(v: f64) -> Rect {
+        Rect {
+            x: v,
+            y: v,
+            width: v,
+            height: v,
+        }
+    }
+
+    fn table() -> RegionTable {
+        let table = RegionTable::new();
+        table.init();
+        table
+    }
+
+    #[test]
+    fn uninitialized_table_is_rejected() {
+        let table = RegionTable::new();
+        assert_eq!(table.read(), Err(TableError::Uninitialized));
+        assert_eq!(
+            table.write(&[], 1.0, Miss::Transparent),
+            Err(TableError::Uninitialized)
+        );
+    }
+
+    #[test]
+    fn other_layout_version_is_rejected() {
+        let table = table();
+        table.version.store(LAYOUT_VERSION + 1, Ordering::Relaxed);
+        assert_eq!(
+            table.read(),
+            Err(TableError::Version {
+                expected: LAYOUT_VERSION,
+                found: LAYOUT_VERSION + 1,
+            })
+        );
+    }
+
+    #[test]
+    fn write_then_read() {
+        let table = table();
+        let regions = [rect(1.0), rect(2.0)];
+        assert_eq!(table.write(&regions, 1.5, Miss::Nowhere), Ok(1));
+        let snapshot = table.read().unwrap();
+        assert_eq!(snapshot.generation, 1);
+        assert_eq!(snapshot.regions(), &regions);
+        assert_eq!(snapshot.scale, 1.5);
+        assert_eq!(snapshot.miss, Miss::Nowhere);
+
+        assert_eq!(table.write(&[], 1.0, Miss::Transparent), Ok(2));
+        let snapshot = table.read().unwrap();
+        assert_eq!(snapshot.generation, 2);
+        assert!(snapshot.regions().is_empty());
+        assert_eq!(snapshot.miss, Miss::Transparent);
+    }
+
+    #[test]
+    fn too_many_regions_are_rejected() {
+        let table = table();
+        let regions = vec![rect(1.0); MAX_REGIONS + 1];
+        assert_eq!(
+            table.write(&regions, 1.0, Miss::Transparent),
+            Err(TableError::TooManyRegions {
+                count: MAX_REGIONS + 1
+            })
+        );
+        assert_eq!(
+            table.write(&regions[..MAX_REGIONS], 1.0, Miss::Transparent),
+            Ok(1)
+        );
+        assert_eq!(table.read().unwrap().regions().len(), MAX_REGIONS);
+    }
+
+    #[test]
+    fn init_keeps_an_initialized_table() {
+        let table = table();
+        table.write(&[rect(3.0)], 1.0, Miss::Transparent).unwrap();
+        table.init();
+        assert_eq!(table.read().unwrap().regions(), &[rect(3.0)]);
+    }
+
+    #[test]
+    fn reader_gives_up_while_writing() {
+        let table = table();
+        table.seq.store(1, Ordering::Relaxed);
+        assert_eq!(table.read(), Err(TableError::Busy));
+        // 写到一半退出的写者留下的奇数 seq 由下一个写者接着用
+        assert_eq!(table.write(&[rect(4.0)], 1.0, Miss::Transparent), Ok(1));
+        assert_eq!(table.read().unwrap().regions(), &[rect(4.0)]);
+    }
+
+    #[test]
+    fn snapshot_hit_test_sanitizes_scale() {
+        let table = table();
+        table.write(&[rect(10.0)], f64::NAN, Miss::Nowhere).unwrap();
+        let snapshot = table.read().unwrap();
+        assert_eq!(snapshot.hit_test((15, 15), (100, 100)), HitTest::Client);
+        assert_eq!(snapshot.hit_test((50, 50), (100, 100)), HitTest::Nowhere);
+        assert_eq!(snapshot.hit_test((-1, 0), (100, 100)), HitTest::Default);
+    }
+
+    fn miss(i: usize) -> Miss {
+        if i.is_multiple_of(2) {
+            Miss::Transparent
+        } else {
+            Miss::Nowhere
+        }
+    }
+
+    /// 第 `i` 次写入的所有字段都由 `i` 算出来，读到混合了两次写入的内容就能发现
+    #[test]
+    fn concurrent_readers_never_see_torn_writes() {
+        const WRITES: usize = 20_000;
+        const READERS: usize = 4;
+
+        let table = Arc::new(table());
+        let done = Arc::new(AtomicBool::new(false));
+        let reads = Arc::new(AtomicUsize::new(0));
+
+        let readers: Vec<_> = (0..READERS)
+            .map(|_| {
+                let (table, done, reads) = (table.clone(), done.clone(), reads.clone());
+                thread::spawn(move || {
+                    let mut last = 0;
+                    // 至少读一次，写者可能在读者启动之前就写完了
+                    loop {
+                        let finished = done.load(Ordering::Relaxed);
+                        let snapshot = match table.read() {
+                            Ok(snapshot) => snapshot,
+                            Err(TableError::Busy) => continue,
+                            Err(err) => panic!("{}", err),
+                        };
+                        assert!(snapshot.generation >= last, "generation went backwards");
+                        last = snapshot.generation;
+                        let i = snapshot.scale;
+                        assert_eq!(snapshot.regions().len(), i as usize % MAX_REGIONS);
+                        assert!(snapshot.regions().iter().all(|&region| region == rect(i)));
+                        assert_eq!(snapshot.miss, miss(i as usize));
+                        reads.fetch_add(1, Ordering::Relaxed);
+                        if finished {
+                            break;
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        let regions = [rect(0.0); MAX_REGIONS];
+        for i in 1..=WRITES {
+            let v = i as f64;
+            let regions = regions.map(|_| rect(v));
+            assert_eq!(
+                table.write(&regions[..i % MAX_REGIONS], v, miss(i)),
+                Ok(i as u32)
+            );
+        }
+        done.store(true, Ordering::Relaxed);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert!(reads.load(Ordering::Relaxed) > 0);
+        assert_eq!(table.read().unwrap().generation, WRITES as u32);
+    }
+}
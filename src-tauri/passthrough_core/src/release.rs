@@ -0,0 +1,270 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+use crate::clock::Clock;
+
+/// 恢复可交互的条件，满足任意一个即可
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Condition {
+    After(Duration),
+    Focus,
+    ForegroundChange,
+    KeyChord,
+}
+
+/// 实际触发恢复的条件
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReleaseCause {
+    Timeout,
+    Focus,
+    ForegroundChange,
+    KeyChord,
+}
+
+#[derive(Debug)]
+struct Pending {
+    deadline: Option<Instant>,
+    focus: bool,
+    /// 设置时的前台窗口
+    foreground: Option<usize>,
+    key_chord: bool,
+}
+
+pub struct ReleaseTracker<K, C> {
+    clock: C,
+    pending: HashMap<K, Pending>,
+}
+
+impl<K: Eq + Hash + Clone, C: Clock> ReleaseTracker<K, C> {
+    pub fn new(clock: C) -> Self {
+        Self {
+            clock,
+            pending: HashMap::new(),
+        }
+    }
+
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    /// 开始等待，会替换掉 `key` 之前的条件
+    pub fn arm(&mut self, key: K, conditions: &[Condition], foreground: usize) {
+        let now = self.clock.now();
+        let mut pending = Pending {
+            deadline: None,
+            focus: false,
+            foreground: None,
+            key_chord: false,
+        };
+        for condition in conditions {
+            match *condition {
+                Condition::After(duration) => {
+                    let deadline = now + duration;
+                    pending.deadline = Some(pending.deadline.map_or(deadline, |d| d.min(deadline)));
+                }
+                Condition::Focus => pending.focus = true,
+                Condition::ForegroundChange => pending.foreground = Some(foreground),
+                Condition::KeyChord => pending.key_chord = true,
+            }
+        }
+        self.pending.insert(key, pending);
+    }
+
+    pub fn cancel(&mut self, key: &K) -> bool {
+        self.pending.remove(key).is_some()
+    }
+
+    pub fn is_armed(&self, key: &K) -> bool {
+        self.pending.contains_key(key)
+    }
+
+    pub fn focused(&mut self, key: &K) -> Option<ReleaseCause> {
+        self.release_if(key, |pending| pending.focus, ReleaseCause::Focus)
+    }
+
+    pub fn key_chord(&mut self, key: &K) -> Option<ReleaseCause> {
+        self.release_if(key, |pending| pending.key_chord, ReleaseCause::KeyChord)
+    }
+
+    fn release_if<F: Fn(&Pending) -> bool>(
+        &mut self,
+        key: &K,
+        matches: F,
+        cause: ReleaseCause,
+    ) -> Option<ReleaseCause> {
+        if self.pending.get(key).is_some_and(matches) {
+            self.pending.remove(key);
+            Some(cause)
+        } else {
+            None
+        }
+    }
+
+    /// 前台窗口和设置时不同的全部释放
+    pub fn observe_foreground(&mut self, foreground: usize) -> Vec<(K, ReleaseCause)> {
+        self.drain(|pending| {
+            pending
+                .foreground
+                .is_some_and(|initial| initial != foreground)
+                .then_some(ReleaseCause::ForegroundChange)
+        })
+    }
+
+    /// 到期的全部释放
+    pub fn poll(&mut self) -> Vec<(K, ReleaseCause)> {
+        let now = self.clock.now();
+        self.drain(|pending| {
+            pending
+                .deadline
+                .is_some_and(|deadline| deadline <= now)
+                .then_some(ReleaseCause::Timeout)
+        })
+    }
+
+    fn drain<F: Fn(&Pending) -> Option<ReleaseCause>>(&mut self, f: F) -> Vec<(K, ReleaseCause)> {
+        let released: Vec<(K, ReleaseCause)> = self
+            .pending
+            .iter()
+            .filter_map(|(key, pending)| f(pending).map(|cause| (key.clone(), cause)))
+            .collect();
+        for (key, _) in &released {
+            self.pending.remove(key);
+        }
+        released
+    }
+
+    /// 距离最近的到期时间还有多久
+    pub fn next_timeout(&self) -> Option<Duration> {
+        let now = self.clock.now();
+        self.pending
+            .values()
+            .filter_map(|pending| pending.deadline)
+            .min()
+            .map(|deadline| deadline.saturating_duration_since(now))
+    }
+
+    /// 是否有需要轮询前台窗口的条件
+    pub fn watches_foreground(&self) -> bool {
+        self.pending
+            .values()
+            .any(|pending| pending.foreground.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    fn tracker() -> ReleaseTracker<&'static str, MockClock> {
+        ReleaseTracker::new(MockClock::new())
+    }
+
+    #[test]
+    fn timeout_releases_at_deadline() {
+        let mut tracker = tracker();
+        tracker.arm("main", &[Condition::After(Duration::from_secs(5))], 0);
+        assert_eq!(tracker.next_timeout(), Some(Duration::from_secs(5)));
+
+        tracker.clock().advance(Duration::from_millis(4999));
+        assert!(tracker.poll().is_empty());
+        assert!(tracker.is_armed(&"main"));
+        assert_eq!(tracker.next_timeout(), Some(Duration::from_millis(1)));
+
+        tracker.clock().advance(Duration::from_millis(1));
+        assert_eq!(tracker.poll(), vec![("main", ReleaseCause::Timeout)]);
+        assert!(!tracker.is_armed(&"main"));
+        assert_eq!(tracker.next_timeout(), None);
+    }
+
+    #[test]
+    fn earliest_deadline_wins() {
+        let mut tracker = tracker();
+        let conditions = [
+            Condition::After(Duration::from_secs(10)),
+            Condition::After(Duration::from_secs(2)),
+        ];
+        tracker.arm("main", &conditions, 0);
+        assert_eq!(tracker.next_timeout(), Some(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn overdue_timeout_is_zero() {
+        let mut tracker = tracker();
+        tracker.arm("main", &[Condition::After(Duration::from_secs(1))], 0);
+        tracker.clock().advance(Duration::from_secs(3));
+        assert_eq!(tracker.next_timeout(), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn arm_replaces_previous_conditions() {
+        let mut tracker = tracker();
+        tracker.arm("main", &[Condition::After(Duration::from_secs(1))], 0);
+        tracker.arm("main", &[Condition::Focus], 0);
+        tracker.clock().advance(Duration::from_secs(2));
+        assert!(tracker.poll().is_empty());
+        assert_eq!(tracker.next_timeout(), None);
+        assert_eq!(tracker.focused(&"main"), Some(ReleaseCause::Focus));
+    }
+
+    #[test]
+    fn rearm_restarts_the_deadline() {
+        let mut tracker = tracker();
+        tracker.arm("main", &[Condition::After(Duration::from_secs(5))], 0);
+        tracker.clock().advance(Duration::from_secs(4));
+        tracker.arm("main", &[Condition::After(Duration::from_secs(5))], 0);
+        tracker.clock().advance(Duration::from_secs(4));
+        assert!(tracker.poll().is_empty());
+        tracker.clock().advance(Duration::from_secs(1));
+        assert_eq!(tracker.poll(), vec![("main", ReleaseCause::Timeout)]);
+    }
+
+    #[test]
+    fn only_armed_conditions_release() {
+        let mut tracker = tracker();
+        tracker.arm("main", &[Condition::KeyChord], 0);
+        assert_eq!(tracker.focused(&"main"), None);
+        assert!(tracker.observe_foreground(1).is_empty());
+        assert!(tracker.is_armed(&"main"));
+        assert_eq!(tracker.key_chord(&"main"), Some(ReleaseCause::KeyChord));
+        assert_eq!(tracker.key_chord(&"main"), None);
+    }
+
+    #[test]
+    fn foreground_change_releases() {
+        let mut tracker = tracker();
+        tracker.arm("main", &[Condition::ForegroundChange], 7);
+        tracker.arm("other", &[Condition::Focus], 7);
+        assert!(tracker.watches_foreground());
+        assert!(tracker.observe_foreground(7).is_empty());
+        assert_eq!(
+            tracker.observe_foreground(8),
+            vec![("main", ReleaseCause::ForegroundChange)]
+        );
+        assert!(!tracker.watches_foreground());
+        assert!(tracker.is_armed(&"other"));
+    }
+
+    #[test]
+    fn cancel_disarms() {
+        let mut tracker = tracker();
+        tracker.arm("main", &[Condition::After(Duration::from_secs(1))], 0);
+        assert!(tracker.cancel(&"main"));
+        assert!(!tracker.cancel(&"main"));
+        tracker.clock().advance(Duration::from_secs(2));
+        assert!(tracker.poll().is_empty());
+    }
+
+    #[test]
+    fn poll_releases_only_due_windows() {
+        let mut tracker = tracker();
+        tracker.arm("a", &[Condition::After(Duration::from_secs(1))], 0);
+        tracker.arm("b", &[Condition::After(Duration::from_secs(3))], 0);
+        tracker.clock().advance(Duration::from_secs(2));
+        assert_eq!(tracker.poll(), vec![("a", ReleaseCause::Timeout)]);
+        assert_eq!(tracker.next_timeout(), Some(Duration::from_secs(1)));
+    }
+}
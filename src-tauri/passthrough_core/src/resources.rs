@@ -0,0 +1,189 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// 在其他进程里占用的资源，或者为了操作其他进程在本进程打开的句柄
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResourceKind {
+    /// `VirtualAllocEx` 分配的远程内存
+    Allocation,
+    /// `CreateRemoteThread` 的线程句柄
+    Thread,
+    /// `OpenProcess` 的进程句柄
+    Process,
+    /// 这次注入 `LoadLibraryW` 加载、还没交给窗口表管理的远程模块
+    Module,
+}
+
+const KINDS: usize = 4;
+
+impl ResourceKind {
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// 每种资源当前占用的数量，还有没等远程线程结束只能先放着的远程内存
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ResourceSnapshot {
+    pub allocations: usize,
+    pub threads: usize,
+    pub processes: usize,
+    pub modules: usize,
+    pub abandoned_allocations: usize,
+}
+
+impl ResourceSnapshot {
+    /// 除了进程句柄，其他资源都只在一次注入里使用，空闲时应该都是 0
+    pub fn transient(&self) -> usize {
+        self.allocations + self.threads + self.modules
+    }
+}
+
+/// 资源守卫创建时 `acquired`，释放时 `released`
+pub struct ResourceCounters {
+    live: [AtomicUsize; KINDS],
+    abandoned: AtomicUsize,
+}
+
+impl ResourceCounters {
+    pub const fn new() -> Self {
+        Self {
+            live: [const { AtomicUsize::new(0) }; KINDS],
+            abandoned: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn acquired(&self, kind: ResourceKind) {
+        self.live[kind.index()].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn released(&self, kind: ResourceKind) {
+        self.live[kind.index()].fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// 远程内存还在远程线程手里，暂时不能释放
+    pub fn abandoned(&self) {
+        self.abandoned.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 之前放着的远程内存最终被释放了
+    pub fn reclaimed(&self) {
+        self.abandoned.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> ResourceSnapshot {
+        let live = |kind: ResourceKind| self.live[kind.index()].load(Ordering::Relaxed);
+        ResourceSnapshot {
+            allocations: live(ResourceKind::Allocation),
+            threads: live(ResourceKind::Thread),
+            processes: live(ResourceKind::Process),
+            modules: live(ResourceKind::Module),
+            abandoned_allocations: self.abandoned.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Default for ResourceCounters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [ResourceKind; KINDS] = [
+        ResourceKind::Allocation,
+        ResourceKind::Thread,
+        ResourceKind::Process,
+        ResourceKind::Module,
+    ];
+
+    #[test]
+    fn new_counters_are_empty() {
+        let counters = ResourceCounters::new();
+        assert_eq!(counters.snapshot(), ResourceSnapshot::default());
+        assert_eq!(counters.snapshot().transient(), 0);
+    }
+
+    #[test]
+    fn each_kind_has_its_own_counter() {
+        let counters = ResourceCounters::new();
+        for (i, kind) in ALL.into_iter().enumerate() {
+            for _ in 0..=i {
+                counters.acquired(kind);
+            }
+        }
+        assert_eq!(
+            counters.snapshot(),
+            ResourceSnapshot {
+                allocations: 1,
+                threads: 2,
+                processes: 3,
+                modules: 4,
+                abandoned_allocations: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn released_balances_acquired() {
+        let counters = ResourceCounters::new();
+        for kind in ALL {
+            counters.acquired(kind);
+            counters.acquired(kind);
+            counters.released(kind);
+        }
+        assert_eq!(counters.snapshot().transient(), 3);
+        for kind in ALL {
+            counters.released(kind);
+        }
+        assert_eq!(counters.snapshot(), ResourceSnapshot::default());
+    }
+
+    #[test]
+    fn transient_leaves_out_process_handles() {
+        let counters = ResourceCounters::new();
+        counters.acquired(ResourceKind::Process);
+        assert_eq!(counters.snapshot().transient(), 0);
+        counters.acquired(ResourceKind::Module);
+        assert_eq!(counters.snapshot().transient(), 1);
+    }
+
+    /// 放着的远程内存不再算在 `allocations` 里，单独记数直到被释放
+    #[test]
+    fn abandoned_allocations_are_counted_separately() {
+        let counters = ResourceCounters::new();
+        counters.acquired(ResourceKind::Allocation);
+        counters.released(ResourceKind::Allocation);
+        counters.abandoned();
+        let snapshot = counters.snapshot();
+        assert_eq!(snapshot.allocations, 0);
+        assert_eq!(snapshot.abandoned_allocations, 1);
+        assert_eq!(snapshot.transient(), 0);
+        counters.reclaimed();
+        assert_eq!(counters.snapshot(), ResourceSnapshot::default());
+    }
+
+    #[test]
+    fn counters_are_shared_between_threads() {
+        static COUNTERS: ResourceCounters = ResourceCounters::new();
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    for _ in 0..1000 {
+                        COUNTERS.acquired(ResourceKind::Thread);
+                        COUNTERS.released(ResourceKind::Thread);
+                        COUNTERS.acquired(ResourceKind::Module);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let snapshot = COUNTERS.snapshot();
+        assert_eq!(snapshot.threads, 0);
+        assert_eq!(snapshot.modules, 4000);
+    }
+}
@@ -0,0 +1,552 @@
+//! sub_dll 写、host 读的计数器和生命周期事件，放在命名共享内存里。
+//!
+//! 事件是一个环形缓冲：写者用 `head` 领一个序号，按序号写到对应的槽里，槽的 `seq`
+//! 写的时候是奇数、写完是 `序号 * 2 + 2`。写者从不等读者，读者跟不上时旧的事件被覆盖，
+//! 读者会知道丢了多少。所有字段都是原子类型，可以多个线程、两个进程同时访问
+
+use std::{
+    fmt,
+    sync::atomic::{fence, AtomicU32, AtomicU64, Ordering},
+};
+
+/// `"PTTM"`
+pub const MAGIC: u32 = u32::from_le_bytes(*b"PTTM");
+/// 内存布局版本，字段变化时加一
+pub const LAYOUT_VERSION: u32 = 1;
+/// 环形缓冲能放的事件数
+pub const CAPACITY: usize = 256;
+/// 共享内存段至少要这么大
+pub const RING_SIZE: usize = std::mem::size_of::<TelemetryRing>();
+/// 写者碰上同一个槽正在被写时最多等这么多次，超过就丢掉这个事件
+const WRITE_SPINS: usize = 64;
+
+/// 共享内存的名字，每个被注入的 WebView2 进程一个
+pub fn channel_name(pid: u32) -> String {
+    format!("Local\\passthrough-telemetry-{}", pid)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Counter {
+    /// 子类化过程收到的消息
+    Messages,
+    /// 被过滤规则吞掉的消息
+    Swallowed,
+    /// 被过滤规则改写的消息
+    Rewritten,
+    /// 按区域表回答的 `WM_NCHITTEST`
+    HitTests,
+    /// 写者没抢到槽丢掉的事件
+    Dropped,
+}
+
+impl Counter {
+    pub const ALL: [Counter; COUNTERS] = [
+        Counter::Messages,
+        Counter::Swallowed,
+        Counter::Rewritten,
+        Counter::HitTests,
+        Counter::Dropped,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Counter::Messages => "messages",
+            Counter::Swallowed => "swallowed",
+            Counter::Rewritten => "rewritten",
+            Counter::HitTests => "hitTests",
+            Counter::Dropped => "dropped",
+        }
+    }
+}
+
+pub const COUNTERS: usize = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    /// 窗口子类化成功，`detail` 是原来的窗口过程
+    Installed,
+    /// 恢复了原来的窗口过程
+    Removed,
+    /// 子类化的窗口被销毁了
+    WindowDestroyed,
+    /// 恢复时发现窗口过程已经被别人换掉了，`detail` 是现在的窗口过程
+    ProcReplaced,
+}
+
+impl EventKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            EventKind::Installed => "installed",
+            EventKind::Removed => "removed",
+            EventKind::WindowDestroyed => "windowDestroyed",
+            EventKind::ProcReplaced => "procReplaced",
+        }
+    }
+
+    fn to_u32(self) -> u32 {
+        match self {
+            EventKind::Installed => 1,
+            EventKind::Removed => 2,
+            EventKind::WindowDestroyed => 3,
+            EventKind::ProcReplaced => 4,
+        }
+    }
+
+    fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            1 => Some(EventKind::Installed),
+            2 => Some(EventKind::Removed),
+            3 => Some(EventKind::WindowDestroyed),
+            4 => Some(EventKind::ProcReplaced),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Event {
+    /// 写者领到的序号，从 0 开始连续递增
+    pub sequence: u64,
+    pub kind: EventKind,
+    pub hwnd: u64,
+    pub detail: u64,
+    /// 写者进程里的 `GetTickCount64`
+    pub time_ms: u64,
+}
+
+#[repr(C)]
+struct Slot {
+    seq: AtomicU64,
+    kind: AtomicU32,
+    reserved: AtomicU32,
+    hwnd: AtomicU64,
+    detail: AtomicU64,
+    time_ms: AtomicU64,
+}
+
+impl Slot {
+    const fn new() -> Self {
+        Self {
+            seq: AtomicU64::new(0),
+            kind: AtomicU32::new(0),
+            reserved: AtomicU32::new(0),
+            hwnd: AtomicU64::new(0),
+            detail: AtomicU64::new(0),
+            time_ms: AtomicU64::new(0),
+        }
+    }
+}
+
+/// 共享内存里的布局，字段顺序和大小不能随便改，改了要加 `LAYOUT_VERSION`
+#[repr(C)]
+pub struct TelemetryRing {
+    magic: AtomicU32,
+    version: AtomicU32,
+    /// 写者的进程 id，host 初始化时填
+    pid: AtomicU32,
+    reserved: AtomicU32,
+    /// 下一个事件的序号
+    head: AtomicU64,
+    counters: [AtomicU64; COUNTERS],
+    slots: [Slot; CAPACITY],
+}
+
+impl TelemetryRing {
+    /// 进程内使用，共享内存用 `from_ptr`
+    pub const fn new() -> Self {
+        Self {
+            magic: AtomicU32::new(0),
+            version: AtomicU32::new(0),
+            pid: AtomicU32::new(0),
+            reserved: AtomicU32::new(0),
+            head: AtomicU64::new(0),
+            counters: [const { AtomicU64::new(0) }; COUNTERS],
+            slots: [const { Slot::new() }; CAPACITY],
+        }
+    }
+
+    /// # Safety
+    /// `ptr` 按 8 字节对齐，至少有 `RING_SIZE` 字节，在返回的引用用完之前一直有效
+    pub unsafe fn from_ptr<'a>(ptr: *const u8) -> &'a TelemetryRing {
+        &*(ptr as *const TelemetryRing)
+    }
+
+    /// host 建好共享内存后调用，已经初始化过的保持不变
+    pub fn init(&self, pid: u32) {
+        if self.magic.load(Ordering::Acquire) == MAGIC {
+            return;
+        }
+        self.version.store(LAYOUT_VERSION, Ordering::Relaxed);
+        self.pid.store(pid, Ordering::Relaxed);
+        self.magic.store(MAGIC, Ordering::Release);
+    }
+
+    /// 布局版本一致才能读写，不一致时 sub_dll 不写
+    pub fn is_ready(&self) -> bool {
+        self.magic.load(Ordering::Acquire) == MAGIC
+            && self.version.load(Ordering::Relaxed) == LAYOUT_VERSION
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid.load(Ordering::Relaxed)
+    }
+
+    pub fn add(&self, counter: Counter, n: u64) {
+        self.counters[counter as usize].fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn counters(&self) -> [u64; COUNTERS] {
+        Counter::ALL.map(|counter| self.counters[counter as usize].load(Ordering::Relaxed))
+    }
+
+    /// 写一个事件，不会阻塞。槽被更早的写者占着时最多等 `WRITE_SPINS` 次
+    pub fn publish(&self, kind: EventKind, hwnd: u64, detail: u64, time_ms: u64) {
+        let sequence = self.head.fetch_add(1, Ordering::Relaxed);
+        let slot = &self.slots[sequence as usize % CAPACITY];
+        let writing = sequence * 2 + 1;
+        let mut claimed = false;
+        for _ in 0..WRITE_SPINS {
+            let current = slot.seq.load(Ordering::Relaxed);
+            // 已经被转了一圈之后的写者占了，这个事件反正会被覆盖
+            if current >= writing {
+                break;
+            }
+            if current & 1 == 1 {
+                std::hint::spin_loop();
+                continue;
+            }
+            if slot
+                .seq
+                .compare_exchange_weak(current, writing, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+            {
+                claimed = true;
+                break;
+            }
+        }
+        if !claimed {
+            self.add(Counter::Dropped, 1);
+            return;
+        }
+        fence(Ordering::Release);
+        slot.kind.store(kind.to_u32(), Ordering::Relaxed);
+        slot.hwnd.store(hwnd, Ordering::Relaxed);
+        slot.detail.store(detail, Ordering::Relaxed);
+        slot.time_ms.store(time_ms, Ordering::Relaxed);
+        slot.seq.store(writing + 1, Ordering::Release);
+    }
+}
+
+impl Default for TelemetryRing {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 一次 `drain` 的结果
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Drained {
+    pub events: Vec<Event>,
+    /// 被覆盖或者写者放弃的事件数
+    pub lost: u64,
+}
+
+/// 读者自己记录读到了哪里，每个 `TelemetryRing` 只能有一个读者
+#[derive(Clone, Debug, Default)]
+pub struct Reader {
+    next: u64,
+}
+
+impl Reader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 从现在的 `head` 开始读。重新打开还在用的通道时，之前的事件上一个读者已经读过，
+    /// 从 0 开始会把它们重读一遍或者算成丢失；新建的通道 `head` 是 0，和 `new` 一样
+    pub fn attach(ring: &TelemetryRing) -> Self {
+        Self {
+            next: ring.head.load(Ordering::Acquire),
+        }
+    }
+
+    /// 读出上次之后写完的事件。遇到还没写完的就停下，下次再读；
+    /// 但落后超过半圈时当作丢了，防止一个卡住的写者挡住后面所有事件
+    pub fn drain(&mut self, ring: &TelemetryRing) -> Drained {
+        let mut drained = Drained::default();
+        let head = ring.head.load(Ordering::Acquire);
+        if head.saturating_sub(self.next) > CAPACITY as u64 {
+            let skip = head - CAPACITY as u64;
+            drained.lost += skip - self.next;
+            self.next = skip;
+        }
+        while self.next < head {
+            let sequence = self.next;
+            let slot = &ring.slots[sequence as usize % CAPACITY];
+            let committed = sequence * 2 + 2;
+            let before = slot.seq.load(Ordering::Acquire);
+            if before == committed {
+                let kind = slot.kind.load(Ordering::Relaxed);
+                let hwnd = slot.hwnd.load(Ordering::Relaxed);
+                let detail = slot.detail.load(Ordering::Relaxed);
+                let time_ms = slot.time_ms.load(Ordering::Relaxed);
+                fence(Ordering::Acquire);
+                match EventKind::from_u32(kind) {
+                    Some(kind) if slot.seq.load(Ordering::Relaxed) == before => {
+                        drained.events.push(Event {
+                            sequence,
+                            kind,
+                            hwnd,
+                            detail,
+                            time_ms,
+                        })
+                    }
+                    // 读的时候被下一圈覆盖了
+                    _ => drained.lost += 1,
+                }
+            } else if before > committed || head - sequence > CAPACITY as u64 / 2 {
+                drained.lost += 1;
+            } else {
+                break;
+            }
+            self.next += 1;
+        }
+        drained
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    use super::*;
+
+    const MASK: u64 = 0x5a5a_5a5a_5a5a_5a5a;
+
+    fn initialized() -> Box<TelemetryRing> {
+        let ring = Box::new(TelemetryRing::new());
+        ring.init(42);
+        ring
+    }
+
+    /// 每个字段都由 `n` 决定，读到的字段对不上说明读到了写了一半的槽
+    fn publish(ring: &TelemetryRing, n: u64) {
+        ring.publish(EventKind::Installed, n, n ^ MASK, n.wrapping_mul(3));
+    }
+
+    fn assert_consistent(event: &Event) {
+        assert_eq!(event.detail, event.hwnd ^ MASK, "{:?}", event);
+        assert_eq!(event.time_ms, event.hwnd.wrapping_mul(3), "{:?}", event);
+        assert_eq!(event.sequence, event.hwnd, "{:?}", event);
+    }
+
+    fn sequences(drained: &Drained) -> Vec<u64> {
+        drained.events.iter().map(|event| event.sequence).collect()
+    }
+
+    #[test]
+    fn init_keeps_an_initialized_ring() {
+        let ring = initialized();
+        assert!(ring.is_ready());
+        assert_eq!(ring.pid(), 42);
+        ring.init(7);
+        assert_eq!(ring.pid(), 42);
+        assert!(!TelemetryRing::new().is_ready());
+    }
+
+    #[test]
+    fn counters_accumulate() {
+        let ring = initialized();
+        ring.add(Counter::Messages, 3);
+        ring.add(Counter::Messages, 2);
+        ring.add(Counter::HitTests, 1);
+        assert_eq!(ring.counters(), [5, 0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn single_writer_wraps_around_without_losing_events() {
+        let ring = initialized();
+        let mut reader = Reader::new();
+        let mut seen = Vec::new();
+        for n in 0..3 * CAPACITY as u64 {
+            publish(&ring, n);
+            if n % (CAPACITY as u64 / 2) == 0 {
+                let drained = reader.drain(&ring);
+                assert_eq!(drained.lost, 0);
+                seen.extend(drained.events);
+            }
+        }
+        seen.extend(reader.drain(&ring).events);
+        assert_eq!(seen.len(), 3 * CAPACITY);
+        for (n, event) in seen.iter().enumerate() {
+            assert_eq!(event.sequence, n as u64);
+            assert_consistent(event);
+        }
+        assert_eq!(reader.drain(&ring), Drained::default());
+    }
+
+    #[test]
+    fn lapped_reader_counts_overwritten_events_as_lost() {
+        let ring = initialized();
+        let mut reader = Reader::new();
+        let total = 2 * CAPACITY as u64 + 10;
+        for n in 0..total {
+            publish(&ring, n);
+        }
+        let drained = reader.drain(&ring);
+        assert_eq!(drained.lost, CAPACITY as u64 + 10);
+        // 只剩最后一圈
+        assert_eq!(
+            sequences(&drained),
+            (CAPACITY as u64 + 10..total).collect::<Vec<_>>()
+        );
+        drained.events.iter().for_each(assert_consistent);
+
+        publish(&ring, total);
+        let drained = reader.drain(&ring);
+        assert_eq!((sequences(&drained), drained.lost), (vec![total], 0));
+    }
+
+    #[test]
+    fn reader_waits_for_a_slot_that_is_still_being_written() {
+        let ring = initialized();
+        let mut reader = Reader::new();
+        publish(&ring, 0);
+        // 模拟领了序号 1 还没写完的写者
+        ring.head.fetch_add(1, Ordering::Relaxed);
+        ring.slots[1].seq.store(3, Ordering::Relaxed);
+        let drained = reader.drain(&ring);
+        assert_eq!((sequences(&drained), drained.lost), (vec![0], 0));
+        assert_eq!(reader.drain(&ring), Drained::default());
+
+        // 写完之后接着读
+        ring.slots[1]
+            .kind
+            .store(EventKind::Removed.to_u32(), Ordering::Relaxed);
+        ring.slots[1].seq.store(4, Ordering::Release);
+        let drained = reader.drain(&ring);
+        assert_eq!(drained.events[0].kind, EventKind::Removed);
+        assert_eq!(sequences(&drained), vec![1]);
+    }
+
+    #[test]
+    fn stuck_writer_is_skipped_after_half_a_lap() {
+        let ring = initialized();
+        let mut reader = Reader::new();
+        // 序号 0 的写者卡住了，后面的写者照常写
+        ring.head.fetch_add(1, Ordering::Relaxed);
+        ring.slots[0].seq.store(1, Ordering::Relaxed);
+        for n in 1..=CAPACITY as u64 / 2 {
+            publish(&ring, n);
+        }
+        let drained = reader.drain(&ring);
+        assert_eq!(drained.lost, 1);
+        assert_eq!(
+            sequences(&drained),
+            (1..=CAPACITY as u64 / 2).collect::<Vec<_>>()
+        );
+    }
+
+    /// 读者读到 `head` 之后，下一圈的写者才领到同一个槽：这个槽里已经不是要读的事件了
+    #[test]
+    fn slot_taken_by_the_next_lap_is_lost_not_torn() {
+        for seq in [2 * CAPACITY as u64 + 1, 2 * CAPACITY as u64 + 2] {
+            let ring = initialized();
+            let mut reader = Reader::new();
+            for n in 0..CAPACITY as u64 {
+                publish(&ring, n);
+            }
+            ring.slots[0].hwnd.store(CAPACITY as u64, Ordering::Relaxed);
+            ring.slots[0].seq.store(seq, Ordering::Release);
+            let drained = reader.drain(&ring);
+            assert_eq!(drained.lost, 1);
+            assert_eq!(
+                sequences(&drained),
+                (1..CAPACITY as u64).collect::<Vec<_>>()
+            );
+            drained.events.iter().for_each(assert_consistent);
+        }
+    }
+
+    #[test]
+    fn concurrent_writer_never_yields_torn_events() {
+        const TOTAL: u64 = 200_000;
+        let ring: Arc<TelemetryRing> = Arc::new(TelemetryRing::new());
+        ring.init(42);
+        let done = Arc::new(AtomicBool::new(false));
+        let writer = {
+            let (ring, done) = (ring.clone(), done.clone());
+            std::thread::spawn(move || {
+                for n in 0..TOTAL {
+                    publish(&ring, n);
+                }
+                done.store(true, Ordering::Release);
+            })
+        };
+
+        let mut reader = Reader::new();
+        let (mut events, mut lost) = (0, 0);
+        let mut last = None;
+        loop {
+            let finished = done.load(Ordering::Acquire);
+            // 读者故意慢一点，经常被写者套圈，正在读的槽会被下一圈覆盖
+            std::thread::yield_now();
+            let drained = reader.drain(&ring);
+            for event in &drained.events {
+                assert_consistent(event);
+                assert!(last < Some(event.sequence), "{:?} after {:?}", event, last);
+                last = Some(event.sequence);
+            }
+            events += drained.events.len() as u64;
+            lost += drained.lost;
+            if finished && drained == Drained::default() {
+                break;
+            }
+        }
+        writer.join().unwrap();
+        // 只有一个写者，不会抢不到槽
+        assert_eq!(ring.counters()[Counter::Dropped as usize], 0);
+        assert_eq!(events + lost, TOTAL);
+        assert_eq!(last, Some(TOTAL - 1));
+    }
+
+    /// host 关掉通道后又注入同一个进程：共享内存还在，新的读者不能从 0 开始
+    #[test]
+    fn reattached_reader_starts_at_the_head() {
+        let ring = initialized();
+        let mut reader = Reader::new();
+        for n in 0..10 {
+            publish(&ring, n);
+        }
+        assert_eq!(reader.drain(&ring).events.len(), 10);
+        // 没有读者的时候写的事件没人要
+        for n in 10..CAPACITY as u64 + 20 {
+            publish(&ring, n);
+        }
+
+        ring.init(42);
+        let mut reader = Reader::attach(&ring);
+        assert_eq!(reader.drain(&ring), Drained::default());
+        publish(&ring, CAPACITY as u64 + 20);
+        let drained = reader.drain(&ring);
+        assert_eq!(
+            (sequences(&drained), drained.lost),
+            (vec![CAPACITY as u64 + 20], 0)
+        );
+
+        // 新建的通道和 `new` 一样从 0 开始
+        let fresh = initialized();
+        let mut reader = Reader::attach(&fresh);
+        publish(&fresh, 0);
+        assert_eq!(sequences(&reader.drain(&fresh)), vec![0]);
+    }
+}
@@ -0,0 +1,293 @@
+//! 枚举进程、模块和窗口，host 的诊断信息和 passthrough-doctor 共用。
+//! 只读，不需要注入的权限；[`INJECT_ACCESS`] 只用来打开进程
+
+use std::{ffi::c_void, path::Path};
+
+use windows::{
+    core::{Error as WindowsError, PWSTR},
+    Win32::{
+        Foundation::{
+            CloseHandle, BOOL, ERROR_INSUFFICIENT_BUFFER, ERROR_NOT_FOUND, HANDLE, HMODULE, HWND,
+            LPARAM, RECT,
+        },
+        System::{
+            Diagnostics::ToolHelp::{
+                CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W,
+                TH32CS_SNAPPROCESS,
+            },
+            ProcessStatus::{
+                K32EnumProcessModulesEx, K32GetModuleFileNameExW, K32GetModuleInformation,
+                LIST_MODULES_ALL, MODULEINFO,
+            },
+            Threading::{
+                OpenProcess, QueryFullProcessImageNameW, PROCESS_ACCESS_RIGHTS,
+                PROCESS_CREATE_THREAD, PROCESS_NAME_WIN32, PROCESS_QUERY_INFORMATION,
+                PROCESS_VM_OPERATION, PROCESS_VM_READ, PROCESS_VM_WRITE,
+            },
+        },
+        UI::WindowsAndMessaging::{
+            EnumWindows, GetClassNameW, GetWindow, GetWindowLongPtrW, GetWindowRect,
+            GetWindowTextLengthW, GetWindowTextW, GetWindowThreadProcessId, IsWindowVisible,
+            GWLP_WNDPROC, GW_CHILD, GW_HWNDNEXT,
+        },
+    },
+};
+
+use crate::inspect::{self, ModuleInfo, ProcessEntry, WindowNode};
+
+/// 长路径最多 32767 个字符，缓冲区加倍到这里还不够就不再试
+const MAX_PATH_CHARS: usize = 32768;
+
+/// 读取诊断信息需要的权限，不需要注入的权限
+pub const INSPECT_ACCESS: PROCESS_ACCESS_RIGHTS =
+    PROCESS_ACCESS_RIGHTS(PROCESS_QUERY_INFORMATION.0 | PROCESS_VM_READ.0);
+
+/// 注入和远程调用需要的权限：分配、读写内存，创建远程线程，枚举模块。
+/// passthrough-doctor 用它检查 host 能不能注入
+pub const INJECT_ACCESS: PROCESS_ACCESS_RIGHTS = PROCESS_ACCESS_RIGHTS(
+    PROCESS_CREATE_THREAD.0
+        | PROCESS_QUERY_INFORMATION.0
+        | PROCESS_VM_OPERATION.0
+        | PROCESS_VM_READ.0
+        | PROCESS_VM_WRITE.0,
+);
+
+/// `OpenProcess` 打开的进程句柄，drop 时关闭
+pub struct ProcessHandle(HANDLE);
+
+unsafe impl Send for ProcessHandle {}
+unsafe impl Sync for ProcessHandle {}
+
+impl ProcessHandle {
+    pub unsafe fn open(access: PROCESS_ACCESS_RIGHTS, pid: u32) -> Result<Self, WindowsError> {
+        OpenProcess(access, BOOL(0), pid).map(Self)
+    }
+
+    /// 接管别处打开的进程句柄，之后由它关闭
+    pub unsafe fn from_raw(handle: HANDLE) -> Self {
+        Self(handle)
+    }
+
+    pub fn handle(&self) -> HANDLE {
+        self.0
+    }
+}
+
+impl Drop for ProcessHandle {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = CloseHandle(self.0);
+        }
+    }
+}
+
+fn from_wide(buffer: &[u16]) -> String {
+    let len = buffer.iter().position(|&c| c == 0).unwrap_or(buffer.len());
+    String::from_utf16_lossy(&buffer[..len])
+}
+
+/// 用 `read` 读一个长度不定的字符串，返回值等于缓冲区大小时说明被截断了，加大缓冲区再读
+fn read_growing(mut read: impl FnMut(&mut [u16]) -> usize) -> Result<String, WindowsError> {
+    let mut buffer = vec![0u16; 260];
+    loop {
+        let len = read(&mut buffer);
+        if len == 0 {
+            return Err(WindowsError::from_win32());
+        }
+        if len < buffer.len() || buffer.len() >= MAX_PATH_CHARS {
+            return Ok(String::from_utf16_lossy(&buffer[..len.min(buffer.len())]));
+        }
+        buffer.resize(buffer.len() * 2, 0);
+    }
+}
+
+/// 系统里所有进程的 pid、父进程和进程名
+///
+/// # Safety
+/// 只调用 Win32 API，没有额外的要求
+pub unsafe fn process_entries() -> Result<Vec<ProcessEntry>, WindowsError> {
+    let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0)?;
+    let mut entry = PROCESSENTRY32W {
+        dwSize: std::mem::size_of::<PROCESSENTRY32W>() as u32,
+        ..Default::default()
+    };
+    let mut entries = Vec::new();
+    let mut next = Process32FirstW(snapshot, &mut entry);
+    while next.is_ok() {
+        entries.push(ProcessEntry {
+            pid: entry.th32ProcessID,
+            parent_pid: entry.th32ParentProcessID,
+            name: from_wide(&entry.szExeFile),
+        });
+        next = Process32NextW(snapshot, &mut entry);
+    }
+    let _ = CloseHandle(snapshot);
+    Ok(entries)
+}
+
+/// 进程的可执行文件路径
+///
+/// # Safety
+/// `h_process` 是有 `PROCESS_QUERY_LIMITED_INFORMATION` 权限的进程句柄
+pub unsafe fn image_path(h_process: HANDLE) -> Result<String, WindowsError> {
+    read_growing(|buffer| {
+        let mut len = buffer.len() as u32;
+        match QueryFullProcessImageNameW(
+            h_process,
+            PROCESS_NAME_WIN32,
+            PWSTR(buffer.as_mut_ptr()),
+            &mut len,
+        ) {
+            Ok(()) => len as usize,
+            // 缓冲区不够时失败，当作被截断
+            Err(err) if err.code() == ERROR_INSUFFICIENT_BUFFER.into() => buffer.len(),
+            Err(_) => 0,
+        }
+    })
+}
+
+/// 远程进程里模块的完整路径，不限长度
+///
+/// # Safety
+/// `h_process` 有 [`INSPECT_ACCESS`] 权限
+pub unsafe fn module_path(h_process: HANDLE, module: HMODULE) -> Result<String, WindowsError> {
+    read_growing(|buffer| K32GetModuleFileNameExW(h_process, module, buffer) as usize)
+}
+
+/// 进程加载的所有模块。两次调用之间可能又加载了模块，数量不够时加大数组重新枚举；
+/// 枚举之后被卸载的模块跳过
+///
+/// # Safety
+/// `h_process` 有 [`INSPECT_ACCESS`] 权限
+pub unsafe fn process_modules(h_process: HANDLE) -> Result<Vec<ModuleInfo>, WindowsError> {
+    let mut handles = vec![HMODULE::default(); 256];
+    loop {
+        let mut needed = 0;
+        K32EnumProcessModulesEx(
+            h_process,
+            handles.as_mut_ptr(),
+            std::mem::size_of_val(handles.as_slice()) as u32,
+            &mut needed,
+            LIST_MODULES_ALL.0,
+        )
+        .ok()?;
+        let count = needed as usize / std::mem::size_of::<HMODULE>();
+        if count <= handles.len() {
+            handles.truncate(count);
+            break;
+        }
+        handles.resize(count + 16, HMODULE::default());
+    }
+    Ok(handles
+        .into_iter()
+        .filter_map(|module| module_info(h_process, module).ok())
+        .collect())
+}
+
+unsafe fn module_info(h_process: HANDLE, module: HMODULE) -> Result<ModuleInfo, WindowsError> {
+    let mut info = MODULEINFO::default();
+    K32GetModuleInformation(
+        h_process,
+        module,
+        &mut info,
+        std::mem::size_of::<MODULEINFO>() as u32,
+    )
+    .ok()?;
+    let path = module_path(h_process, module)?;
+    let name = Path::new(&path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    Ok(ModuleInfo {
+        name,
+        path,
+        base: info.lpBaseOfDll as u64,
+        size: info.SizeOfImage,
+    })
+}
+
+/// 按文件名在远程进程里找模块，不区分大小写
+///
+/// # Safety
+/// `h_process` 有 [`INSPECT_ACCESS`] 权限
+pub unsafe fn find_module(h_process: HANDLE, name: &str) -> Result<HMODULE, WindowsError> {
+    let modules = process_modules(h_process)?;
+    match inspect::find_module(&modules, name) {
+        Some(module) => Ok(HMODULE(module.base as *mut c_void)),
+        None => Err(WindowsError::new(
+            ERROR_NOT_FOUND.into(),
+            format!("{} is not loaded", name),
+        )),
+    }
+}
+
+unsafe fn window_title(hwnd: HWND) -> String {
+    let len = GetWindowTextLengthW(hwnd);
+    if len <= 0 {
+        return String::new();
+    }
+    let mut buffer = vec![0u16; len as usize + 1];
+    let len = GetWindowTextW(hwnd, &mut buffer).max(0) as usize;
+    String::from_utf16_lossy(&buffer[..len])
+}
+
+/// 窗口和它所有的子窗口，子窗口可能属于别的进程
+///
+/// # Safety
+/// 只调用 Win32 API，窗口在中途销毁时读到的是空值
+pub unsafe fn window_tree(hwnd: HWND) -> WindowNode {
+    let mut pid = 0;
+    let thread_id = GetWindowThreadProcessId(hwnd, Some(&mut pid));
+    // 窗口类名最长 256 个字符
+    let mut class_name = [0u16; 257];
+    let len = GetClassNameW(hwnd, &mut class_name).max(0) as usize;
+    let mut rect = RECT::default();
+    let _ = GetWindowRect(hwnd, &mut rect);
+    let mut children = Vec::new();
+    let mut child = GetWindow(hwnd, GW_CHILD);
+    while let Ok(hwnd) = child {
+        children.push(window_tree(hwnd));
+        child = GetWindow(hwnd, GW_HWNDNEXT);
+    }
+    WindowNode {
+        hwnd: hwnd.0 as u64,
+        pid,
+        thread_id,
+        class_name: String::from_utf16_lossy(&class_name[..len]),
+        title: window_title(hwnd),
+        visible: IsWindowVisible(hwnd).as_bool(),
+        rect: [rect.left, rect.top, rect.right, rect.bottom],
+        wnd_proc: GetWindowLongPtrW(hwnd, GWLP_WNDPROC) as u64,
+        subclassed: false,
+        children,
+    }
+}
+
+unsafe extern "system" fn collect_window(hwnd: HWND, lparam: LPARAM) -> BOOL {
+    let windows = &mut *(lparam.0 as *mut Vec<HWND>);
+    windows.push(hwnd);
+    BOOL::from(true)
+}
+
+/// 所有顶层窗口
+///
+/// # Safety
+/// 只调用 Win32 API，没有额外的要求
+pub unsafe fn top_level_windows() -> Vec<HWND> {
+    let mut windows: Vec<HWND> = Vec::new();
+    let _ = EnumWindows(
+        Some(collect_window),
+        LPARAM(&mut windows as *mut Vec<HWND> as isize),
+    );
+    windows
+}
+
+/// 创建窗口的进程
+///
+/// # Safety
+/// 只调用 Win32 API，窗口已经销毁时返回 0
+pub unsafe fn window_pid(hwnd: HWND) -> u32 {
+    let mut pid = 0;
+    GetWindowThreadProcessId(hwnd, Some(&mut pid));
+    pid
+}
@@ -0,0 +1,317 @@
+//! 只存储不压缩的 zip 写入器，导出问题报告用，不依赖外部 crate。
+//!
+//! 报告里只有几个不大的文本文件，不压缩也不会太大，任何解压工具都能打开。
+//! 不支持 zip64：单个文件和整个压缩包都不能超过 4 GiB，最多 65535 个文件。
+
+use std::io::{self, Write};
+
+use crate::clock::UtcTime;
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+/// 2.0，最早支持目录的版本
+const VERSION: u16 = 20;
+/// 文件名是 UTF-8
+const FLAG_UTF8: u16 = 1 << 11;
+const METHOD_STORED: u16 = 0;
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                0xedb88320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// zip 用的 CRC-32（IEEE 802.3）
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc = CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// MS-DOS 格式的 `(时间, 日期)`，只能表示 1980 到 2107 年，精度 2 秒
+fn dos_time(time: UtcTime) -> (u16, u16) {
+    if time.year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let year = (time.year - 1980).min(127);
+    (
+        ((time.hour << 11) | (time.minute << 5) | (time.second / 2)) as u16,
+        ((year << 9) | (time.month << 5) | time.day) as u16,
+    )
+}
+
+fn too_large(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{} is too large for a zip without zip64", what),
+    )
+}
+
+struct Entry {
+    name: String,
+    crc: u32,
+    size: u32,
+    offset: u32,
+}
+
+pub struct ZipWriter<W: Write> {
+    writer: W,
+    entries: Vec<Entry>,
+    offset: u64,
+    time: u16,
+    date: u16,
+}
+
+impl<W: Write> ZipWriter<W> {
+    /// 所有文件的修改时间都是 `modified`
+    pub fn new(writer: W, modified: UtcTime) -> Self {
+        let (time, date) = dos_time(modified);
+        Self {
+            writer,
+            entries: Vec::new(),
+            offset: 0,
+            time,
+            date,
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.writer.write_all(data)?;
+        self.offset += data.len() as u64;
+        Ok(())
+    }
+
+    /// 写入一个文件，`name` 用 `/` 分隔目录
+    pub fn add(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        if self.entries.len() == u16::MAX as usize {
+            return Err(too_large("the number of files"));
+        }
+        let size = u32::try_from(data.len()).map_err(|_| too_large(name))?;
+        let offset = u32::try_from(self.offset).map_err(|_| too_large("the archive"))?;
+        let name_len = u16::try_from(name.len()).map_err(|_| too_large("the file name"))?;
+        let crc = crc32(data);
+
+        let mut header = Vec::with_capacity(30 + name.len());
+        header.extend(LOCAL_HEADER.to_le_bytes());
+        header.extend(VERSION.to_le_bytes());
+        header.extend(FLAG_UTF8.to_le_bytes());
+        header.extend(METHOD_STORED.to_le_bytes());
+        header.extend(self.time.to_le_bytes());
+        header.extend(self.date.to_le_bytes());
+        header.extend(crc.to_le_bytes());
+        // 压缩后和压缩前的大小
+        header.extend(size.to_le_bytes());
+        header.extend(size.to_le_bytes());
+        header.extend(name_len.to_le_bytes());
+        // 扩展字段长度
+        header.extend(0u16.to_le_bytes());
+        header.extend(name.as_bytes());
+        self.write(&header)?;
+        self.write(data)?;
+        self.entries.push(Entry {
+            name: name.to_string(),
+            crc,
+            size,
+            offset,
+        });
+        Ok(())
+    }
+
+    /// 写入中央目录，返回底层的写入器
+    pub fn finish(mut self) -> io::Result<W> {
+        let start = u32::try_from(self.offset).map_err(|_| too_large("the archive"))?;
+        let mut directory = Vec::new();
+        for entry in &self.entries {
+            directory.extend(CENTRAL_HEADER.to_le_bytes());
+            // 生成的版本，高字节 0 表示 MS-DOS 的文件属性
+            directory.extend(VERSION.to_le_bytes());
+            directory.extend(VERSION.to_le_bytes());
+            directory.extend(FLAG_UTF8.to_le_bytes());
+            directory.extend(METHOD_STORED.to_le_bytes());
+            directory.extend(self.time.to_le_bytes());
+            directory.extend(self.date.to_le_bytes());
+            directory.extend(entry.crc.to_le_bytes());
+            directory.extend(entry.size.to_le_bytes());
+            directory.extend(entry.size.to_le_bytes());
+            directory.extend((entry.name.len() as u16).to_le_bytes());
+            // 扩展字段、注释的长度，起始磁盘号，内部和外部属性
+            directory.extend(0u16.to_le_bytes());
+            directory.extend(0u16.to_le_bytes());
+            directory.extend(0u16.to_le_bytes());
+            directory.extend(0u16.to_le_bytes());
+            directory.extend(0u32.to_le_bytes());
+            directory.extend(entry.offset.to_le_bytes());
+            directory.extend(entry.name.as_bytes());
+        }
+        let size = u32::try_from(directory.len()).map_err(|_| too_large("the archive"))?;
+        let count = self.entries.len() as u16;
+        directory.extend(END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        // 当前磁盘号和中央目录所在的磁盘号
+        directory.extend(0u16.to_le_bytes());
+        directory.extend(0u16.to_le_bytes());
+        directory.extend(count.to_le_bytes());
+        directory.extend(count.to_le_bytes());
+        directory.extend(size.to_le_bytes());
+        directory.extend(start.to_le_bytes());
+        // 注释长度
+        directory.extend(0u16.to_le_bytes());
+        self.write(&directory)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIME: UtcTime = UtcTime {
+        year: 2024,
+        month: 5,
+        day: 1,
+        hour: 8,
+        minute: 30,
+        second: 59,
+    };
+
+    fn u16_at(bytes: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap())
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    fn archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Vec::new(), TIME);
+        for (name, data) in files {
+            zip.add(name, data).unwrap();
+        }
+        zip.finish().unwrap()
+    }
+
+    /// 按中央目录找到每个文件，检查本地文件头和 CRC，像解压工具一样读出来
+    fn read(bytes: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let end = bytes.len() - 22;
+        assert_eq!(u32_at(bytes, end), END_OF_CENTRAL_DIRECTORY);
+        let count = u16_at(bytes, end + 10) as usize;
+        assert_eq!(u16_at(bytes, end + 8) as usize, count);
+        let size = u32_at(bytes, end + 12) as usize;
+        let mut at = u32_at(bytes, end + 16) as usize;
+        assert_eq!(at + size, end);
+
+        let mut files = Vec::new();
+        for _ in 0..count {
+            assert_eq!(u32_at(bytes, at), CENTRAL_HEADER);
+            assert_eq!(u16_at(bytes, at + 10), METHOD_STORED);
+            let crc = u32_at(bytes, at + 16);
+            let size = u32_at(bytes, at + 20) as usize;
+            assert_eq!(u32_at(bytes, at + 24) as usize, size);
+            let name_len = u16_at(bytes, at + 28) as usize;
+            let offset = u32_at(bytes, at + 42) as usize;
+            let name = String::from_utf8(bytes[at + 46..at + 46 + name_len].to_vec()).unwrap();
+            at += 46 + name_len;
+
+            assert_eq!(u32_at(bytes, offset), LOCAL_HEADER);
+            assert_eq!(u32_at(bytes, offset + 14), crc);
+            assert_eq!(u16_at(bytes, offset + 26) as usize, name_len);
+            assert_eq!(&bytes[offset + 30..offset + 30 + name_len], name.as_bytes());
+            let start = offset + 30 + name_len;
+            let data = bytes[start..start + size].to_vec();
+            assert_eq!(crc32(&data), crc);
+            files.push((name, data));
+        }
+        assert_eq!(at, end);
+        files
+    }
+
+    #[test]
+    fn crc32_check_values() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(
+            crc32(b"The quick brown fox jumps over the lazy dog"),
+            0x414fa339
+        );
+    }
+
+    #[test]
+    fn dos_time_fields() {
+        // 秒只有 2 秒的精度
+        assert_eq!(
+            dos_time(TIME),
+            ((8 << 11) | (30 << 5) | 29, (44 << 9) | (5 << 5) | 1)
+        );
+        let early = UtcTime::from_unix(0);
+        assert_eq!(dos_time(early), (0, (1 << 5) | 1));
+    }
+
+    #[test]
+    fn empty_archive_is_only_the_end_record() {
+        let bytes = archive(&[]);
+        let mut expected = END_OF_CENTRAL_DIRECTORY.to_le_bytes().to_vec();
+        expected.extend([0; 18]);
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn single_file_layout() {
+        let bytes = archive(&[("a.txt", b"hi")]);
+        let (time, date) = dos_time(TIME);
+        let crc = crc32(b"hi");
+        let mut local = Vec::new();
+        local.extend(LOCAL_HEADER.to_le_bytes());
+        local.extend([20, 0, 0, 8, 0, 0]);
+        local.extend(time.to_le_bytes());
+        local.extend(date.to_le_bytes());
+        local.extend(crc.to_le_bytes());
+        local.extend([2, 0, 0, 0, 2, 0, 0, 0, 5, 0, 0, 0]);
+        local.extend(b"a.txt");
+        local.extend(b"hi");
+        assert_eq!(&bytes[..local.len()], local.as_slice());
+
+        let central = &bytes[local.len()..];
+        assert_eq!(u32_at(central, 0), CENTRAL_HEADER);
+        assert_eq!(u32_at(central, 42), 0, "offset of the local header");
+        // 中央目录 46 + 5 字节，结尾记录 22 字节
+        assert_eq!(central.len(), 46 + 5 + 22);
+        assert_eq!(u32_at(central, 51 + 12), 51);
+        assert_eq!(u32_at(central, 51 + 16), local.len() as u32);
+    }
+
+    #[test]
+    fn reader_gets_every_file_back() {
+        let big = vec![0xa5u8; 100_000];
+        let files: [(&str, &[u8]); 4] = [
+            ("manifest.json", b"{}"),
+            ("logs/passthrough.log", b"line 1\nline 2\n"),
+            ("empty", b""),
+            ("窗口.bin", &big),
+        ];
+        let read = read(&archive(&files));
+        assert_eq!(read.len(), files.len());
+        for ((name, data), (read_name, read_data)) in files.iter().zip(&read) {
+            assert_eq!(name, read_name);
+            assert_eq!(data, read_data);
+        }
+    }
+}
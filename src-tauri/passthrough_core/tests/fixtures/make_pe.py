@@ -0,0 +1,202 @@
+#!/usr/bin/env python3
+"""生成 pe.rs 测试用的最小 DLL：x86、x64 和 ARM64 各一个。
+
+每个 DLL 有一个 .rdata 节放导出表，一个 .text 节放导出函数（只有返回指令）。
+导出名按字母排序，和函数表的顺序不同，用来检查序号的换算。
+HeapAlloc 转发到 NTDLL.RtlAllocateHeap。
+
+    python3 make_pe.py
+"""
+
+import struct
+from pathlib import Path
+
+FILE_ALIGNMENT = 0x200
+SECTION_ALIGNMENT = 0x1000
+IMAGE_BASE_32 = 0x10000000
+IMAGE_BASE_64 = 0x180000000
+
+RDATA_RVA = 0x1000
+TEXT_RVA = 0x2000
+
+# 函数表的顺序，序号从 ORDINAL_BASE 开始
+FUNCTIONS = ["set_subclass", "remove_subclass", "sub_dll_abi", "HeapAlloc"]
+FORWARDS = {"HeapAlloc": "NTDLL.RtlAllocateHeap"}
+ORDINAL_BASE = 1
+DLL_NAME = "sub_dll.dll"
+
+MACHINES = {
+    "x86": (0x014C, False, b"\xc3"),
+    "x64": (0x8664, True, b"\xc3"),
+    "arm64": (0xAA64, True, struct.pack("<I", 0xD65F03C0)),
+}
+
+
+def align(value, alignment):
+    return (value + alignment - 1) // alignment * alignment
+
+
+def rdata(function_rvas):
+    """导出目录、函数表、名字表、序号表和字符串，返回 (数据, 导出目录大小)"""
+    names = sorted(FUNCTIONS)
+    directory_size = 40
+    functions_at = directory_size
+    names_at = functions_at + 4 * len(FUNCTIONS)
+    ordinals_at = names_at + 4 * len(names)
+    strings_at = ordinals_at + 2 * len(names)
+
+    strings = bytearray()
+
+    def string(text):
+        offset = strings_at + len(strings)
+        strings.extend(text.encode() + b"\0")
+        return RDATA_RVA + offset
+
+    dll_name = string(DLL_NAME)
+    name_rvas = [string(name) for name in names]
+    targets = []
+    for name in FUNCTIONS:
+        if name in FORWARDS:
+            targets.append(string(FORWARDS[name]))
+        else:
+            targets.append(function_rvas[name])
+
+    data = bytearray()
+    data += struct.pack(
+        "<IIHHIIIIIII",
+        0,  # Characteristics
+        0,  # TimeDateStamp
+        0,  # MajorVersion
+        0,  # MinorVersion
+        dll_name,
+        ORDINAL_BASE,
+        len(FUNCTIONS),
+        len(names),
+        RDATA_RVA + functions_at,
+        RDATA_RVA + names_at,
+        RDATA_RVA + ordinals_at,
+    )
+    for target in targets:
+        data += struct.pack("<I", target)
+    for rva in name_rvas:
+        data += struct.pack("<I", rva)
+    for name in names:
+        data += struct.pack("<H", FUNCTIONS.index(name))
+    data += strings
+    # 转发字符串必须在导出目录的范围里
+    return bytes(data), len(data)
+
+
+def section(name, rva, data, raw_offset, characteristics):
+    return struct.pack(
+        "<8sIIIIIIHHI",
+        name,
+        len(data),
+        rva,
+        align(len(data), FILE_ALIGNMENT),
+        raw_offset,
+        0,
+        0,
+        0,
+        0,
+        characteristics,
+    )
+
+
+def build(machine, pe32_plus, ret):
+    text = bytearray()
+    function_rvas = {}
+    for name in FUNCTIONS:
+        if name not in FORWARDS:
+            function_rvas[name] = TEXT_RVA + len(text)
+            text += ret
+            text += b"\0" * (align(len(text), 16) - len(text))
+    export, export_size = rdata(function_rvas)
+
+    optional_size = 240 if pe32_plus else 224
+    headers_size = align(0x40 + 4 + 20 + optional_size + 2 * 40, FILE_ALIGNMENT)
+    rdata_offset = headers_size
+    text_offset = rdata_offset + align(len(export), FILE_ALIGNMENT)
+    file_size = text_offset + align(len(text), FILE_ALIGNMENT)
+    image_size = align(TEXT_RVA + len(text), SECTION_ALIGNMENT)
+
+    dos = bytearray(0x40)
+    dos[0:2] = b"MZ"
+    struct.pack_into("<I", dos, 0x3C, 0x40)
+
+    # DLL | EXECUTABLE_IMAGE，32 位加 32BIT_MACHINE，64 位加 LARGE_ADDRESS_AWARE
+    characteristics = 0x2002 | (0x0020 if pe32_plus else 0x0100)
+    coff = struct.pack("<HHIIIHH", machine, 2, 0, 0, 0, optional_size, characteristics)
+
+    if pe32_plus:
+        optional = struct.pack(
+            "<HBBIIIII",
+            0x20B,
+            14,
+            0,
+            align(len(text), FILE_ALIGNMENT),
+            align(len(export), FILE_ALIGNMENT),
+            0,
+            0,  # AddressOfEntryPoint，没有入口
+            TEXT_RVA,
+        )
+        optional += struct.pack("<Q", IMAGE_BASE_64)
+    else:
+        optional = struct.pack(
+            "<HBBIIIIII",
+            0x10B,
+            14,
+            0,
+            align(len(text), FILE_ALIGNMENT),
+            align(len(export), FILE_ALIGNMENT),
+            0,
+            0,
+            TEXT_RVA,
+            RDATA_RVA,  # BaseOfData
+        )
+        optional += struct.pack("<I", IMAGE_BASE_32)
+    optional += struct.pack(
+        "<IIHHHHHHIIIIHH",
+        SECTION_ALIGNMENT,
+        FILE_ALIGNMENT,
+        6,
+        0,
+        0,
+        0,
+        6,
+        0,
+        0,
+        image_size,
+        headers_size,
+        0,
+        3,  # WINDOWS_CUI
+        0x0160 if pe32_plus else 0x0140,  # DYNAMIC_BASE | NX_COMPAT (| HIGH_ENTROPY_VA)
+    )
+    stack = "<QQQQ" if pe32_plus else "<IIII"
+    optional += struct.pack(stack, 0x100000, 0x1000, 0x100000, 0x1000)
+    optional += struct.pack("<II", 0, 16)
+    directories = [(0, 0)] * 16
+    directories[0] = (RDATA_RVA, export_size)
+    for rva, size in directories:
+        optional += struct.pack("<II", rva, size)
+    assert len(optional) == optional_size
+
+    sections = section(b".rdata", RDATA_RVA, export, rdata_offset, 0x40000040)
+    sections += section(b".text", TEXT_RVA, text, text_offset, 0x60000020)
+
+    image = bytearray(file_size)
+    headers = dos + b"PE\0\0" + coff + optional + sections
+    image[: len(headers)] = headers
+    image[rdata_offset : rdata_offset + len(export)] = export
+    image[text_offset : text_offset + len(text)] = text
+    return bytes(image)
+
+
+def main():
+    out = Path(__file__).resolve().parent
+    for name, (machine, pe32_plus, ret) in MACHINES.items():
+        (out / f"exports_{name}.dll").write_bytes(build(machine, pe32_plus, ret))
+
+
+if __name__ == "__main__":
+    main()
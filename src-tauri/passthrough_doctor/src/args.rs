@@ -0,0 +1,295 @@
+use std::{fmt, path::PathBuf};
+
+pub const USAGE: &str = "\
+passthrough-doctor: inspect WebView2 processes, sub_dll and subclassing
+
+USAGE:
+    passthrough-doctor [OPTIONS]
+
+OPTIONS:
+    --pid <PID>          inspect this process, can be repeated
+    --host <PID>         inspect the WebView2 processes started by this app
+    --dll <PATH>         sub_dll to plan a dry-run injection with,
+                         defaults to the one already loaded
+    --record <FILE>      also save the collected data for `--snapshot`
+    --snapshot <FILE>    build the report from recorded data instead of this machine
+    --out <FILE>         write the JSON report to FILE
+    --format <FORMAT>    stdout format: text (default) or json
+    -h, --help           print this help
+
+Without --pid or --host every msedgewebview2.exe process is inspected.";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Args {
+    pub pids: Vec<u32>,
+    pub host: Option<u32>,
+    pub dll: Option<PathBuf>,
+    pub record: Option<PathBuf>,
+    pub snapshot: Option<PathBuf>,
+    pub out: Option<PathBuf>,
+    pub format: Format,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Run(Args),
+    Help,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ArgsError {
+    Unknown(String),
+    MissingValue(&'static str),
+    InvalidValue {
+        flag: &'static str,
+        value: String,
+    },
+    /// `--snapshot` 不收集数据，不能和收集用的参数一起用
+    Conflict(&'static str),
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgsError::Unknown(arg) => write!(f, "unknown argument `{}`", arg),
+            ArgsError::MissingValue(flag) => write!(f, "`{}` needs a value", flag),
+            ArgsError::InvalidValue { flag, value } => {
+                write!(f, "invalid value `{}` for `{}`", value, flag)
+            }
+            ArgsError::Conflict(flag) => {
+                write!(f, "`{}` cannot be used together with `--snapshot`", flag)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ArgsError {}
+
+const FLAGS: [&str; 7] = [
+    "--pid",
+    "--host",
+    "--dll",
+    "--record",
+    "--snapshot",
+    "--out",
+    "--format",
+];
+
+fn parse_pid(flag: &'static str, value: String) -> Result<u32, ArgsError> {
+    value
+        .parse()
+        .map_err(|_| ArgsError::InvalidValue { flag, value })
+}
+
+/// 解析命令行参数，不包括程序名。值可以写成 `--pid 42` 或者 `--pid=42`
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command, ArgsError> {
+    let mut parsed = Args::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            return Ok(Command::Help);
+        }
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_string(), Some(value.to_string())),
+            None => (arg.clone(), None),
+        };
+        let Some(&flag) = FLAGS.iter().find(|flag| **flag == name) else {
+            return Err(ArgsError::Unknown(arg));
+        };
+        let Some(value) = inline.or_else(|| args.next()) else {
+            return Err(ArgsError::MissingValue(flag));
+        };
+        match flag {
+            "--pid" => parsed.pids.push(parse_pid(flag, value)?),
+            "--host" => parsed.host = Some(parse_pid(flag, value)?),
+            "--dll" => parsed.dll = Some(value.into()),
+            "--record" => parsed.record = Some(value.into()),
+            "--snapshot" => parsed.snapshot = Some(value.into()),
+            "--out" => parsed.out = Some(value.into()),
+            _ => {
+                parsed.format = match value.as_str() {
+                    "text" => Format::Text,
+                    "json" => Format::Json,
+                    _ => return Err(ArgsError::InvalidValue { flag, value }),
+                }
+            }
+        }
+    }
+    if parsed.snapshot.is_some() {
+        let conflict = [
+            ("--pid", !parsed.pids.is_empty()),
+            ("--host", parsed.host.is_some()),
+            ("--dll", parsed.dll.is_some()),
+            ("--record", parsed.record.is_some()),
+        ]
+        .into_iter()
+        .find(|(_, used)| *used);
+        if let Some((flag, _)) = conflict {
+            return Err(ArgsError::Conflict(flag));
+        }
+    }
+    Ok(Command::Run(parsed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Command, ArgsError> {
+        parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn run(args: &[&str]) -> Args {
+        match parse_args(args) {
+            Ok(Command::Run(args)) => args,
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn no_arguments() {
+        assert_eq!(run(&[]), Args::default());
+    }
+
+    #[test]
+    fn all_flags() {
+        let args = run(&[
+            "--pid", "1", "--pid", "2", "--host", "3", "--dll", "a.dll", "--record", "r.json",
+            "--out", "o.json", "--format", "json",
+        ]);
+        assert_eq!(
+            args,
+            Args {
+                pids: vec![1, 2],
+                host: Some(3),
+                dll: Some("a.dll".into()),
+                record: Some("r.json".into()),
+                snapshot: None,
+                out: Some("o.json".into()),
+                format: Format::Json,
+            }
+        );
+    }
+
+    #[test]
+    fn inline_values() {
+        let args = run(&["--pid=42", "--format=text", "--out=C:\\a=b.json"]);
+        assert_eq!(args.pids, vec![42]);
+        assert_eq!(args.format, Format::Text);
+        // 只按第一个 `=` 分开
+        assert_eq!(args.out, Some("C:\\a=b.json".into()));
+    }
+
+    #[test]
+    fn empty_inline_value() {
+        assert_eq!(
+            parse_args(&["--pid="]),
+            Err(ArgsError::InvalidValue {
+                flag: "--pid",
+                value: String::new(),
+            })
+        );
+    }
+
+    #[test]
+    fn help_wins() {
+        assert_eq!(parse_args(&["-h"]), Ok(Command::Help));
+        assert_eq!(parse_args(&["--pid", "1", "--help"]), Ok(Command::Help));
+    }
+
+    #[test]
+    fn unknown_arguments() {
+        assert_eq!(
+            parse_args(&["--verbose"]),
+            Err(ArgsError::Unknown("--verbose".to_string()))
+        );
+        assert_eq!(
+            parse_args(&["--verbose=1"]),
+            Err(ArgsError::Unknown("--verbose=1".to_string()))
+        );
+        assert_eq!(
+            parse_args(&["42"]),
+            Err(ArgsError::Unknown("42".to_string()))
+        );
+    }
+
+    #[test]
+    fn missing_value() {
+        assert_eq!(
+            parse_args(&["--host"]),
+            Err(ArgsError::MissingValue("--host"))
+        );
+    }
+
+    #[test]
+    fn invalid_values() {
+        assert_eq!(
+            parse_args(&["--pid", "-1"]),
+            Err(ArgsError::InvalidValue {
+                flag: "--pid",
+                value: "-1".to_string(),
+            })
+        );
+        assert_eq!(
+            parse_args(&["--format", "xml"]),
+            Err(ArgsError::InvalidValue {
+                flag: "--format",
+                value: "xml".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn snapshot_conflicts_with_collecting() {
+        for flag in ["--pid", "--host", "--dll", "--record"] {
+            let value = if flag == "--dll" || flag == "--record" {
+                "x"
+            } else {
+                "1"
+            };
+            assert_eq!(
+                parse_args(&["--snapshot", "s.json", flag, value]),
+                Err(ArgsError::Conflict(flag))
+            );
+            // 顺序不影响
+            assert_eq!(
+                parse_args(&[flag, value, "--snapshot=s.json"]),
+                Err(ArgsError::Conflict(flag))
+            );
+        }
+    }
+
+    #[test]
+    fn snapshot_with_output_flags() {
+        let args = run(&[
+            "--snapshot",
+            "s.json",
+            "--out",
+            "o.json",
+            "--format",
+            "json",
+        ]);
+        assert_eq!(args.snapshot, Some("s.json".into()));
+        assert_eq!(args.out, Some("o.json".into()));
+        assert_eq!(args.format, Format::Json);
+    }
+
+    #[test]
+    fn error_messages() {
+        assert_eq!(
+            ArgsError::Conflict("--pid").to_string(),
+            "`--pid` cannot be used together with `--snapshot`"
+        );
+        assert_eq!(
+            ArgsError::MissingValue("--out").to_string(),
+            "`--out` needs a value"
+        );
+    }
+}
@@ -0,0 +1,202 @@
+//! 在这台机器上收集 [`Snapshot`]。和 host 的 `inspect` 一样用 `passthrough_core::win32`
+//! 只读进程和窗口，不注入：sub_dll 只在 host 已经注入过的进程里检查，命令钩子只用 `Ping` 检查。
+//! doctor 没有 host 的命令令牌，钩子回复“令牌不对”就说明它还在，命令本身不会执行。
+
+use std::{fs::File, path::Path, time::Instant};
+
+use passthrough_core::{
+    abi::{COMMAND_HOOK_OK, COMMAND_UNAUTHORIZED},
+    command::Command,
+    dll_cache,
+    inject::Plan,
+    inspect::{self, ProcessEntry, ProcessInfo, SubDllStatus, WindowNode},
+    sha256,
+    win32::{
+        image_path, process_entries, process_modules, top_level_windows, window_pid, window_tree,
+        ProcessHandle, INJECT_ACCESS, INSPECT_ACCESS,
+    },
+};
+use windows::{
+    core::{Error as WindowsError, HSTRING},
+    Win32::{
+        Foundation::{E_FAIL, HWND, LPARAM, WPARAM},
+        UI::WindowsAndMessaging::{RegisterWindowMessageW, SendMessageTimeoutW, SMTO_ABORTIFHUNG},
+    },
+};
+
+use crate::{
+    args::Args,
+    snapshot::{Ping, ProcessProbe, Snapshot},
+};
+
+const PING_TIMEOUT_MS: u32 = 1000;
+
+unsafe fn ping(message: u32, hwnd: u64) -> Ping {
+    let command = Command::Ping;
+    let (wparam, lparam) = command.encode(0);
+    let mut result = 0;
+    let started = Instant::now();
+    let sent = SendMessageTimeoutW(
+        HWND(hwnd as _),
+        message,
+        WPARAM(wparam),
+        LPARAM(lparam),
+        SMTO_ABORTIFHUNG,
+        PING_TIMEOUT_MS,
+        Some(&mut result),
+    );
+    let elapsed = started.elapsed();
+    let reply = if sent.0 == 0 {
+        Err(WindowsError::from_win32())
+    } else {
+        command
+            .parse_reply(result as isize)
+            .map_err(|err| WindowsError::new(E_FAIL, err.to_string()))
+            .and_then(|reply| match reply as u32 {
+                COMMAND_UNAUTHORIZED | COMMAND_HOOK_OK => Ok(()),
+                code => Err(WindowsError::new(
+                    E_FAIL,
+                    format!("ping failed with code {}", code),
+                )),
+            })
+    };
+    match reply {
+        Ok(()) => Ping {
+            hwnd,
+            round_trip_us: Some(elapsed.as_micros() as u64),
+            error: None,
+        },
+        Err(err) => Ping {
+            hwnd,
+            round_trip_us: None,
+            error: Some(err.to_string()),
+        },
+    }
+}
+
+fn file_sha256(path: &str) -> Option<String> {
+    let file = File::open(path).ok()?;
+    let digest = sha256::digest_reader(file).ok()?;
+    Some(sha256::to_hex(&digest))
+}
+
+/// 要检查的进程：`--pid` 指定的，`--host` 的 WebView2 子进程，或者所有 WebView2 进程
+fn targets<'a>(args: &Args, entries: &'a [ProcessEntry]) -> Vec<&'a ProcessEntry> {
+    if !args.pids.is_empty() {
+        return entries
+            .iter()
+            .filter(|entry| args.pids.contains(&entry.pid))
+            .collect();
+    }
+    match args.host {
+        Some(host) => inspect::webview2_processes(entries, host),
+        None => entries
+            .iter()
+            .filter(|entry| entry.name.eq_ignore_ascii_case(inspect::WEBVIEW2_PROCESS))
+            .collect(),
+    }
+}
+
+unsafe fn probe(
+    entry: &ProcessEntry,
+    windows: Vec<WindowNode>,
+    message: u32,
+    dll: Option<&Path>,
+) -> ProcessProbe {
+    let pid = entry.pid;
+    let mut info = ProcessInfo {
+        pid,
+        parent_pid: entry.parent_pid,
+        name: entry.name.clone(),
+        windows,
+        ..Default::default()
+    };
+    match ProcessHandle::open(INSPECT_ACCESS, pid) {
+        Ok(process) => {
+            info.path = image_path(process.handle()).ok();
+            match process_modules(process.handle()) {
+                Ok(modules) => info.modules = modules,
+                Err(err) => info.error = Some(err.to_string()),
+            }
+        }
+        Err(err) => info.error = Some(err.to_string()),
+    }
+    let inject_access_error = ProcessHandle::open(INJECT_ACCESS, pid)
+        .err()
+        .map(|err| err.to_string());
+
+    let mut probe = ProcessProbe {
+        inject_access_error,
+        ..Default::default()
+    };
+    if let Some(module) = inspect::find_module(&info.modules, dll_cache::DLL_NAME).cloned() {
+        let mut subclassed_windows = Vec::new();
+        for window in &mut info.windows {
+            window.mark_subclassed(pid, &module);
+            subclassed_windows.extend(
+                window
+                    .walk()
+                    .into_iter()
+                    .filter(|node| node.subclassed)
+                    .map(|node| node.hwnd),
+            );
+        }
+        probe.sub_dll_sha256 = file_sha256(&module.path);
+        probe.sub_dll_expected_sha256 = dll_cache::recorded_sha256(Path::new(&module.path));
+        probe.pings = subclassed_windows
+            .iter()
+            .map(|&hwnd| ping(message, hwnd))
+            .collect();
+        info.sub_dll = Some(SubDllStatus {
+            module,
+            // 握手的结果只有 host 知道
+            abi: None,
+            subclassed_windows,
+        });
+    }
+    let dll_path = match dll {
+        Some(dll) => Some(dll.to_string_lossy().into_owned()),
+        None => info
+            .sub_dll
+            .as_ref()
+            .map(|status| status.module.path.clone()),
+    };
+    probe.dry_run = dll_path.map(|path| {
+        let path: Vec<u16> = path.encode_utf16().collect();
+        Plan::load_library(&path, dll_cache::DLL_NAME).to_string()
+    });
+    probe.process = info;
+    probe
+}
+
+pub fn collect(args: &Args) -> Result<Snapshot, WindowsError> {
+    unsafe {
+        let entries = process_entries()?;
+        let targets = targets(args, &entries);
+        // WebView2 的窗口嵌在应用的窗口里，从目标进程和它们的父进程的顶层窗口往下找
+        let mut owners: Vec<u32> = targets.iter().map(|entry| entry.pid).collect();
+        owners.extend(targets.iter().map(|entry| entry.parent_pid));
+        owners.extend(args.host);
+        let roots: Vec<WindowNode> = top_level_windows()
+            .into_iter()
+            .filter(|&hwnd| owners.contains(&window_pid(hwnd)))
+            .map(|hwnd| window_tree(hwnd))
+            .collect();
+        let mut windows = inspect::split_by_process(&roots);
+
+        let message =
+            RegisterWindowMessageW(&HSTRING::from(passthrough_core::command::MESSAGE_NAME));
+        let processes = targets
+            .into_iter()
+            .map(|entry| {
+                let trees = windows.remove(&entry.pid).unwrap_or_default();
+                probe(entry, trees, message, args.dll.as_deref())
+            })
+            .collect();
+        Ok(Snapshot {
+            doctor_version: env!("CARGO_PKG_VERSION").to_string(),
+            host_pid: args.host,
+            processes,
+        })
+    }
+}
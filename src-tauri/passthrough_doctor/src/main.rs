@@ -0,0 +1,112 @@
+mod args;
+#[cfg(windows)]
+mod collect;
+mod report;
+mod snapshot;
+
+use std::{error::Error, fs, path::Path, process::ExitCode};
+
+use args::{Args, Command, Format, USAGE};
+use report::{Level, Report};
+use snapshot::Snapshot;
+
+fn write_json(path: &Path, value: &impl serde::Serialize) -> Result<(), Box<dyn Error>> {
+    let json = serde_json::to_string_pretty(value)?;
+    fs::write(path, json + "\n").map_err(|err| format!("{}: {}", path.display(), err))?;
+    Ok(())
+}
+
+#[cfg(windows)]
+fn collect(args: &Args) -> Result<Snapshot, Box<dyn Error>> {
+    Ok(collect::collect(args)?)
+}
+
+#[cfg(not(windows))]
+fn collect(_args: &Args) -> Result<Snapshot, Box<dyn Error>> {
+    Err("collecting needs Windows, use `--snapshot` with data recorded by `--record`".into())
+}
+
+fn run(args: Args) -> Result<Level, Box<dyn Error>> {
+    let snapshot = match &args.snapshot {
+        Some(path) => {
+            let json =
+                fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+            serde_json::from_str::<Snapshot>(&json)
+                .map_err(|err| format!("{}: {}", path.display(), err))?
+        }
+        None => collect(&args)?,
+    };
+    if let Some(path) = &args.record {
+        write_json(path, &snapshot)?;
+    }
+    let report = Report::from_snapshot(&snapshot);
+    if let Some(path) = &args.out {
+        write_json(path, &report)?;
+    }
+    match args.format {
+        Format::Text => print!("{}", report),
+        Format::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+    }
+    Ok(report.worst())
+}
+
+/// 警告不算失败，有错误时返回 1，方便脚本判断
+fn exit_status(level: Level) -> u8 {
+    match level {
+        Level::Error => 1,
+        Level::Ok | Level::Warning => 0,
+    }
+}
+
+fn main() -> ExitCode {
+    let args = match args::parse(std::env::args().skip(1)) {
+        Ok(Command::Run(args)) => args,
+        Ok(Command::Help) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    match run(args) {
+        Ok(level) => ExitCode::from(exit_status(level)),
+        Err(err) => {
+            eprintln!("passthrough-doctor: {}", err);
+            ExitCode::from(2)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_snapshot_exits_with_1() {
+        let args = Args {
+            snapshot: Some(concat!(env!("CARGO_MANIFEST_DIR"), "/snapshots/example.json").into()),
+            ..Args::default()
+        };
+        assert_eq!(exit_status(run(args).unwrap()), 1);
+    }
+
+    #[test]
+    fn warnings_are_not_failures() {
+        assert_eq!(exit_status(Level::Ok), 0);
+        assert_eq!(exit_status(Level::Warning), 0);
+    }
+
+    #[test]
+    fn missing_snapshot_is_an_error() {
+        let args = Args {
+            snapshot: Some("does-not-exist.json".into()),
+            ..Args::default()
+        };
+        assert!(run(args)
+            .unwrap_err()
+            .to_string()
+            .starts_with("does-not-exist.json: "));
+    }
+}
@@ -0,0 +1,532 @@
+use std::fmt;
+
+use passthrough_core::{
+    abi::ABI_VERSION,
+    dll_cache,
+    inspect::{self, WindowNode},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::snapshot::{ProcessProbe, Snapshot};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Level {
+    Ok,
+    Warning,
+    Error,
+}
+
+impl Level {
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Ok => "ok",
+            Level::Warning => "warning",
+            Level::Error => "error",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Finding {
+    pub level: Level,
+    pub pid: Option<u32>,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubDllReport {
+    pub path: String,
+    /// 从缓存目录认出来的版本，不在缓存目录里时是 `None`
+    pub version: Option<String>,
+    pub abi: Option<u16>,
+    pub sha256: Option<String>,
+    /// 应用解压时记下的 SHA-256，和 `sha256` 不一样说明文件被换过
+    pub expected_sha256: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessReport {
+    pub pid: u32,
+    pub name: String,
+    pub path: Option<String>,
+    pub sub_dll: Option<SubDllReport>,
+    pub subclassed_windows: Vec<u64>,
+    /// 命令钩子回复了 `Ping` 的窗口
+    pub command_hook_windows: Vec<u64>,
+    pub injectable: bool,
+    pub windows: Vec<WindowNode>,
+    pub dry_run: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Report {
+    pub doctor_version: String,
+    pub expected_abi: u16,
+    pub host_pid: Option<u32>,
+    pub processes: Vec<ProcessReport>,
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    pub fn from_snapshot(snapshot: &Snapshot) -> Self {
+        let mut findings = Vec::new();
+        if snapshot.processes.is_empty() {
+            findings.push(Finding {
+                level: Level::Warning,
+                pid: None,
+                message: "no WebView2 process found".to_string(),
+            });
+        }
+        let processes = snapshot
+            .processes
+            .iter()
+            .map(|probe| process_report(probe, &mut findings))
+            .collect();
+        Self {
+            doctor_version: snapshot.doctor_version.clone(),
+            expected_abi: ABI_VERSION,
+            host_pid: snapshot.host_pid,
+            processes,
+            findings,
+        }
+    }
+
+    pub fn worst(&self) -> Level {
+        self.findings
+            .iter()
+            .map(|finding| finding.level)
+            .max()
+            .unwrap_or(Level::Ok)
+    }
+}
+
+fn process_report(probe: &ProcessProbe, findings: &mut Vec<Finding>) -> ProcessReport {
+    let process = &probe.process;
+    let mut finding = |level, message: String| {
+        findings.push(Finding {
+            level,
+            pid: Some(process.pid),
+            message,
+        })
+    };
+    if let Some(err) = &process.error {
+        finding(Level::Error, format!("cannot read the process: {}", err));
+    }
+    if let Some(err) = &probe.inject_access_error {
+        finding(
+            Level::Error,
+            format!("cannot be opened for injection: {}", err),
+        );
+    }
+
+    let module = process
+        .sub_dll
+        .as_ref()
+        .map(|status| &status.module)
+        .or_else(|| inspect::find_module(&process.modules, dll_cache::DLL_NAME));
+    let sub_dll = module.map(|module| {
+        let version = dll_cache::version_of(&module.path).map(String::from);
+        let abi = version.as_deref().and_then(dll_cache::abi_of_version);
+        SubDllReport {
+            path: module.path.clone(),
+            version,
+            abi,
+            sha256: probe.sub_dll_sha256.clone(),
+            expected_sha256: probe.sub_dll_expected_sha256.clone(),
+        }
+    });
+    let subclassed_windows = process
+        .sub_dll
+        .as_ref()
+        .map(|status| status.subclassed_windows.clone())
+        .unwrap_or_default();
+    let command_hook_windows: Vec<u64> = probe
+        .pings
+        .iter()
+        .filter(|ping| ping.round_trip_us.is_some())
+        .map(|ping| ping.hwnd)
+        .collect();
+
+    match &sub_dll {
+        None => finding(Level::Warning, "sub_dll is not loaded".to_string()),
+        Some(SubDllReport {
+            abi: None, path, ..
+        }) => finding(
+            Level::Warning,
+            format!("sub_dll at {} is not from the app's cache directory", path),
+        ),
+        Some(SubDllReport {
+            abi: Some(abi),
+            version,
+            ..
+        }) if *abi != ABI_VERSION => finding(
+            Level::Error,
+            format!(
+                "sub_dll {} uses ABI {}, this build expects ABI {}",
+                version.as_deref().unwrap_or_default(),
+                abi,
+                ABI_VERSION
+            ),
+        ),
+        Some(_) => {}
+    }
+    match &sub_dll {
+        Some(SubDllReport {
+            sha256: Some(found),
+            expected_sha256: Some(expected),
+            ..
+        }) if !found.eq_ignore_ascii_case(expected) => finding(
+            Level::Error,
+            format!(
+                "sub_dll sha256 is {}, the app extracted {}",
+                found, expected
+            ),
+        ),
+        Some(SubDllReport {
+            abi: Some(_),
+            expected_sha256: None,
+            ..
+        }) => finding(
+            Level::Warning,
+            "the app did not record the sub_dll sha256, the file cannot be checked".to_string(),
+        ),
+        _ => {}
+    }
+    if sub_dll.is_some() {
+        if subclassed_windows.is_empty() {
+            finding(
+                Level::Warning,
+                "sub_dll is loaded but no window is subclassed".to_string(),
+            );
+        } else if command_hook_windows.is_empty() {
+            finding(
+                Level::Warning,
+                "the command hook does not reply, the app falls back to remote threads".to_string(),
+            );
+        } else {
+            finding(
+                Level::Ok,
+                format!(
+                    "{} window(s) subclassed, the command hook replies",
+                    subclassed_windows.len()
+                ),
+            );
+        }
+    }
+
+    ProcessReport {
+        pid: process.pid,
+        name: process.name.clone(),
+        path: process.path.clone(),
+        sub_dll,
+        subclassed_windows,
+        command_hook_windows,
+        injectable: probe.inject_access_error.is_none() && process.error.is_none(),
+        windows: process.windows.clone(),
+        dry_run: probe.dry_run.clone(),
+    }
+}
+
+fn write_window(f: &mut fmt::Formatter<'_>, node: &WindowNode, depth: usize) -> fmt::Result {
+    write!(
+        f,
+        "{:indent$}{:#010x} pid {} {}",
+        "",
+        node.hwnd,
+        node.pid,
+        node.class_name,
+        indent = 4 + depth * 2
+    )?;
+    if !node.title.is_empty() {
+        write!(f, " {:?}", node.title)?;
+    }
+    if !node.visible {
+        write!(f, " (hidden)")?;
+    }
+    if node.subclassed {
+        write!(f, " (subclassed)")?;
+    }
+    writeln!(f)?;
+    for child in &node.children {
+        write_window(f, child, depth + 1)?;
+    }
+    Ok(())
+}
+
+fn hwnds(windows: &[u64]) -> String {
+    if windows.is_empty() {
+        return "none".to_string();
+    }
+    let hwnds: Vec<String> = windows.iter().map(|hwnd| format!("{:#x}", hwnd)).collect();
+    hwnds.join(", ")
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "passthrough-doctor {} (sub_dll ABI {})",
+            self.doctor_version, self.expected_abi
+        )?;
+        if let Some(pid) = self.host_pid {
+            writeln!(f, "host process {}", pid)?;
+        }
+        for process in &self.processes {
+            writeln!(f)?;
+            writeln!(f, "process {} {}", process.pid, process.name)?;
+            if let Some(path) = &process.path {
+                writeln!(f, "  path: {}", path)?;
+            }
+            match &process.sub_dll {
+                Some(sub_dll) => {
+                    writeln!(f, "  sub_dll: {}", sub_dll.path)?;
+                    if let Some(version) = &sub_dll.version {
+                        writeln!(f, "    version: {}", version)?;
+                    }
+                    if let Some(sha256) = &sub_dll.sha256 {
+                        writeln!(f, "    sha256: {}", sha256)?;
+                    }
+                    if let Some(expected) = &sub_dll.expected_sha256 {
+                        writeln!(f, "    expected sha256: {}", expected)?;
+                    }
+                }
+                None => writeln!(f, "  sub_dll: not loaded")?,
+            }
+            writeln!(
+                f,
+                "  subclassed windows: {}",
+                hwnds(&process.subclassed_windows)
+            )?;
+            writeln!(
+                f,
+                "  command hook replies from: {}",
+                hwnds(&process.command_hook_windows)
+            )?;
+            writeln!(
+                f,
+                "  injection: {}",
+                if process.injectable {
+                    "allowed"
+                } else {
+                    "denied"
+                }
+            )?;
+            writeln!(f, "  windows:")?;
+            for window in &process.windows {
+                write_window(f, window, 0)?;
+            }
+            if let Some(plan) = &process.dry_run {
+                writeln!(f, "  dry-run injection plan:")?;
+                for line in plan.lines() {
+                    writeln!(f, "    {}", line)?;
+                }
+            }
+        }
+        writeln!(f)?;
+        writeln!(f, "findings:")?;
+        for finding in &self.findings {
+            write!(f, "  [{}] ", finding.level.name())?;
+            if let Some(pid) = finding.pid {
+                write!(f, "process {}: ", pid)?;
+            }
+            writeln!(f, "{}", finding.message)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> Snapshot {
+        serde_json::from_str(include_str!("../snapshots/example.json")).unwrap()
+    }
+
+    fn findings(report: &Report) -> Vec<(Level, Option<u32>)> {
+        report
+            .findings
+            .iter()
+            .map(|finding| (finding.level, finding.pid))
+            .collect()
+    }
+
+    #[test]
+    fn example_findings() {
+        let report = Report::from_snapshot(&example());
+        assert_eq!(
+            findings(&report),
+            vec![
+                (Level::Ok, Some(9344)),
+                (Level::Error, Some(10212)),
+                (Level::Error, Some(10212)),
+                (Level::Warning, Some(10212)),
+            ]
+        );
+        assert_eq!(report.worst(), Level::Error);
+        assert_eq!(report.host_pid, Some(8120));
+        assert_eq!(report.expected_abi, ABI_VERSION);
+    }
+
+    #[test]
+    fn example_processes() {
+        let report = Report::from_snapshot(&example());
+        let [ok, denied] = report.processes.as_slice() else {
+            panic!("{:?}", report.processes);
+        };
+        let sub_dll = ok.sub_dll.as_ref().unwrap();
+        assert_eq!(sub_dll.version.as_deref(), Some("0.1.0-abi3"));
+        assert_eq!(sub_dll.abi, Some(3));
+        assert_eq!(ok.subclassed_windows, vec![264004]);
+        assert_eq!(ok.command_hook_windows, vec![264004]);
+        assert!(ok.injectable);
+
+        assert_eq!(denied.sub_dll, None);
+        assert!(!denied.injectable);
+    }
+
+    #[test]
+    fn example_text() {
+        let text = Report::from_snapshot(&example()).to_string();
+        for line in [
+            "passthrough-doctor 0.1.0 (sub_dll ABI 3)",
+            "host process 8120",
+            "process 9344 msedgewebview2.exe",
+            "    version: 0.1.0-abi3",
+            "  subclassed windows: 0x40744",
+            "  command hook replies from: 0x40744",
+            "  injection: allowed",
+            "      0x00040744 pid 9344 Chrome_RenderWidgetHostHWND \"Chrome Legacy Window\" (subclassed)",
+            "    1. alloc buffer #0 (146 bytes, ReadWrite)",
+            "    3. resolve kernel32.dll!LoadLibraryW",
+            "  subclassed windows: none",
+            "  injection: denied",
+            "  [ok] process 9344: 1 window(s) subclassed, the command hook replies",
+            "  [error] process 10212: cannot read the process: Access is denied. (0x80070005)",
+            "  [warning] process 10212: sub_dll is not loaded",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {:?} in\n{}", line, text);
+        }
+        assert!(text.ends_with("[warning] process 10212: sub_dll is not loaded\n"));
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let report = Report::from_snapshot(&example());
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(serde_json::from_str::<Report>(&json).unwrap(), report);
+    }
+
+    #[test]
+    fn no_process_is_a_warning() {
+        let report = Report::from_snapshot(&Snapshot::default());
+        assert_eq!(findings(&report), vec![(Level::Warning, None)]);
+        assert_eq!(report.worst(), Level::Warning);
+        assert!(report
+            .to_string()
+            .ends_with("findings:\n  [warning] no WebView2 process found\n"));
+    }
+
+    #[test]
+    fn abi_mismatch_is_an_error() {
+        let mut snapshot = example();
+        snapshot.processes.truncate(1);
+        let status = snapshot.processes[0].process.sub_dll.as_mut().unwrap();
+        status.module.path = status.module.path.replace("abi3", "abi1");
+        let report = Report::from_snapshot(&snapshot);
+        assert_eq!(report.findings[0].level, Level::Error);
+        assert_eq!(
+            report.findings[0].message,
+            format!(
+                "sub_dll 0.1.0-abi1 uses ABI 1, this build expects ABI {}",
+                ABI_VERSION
+            )
+        );
+    }
+
+    #[test]
+    fn sub_dll_outside_the_cache_is_a_warning() {
+        let mut snapshot = example();
+        snapshot.processes.truncate(1);
+        let status = snapshot.processes[0].process.sub_dll.as_mut().unwrap();
+        status.module.path = r"C:\Temp\sub_dll.dll".to_string();
+        let report = Report::from_snapshot(&snapshot);
+        assert_eq!(
+            findings(&report),
+            vec![(Level::Warning, Some(9344)), (Level::Ok, Some(9344))]
+        );
+        assert_eq!(report.worst(), Level::Warning);
+    }
+
+    #[test]
+    fn replaced_sub_dll_is_an_error() {
+        let mut snapshot = example();
+        snapshot.processes.truncate(1);
+        snapshot.processes[0].sub_dll_expected_sha256 = Some("0".repeat(64));
+        let found = snapshot.processes[0].sub_dll_sha256.clone().unwrap();
+        let report = Report::from_snapshot(&snapshot);
+        assert_eq!(
+            findings(&report),
+            vec![(Level::Error, Some(9344)), (Level::Ok, Some(9344))]
+        );
+        assert_eq!(
+            report.findings[0].message,
+            format!(
+                "sub_dll sha256 is {}, the app extracted {}",
+                found,
+                "0".repeat(64)
+            )
+        );
+        // 大小写不同不算被换过
+        snapshot.processes[0].sub_dll_expected_sha256 = Some(found.to_uppercase());
+        let report = Report::from_snapshot(&snapshot);
+        assert_eq!(findings(&report), vec![(Level::Ok, Some(9344))]);
+    }
+
+    #[test]
+    fn missing_sha256_record_is_a_warning() {
+        let mut snapshot = example();
+        snapshot.processes.truncate(1);
+        snapshot.processes[0].sub_dll_expected_sha256 = None;
+        let report = Report::from_snapshot(&snapshot);
+        assert_eq!(
+            findings(&report),
+            vec![(Level::Warning, Some(9344)), (Level::Ok, Some(9344))]
+        );
+        assert_eq!(
+            report.findings[0].message,
+            "the app did not record the sub_dll sha256, the file cannot be checked"
+        );
+    }
+
+    #[test]
+    fn silent_command_hook_is_a_warning() {
+        let mut snapshot = example();
+        snapshot.processes.truncate(1);
+        snapshot.processes[0].pings[0].round_trip_us = None;
+        let report = Report::from_snapshot(&snapshot);
+        assert!(report.processes[0].command_hook_windows.is_empty());
+        assert_eq!(
+            report.findings[0].message,
+            "the command hook does not reply, the app falls back to remote threads"
+        );
+    }
+
+    #[test]
+    fn module_list_is_used_without_sub_dll_status() {
+        let mut snapshot = example();
+        snapshot.processes.truncate(1);
+        snapshot.processes[0].process.sub_dll = None;
+        let report = Report::from_snapshot(&snapshot);
+        assert!(report.processes[0].sub_dll.is_some());
+        assert_eq!(
+            report.findings[0].message,
+            "sub_dll is loaded but no window is subclassed"
+        );
+    }
+}
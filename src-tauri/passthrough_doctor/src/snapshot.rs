@@ -0,0 +1,39 @@
+use passthrough_core::inspect::ProcessInfo;
+use serde::{Deserialize, Serialize};
+
+/// 在用户机器上收集到的原始数据，报告只从这里生成。
+/// `--record` 把它存成 JSON，`--snapshot` 在任何平台上读回来重新生成报告
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Snapshot {
+    pub doctor_version: String,
+    /// `--host` 指定的应用进程
+    pub host_pid: Option<u32>,
+    pub processes: Vec<ProcessProbe>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessProbe {
+    /// 模块和窗口树，`windows` 里包括嵌在应用窗口里的子窗口
+    pub process: ProcessInfo,
+    /// 用注入需要的权限打开进程失败的原因，能打开时是 `None`
+    pub inject_access_error: Option<String>,
+    /// 加载着的 sub_dll 文件的 SHA-256
+    pub sub_dll_sha256: Option<String>,
+    /// 应用解压 sub_dll 时在旁边记下的 SHA-256，见 `dll_cache::sha256_path`
+    pub sub_dll_expected_sha256: Option<String>,
+    /// 给子类化的窗口发 `Ping` 命令的结果
+    pub pings: Vec<Ping>,
+    /// 干跑的注入计划，没有可以注入的 DLL 时是 `None`
+    pub dry_run: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Ping {
+    pub hwnd: u64,
+    /// 收到回复时的往返时间
+    pub round_trip_us: Option<u64>,
+    pub error: Option<String>,
+}
@@ -0,0 +1,197 @@
+use std::{collections::HashMap, sync::LazyLock, time::Duration};
+
+use crossbeam::channel::{Receiver, RecvTimeoutError, Sender};
+use passthrough_core::{
+    clock::SystemClock,
+    release::{Condition, ReleaseCause, ReleaseTracker},
+};
+use serde::{Deserialize, Serialize};
+use tauri::Emitter;
+use windows::Win32::UI::WindowsAndMessaging::GetForegroundWindow;
+
+use crate::{
+    error::{Error, Result},
+    keyboard_event::{KeyChord, KEY_BINDINGS},
+    passthrough::{ChangeReason, PassthroughRequest, PassthroughState, PASSTHROUGH},
+    set_passthrough,
+};
+
+pub const RELEASED_EVENT: &str = "passthrough-released";
+
+/// 等待前台窗口变化时的轮询间隔
+const FOREGROUND_POLL: Duration = Duration::from_millis(100);
+/// 没有任何到期时间时的最长等待
+const IDLE_WAIT: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ReleaseCondition {
+    Duration { ms: u64 },
+    Focus,
+    ForegroundChange,
+    KeyChord { chord: KeyChord },
+}
+
+impl ReleaseCondition {
+    fn condition(&self) -> Condition {
+        match self {
+            ReleaseCondition::Duration { ms } => Condition::After(Duration::from_millis(*ms)),
+            ReleaseCondition::Focus => Condition::Focus,
+            ReleaseCondition::ForegroundChange => Condition::ForegroundChange,
+            ReleaseCondition::KeyChord { .. } => Condition::KeyChord,
+        }
+    }
+}
+
+fn cause_name(cause: ReleaseCause) -> &'static str {
+    match cause {
+        ReleaseCause::Timeout => "timeout",
+        ReleaseCause::Focus => "focus",
+        ReleaseCause::ForegroundChange => "foregroundChange",
+        ReleaseCause::KeyChord => "keyChord",
+    }
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Released<'a> {
+    label: &'a str,
+    cause: &'static str,
+}
+
+enum Message {
+    Arm {
+        window: tauri::Window,
+        previous: PassthroughRequest,
+        conditions: Vec<Condition>,
+    },
+    Cancel(String),
+    Focused(String),
+    KeyChord(String),
+}
+
+fn foreground_window() -> usize {
+    unsafe { GetForegroundWindow().0 as usize }
+}
+
+fn chord_binding(label: &str) -> String {
+    format!("{}:until:", label)
+}
+
+fn run(rx: Receiver<Message>) {
+    let mut tracker = ReleaseTracker::new(SystemClock);
+    // label -> (窗口, 恢复成的状态)
+    let mut armed: HashMap<String, (tauri::Window, PassthroughRequest)> = HashMap::new();
+    loop {
+        let mut timeout = tracker.next_timeout().unwrap_or(IDLE_WAIT);
+        if tracker.watches_foreground() {
+            timeout = timeout.min(FOREGROUND_POLL);
+        }
+
+        let mut released = Vec::new();
+        match rx.recv_timeout(timeout) {
+            Ok(Message::Arm {
+                window,
+                previous,
+                conditions,
+            }) => {
+                let label = window.label().to_string();
+                tracker.arm(label.clone(), &conditions, foreground_window());
+                // 重复设置时保留最早的状态
+                armed.entry(label).or_insert((window, previous));
+            }
+            Ok(Message::Cancel(label)) => {
+                tracker.cancel(&label);
+                armed.remove(&label);
+                KEY_BINDINGS.unbind_prefix(&chord_binding(&label));
+            }
+            Ok(Message::Focused(label)) => {
+                if let Some(cause) = tracker.focused(&label) {
+                    released.push((label, cause));
+                }
+            }
+            Ok(Message::KeyChord(label)) => {
+                if let Some(cause) = tracker.key_chord(&label) {
+                    released.push((label, cause));
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        released.extend(tracker.poll());
+        if tracker.watches_foreground() {
+            released.extend(tracker.observe_foreground(foreground_window()));
+        }
+
+        for (label, cause) in released {
+            KEY_BINDINGS.unbind_prefix(&chord_binding(&label));
+            if let Some((window, previous)) = armed.remove(&label) {
+                set_passthrough(&window, &previous, ChangeReason::Released);
+                let payload = Released {
+                    label: &label,
+                    cause: cause_name(cause),
+                };
+                if let Err(err) = window.emit(RELEASED_EVENT, payload) {
+                    tracing::error!("emit {} error: {}", RELEASED_EVENT, err);
+                }
+            }
+        }
+    }
+}
+
+static AUTO_RESTORE_TX: LazyLock<Sender<Message>> = LazyLock::new(|| {
+    let (tx, rx) = crossbeam::channel::unbounded();
+    std::thread::spawn(move || run(rx));
+    tx
+});
+
+fn send(message: Message) {
+    let _ = AUTO_RESTORE_TX.send(message);
+}
+
+pub fn cancel(label: &str) {
+    send(Message::Cancel(label.to_string()));
+}
+
+pub fn focused(label: &str) {
+    send(Message::Focused(label.to_string()));
+}
+
+/// 暂时穿透，满足 `until` 中任意一个条件后恢复成调用前的状态
+#[tauri::command]
+pub async fn ignore_mouse_events_until(
+    window: tauri::Window,
+    forward: Option<bool>,
+    until: Vec<ReleaseCondition>,
+) -> Result<PassthroughState> {
+    if until.is_empty() {
+        return Err(Error::InvalidArgument(
+            "`until` needs at least one release condition".to_string(),
+        ));
+    }
+    let label = window.label().to_string();
+    let previous = PASSTHROUGH
+        .get(&label)
+        .map(|state| state.request())
+        .unwrap_or_default();
+
+    for (i, condition) in until.iter().enumerate() {
+        if let ReleaseCondition::KeyChord { chord } = condition {
+            let label = label.clone();
+            let id = format!("{}{}", chord_binding(&label), i);
+            KEY_BINDINGS.bind(id, *chord, move || {
+                send(Message::KeyChord(label.clone()));
+            });
+        }
+    }
+
+    // 和 `ignore_mouse_events` 一样只改穿透和转发，恢复时区域和转发方式都还在
+    let request = previous.with_passthrough(true, forward.unwrap_or(false));
+    let state = set_passthrough(&window, &request, ChangeReason::Timed);
+    send(Message::Arm {
+        window,
+        previous,
+        conditions: until.iter().map(ReleaseCondition::condition).collect(),
+    });
+    Ok(state)
+}
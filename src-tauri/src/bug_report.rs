@@ -0,0 +1,150 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::BufWriter,
+    path::{Path, PathBuf},
+    sync::{Mutex, TryLockError},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use passthrough_core::{
+    bug_report::{
+        self, Manifest, PointerEvent, PointerKind, Recent, DIAGNOSTICS_FILE, LOG_FILE,
+        MANIFEST_FILE, METRICS_FILE, POINTER_EVENTS_FILE, STATE_FILE,
+    },
+    clock::UtcTime,
+    zip::ZipWriter,
+};
+use tauri::{AppHandle, Manager};
+use windows::Win32::{Foundation::POINT, System::Threading::GetCurrentProcessId};
+
+use crate::{error::Error, inspect, logging, passthrough::METRICS};
+
+/// 保留的鼠标事件数，高回报率的鼠标一秒钟上千个事件
+const POINTER_CAPACITY: usize = 60_000;
+const DEFAULT_SECONDS: u64 = 30;
+const MAX_SECONDS: u64 = 300;
+
+static POINTER_EVENTS: Mutex<Recent<PointerEvent>> = Mutex::new(Recent::new(POINTER_CAPACITY));
+
+pub fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// 在鼠标钩子里调用，不能阻塞：正在导出报告时丢掉这个事件
+pub fn record_pointer(kind: PointerKind, pt: POINT, delta: i16) {
+    let mut events = match POINTER_EVENTS.try_lock() {
+        Ok(events) => events,
+        // 拿着锁的线程 panic 过，缓冲本身还是完整的
+        Err(TryLockError::Poisoned(err)) => err.into_inner(),
+        Err(TryLockError::WouldBlock) => return,
+    };
+    let time_ms = unix_ms();
+    events.push(
+        time_ms,
+        PointerEvent {
+            time_ms,
+            kind,
+            x: pt.x,
+            y: pt.y,
+            delta,
+        },
+    );
+}
+
+fn json(value: &impl serde::Serialize) -> std::io::Result<Vec<u8>> {
+    Ok(serde_json::to_vec_pretty(value)?)
+}
+
+fn write_zip(
+    path: &Path,
+    created: UtcTime,
+    files: &[(&'static str, Vec<u8>)],
+) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut zip = ZipWriter::new(BufWriter::new(File::create(path)?), created);
+    for (name, data) in files {
+        zip.add(name, data)?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+/// 导出问题报告：每个窗口的穿透状态、窗口树和注入的进程、最近的日志和鼠标事件。
+/// 默认隐藏其他程序的窗口标题。总是写到应用的日志目录，前端不能指定路径，返回 zip 文件的路径
+#[tauri::command]
+pub async fn export_passthrough_report(
+    app: AppHandle,
+    seconds: Option<u64>,
+    redact_titles: Option<bool>,
+) -> crate::error::Result<PathBuf> {
+    let seconds = seconds.unwrap_or(DEFAULT_SECONDS);
+    if seconds == 0 || seconds > MAX_SECONDS {
+        return Err(Error::InvalidArgument(format!(
+            "`seconds` must be between 1 and {}",
+            MAX_SECONDS
+        )));
+    }
+    let now_ms = unix_ms();
+    let path = app
+        .path()
+        .app_log_dir()?
+        .join(format!("passthrough-report-{}.zip", now_ms / 1000));
+    let since_ms = now_ms.saturating_sub(seconds * 1000);
+
+    let states: BTreeMap<_, _> = app
+        .webview_windows()
+        .into_keys()
+        .filter_map(|label| {
+            let state = crate::get_passthrough_state(app.clone(), label.clone())?;
+            Some((label, state))
+        })
+        .collect();
+    let mut files = vec![
+        (STATE_FILE, json(&states)?),
+        (METRICS_FILE, json(&METRICS.snapshot())?),
+    ];
+    let mut errors = Vec::new();
+    let mut redacted = None;
+    // 读不到窗口树时其他部分照样导出
+    match unsafe { inspect::collect(&app) } {
+        Ok(mut diagnostics) => {
+            if redact_titles.unwrap_or(true) {
+                redacted = Some(bug_report::redact_titles(&mut diagnostics));
+            }
+            files.push((DIAGNOSTICS_FILE, json(&diagnostics)?));
+        }
+        Err(err) => errors.push(format!("{}: {}", DIAGNOSTICS_FILE, err)),
+    }
+    let log = logging::recent(since_ms);
+    files.push((LOG_FILE, bug_report::log_text(&log).into_bytes()));
+    // 鼠标钩子拿不到锁就丢事件，拿着锁的时候只整块拷贝，过滤和序列化都在放开锁之后
+    let pointer_events = POINTER_EVENTS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .copy_out();
+    files.push((POINTER_EVENTS_FILE, json(&pointer_events.since(since_ms))?));
+
+    let created = UtcTime::from_unix(now_ms / 1000);
+    let manifest = Manifest {
+        app_version: app.package_info().version.to_string(),
+        created: created.to_string(),
+        host_pid: unsafe { GetCurrentProcessId() },
+        seconds,
+        redacted_titles: redacted,
+        errors,
+        files: files.iter().map(|(name, _)| name.to_string()).collect(),
+    };
+    files.insert(0, (MANIFEST_FILE, json(&manifest)?));
+
+    // 报告可能有几 MB，不能在异步运行时的线程上写
+    let written = path.clone();
+    tauri::async_runtime::spawn_blocking(move || write_zip(&written, created, &files)).await??;
+    tracing::info!("passthrough report: {}", path.display());
+    Ok(path)
+}
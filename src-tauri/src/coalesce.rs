@@ -0,0 +1,82 @@
+use std::{
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+
+use crossbeam::channel::{Receiver, RecvTimeoutError, Sender};
+use passthrough_core::{
+    clock::SystemClock,
+    coalesce::{Coalescer, Submit},
+};
+
+use crate::{
+    passthrough::{ChangeReason, PassthroughRequest, METRICS},
+    set_passthrough,
+};
+
+pub const DEFAULT_WINDOW_MS: u64 = 50;
+
+/// 没有待生效请求时的最长等待
+const IDLE_WAIT: Duration = Duration::from_secs(60);
+
+type Pending = (tauri::Window, PassthroughRequest);
+
+static COALESCER: LazyLock<Mutex<Coalescer<String, Pending, SystemClock>>> = LazyLock::new(|| {
+    Mutex::new(Coalescer::new(
+        SystemClock,
+        Duration::from_millis(DEFAULT_WINDOW_MS),
+    ))
+});
+
+/// 有新的延迟请求时唤醒后台线程重新计算等待时间
+static WAKE_TX: LazyLock<Sender<()>> = LazyLock::new(|| {
+    let (tx, rx) = crossbeam::channel::unbounded();
+    std::thread::spawn(move || run(rx));
+    tx
+});
+
+fn run(rx: Receiver<()>) {
+    loop {
+        let timeout = COALESCER
+            .lock()
+            .unwrap()
+            .next_timeout()
+            .unwrap_or(IDLE_WAIT);
+        if let Err(RecvTimeoutError::Disconnected) = rx.recv_timeout(timeout) {
+            break;
+        }
+        let due = COALESCER.lock().unwrap().poll();
+        for (_, (window, request)) in due {
+            set_passthrough(&window, &request, ChangeReason::Command);
+        }
+    }
+}
+
+pub fn set_window(window: Duration) {
+    COALESCER.lock().unwrap().set_window(window);
+}
+
+/// 丢弃窗口还没生效的请求，例如切换了 profile 之后
+pub fn discard(label: &str) {
+    if COALESCER.lock().unwrap().discard(&label.to_string()) {
+        METRICS.coalesced();
+    }
+}
+
+/// 合并前端频繁的 `ignore_mouse_events` 调用
+pub fn submit(window: tauri::Window, request: PassthroughRequest) {
+    METRICS.requested();
+    let label = window.label().to_string();
+    let submit = COALESCER.lock().unwrap().submit(label, (window, request));
+    match submit {
+        Submit::Apply((window, request)) => {
+            set_passthrough(&window, &request, ChangeReason::Command);
+        }
+        Submit::Deferred { replaced } => {
+            if replaced {
+                METRICS.coalesced();
+            }
+            let _ = WAKE_TX.send(());
+        }
+    }
+}
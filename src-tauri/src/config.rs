@@ -0,0 +1,294 @@
+use std::{collections::HashMap, time::Duration};
+
+use passthrough_core::{hit_test::Miss, region_table::MAX_REGIONS};
+use serde::Deserialize;
+use tauri::{
+    plugin::{Builder, TauriPlugin},
+    Manager, RunEvent, WindowEvent, Wry,
+};
+
+use crate::{
+    auto_restore, coalesce,
+    error::{Error, Result},
+    hook_sub,
+    keyboard_event::{KeyChord, KEY_BINDINGS},
+    nc_hit_test,
+    passthrough::{
+        ChangeReason, EventMask, ForwardStrategy, HitTestMode, PassthroughRequest,
+        PassthroughState, Region, PASSTHROUGH,
+    },
+    persist::{self, PreferenceStore},
+    set_passthrough,
+};
+
+/// `tauri.conf.json` 中的 `plugins.passthrough`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct PassthroughConfig {
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+    /// 窗口 label -> 启动时使用的 profile
+    #[serde(default)]
+    pub windows: HashMap<String, String>,
+    /// 合并 `ignore_mouse_events` 调用的时间窗口，0 表示不合并
+    #[serde(default = "default_coalesce_ms")]
+    pub coalesce_ms: u64,
+    /// 每个远程操作（注入、子类化、握手）最多等待的时间
+    #[serde(default = "default_remote_timeout_ms")]
+    pub remote_timeout_ms: u64,
+    /// 只打印注入计划，不操作 WebView2 进程，调试用
+    #[serde(default)]
+    pub injection_dry_run: bool,
+    /// `ncHitTest` 转发方式下，可交互区域以外的点怎么回答 `WM_NCHITTEST`
+    #[serde(default)]
+    pub nc_hit_test_miss: HitTestMiss,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum HitTestMiss {
+    /// `HTTRANSPARENT`
+    #[default]
+    Transparent,
+    /// `HTNOWHERE`
+    Nowhere,
+}
+
+impl From<HitTestMiss> for Miss {
+    fn from(miss: HitTestMiss) -> Self {
+        match miss {
+            HitTestMiss::Transparent => Miss::Transparent,
+            HitTestMiss::Nowhere => Miss::Nowhere,
+        }
+    }
+}
+
+fn default_coalesce_ms() -> u64 {
+    coalesce::DEFAULT_WINDOW_MS
+}
+
+fn default_remote_timeout_ms() -> u64 {
+    hook_sub::DEFAULT_REMOTE_TIMEOUT_MS
+}
+
+impl Default for PassthroughConfig {
+    fn default() -> Self {
+        Self {
+            profiles: HashMap::new(),
+            windows: HashMap::new(),
+            coalesce_ms: default_coalesce_ms(),
+            remote_timeout_ms: default_remote_timeout_ms(),
+            injection_dry_run: false,
+            nc_hit_test_miss: HitTestMiss::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Profile {
+    #[serde(default)]
+    pub ignore: bool,
+    #[serde(default)]
+    pub forward: bool,
+    #[serde(default = "default_events")]
+    pub events: EventMask,
+    #[serde(default)]
+    pub regions: Vec<Region>,
+    #[serde(default)]
+    pub hit_test: HitTestMode,
+    #[serde(default)]
+    pub strategy: ForwardStrategy,
+    #[serde(default)]
+    pub hotkeys: Hotkeys,
+}
+
+fn default_events() -> EventMask {
+    EventMask::MOVE
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Hotkeys {
+    /// 在 profile 和完全可交互之间切换
+    pub toggle: Option<KeyChord>,
+    /// 立即恢复可交互
+    pub interactive: Option<KeyChord>,
+}
+
+impl Profile {
+    pub fn request(&self, name: &str) -> PassthroughRequest {
+        PassthroughRequest {
+            ignore: self.ignore,
+            forward: self.forward,
+            event_mask: self.events,
+            regions: self.regions.clone(),
+            hit_test: self.hit_test,
+            strategy: self.strategy,
+            profile: Some(name.to_string()),
+        }
+    }
+
+    fn validate(&self, name: &str) -> std::result::Result<(), String> {
+        let at = format!("plugins.passthrough.profiles.{}", name);
+        for (i, region) in self.regions.iter().enumerate() {
+            let finite = [region.x, region.y, region.width, region.height]
+                .iter()
+                .all(|v| v.is_finite());
+            if !finite || region.width <= 0.0 || region.height <= 0.0 {
+                return Err(format!(
+                    "{}.regions[{}]: width and height must be positive numbers",
+                    at, i
+                ));
+            }
+        }
+        if self.regions.len() > MAX_REGIONS {
+            return Err(format!(
+                "{}: at most {} regions are supported",
+                at, MAX_REGIONS
+            ));
+        }
+        if self.forward && self.events.is_empty() {
+            return Err(format!("{}: `forward` is set but `events` is empty", at));
+        }
+        if self.forward && self.strategy == ForwardStrategy::NcHitTest && self.regions.is_empty() {
+            return Err(format!(
+                "{}: strategy `ncHitTest` requires at least one region",
+                at
+            ));
+        }
+        if self.hit_test == HitTestMode::Regions {
+            if self.regions.is_empty() {
+                return Err(format!(
+                    "{}: hitTest `regions` requires at least one region",
+                    at
+                ));
+            }
+            if !self.forward {
+                return Err(format!(
+                    "{}: hitTest `regions` requires `forward: true` to track the pointer",
+                    at
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PassthroughConfig {
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        if self.remote_timeout_ms == 0 {
+            return Err("plugins.passthrough.remoteTimeoutMs must be greater than 0".to_string());
+        }
+        for (name, profile) in &self.profiles {
+            profile.validate(name)?;
+        }
+        for (label, profile) in &self.windows {
+            if !self.profiles.contains_key(profile) {
+                return Err(format!(
+                    "plugins.passthrough.windows.{}: unknown profile `{}`",
+                    label, profile
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn bind_hotkeys(window: &tauri::Window, profile: &Profile, request: PassthroughRequest) {
+    let prefix = format!("{}:", window.label());
+    KEY_BINDINGS.unbind_prefix(&prefix);
+
+    let interactive = PassthroughRequest {
+        ignore: false,
+        forward: false,
+        event_mask: EventMask::NONE,
+        hit_test: HitTestMode::Frontend,
+        ..request.clone()
+    };
+    if let Some(chord) = profile.hotkeys.toggle {
+        let window = window.clone();
+        let (request, interactive) = (request.clone(), interactive.clone());
+        KEY_BINDINGS.bind(format!("{}toggle", prefix), chord, move || {
+            let ignoring = PASSTHROUGH
+                .get(window.label())
+                .is_some_and(|state| state.ignore);
+            let next = if ignoring { &interactive } else { &request };
+            set_passthrough(&window, next, ChangeReason::Hotkey);
+        });
+    }
+    if let Some(chord) = profile.hotkeys.interactive {
+        let window = window.clone();
+        KEY_BINDINGS.bind(format!("{}interactive", prefix), chord, move || {
+            set_passthrough(&window, &interactive, ChangeReason::Hotkey);
+        });
+    }
+}
+
+pub fn apply_profile(
+    window: &tauri::Window,
+    name: &str,
+    reason: ChangeReason,
+) -> Result<PassthroughState> {
+    let config = window.state::<PassthroughConfig>();
+    let profile = config
+        .profiles
+        .get(name)
+        .ok_or_else(|| Error::UnknownProfile(name.to_string()))?;
+    let request = profile.request(name);
+    // 还没生效的前端请求比切换 profile 更早，不能再覆盖
+    coalesce::discard(window.label());
+    bind_hotkeys(window, profile, request.clone());
+    Ok(set_passthrough(window, &request, reason))
+}
+
+pub fn init() -> TauriPlugin<Wry, Option<PassthroughConfig>> {
+    Builder::<Wry, Option<PassthroughConfig>>::new("passthrough")
+        .setup(|app, api| {
+            let config = api.config().clone().unwrap_or_default();
+            config.validate()?;
+            coalesce::set_window(Duration::from_millis(config.coalesce_ms));
+            hook_sub::set_remote_timeout(Duration::from_millis(config.remote_timeout_ms));
+            hook_sub::set_injection_dry_run(config.injection_dry_run);
+            nc_hit_test::set_miss(config.nc_hit_test_miss.into());
+            app.manage(config);
+            let data_dir = app.path().app_data_dir()?;
+            app.manage(PreferenceStore::load(data_dir.join(persist::FILE_NAME)));
+            // 解压失败时注入会报错，其他功能照常
+            match hook_sub::SUB_CLASS_HWND.install_dll(&app.path().app_cache_dir()?) {
+                Ok(path) => tracing::info!("sub_dll: {}", path.display()),
+                Err(err) => tracing::error!("extract sub_dll error: {}", err),
+            }
+            Ok(())
+        })
+        .on_window_ready(|window| {
+            // 用户保存过的设置优先于配置里的启动 profile
+            if persist::restore(&window) {
+                return;
+            }
+            let profile = window
+                .state::<PassthroughConfig>()
+                .windows
+                .get(window.label())
+                .cloned();
+            if let Some(profile) = profile {
+                if let Err(err) = apply_profile(&window, &profile, ChangeReason::Profile) {
+                    tracing::error!("apply passthrough profile error: {}", err);
+                }
+            }
+        })
+        .on_event(|app, event| match event {
+            RunEvent::WindowEvent {
+                label,
+                event: WindowEvent::Focused(true),
+                ..
+            } => auto_restore::focused(label),
+            RunEvent::Exit => {
+                if let Some(store) = app.try_state::<PreferenceStore>() {
+                    store.flush();
+                }
+            }
+            _ => {}
+        })
+        .build()
+}
@@ -0,0 +1,60 @@
+use std::fmt;
+
+use passthrough_core::preflight::PreflightError;
+use serde::{Serialize, Serializer};
+use windows::core::Error as WindowsError;
+
+#[derive(Debug)]
+pub enum Error {
+    Windows(WindowsError),
+    Tauri(tauri::Error),
+    Io(std::io::Error),
+    UnknownWindow(String),
+    UnknownProfile(String),
+    InvalidArgument(String),
+    /// 注入前的检查没通过，没有碰目标进程
+    Refused(PreflightError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Windows(err) => write!(f, "windows error: {}", err),
+            Error::Tauri(err) => write!(f, "tauri error: {}", err),
+            Error::Io(err) => write!(f, "io error: {}", err),
+            Error::UnknownWindow(label) => write!(f, "window `{}` not found", label),
+            Error::UnknownProfile(name) => write!(f, "passthrough profile `{}` not found", name),
+            Error::InvalidArgument(message) => write!(f, "invalid argument: {}", message),
+            Error::Refused(err) => write!(f, "injection refused: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<WindowsError> for Error {
+    fn from(err: WindowsError) -> Self {
+        Error::Windows(err)
+    }
+}
+
+impl From<tauri::Error> for Error {
+    fn from(err: tauri::Error) -> Self {
+        Error::Tauri(err)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+// 命令返回给前端时只需要错误信息
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
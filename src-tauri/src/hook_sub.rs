@@ -1,340 +1,1069 @@
 use std::{
-    collections::HashMap,
-    ffi::CString,
-    sync::{LazyLock, Mutex},
+    collections::{hash_map::Entry, HashMap},
+    ffi::{c_void, CString},
+    fs::{File, OpenOptions},
+    os::windows::{ffi::OsStrExt, fs::OpenOptionsExt},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, LazyLock, Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
 };
 
+use passthrough_core::{
+    abi::{
+        AbiInfo, ABI_VERSION, CAP_COMMANDS, COMMAND_HOOK_OK, COMMAND_UNAUTHORIZED, FILTER_OK,
+        MARSHAL_OK, SUBCLASS_ALREADY_SET, SUBCLASS_NOT_SET, SUBCLASS_OK,
+    },
+    cancel::{wait_sliced, CancelToken, WaitOutcome},
+    clock::SystemClock,
+    command::{self, Command},
+    command_args::{self, CommandArgs, SECTION_SIZE},
+    dll_cache,
+    filter::{self, Rule},
+    inject::{self, Executor, Plan, PlanError, Protection, RecordingExecutor},
+    marshal::{self, Decode, Encode},
+    pe::{self, PeImage},
+    preflight::{Check, PreflightError},
+    resources::ResourceKind,
+    win32::{self, INJECT_ACCESS},
+};
 use windows::{
-    core::{s, Error as WindowsError, PCSTR},
+    core::{s, Error as WindowsError, HSTRING, PCSTR},
     Win32::{
-        Foundation::{CloseHandle, FreeLibrary, BOOL, ERROR_NOT_FOUND, HANDLE, HMODULE, HWND},
+        Foundation::{
+            ERROR_CANCELLED, ERROR_NOT_FOUND, ERROR_TIMEOUT, E_ABORT, E_ACCESSDENIED, E_FAIL,
+            HANDLE, HMODULE, HWND, LPARAM, WAIT_OBJECT_0, WPARAM,
+        },
+        Storage::FileSystem::FILE_SHARE_READ,
         System::{
-            Diagnostics::Debug::WriteProcessMemory,
-            LibraryLoader::{
-                GetModuleHandleA, GetProcAddress, LoadLibraryA, LoadLibraryExA,
-                LOAD_LIBRARY_SEARCH_DLL_LOAD_DIR,
-            },
-            Memory::{
-                VirtualAllocEx, VirtualFreeEx, MEM_COMMIT, MEM_RELEASE, PAGE_EXECUTE_READWRITE,
-                PAGE_READWRITE,
-            },
-            ProcessStatus::{K32EnumProcessModules, K32GetModuleBaseNameA},
+            Diagnostics::Debug::{ReadProcessMemory, WriteProcessMemory},
+            LibraryLoader::{GetModuleHandleA, GetProcAddress},
+            Memory::{PAGE_EXECUTE_READWRITE, PAGE_READWRITE},
             Threading::{
-                CreateRemoteThread, GetExitCodeThread, OpenProcess, WaitForSingleObject,
-                LPTHREAD_START_ROUTINE, PROCESS_ALL_ACCESS,
+                CreateRemoteThread, GetExitCodeThread, GetProcessId, WaitForSingleObject,
+                LPTHREAD_START_ROUTINE,
             },
         },
-        UI::WindowsAndMessaging::{GetWindow, GetWindowThreadProcessId, GW_CHILD},
+        UI::WindowsAndMessaging::{
+            GetWindow, GetWindowThreadProcessId, RegisterWindowMessageW, SendMessageTimeoutW,
+            GW_CHILD, SMTO_ABORTIFHUNG,
+        },
     },
 };
 
-use crate::get_last_error_message;
+use crate::{
+    error::Error,
+    get_last_error_message, preflight,
+    remote::{self, OwnedProcess, RemoteAlloc, RemoteThread, RESOURCES},
+    shared_memory::SharedMemory,
+    telemetry,
+};
+
+/// 注入成功的 WebView2 进程和握手得到的 DLL 版本
+#[derive(Clone)]
+struct Injected {
+    /// 同一个窗口的句柄只有这一份，调用远程函数时克隆出来，不用一直拿着锁
+    process: Arc<OwnedProcess>,
+    abi: AbiInfo,
+    /// 远程进程里 sub_dll 的模块句柄，退出时用来卸载
+    module: HMODULE,
+    /// 被子类化的 WebView2 窗口，退出时 Tauri 窗口可能已经销毁，不能再从它查找
+    webview: usize,
+    /// 装上了命令钩子，子类化和过滤规则用窗口消息发给 sub_dll
+    commands: bool,
+}
+
+/// 退出清理的结果，`failures` 是没能清理干净的东西
+#[derive(Debug, Default)]
+pub struct ShutdownReport {
+    pub restored: usize,
+    pub ejected: usize,
+    pub freed: usize,
+    pub failures: Vec<String>,
+}
+
+impl std::fmt::Display for ShutdownReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "restored {} subclass(es), ejected sub_dll from {} process(es), freed {} remote allocation(s)",
+            self.restored, self.ejected, self.freed
+        )?;
+        for failure in &self.failures {
+            write!(f, "\n  not cleaned: {}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+/// 远程线程的默认超时
+pub const DEFAULT_REMOTE_TIMEOUT_MS: u64 = 5000;
+/// 等待远程线程时每隔这么久检查一次是否取消
+const WAIT_SLICE: Duration = Duration::from_millis(50);
+
+static REMOTE_TIMEOUT_MS: AtomicU64 = AtomicU64::new(DEFAULT_REMOTE_TIMEOUT_MS);
+
+pub fn set_remote_timeout(timeout: Duration) {
+    REMOTE_TIMEOUT_MS.store(timeout.as_millis() as u64, Ordering::Relaxed);
+}
+
+/// 没有管理员权限时打不开权限更高的 WebView2 进程
+pub fn is_access_denied(err: &WindowsError) -> bool {
+    err.code() == E_ACCESSDENIED
+}
+
+pub fn is_timed_out(err: &WindowsError) -> bool {
+    err.code() == ERROR_TIMEOUT.into()
+}
+
+pub fn is_cancelled(err: &WindowsError) -> bool {
+    err.code() == ERROR_CANCELLED.into()
+}
+
+/// 超时或取消时远程线程还在运行，它用到的远程内存不能释放
+fn is_abandoned(err: &WindowsError) -> bool {
+    is_timed_out(err) || is_cancelled(err)
+}
+
+fn cancelled() -> WindowsError {
+    WindowsError::new(ERROR_CANCELLED.into(), "remote operation cancelled")
+}
+
+/// 在两步远程操作之间检查是否取消
+fn check_cancel(cancel: &CancelToken) -> Result<(), WindowsError> {
+    if cancel.is_cancelled() {
+        return Err(cancelled());
+    }
+    Ok(())
+}
+
+/// `subclassed_windows` 的结果区大小，4 字节数量 + 每个窗口 8 字节
+const SUBCLASSED_WINDOWS_CAPACITY: usize = 4 + 8 * 256;
 
 pub struct SubClassHwnd {
-    inners: Mutex<HashMap<usize, HANDLE>>,
-    dll_path: CString,
+    inners: Mutex<HashMap<usize, Injected>>,
+    /// 解压到缓存目录的 sub_dll，插件 setup 时设置
+    dll_path: OnceLock<PathBuf>,
 }
 
 unsafe impl Send for SubClassHwnd {}
 unsafe impl Sync for SubClassHwnd {}
 
-pub static SUB_CLASS_HWND: LazyLock<SubClassHwnd> = LazyLock::new(|| {
-    // debug: current_path = src-tauri
-    let current_path = std::env::current_dir().unwrap();
-    let dll_path = current_path.join("sub_dll/target/release/sub_dll.dll");
-    SubClassHwnd::new(CString::new(dll_path.to_str().unwrap()).unwrap())
-});
+pub static SUB_CLASS_HWND: LazyLock<SubClassHwnd> = LazyLock::new(SubClassHwnd::new);
+
+/// build.rs 编译好的 sub_dll
+static SUB_DLL: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/sub_dll.dll"));
+/// build.rs 记录的 sub_dll 的 SHA-256
+const SUB_DLL_SHA256: &str = env!("SUB_DLL_SHA256");
+
+/// 缓存目录按应用版本和接口版本区分
+pub fn sub_dll_version() -> String {
+    format!("{}-abi{}", env!("CARGO_PKG_VERSION"), ABI_VERSION)
+}
 
 impl SubClassHwnd {
-    pub fn new(dll_path: CString) -> Self {
+    fn new() -> Self {
         Self {
             inners: Mutex::new(HashMap::new()),
-            dll_path,
+            dll_path: OnceLock::new(),
         }
     }
 
-    pub unsafe fn reject_dll(&self, hwnd: HWND) -> Result<(), WindowsError> {
+    /// 把嵌入的 sub_dll 解压到 `cache_root` 下当前版本的目录，顺便清理旧版本
+    pub fn install_dll(&self, cache_root: &Path) -> std::io::Result<PathBuf> {
+        let version = sub_dll_version();
+        let path = dll_cache::extract(cache_root, &version, dll_cache::DLL_NAME, SUB_DLL)?;
+        let removed = dll_cache::remove_stale(cache_root, &version);
+        if removed > 0 {
+            tracing::info!("removed {} stale sub_dll versions", removed);
+        }
+        let _ = self.dll_path.set(path.clone());
+        Ok(path)
+    }
+
+    pub fn dll_path(&self) -> Result<&Path, WindowsError> {
+        self.dll_path.get().map(PathBuf::as_path).ok_or_else(|| {
+            WindowsError::new(ERROR_NOT_FOUND.into(), "sub_dll has not been extracted")
+        })
+    }
+
+    /// 注入 sub_dll 并子类化窗口，每个远程步骤都有超时，`cancel` 之后在下一步之前停止。
+    /// 目标进程没通过 `preflight` 检查时返回 `Error::Refused`
+    pub unsafe fn reject_dll(&self, hwnd: HWND, cancel: &CancelToken) -> crate::error::Result<()> {
         let hwnd_value = hwnd.0 as usize;
         if self.inners.lock().unwrap().contains_key(&hwnd_value) {
             return Ok(());
         }
-        let hwnd = GetWindow(hwnd, GW_CHILD)?;
-        let hwnd = GetWindow(hwnd, GW_CHILD)?;
-        let hwnd = GetWindow(hwnd, GW_CHILD)?;
-        let hwnd = GetWindow(hwnd, GW_CHILD)?;
+        let hwnd = webview_hwnd(hwnd)?;
 
         let mut pid: u32 = 0;
         let _ = GetWindowThreadProcessId(hwnd, Some(&mut pid));
-        let h_process = OpenProcess(PROCESS_ALL_ACCESS, BOOL(0), pid).unwrap();
-        if get_module_from_process(h_process, "sub_dll.dll").is_err() {
-            hook_sub(h_process, &self.dll_path)?;
+        let dll_path = self.dll_path()?;
+        // dry run 在任何检查和打开进程之前分开，WebView2 进程完全不受影响
+        if INJECTION_DRY_RUN.load(Ordering::Relaxed) {
+            return Err(dry_run(pid, dll_path).into());
         }
-        call_remote_function::<HWND, u32>(
-            h_process,
-            PCSTR::from_raw(self.dll_path.as_ptr() as *const u8),
-            s!("set_subclass"),
-            Some(hwnd),
-        )?;
-        self.inners.lock().unwrap().insert(hwnd_value, h_process);
+        preflight::run(pid, dll_path)?;
+        check_cancel(cancel)?;
+        // 没有遥测通道时 sub_dll 照常工作，只是看不到它在做什么
+        if let Err(err) = telemetry::open(pid) {
+            tracing::error!("open telemetry channel error: {}", err);
+        }
+        let subclassed = (|| {
+            let process = OwnedProcess::open(INJECT_ACCESS, pid)?;
+            let (module, abi, commands) =
+                self.subclass_in(process.handle(), dll_path, hwnd, cancel)?;
+            crate::error::Result::Ok((process, module, abi, commands))
+        })();
+        let (process, module, abi, commands) = match subclassed {
+            Ok(subclassed) => subclassed,
+            Err(err) => {
+                // 同一个进程里别的窗口还在用这个通道
+                if !self.injected_in(pid) {
+                    telemetry::close(pid);
+                    close_command_args(pid);
+                }
+                return Err(err);
+            }
+        };
+        self.inners.lock().unwrap().insert(
+            hwnd_value,
+            Injected {
+                process: Arc::new(process),
+                abi,
+                module,
+                webview: hwnd.0 as usize,
+                commands,
+            },
+        );
         Ok(())
     }
 
+    unsafe fn subclass_in(
+        &self,
+        h_process: HANDLE,
+        dll_path: &Path,
+        hwnd: HWND,
+        cancel: &CancelToken,
+    ) -> crate::error::Result<(HMODULE, AbiInfo, bool)> {
+        // 已经加载的 DLL 可能还有别的窗口在用，出错时只卸载这次加载的
+        let (module, mut loaded) = match win32::find_module(h_process, dll_cache::DLL_NAME) {
+            Ok(module) => (module, None),
+            Err(_) => {
+                check_cancel(cancel)?;
+                // 缓存目录里的文件可能被换掉了，写入路径之前最后确认一次，加载完才放开
+                let _dll = open_verified_dll(dll_path).map_err(Error::Refused)?;
+                let module = hook_sub(h_process, dll_path, cancel)?;
+                (module, Some(LoadedModule::new(h_process, module)))
+            }
+        };
+        check_cancel(cancel)?;
+        // 进程里可能还留着旧版本的 DLL，版本不对时不能调用它的导出函数
+        let abi = handshake(h_process, module, cancel)?;
+        if let Some(loaded) = &mut loaded {
+            loaded.abi = Some(abi);
+        }
+        check_cancel(cancel)?;
+        let commands = install_commands(h_process, module, abi, hwnd, cancel);
+        check_cancel(cancel)?;
+        let code = if commands {
+            send_command(hwnd, Command::Subclass)? as u32
+        } else {
+            call_remote_function::<HWND>(h_process, module, "set_subclass", Some(hwnd), cancel)?
+        };
+        if code != SUBCLASS_OK && code != SUBCLASS_ALREADY_SET {
+            return Err(WindowsError::new(
+                E_FAIL,
+                format!("set_subclass failed with code {}", code),
+            ));
+        }
+        // 窗口已经指向 DLL 里的窗口过程，之后由 `eject_all` 卸载
+        if let Some(loaded) = loaded {
+            loaded.commit();
+        }
+        let count = call_remote_function::<()>(h_process, module, "subclass_count", None, cancel)?;
+        tracing::info!("subclass_count: {}", count);
+        Ok((module, abi, commands))
+    }
+
     pub unsafe fn unhook_sub(&self, hwnd: HWND) -> Result<(), WindowsError> {
-        let hwnd_value = hwnd.0 as usize;
-        if !self.inners.lock().unwrap().contains_key(&hwnd_value) {
+        let Some(injected) = self.inners.lock().unwrap().remove(&(hwnd.0 as usize)) else {
             return Ok(());
+        };
+        let pid = GetProcessId(injected.process.handle());
+        let result = remove_subclass(&injected, &CancelToken::new());
+        // 进程里没有子类化的窗口了，sub_dll 不会再记录事件
+        if !self.injected_in(pid) {
+            telemetry::close(pid);
+            close_command_args(pid);
+        }
+        let code = result?;
+        if code == SUBCLASS_NOT_SET {
+            tracing::info!(
+                "remove_subclass: window {:#x} was not subclassed",
+                injected.webview
+            );
+        }
+        Ok(())
+    }
+
+    /// 进程里还有没有注入成功的窗口
+    fn injected_in(&self, pid: u32) -> bool {
+        self.inners
+            .lock()
+            .unwrap()
+            .values()
+            .any(|injected| unsafe { GetProcessId(injected.process.handle()) } == pid)
+    }
+
+    /// 恢复所有子类化的窗口，从 WebView2 进程里卸载 sub_dll，释放残留的远程内存。
+    /// panic hook 里也会调用，所以锁被占用时直接跳过而不是等待
+    pub unsafe fn eject_all(&self) -> ShutdownReport {
+        let mut report = ShutdownReport::default();
+        let injected: Vec<Injected> = match self.inners.try_lock() {
+            Ok(mut inners) => inners.drain().map(|(_, injected)| injected).collect(),
+            Err(_) => {
+                report
+                    .failures
+                    .push("injected window table is locked, nothing was ejected".to_string());
+                return report;
+            }
+        };
+        let cancel = CancelToken::new();
+
+        // 同一个进程可能有多个窗口，每个窗口有自己的进程句柄，drop 时关闭
+        let mut processes: HashMap<u32, Injected> = HashMap::new();
+        for item in injected {
+            let webview = HWND(item.webview as *mut c_void);
+            match remove_subclass(&item, &cancel) {
+                Ok(SUBCLASS_OK) | Ok(SUBCLASS_NOT_SET) => report.restored += 1,
+                Ok(code) => report
+                    .failures
+                    .push(format!("remove_subclass({:?}) returned {}", webview, code)),
+                Err(err) => report
+                    .failures
+                    .push(format!("remove_subclass({:?}): {}", webview, err)),
+            }
+            processes
+                .entry(GetProcessId(item.process.handle()))
+                .or_insert(item);
+        }
+
+        for (pid, item) in processes {
+            match unload_sub_dll(item.process.handle(), item.module, Some(item.abi), &cancel) {
+                Ok(()) => report.ejected += 1,
+                Err(err) => report.failures.push(format!("process {}: {}", pid, err)),
+            }
+        }
+
+        telemetry::close_all(&mut report.failures);
+        match COMMAND_ARGS.try_lock() {
+            Ok(mut sections) => sections.clear(),
+            Err(_) => report
+                .failures
+                .push("command argument sections are locked, left open".to_string()),
+        }
+        report.freed = remote::free_abandoned(&mut report.failures);
+        // 进程句柄在上面 drop 掉了，剩下的都是没有守卫管着的资源
+        let leaked = remote::resources();
+        if leaked.transient() + leaked.processes != 0 {
+            report
+                .failures
+                .push(format!("remote resources still open: {:?}", leaked));
+        }
+        report
+    }
+
+    /// 设置 WebView2 窗口的消息过滤规则，窗口需要先 `reject_dll`
+    pub unsafe fn set_message_filter(
+        &self,
+        hwnd: HWND,
+        rules: &[Rule],
+    ) -> Result<(), WindowsError> {
+        let Some(Injected {
+            process,
+            module,
+            commands,
+            ..
+        }) = self.inners.lock().unwrap().get(&(hwnd.0 as usize)).cloned()
+        else {
+            return Err(WindowsError::new(
+                ERROR_NOT_FOUND.into(),
+                "sub_dll is not injected for this window",
+            ));
+        };
+        let hwnd = webview_hwnd(hwnd)?;
+        let args = filter::encode(hwnd.0 as u64, rules);
+        let code = if commands {
+            update_filter_command(GetProcessId(process.handle()), hwnd, &args)?
+        } else {
+            call_remote_function_bytes(
+                process.handle(),
+                module,
+                "set_message_filter",
+                Some(&args),
+                &CancelToken::new(),
+            )?
+        };
+        if code != FILTER_OK {
+            return Err(WindowsError::new(
+                E_FAIL,
+                format!("set_message_filter failed with code {}", code),
+            ));
         }
-        let hwnd = GetWindow(hwnd, GW_CHILD)?;
-        let hwnd = GetWindow(hwnd, GW_CHILD)?;
-        let hwnd = GetWindow(hwnd, GW_CHILD)?;
-        let hwnd = GetWindow(hwnd, GW_CHILD)?;
-        let h_process = self.inners.lock().unwrap().remove(&hwnd_value).unwrap();
-        call_remote_function::<HWND, u32>(
-            h_process,
-            PCSTR::from_raw(self.dll_path.as_ptr() as *const u8),
-            s!("remove_subclass"),
-            Some(hwnd),
-        )?;
-        CloseHandle(h_process)?;
         Ok(())
     }
+
+    /// 返回已经注入的 WebView2 进程 id 和 DLL 版本
+    pub fn injected(&self, hwnd: HWND) -> Option<(u32, AbiInfo)> {
+        let inners = self.inners.lock().unwrap();
+        inners.get(&(hwnd.0 as usize)).map(|injected| {
+            (
+                unsafe { GetProcessId(injected.process.handle()) },
+                injected.abi,
+            )
+        })
+    }
+
+    /// 注入到 `pid` 里的 sub_dll 的模块基址和子类化了的 WebView2 窗口，没有注入过时是 `None`。
+    /// 读不到远程进程的模块列表时诊断信息用它报告注入状态
+    pub fn injected_module(&self, pid: u32) -> Option<(u64, Vec<u64>)> {
+        let inners = self.inners.lock().unwrap();
+        let mut injected = inners
+            .values()
+            .filter(|injected| unsafe { GetProcessId(injected.process.handle()) } == pid)
+            .peekable();
+        let base = injected.peek()?.module.0 as u64;
+        let mut webviews: Vec<u64> = injected.map(|injected| injected.webview as u64).collect();
+        webviews.sort_unstable();
+        Some((base, webviews))
+    }
 }
 
-unsafe fn hook_sub(h_process: HANDLE, dll_path: &CString) -> Result<(), WindowsError> {
-    let alloc_size = dll_path.as_bytes_with_nul().len();
-    let remote_buffer = VirtualAllocEx(
-        h_process,
-        None,
-        alloc_size,
-        MEM_COMMIT,
-        PAGE_EXECUTE_READWRITE,
+/// Tauri 窗口下面第四层子窗口才是 WebView2 进程里真正接收鼠标消息的窗口
+pub unsafe fn webview_hwnd(hwnd: HWND) -> Result<HWND, WindowsError> {
+    let hwnd = GetWindow(hwnd, GW_CHILD)?;
+    let hwnd = GetWindow(hwnd, GW_CHILD)?;
+    let hwnd = GetWindow(hwnd, GW_CHILD)?;
+    GetWindow(hwnd, GW_CHILD)
+}
+
+static COMMAND_MESSAGE: LazyLock<u32> =
+    LazyLock::new(|| unsafe { RegisterWindowMessageW(&HSTRING::from(command::MESSAGE_NAME)) });
+
+/// 这次启动的命令令牌，握手时交给 sub_dll，之后每个命令都带上
+static COMMAND_TOKEN: LazyLock<u32> = LazyLock::new(command::new_token);
+
+/// 用注册的窗口消息给 sub_dll 发命令，在 WebView2 窗口自己的线程里执行，返回结果码
+unsafe fn send_command(hwnd: HWND, command: Command) -> Result<u16, WindowsError> {
+    let (wparam, lparam) = command.encode(*COMMAND_TOKEN);
+    let mut result = 0;
+    let sent = SendMessageTimeoutW(
+        hwnd,
+        *COMMAND_MESSAGE,
+        WPARAM(wparam),
+        LPARAM(lparam),
+        SMTO_ABORTIFHUNG,
+        REMOTE_TIMEOUT_MS.load(Ordering::Relaxed) as u32,
+        Some(&mut result),
     );
+    // 超时时错误码是 `ERROR_TIMEOUT`
+    if sent.0 == 0 {
+        return Err(WindowsError::from_win32());
+    }
+    command
+        .parse_reply(result as isize)
+        .map_err(|err| WindowsError::new(E_FAIL, format!("{}: {}", command.name(), err)))
+}
 
-    println!("remote_buffer: {:?}", remote_buffer.is_null());
+/// 检查命令钩子还在，返回往返时间
+unsafe fn ping(hwnd: HWND) -> Result<Duration, WindowsError> {
+    let started = Instant::now();
+    match send_command(hwnd, Command::Ping)? as u32 {
+        COMMAND_HOOK_OK => Ok(started.elapsed()),
+        // 握手时没能设置令牌，命令钩子不执行任何命令
+        COMMAND_UNAUTHORIZED => Err(WindowsError::new(
+            E_FAIL,
+            "sub_dll rejected the command token",
+        )),
+        code => Err(WindowsError::new(
+            E_FAIL,
+            format!("ping failed with code {}", code),
+        )),
+    }
+}
 
-    WriteProcessMemory(
+/// 在 WebView2 窗口的线程上装命令钩子并 ping 一次。
+/// 返回 false 时这个窗口继续用远程线程调用导出函数
+unsafe fn install_commands(
+    h_process: HANDLE,
+    module: HMODULE,
+    abi: AbiInfo,
+    hwnd: HWND,
+    cancel: &CancelToken,
+) -> bool {
+    if abi.capabilities & CAP_COMMANDS == 0 {
+        return false;
+    }
+    match call_remote_function::<HWND>(
         h_process,
-        remote_buffer,
-        dll_path.as_ptr() as _,
-        alloc_size,
-        None,
+        module,
+        "install_command_hook",
+        Some(hwnd),
+        cancel,
+    ) {
+        Ok(COMMAND_HOOK_OK) => {}
+        Ok(code) => {
+            tracing::error!("install_command_hook failed with code {}", code);
+            return false;
+        }
+        Err(err) => {
+            tracing::error!("install_command_hook error: {}", err);
+            return false;
+        }
+    }
+    match ping(hwnd) {
+        Ok(elapsed) => {
+            tracing::info!("sub_dll command round trip: {:?}", elapsed);
+            true
+        }
+        Err(err) => {
+            tracing::error!("sub_dll command ping error: {}", err);
+            false
+        }
+    }
+}
+
+/// 恢复 WebView2 窗口的原始过程，装了命令钩子时在窗口自己的线程里做
+unsafe fn remove_subclass(injected: &Injected, cancel: &CancelToken) -> Result<u32, WindowsError> {
+    let webview = HWND(injected.webview as *mut c_void);
+    if injected.commands {
+        match send_command(webview, Command::Unsubclass) {
+            Ok(code) => return Ok(code as u32),
+            // 窗口线程没有响应时退回远程线程
+            Err(err) => tracing::error!("unsubclass command error: {}", err),
+        }
+    }
+    call_remote_function::<HWND>(
+        injected.process.handle(),
+        injected.module,
+        "remove_subclass",
+        Some(webview),
+        cancel,
     )
-    .unwrap();
+}
 
-    // 获取 LoadLibraryA 地址
-    let kernel32 = GetModuleHandleA(s!("kernel32.dll")).unwrap();
-    let load_library: LPTHREAD_START_ROUTINE =
-        std::mem::transmute(GetProcAddress(kernel32, s!("LoadLibraryA")));
+// WebView2 进程 pid -> `UpdateFilter` 命令的参数，第一次发这个命令时建
+static COMMAND_ARGS: LazyLock<Mutex<HashMap<u32, SharedMemory>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
 
-    if load_library.is_none() {
-        println!("load_library is none");
-        VirtualFreeEx(h_process, remote_buffer, 0, MEM_RELEASE).unwrap();
-        return Err(WindowsError::new(
+/// 参数写进 `command_args` 共享内存，sub_dll 收到 `UpdateFilter` 命令后自己去读，
+/// 命令里不带地址。拿着锁直到命令返回，同一个进程的两个窗口不会互相覆盖参数
+unsafe fn update_filter_command(pid: u32, hwnd: HWND, args: &[u8]) -> Result<u32, WindowsError> {
+    let mut sections = COMMAND_ARGS.lock().unwrap();
+    let memory = match sections.entry(pid) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => entry.insert(SharedMemory::create(
+            &command_args::section_name(pid),
+            SECTION_SIZE,
+        )?),
+    };
+    let section = CommandArgs::from_ptr(memory.as_ptr());
+    section.init();
+    section
+        .write(args)
+        .map_err(|err| WindowsError::new(E_FAIL, err.to_string()))?;
+    send_command(hwnd, Command::UpdateFilter).map(u32::from)
+}
+
+/// 进程里没有注入的窗口了，不会再发 `UpdateFilter`
+fn close_command_args(pid: u32) {
+    COMMAND_ARGS.lock().unwrap().remove(&pid);
+}
+
+/// 读取远程进程里 sub_dll 的版本，没有 `sub_dll_abi` 导出的是握手之前的旧版本
+unsafe fn handshake(
+    h_process: HANDLE,
+    module: HMODULE,
+    cancel: &CancelToken,
+) -> Result<AbiInfo, WindowsError> {
+    let abi = match remote_export(h_process, module, "sub_dll_abi")? {
+        Some(addr) => AbiInfo::unpack(call_remote_address(h_process, addr, None, cancel)?),
+        None => AbiInfo::LEGACY,
+    };
+    tracing::info!(
+        "sub_dll abi version: {}, capabilities: {:?}",
+        abi.version,
+        abi.capability_names()
+    );
+    // 不卸载旧的 DLL：它子类化过的窗口还指向它的窗口过程，卸载后 WebView2 会崩溃
+    abi.check()
+        .map_err(|mismatch| WindowsError::new(E_FAIL, mismatch.to_string()))?;
+    // 只有能在 WebView2 进程里建线程的调用者才能设置令牌，别的进程发来的命令不会执行。
+    // 设置失败时命令钩子的 ping 不通过，这个窗口改用远程线程
+    if abi.capabilities & CAP_COMMANDS != 0 {
+        match call_remote_function::<u32>(
+            h_process,
+            module,
+            "set_command_token",
+            Some(*COMMAND_TOKEN),
+            cancel,
+        ) {
+            Ok(COMMAND_HOOK_OK) => {}
+            Ok(code) => tracing::error!("set_command_token failed with code {}", code),
+            Err(err) => tracing::error!("set_command_token error: {}", err),
+        }
+    }
+    Ok(abi)
+}
+
+// DLL 文件路径 -> 解析出来的导出表。加载中的 DLL 文件不能被替换，按路径缓存就够了
+static IMAGES: LazyLock<Mutex<HashMap<PathBuf, Arc<PeImage>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// 读取并解析 DLL 文件，不会在本进程加载它
+pub fn module_image(path: &Path) -> Result<Arc<PeImage>, WindowsError> {
+    if let Some(image) = IMAGES.lock().unwrap().get(path) {
+        return Ok(image.clone());
+    }
+    let bytes = std::fs::read(path)
+        .map_err(|err| WindowsError::new(E_FAIL, format!("read {}: {}", path.display(), err)))?;
+    let image = pe::parse(&bytes)
+        .map_err(|err| WindowsError::new(E_FAIL, format!("parse {}: {}", path.display(), err)))?;
+    let image = Arc::new(image);
+    IMAGES
+        .lock()
+        .unwrap()
+        .insert(path.to_path_buf(), image.clone());
+    Ok(image)
+}
+
+/// 远程进程里模块的文件路径
+unsafe fn remote_module_path(h_process: HANDLE, module: HMODULE) -> Result<PathBuf, WindowsError> {
+    win32::module_path(h_process, module).map(PathBuf::from)
+}
+
+/// 按远程进程里实际加载的 DLL 文件计算导出函数的地址：远程模块基址 + 导出表里的 RVA
+unsafe fn remote_export(
+    h_process: HANDLE,
+    module: HMODULE,
+    function_name: &str,
+) -> Result<Option<usize>, WindowsError> {
+    let image = module_image(&remote_module_path(h_process, module)?)?;
+    Ok(image
+        .export_rva(function_name)
+        .map(|rva| module.0 as usize + rva as usize))
+}
+
+unsafe fn remote_function(
+    h_process: HANDLE,
+    module: HMODULE,
+    function_name: &str,
+) -> Result<usize, WindowsError> {
+    remote_export(h_process, module, function_name)?.ok_or_else(|| {
+        WindowsError::new(
             ERROR_NOT_FOUND.into(),
-            get_last_error_message(),
-        ));
+            format!("{} is not exported by sub_dll", function_name),
+        )
+    })
+}
+
+/// 确认没有窗口还指向 DLL 里的窗口过程、去掉命令钩子，再从远程进程卸载 sub_dll。
+/// `abi` 是 `None` 时 DLL 没有握手过，还没有调用过它的导出函数
+unsafe fn unload_sub_dll(
+    h_process: HANDLE,
+    module: HMODULE,
+    abi: Option<AbiInfo>,
+    cancel: &CancelToken,
+) -> Result<(), String> {
+    if let Some(abi) = abi {
+        let windows = call_remote_marshal::<(), Vec<u64>>(
+            h_process,
+            module,
+            "subclassed_windows",
+            &(),
+            SUBCLASSED_WINDOWS_CAPACITY,
+            cancel,
+        )
+        .map_err(|err| format!("subclassed_windows: {}", err))?;
+        // 还有窗口指向 DLL 里的窗口过程时卸载会让 WebView2 崩溃
+        if !windows.is_empty() {
+            return Err(format!(
+                "still has subclassed windows {:x?}, sub_dll left loaded",
+                windows
+            ));
+        }
+        // 钩子也指向 DLL 里的函数，卸载之前要去掉
+        if abi.capabilities & CAP_COMMANDS != 0 {
+            call_remote_function::<()>(h_process, module, "remove_command_hooks", None, cancel)
+                .map_err(|err| format!("remove_command_hooks: {}", err))?;
+        }
     }
+    remote_free_library(h_process, module, cancel).map_err(|err| format!("FreeLibrary: {}", err))
+}
 
-    let thread_handle = CreateRemoteThread(
-        h_process,
-        None,
-        0,
-        load_library,
-        Some(remote_buffer),
-        0,
-        None,
-    )
-    .expect("CreateRemoteThread failed");
+/// 这次注入加载的 sub_dll，没有 `commit` 就 drop 时从远程进程卸载，
+/// 注入中途出错或取消不会在 WebView2 进程里留下没人管的 DLL
+struct LoadedModule {
+    process: HANDLE,
+    module: HMODULE,
+    /// 握手成功后才能调用它的导出函数
+    abi: Option<AbiInfo>,
+}
 
-    WaitForSingleObject(thread_handle, u32::MAX);
+impl LoadedModule {
+    fn new(process: HANDLE, module: HMODULE) -> Self {
+        RESOURCES.acquired(ResourceKind::Module);
+        Self {
+            process,
+            module,
+            abi: None,
+        }
+    }
 
-    let mut module_handle: HMODULE = HMODULE::default();
+    fn commit(self) {
+        std::mem::forget(self);
+        RESOURCES.released(ResourceKind::Module);
+    }
+}
 
-    GetExitCodeThread(thread_handle, &mut module_handle as *mut _ as *mut u32).unwrap();
-    println!("module_handle: {:?}", module_handle);
-    VirtualFreeEx(h_process, remote_buffer, 0, MEM_RELEASE).unwrap();
-    CloseHandle(thread_handle).unwrap();
-    // println!(
-    //     "verify_dll_dependencies: {:?}",
-    //     verify_dll_dependencies(PCSTR::from_raw(dll_path.as_ptr() as *const u8))
-    // );
+impl Drop for LoadedModule {
+    fn drop(&mut self) {
+        RESOURCES.released(ResourceKind::Module);
+        // 注入的 cancel 可能已经触发了，卸载用新的
+        if let Err(err) =
+            unsafe { unload_sub_dll(self.process, self.module, self.abi, &CancelToken::new()) }
+        {
+            tracing::error!("unload sub_dll after failed injection error: {}", err);
+        }
+    }
+}
 
+/// 在远程进程里调用 `FreeLibrary(module)`
+unsafe fn remote_free_library(
+    h_process: HANDLE,
+    module: HMODULE,
+    cancel: &CancelToken,
+) -> Result<(), WindowsError> {
+    let kernel32 = GetModuleHandleA(s!("kernel32.dll"))?;
+    let Some(free_library) = GetProcAddress(kernel32, s!("FreeLibrary")) else {
+        return Err(WindowsError::from_win32());
+    };
+    let ok: u32 = run_remote_thread(
+        h_process,
+        free_library as usize,
+        Some(module.0 as *const c_void),
+        cancel,
+    )?;
+    if ok == 0 {
+        return Err(WindowsError::new(
+            E_FAIL,
+            "remote FreeLibrary returned FALSE",
+        ));
+    }
     Ok(())
 }
 
-unsafe fn call_remote_function<T: Default, R: Default + std::fmt::Display>(
+/// 按 `inject::Plan` 操作远程进程，分配的内存都由 `RemoteAlloc` 管着
+struct Win32Executor<'a> {
     h_process: HANDLE,
-    module_path: PCSTR,
-    function_name: PCSTR,
-    arg: Option<T>,
+    cancel: &'a CancelToken,
+    allocations: HashMap<usize, RemoteAlloc>,
+}
+
+impl<'a> Win32Executor<'a> {
+    fn new(h_process: HANDLE, cancel: &'a CancelToken) -> Self {
+        Self {
+            h_process,
+            cancel,
+            allocations: HashMap::new(),
+        }
+    }
+
+    fn allocation(&self, address: usize) -> Result<&RemoteAlloc, WindowsError> {
+        self.allocations
+            .get(&address)
+            .ok_or_else(|| WindowsError::new(E_FAIL, "remote buffer is not allocated"))
+    }
+}
+
+impl Executor for Win32Executor<'_> {
+    type Error = WindowsError;
+
+    fn alloc(&mut self, size: usize, protection: Protection) -> Result<usize, WindowsError> {
+        check_cancel(self.cancel)?;
+        let protect = match protection {
+            Protection::ReadWrite => PAGE_READWRITE,
+            Protection::ExecuteReadWrite => PAGE_EXECUTE_READWRITE,
+        };
+        let buffer = unsafe { RemoteAlloc::new(self.h_process, size, protect)? };
+        let address = buffer.ptr() as usize;
+        self.allocations.insert(address, buffer);
+        Ok(address)
+    }
+
+    fn write(&mut self, address: usize, data: &[u8]) -> Result<(), WindowsError> {
+        let buffer = self.allocation(address)?;
+        unsafe {
+            WriteProcessMemory(
+                self.h_process,
+                buffer.ptr(),
+                data.as_ptr() as _,
+                data.len(),
+                None,
+            )
+        }
+    }
+
+    fn resolve(&mut self, module: &str, function: &str) -> Result<usize, WindowsError> {
+        let module =
+            CString::new(module).map_err(|err| WindowsError::new(E_FAIL, err.to_string()))?;
+        let function =
+            CString::new(function).map_err(|err| WindowsError::new(E_FAIL, err.to_string()))?;
+        unsafe {
+            let handle = GetModuleHandleA(PCSTR::from_raw(module.as_ptr() as *const u8))?;
+            match GetProcAddress(handle, PCSTR::from_raw(function.as_ptr() as *const u8)) {
+                Some(address) => Ok(address as usize),
+                None => Err(WindowsError::new(
+                    ERROR_NOT_FOUND.into(),
+                    get_last_error_message(),
+                )),
+            }
+        }
+    }
+
+    fn run_thread(&mut self, function: usize, param: Option<usize>) -> Result<u32, WindowsError> {
+        check_cancel(self.cancel)?;
+        let param = param.map(|address| address as *const c_void);
+        unsafe { run_remote_thread(self.h_process, function, param, self.cancel) }
+    }
+
+    fn free(&mut self, address: usize) -> Result<(), WindowsError> {
+        match self.allocations.remove(&address) {
+            Some(buffer) => unsafe { buffer.free() },
+            None => Err(WindowsError::new(E_FAIL, "remote buffer is not allocated")),
+        }
+    }
+
+    fn abandon(&mut self, address: usize) {
+        if let Some(buffer) = self.allocations.remove(&address) {
+            buffer.abandon();
+        }
+    }
+
+    fn find_module(&mut self, name: &str) -> Result<usize, WindowsError> {
+        check_cancel(self.cancel)?;
+        unsafe { win32::find_module(self.h_process, name).map(|module| module.0 as usize) }
+    }
+
+    fn is_abandoned(&self, err: &WindowsError) -> bool {
+        is_abandoned(err)
+    }
+}
+
+/// 为 true 时只打印注入计划，不操作 WebView2 进程
+static INJECTION_DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+pub fn set_injection_dry_run(dry_run: bool) {
+    INJECTION_DRY_RUN.store(dry_run, Ordering::Relaxed);
+}
+
+/// 把注入计划交给 `RecordingExecutor` 走一遍，打印计划和它会做的调用
+fn dry_run(pid: u32, dll_path: &Path) -> WindowsError {
+    let path: Vec<u16> = dll_path.as_os_str().encode_wide().collect();
+    let plan = Plan::load_library(&path, dll_cache::DLL_NAME);
+    let mut recorder = RecordingExecutor::<WindowsError>::new();
+    if let Err(err) = inject::execute(&plan, &mut recorder) {
+        tracing::error!("injection plan is invalid: {}", err);
+    }
+    let calls: Vec<String> = recorder
+        .calls
+        .iter()
+        .map(|call| format!("  {:?}", call))
+        .collect();
+    tracing::info!(
+        "injection plan for process {} (dry run):\n{}calls:\n{}",
+        pid,
+        plan,
+        calls.join("\n")
+    );
+    WindowsError::new(
+        E_ABORT,
+        "dry run: injection plan printed, WebView2 process untouched",
+    )
+}
+
+/// 确认要注入的文件和编译时记录的 SHA-256 一致。返回的文件不允许别人写入、删除或改名，
+/// 要一直拿着直到远程的 `LoadLibraryW` 返回，否则校验之后加载之前文件还能被换掉
+fn open_verified_dll(dll_path: &Path) -> Result<File, PreflightError> {
+    let refused = |err: &dyn std::fmt::Display| {
+        PreflightError::new(
+            Check::DllIntegrity,
+            format!("{}: {}", dll_path.display(), err),
+        )
+    };
+    let mut file = OpenOptions::new()
+        .read(true)
+        .share_mode(FILE_SHARE_READ.0)
+        .open(dll_path)
+        .map_err(|err| refused(&err))?;
+    dll_cache::verify(&mut file, SUB_DLL_SHA256).map_err(|err| refused(&err))?;
+    Ok(file)
+}
+
+/// 把 sub_dll 加载到远程进程，返回它在远程进程里的模块句柄
+unsafe fn hook_sub(
+    h_process: HANDLE,
+    dll_path: &Path,
+    cancel: &CancelToken,
+) -> Result<HMODULE, WindowsError> {
+    let path: Vec<u16> = dll_path.as_os_str().encode_wide().collect();
+    let plan = Plan::load_library(&path, dll_cache::DLL_NAME);
+    let mut executor = Win32Executor::new(h_process, cancel);
+    match inject::execute(&plan, &mut executor) {
+        Ok(outcome) => Ok(HMODULE(outcome.module.unwrap_or_default() as *mut c_void)),
+        // 保留原来的错误码，超时和取消要靠它区分
+        Err(PlanError::Step { error, .. }) => Err(error),
+        Err(PlanError::ZeroExitCode { .. }) => Err(WindowsError::new(
+            E_FAIL,
+            "LoadLibraryW failed in the WebView2 process",
+        )),
+        Err(err @ PlanError::Invalid { .. }) => Err(WindowsError::new(E_FAIL, err.to_string())),
+    }
+}
+
+/// 用 `marshal` 编码参数调用远程函数，结果从远程内存读回来，`result_capacity` 是结果的最大字节数
+unsafe fn call_remote_marshal<A: Encode + ?Sized, R: Decode>(
+    h_process: HANDLE,
+    module: HMODULE,
+    function_name: &str,
+    args: &A,
+    result_capacity: usize,
+    cancel: &CancelToken,
 ) -> Result<R, WindowsError> {
-    // let module_handle = GetModuleHandleA(module_name)?;
-    // let module_handle = get_module_from_process(h_process, module_name)?;
-    // let h_process = GetCurrentProcess();
-    let module_handle = LoadLibraryExA(module_path, None, LOAD_LIBRARY_SEARCH_DLL_LOAD_DIR)?;
-    // println!("module_handle: {:?}", module_handle);
-    // let func_addr = GetProcAddress(module_handle, function_name);
-    let func_addr: LPTHREAD_START_ROUTINE =
-        std::mem::transmute(GetProcAddress(module_handle, function_name));
-
-    if func_addr.is_none() {
-        println!("func_addr is none, error: {}", get_last_error_message());
-        return Ok(R::default());
-    }
-
-    // println!("hwnd size: {} {}", std::mem::size_of::<HWND>(), hwnd_value);
-    let mut param_buffer: Option<*const std::ffi::c_void> = None;
-    if let Some(arg) = arg {
-        let buffer = VirtualAllocEx(
+    let mut frame = marshal::encode_frame(args, result_capacity);
+    let func_addr = remote_function(h_process, module, function_name)?;
+
+    let buffer = RemoteAlloc::new(h_process, frame.len(), PAGE_READWRITE)?;
+    let result = (|| {
+        WriteProcessMemory(
             h_process,
+            buffer.ptr(),
+            frame.as_ptr() as _,
+            frame.len(),
             None,
-            std::mem::size_of::<T>(),
-            MEM_COMMIT,
-            PAGE_READWRITE,
-        );
-        WriteProcessMemory(
+        )?;
+        let code = run_remote_thread(h_process, func_addr, Some(buffer.ptr()), cancel)?;
+        if code != MARSHAL_OK {
+            return Err(WindowsError::new(
+                E_FAIL,
+                format!("remote call failed with code {}", code),
+            ));
+        }
+        ReadProcessMemory(
             h_process,
-            buffer,
-            &arg as *const T as _,
-            std::mem::size_of::<T>(),
+            buffer.ptr(),
+            frame.as_mut_ptr() as _,
+            frame.len(),
             None,
-        )
-        .unwrap();
-        param_buffer = Some(buffer);
+        )?;
+        marshal::read_result(&frame).map_err(|err| WindowsError::new(E_FAIL, err.to_string()))
+    })();
+    if result.as_ref().is_err_and(is_abandoned) {
+        buffer.abandon();
     }
+    result
+}
 
-    // let mut param_buffers = Vec::new();
-    // // 在目标进程分配内存并写入参数
-    // for arg in args {
-    //     let param_buffer = VirtualAllocEx(
-    //         h_process,
-    //         None,
-    //         std::mem::size_of::<u64>(),
-    //         MEM_COMMIT,
-    //         PAGE_READWRITE,
-    //     );
-    //     param_buffers.push(param_buffer);
-    //     WriteProcessMemory(
-    //         h_process,
-    //         param_buffer,
-    //         arg as *const u64 as _,
-    //         std::mem::size_of::<u64>(),
-    //         None,
-    //     )
-    //     .unwrap();
-    // }
-
-    // let return_buffer = VirtualAllocEx(
-    //     h_process,
-    //     None,
-    //     std::mem::size_of::<R>(),
-    //     MEM_COMMIT,
-    //     PAGE_READWRITE,
-    // );
-
-    // println!("return_buffer: {:?}", return_buffer.is_null());
-
-    // WriteProcessMemory(
-    //     h_process,
-    //     return_buffer,
-    //     &R::default() as *const R as *const _,
-    //     std::mem::size_of::<R>(),
-    //     None,
-    // )
-    // .unwrap();
-
-    let thread_handle =
-        CreateRemoteThread(h_process, None, 0, func_addr, param_buffer, 0, None).unwrap();
-
-    WaitForSingleObject(thread_handle, u32::MAX);
-
-    let mut exit_code: R = R::default();
-    GetExitCodeThread(thread_handle, &mut exit_code as *mut _ as *mut u32).unwrap();
-    println!("线程退出码: {}", exit_code);
-
-    // let mut result: R = std::mem::zeroed();
-
-    // ReadProcessMemory(
-    //     h_process,
-    //     return_buffer,
-    //     &mut result as *mut _ as *mut _,
-    //     std::mem::size_of::<R>(),
-    //     None,
-    // )
-    // .unwrap();
-
-    // 释放分配的内存
-    if let Some(buffer) = param_buffer {
-        VirtualFreeEx(h_process, buffer as *mut _, 0, MEM_RELEASE).unwrap();
-    }
-
-    CloseHandle(thread_handle).unwrap();
-    FreeLibrary(module_handle).unwrap();
+unsafe fn call_remote_function<T: Default>(
+    h_process: HANDLE,
+    module: HMODULE,
+    function_name: &str,
+    arg: Option<T>,
+    cancel: &CancelToken,
+) -> Result<u32, WindowsError> {
+    let arg = arg.as_ref().map(|arg| {
+        std::slice::from_raw_parts(arg as *const T as *const u8, std::mem::size_of::<T>())
+    });
+    call_remote_function_bytes(h_process, module, function_name, arg, cancel)
+}
 
-    Ok(exit_code)
+/// 参数是任意长度的字节，远程函数收到的是指向这些字节的指针
+unsafe fn call_remote_function_bytes(
+    h_process: HANDLE,
+    module: HMODULE,
+    function_name: &str,
+    arg: Option<&[u8]>,
+    cancel: &CancelToken,
+) -> Result<u32, WindowsError> {
+    let func_addr = remote_function(h_process, module, function_name)?;
+    call_remote_address(h_process, func_addr, arg, cancel)
 }
 
-unsafe fn get_module_from_process(
+/// 在远程进程里以 `arg` 为参数调用 `func_addr`，返回线程退出码
+unsafe fn call_remote_address(
     h_process: HANDLE,
-    module_name: &str,
-) -> Result<HMODULE, WindowsError> {
-    let mut modules: [HMODULE; 1024] = std::mem::zeroed();
-    let mut needed_bytes = 0;
-    K32EnumProcessModules(
-        h_process,
-        modules.as_mut_ptr(),
-        modules.len() as u32,
-        &mut needed_bytes,
-    )
-    .unwrap();
-
-    // 计算模块数量
-    let module_count = needed_bytes as usize / std::mem::size_of::<HMODULE>();
-
-    println!("module_count: {}", module_count);
-
-    // 收集模块名称
-    for module in &modules[..module_count] {
-        let mut filename = [0u8; 256];
-        let len = K32GetModuleBaseNameA(h_process, *module, &mut filename);
-        if len > 0 {
-            let filename = CString::from_vec_unchecked(filename[..len as usize].to_vec());
-            let filename = filename.to_str().unwrap();
-            if filename == module_name {
-                return Ok(*module);
-            }
-        }
+    func_addr: usize,
+    arg: Option<&[u8]>,
+    cancel: &CancelToken,
+) -> Result<u32, WindowsError> {
+    let mut param_buffer = None;
+    if let Some(arg) = arg {
+        let buffer = RemoteAlloc::new(h_process, arg.len(), PAGE_READWRITE)?;
+        WriteProcessMemory(h_process, buffer.ptr(), arg.as_ptr() as _, arg.len(), None)?;
+        param_buffer = Some(buffer);
     }
 
-    Err(WindowsError::new(
-        ERROR_NOT_FOUND.into(),
-        "module not found",
-    ))
-}
+    let param = param_buffer
+        .as_ref()
+        .map(|buffer| buffer.ptr() as *const c_void);
+    let exit_code = run_remote_thread(h_process, func_addr, param, cancel);
 
-#[allow(unused)]
-unsafe fn verify_dll_dependencies(dll_path: PCSTR) -> Result<(), WindowsError> {
-    // 获取 DLL 所在目录
-    // let dll_dir = Path::new(dll_path).parent().ok_or("Invalid DLL path")?;
+    // 参数内存 drop 时释放，没等到结束的线程可能还在读参数，留到退出时再清理
+    if let Some(buffer) = param_buffer.filter(|_| exit_code.as_ref().is_err_and(is_abandoned)) {
+        buffer.abandon();
+    }
 
-    // // 将 DLL 目录添加到搜索路径
-    // let old_path = std::env::var("PATH").unwrap_or_default();
-    // let new_path = format!("{};{}", dll_dir.display(), old_path);
-    // std::env::set_var("PATH", &new_path);
+    exit_code
+}
 
-    // 现在尝试加载 DLL
-    let handle = LoadLibraryA(dll_path)?;
+/// 在远程进程里创建线程执行 `func_addr(param)`，等待结束并返回退出码。
+/// 超时或取消时不会结束远程线程（它可能正拿着加载器锁），只是不再等待
+unsafe fn run_remote_thread(
+    h_process: HANDLE,
+    func_addr: usize,
+    param: Option<*const c_void>,
+    cancel: &CancelToken,
+) -> Result<u32, WindowsError> {
+    let func_addr: LPTHREAD_START_ROUTINE = std::mem::transmute(func_addr);
+    let thread = RemoteThread::new(CreateRemoteThread(
+        h_process, None, 0, func_addr, param, 0, None,
+    )?);
 
-    // 成功加载后再尝试获取函数
-    let proc_addr = GetProcAddress(handle, s!("dll_main"));
-    if proc_addr.is_none() {
-        FreeLibrary(handle)?;
-        return Err(WindowsError::new(
-            ERROR_NOT_FOUND.into(),
-            get_last_error_message(),
-        ));
+    let timeout = Duration::from_millis(REMOTE_TIMEOUT_MS.load(Ordering::Relaxed));
+    let outcome = wait_sliced(&SystemClock, timeout, WAIT_SLICE, cancel, |slice| {
+        WaitForSingleObject(thread.handle(), slice.as_millis() as u32) == WAIT_OBJECT_0
+    });
+    match outcome {
+        WaitOutcome::Done => {}
+        WaitOutcome::TimedOut => {
+            return Err(WindowsError::new(
+                ERROR_TIMEOUT.into(),
+                format!(
+                    "remote thread did not finish within {} ms",
+                    timeout.as_millis()
+                ),
+            ));
+        }
+        WaitOutcome::Cancelled => return Err(cancelled()),
     }
 
-    FreeLibrary(handle)?;
-    Ok(())
+    // 退出码只有 32 位，更大的返回值要用 `call_remote_marshal`
+    let mut exit_code = 0;
+    GetExitCodeThread(thread.handle(), &mut exit_code)?;
+    tracing::info!("线程退出码: {}", exit_code);
+
+    Ok(exit_code)
 }
@@ -0,0 +1,213 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+};
+
+use passthrough_core::{abi::CAPABILITIES, cancel::CancelToken, preflight::Check};
+use serde::Serialize;
+use tauri::{Emitter, Manager};
+use windows::Win32::Foundation::HWND;
+
+use crate::{
+    error::Error,
+    hook_sub::{self, SUB_CLASS_HWND},
+    passthrough::{
+        ChangeReason, Degradation, Feature, ForwardStrategy, InjectedProcess, InjectionRefusal,
+        PassthroughRequest, PASSTHROUGH,
+    },
+    set_passthrough,
+};
+
+const ELEVATED_HINT: &str = "build with `--features elevated` to run as administrator";
+
+pub const INJECTION_EVENT: &str = "passthrough-injection";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum InjectionStage {
+    Started,
+    Subclassed,
+    Failed,
+    TimedOut,
+    Cancelled,
+    /// 目标进程没通过注入前的检查
+    Refused,
+    /// 没有权限打开 WebView2 进程，降级运行
+    Degraded,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InjectionProgress<'a> {
+    label: &'a str,
+    stage: InjectionStage,
+    error: Option<String>,
+    refusal: Option<InjectionRefusal>,
+}
+
+// 窗口 label -> 正在进行的注入
+static JOBS: LazyLock<Mutex<HashMap<String, CancelToken>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn emit(
+    window: &tauri::Window,
+    stage: InjectionStage,
+    error: Option<String>,
+    refusal: Option<InjectionRefusal>,
+) {
+    let payload = InjectionProgress {
+        label: window.label(),
+        stage,
+        error,
+        refusal,
+    };
+    if let Err(err) = window.emit(INJECTION_EVENT, payload) {
+        tracing::error!("emit {} error: {}", INJECTION_EVENT, err);
+    }
+}
+
+pub fn injected_process(hwnd: HWND) -> Option<InjectedProcess> {
+    SUB_CLASS_HWND
+        .injected(hwnd)
+        .map(|(pid, abi)| InjectedProcess {
+            pid,
+            abi_version: abi.version,
+            capabilities: abi.capability_names(),
+        })
+}
+
+/// 在后台线程注入 sub_dll 并子类化窗口，同一个窗口之前的注入会被取消
+pub fn start(window: &tauri::Window) {
+    let label = window.label().to_string();
+    let cancel = CancelToken::new();
+    if let Some(previous) = JOBS.lock().unwrap().insert(label.clone(), cancel.clone()) {
+        previous.cancel();
+    }
+
+    let window = window.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        emit(&window, InjectionStage::Started, None, None);
+        let hwnd = window.hwnd().unwrap();
+        let result = unsafe { SUB_CLASS_HWND.reject_dll(hwnd, &cancel) };
+        {
+            let mut jobs = JOBS.lock().unwrap();
+            if jobs.get(&label).is_some_and(|job| job.same(&cancel)) {
+                jobs.remove(&label);
+            }
+        }
+
+        let mut refusal = None;
+        // 没注入成功时降级的原因，以及是不是权限不够
+        let mut failure = None;
+        let (stage, error) = match &result {
+            // 注入过程中被取消的时候子类化可能已经完成，要撤销掉
+            Ok(()) if cancel.is_cancelled() => {
+                if let Err(err) = unsafe { SUB_CLASS_HWND.unhook_sub(hwnd) } {
+                    tracing::error!("undo cancelled injection error: {}", err);
+                }
+                (InjectionStage::Cancelled, None)
+            }
+            Ok(()) => (InjectionStage::Subclassed, None),
+            Err(Error::Refused(err)) => {
+                // 完整性级别不够和打不开进程是同一个原因：没有以管理员身份运行
+                failure = Some((
+                    format!("sub_dll not injected ({})", err),
+                    err.check == Check::IntegrityLevel,
+                ));
+                refusal = Some(InjectionRefusal::from(err));
+                (InjectionStage::Refused, Some(err.to_string()))
+            }
+            Err(Error::Windows(err)) if hook_sub::is_access_denied(err) => {
+                failure = Some((format!("sub_dll not injected ({})", err.message()), true));
+                (InjectionStage::Degraded, Some(err.message()))
+            }
+            Err(Error::Windows(err)) if hook_sub::is_cancelled(err) => {
+                (InjectionStage::Cancelled, None)
+            }
+            Err(Error::Windows(err)) if hook_sub::is_timed_out(err) => {
+                failure = Some((
+                    format!("sub_dll injection timed out ({})", err.message()),
+                    false,
+                ));
+                (InjectionStage::TimedOut, Some(err.message()))
+            }
+            Err(Error::Windows(err)) => {
+                failure = Some((
+                    format!("sub_dll injection failed ({})", err.message()),
+                    false,
+                ));
+                (InjectionStage::Failed, Some(err.message()))
+            }
+            Err(err) => {
+                failure = Some((format!("sub_dll injection failed ({})", err), false));
+                (InjectionStage::Failed, Some(err.to_string()))
+            }
+        };
+        if let Some(err) = &error {
+            tracing::error!("inject sub_dll error: {}", err);
+        }
+
+        if stage != InjectionStage::Cancelled {
+            let degraded = failure.map(|(cause, elevate)| degrade(&window, cause, elevate));
+            PASSTHROUGH.update(
+                window.app_handle(),
+                &label,
+                ChangeReason::Injection,
+                |state| {
+                    state.injected_process = injected_process(hwnd);
+                    state.injection_error = error.clone();
+                    state.injection_refusal = refusal.clone();
+                    state.degraded = degraded.clone();
+                },
+            );
+        }
+        emit(&window, stage, error, refusal);
+    });
+}
+
+/// 有可交互区域时改用 `NcHitTest`，不注入也能按区域穿透
+fn fallback(request: &PassthroughRequest) -> Option<PassthroughRequest> {
+    (request.forward && request.strategy == ForwardStrategy::Inject && !request.regions.is_empty())
+        .then(|| PassthroughRequest {
+            strategy: ForwardStrategy::NcHitTest,
+            ..request.clone()
+        })
+}
+
+/// 注入失败后能切换的转发方式先切换过去，再算出哪些功能不可用
+fn degrade(window: &tauri::Window, cause: String, elevate: bool) -> Degradation {
+    let request = PASSTHROUGH
+        .get(window.label())
+        .map(|state| state.request())
+        .unwrap_or_default();
+    let fallback = fallback(&request);
+    let mut reason = match &fallback {
+        Some(fallback) => {
+            // 不是用户的选择，不会保存，下次启动还是先尝试注入
+            set_passthrough(window, fallback, ChangeReason::Injection);
+            format!("{}, switched to `ncHitTest` forwarding", cause)
+        }
+        None => format!("{}, forwarding with the mouse hook only", cause),
+    };
+    if elevate {
+        reason = format!("{}; {}", reason, ELEVATED_HINT);
+    }
+    let fallback = fallback.map(|request| request.strategy);
+    Degradation {
+        reason,
+        unavailable: Feature::unavailable(CAPABILITIES, request.hit_test, fallback),
+        fallback,
+    }
+}
+
+/// 取消窗口正在进行的注入
+pub fn cancel(label: &str) {
+    if let Some(job) = JOBS.lock().unwrap().remove(label) {
+        job.cancel();
+    }
+}
+
+#[tauri::command]
+pub async fn cancel_passthrough_injection(window: tauri::Window) {
+    cancel(window.label());
+}
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+
+use passthrough_core::{
+    dll_cache,
+    inspect::{self, Diagnostics, HostWindow, ModuleInfo, ProcessInfo, SubDllStatus, WindowNode},
+    win32::{
+        image_path, process_entries, process_modules, top_level_windows, window_pid, window_tree,
+        INSPECT_ACCESS,
+    },
+};
+use tauri::{AppHandle, Manager};
+use windows::{core::Error as WindowsError, Win32::System::Threading::GetCurrentProcessId};
+
+use crate::{
+    hook_sub::{self, SUB_CLASS_HWND},
+    remote::OwnedProcess,
+};
+
+/// 收集所有 Tauri 窗口和 WebView2 子进程的诊断信息
+pub unsafe fn collect(app: &AppHandle) -> Result<Diagnostics, WindowsError> {
+    let host_pid = GetCurrentProcessId();
+    let mut windows = Vec::new();
+    let mut abis = HashMap::new();
+    for (label, window) in app.webview_windows() {
+        let Ok(hwnd) = window.hwnd() else {
+            continue;
+        };
+        let injected = SUB_CLASS_HWND.injected(hwnd);
+        if let Some((pid, abi)) = injected {
+            abis.insert(pid, abi);
+        }
+        windows.push(HostWindow {
+            label,
+            hwnd: hwnd.0 as u64,
+            webview: hook_sub::webview_hwnd(hwnd).ok().map(|hwnd| hwnd.0 as u64),
+            injected_pid: injected.map(|(pid, _)| pid),
+            tree: window_tree(hwnd),
+        });
+    }
+    windows.sort_by(|a, b| a.label.cmp(&b.label));
+
+    let trees: Vec<WindowNode> = windows.iter().map(|window| window.tree.clone()).collect();
+    let mut embedded = inspect::split_by_process(&trees);
+    let top_level = top_level_windows();
+    let entries = process_entries()?;
+    let mut processes = Vec::new();
+    for entry in inspect::webview2_processes(&entries, host_pid) {
+        let pid = entry.pid;
+        let mut info = ProcessInfo {
+            pid,
+            parent_pid: entry.parent_pid,
+            name: entry.name.clone(),
+            windows: embedded.remove(&pid).unwrap_or_default(),
+            ..Default::default()
+        };
+        for &hwnd in &top_level {
+            if window_pid(hwnd) == pid {
+                info.windows.push(window_tree(hwnd));
+            }
+        }
+        match OwnedProcess::open(INSPECT_ACCESS, pid) {
+            Ok(process) => {
+                info.path = image_path(process.handle()).ok();
+                match process_modules(process.handle()) {
+                    Ok(modules) => info.modules = modules,
+                    Err(err) => info.error = Some(err.to_string()),
+                }
+            }
+            Err(err) => info.error = Some(err.to_string()),
+        }
+        if let Some(module) = inspect::find_module(&info.modules, dll_cache::DLL_NAME).cloned() {
+            let mut subclassed_windows = Vec::new();
+            for window in &mut info.windows {
+                window.mark_subclassed(pid, &module);
+                subclassed_windows.extend(
+                    window
+                        .walk()
+                        .into_iter()
+                        .filter(|node| node.subclassed)
+                        .map(|node| node.hwnd),
+                );
+            }
+            for window in &mut windows {
+                window.tree.mark_subclassed(pid, &module);
+            }
+            info.sub_dll = Some(SubDllStatus {
+                module,
+                abi: abis.get(&pid).map(|&abi| abi.into()),
+                subclassed_windows,
+            });
+        } else if info.error.is_some() {
+            // 读不到模块列表（比如进程权限更高）时 host 自己记得注入过，
+            // 只是不知道模块的大小，窗口树里标不出子类化的窗口
+            if let Some((base, webviews)) = SUB_CLASS_HWND.injected_module(pid) {
+                info.sub_dll = Some(SubDllStatus {
+                    module: ModuleInfo {
+                        name: dll_cache::DLL_NAME.to_string(),
+                        path: SUB_CLASS_HWND
+                            .dll_path()
+                            .map(|path| path.display().to_string())
+                            .unwrap_or_default(),
+                        base,
+                        size: 0,
+                    },
+                    abi: abis.get(&pid).map(|&abi| abi.into()),
+                    subclassed_windows: webviews,
+                });
+            }
+        }
+        processes.push(info);
+    }
+
+    Ok(Diagnostics {
+        host_pid,
+        sub_dll_version: hook_sub::sub_dll_version(),
+        windows,
+        processes,
+    })
+}
+
+/// WebView2 子进程、它们的模块和窗口树，以及 sub_dll 和子类化在不在，排查问题用
+#[tauri::command]
+pub async fn passthrough_diagnostics(app: AppHandle) -> crate::error::Result<Diagnostics> {
+    Ok(unsafe { collect(&app)? })
+}
@@ -0,0 +1,259 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    str::FromStr,
+    sync::{Arc, LazyLock, Mutex},
+    thread::JoinHandle,
+};
+
+use crossbeam::channel::Sender;
+use serde::{Deserialize, Deserializer};
+use windows::Win32::{
+    Foundation::{HINSTANCE, HWND, LPARAM, LRESULT, WPARAM},
+    System::Threading::GetCurrentThreadId,
+    UI::{
+        Input::KeyboardAndMouse::{
+            GetAsyncKeyState, VIRTUAL_KEY, VK_CONTROL, VK_LWIN, VK_MENU, VK_RWIN, VK_SHIFT,
+        },
+        WindowsAndMessaging::{
+            CallNextHookEx, GetMessageW, PeekMessageW, PostThreadMessageW, SetWindowsHookExW,
+            UnhookWindowsHookEx, HHOOK, KBDLLHOOKSTRUCT, MSG, PM_NOREMOVE, WH_KEYBOARD_LL,
+            WM_KEYDOWN, WM_QUIT, WM_SYSKEYDOWN,
+        },
+    },
+};
+
+/// 安装了低级键盘钩子的线程：线程 id 和句柄
+static KEYBOARD_HOOK_THREAD: Mutex<Option<(u32, JoinHandle<()>)>> = Mutex::new(None);
+
+static mut KEY_DOWN_TX: Option<Sender<KeyChord>> = None;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Modifiers(u8);
+
+impl Modifiers {
+    pub const CTRL: Self = Self(1);
+    pub const ALT: Self = Self(1 << 1);
+    pub const SHIFT: Self = Self(1 << 2);
+    pub const WIN: Self = Self(1 << 3);
+
+    fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+
+    unsafe fn current() -> Self {
+        let pressed = |vk: VIRTUAL_KEY| GetAsyncKeyState(vk.0 as i32) < 0;
+        let mut modifiers = Self::default();
+        if pressed(VK_CONTROL) {
+            modifiers.insert(Self::CTRL);
+        }
+        if pressed(VK_MENU) {
+            modifiers.insert(Self::ALT);
+        }
+        if pressed(VK_SHIFT) {
+            modifiers.insert(Self::SHIFT);
+        }
+        if pressed(VK_LWIN) || pressed(VK_RWIN) {
+            modifiers.insert(Self::WIN);
+        }
+        modifiers
+    }
+}
+
+/// 组合键，例如 `Ctrl+Shift+X`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct KeyChord {
+    pub modifiers: Modifiers,
+    pub vk: u16,
+}
+
+#[derive(Debug)]
+pub struct KeyChordError(String);
+
+impl fmt::Display for KeyChordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for KeyChordError {}
+
+fn parse_key(key: &str) -> Option<u16> {
+    let upper = key.to_ascii_uppercase();
+    let bytes = upper.as_bytes();
+    if bytes.len() == 1 && bytes[0].is_ascii_alphanumeric() {
+        // VK_0..VK_9 与 VK_A..VK_Z 和 ASCII 码相同
+        return Some(bytes[0] as u16);
+    }
+    if let Some(n) = upper.strip_prefix('F').and_then(|n| n.parse::<u16>().ok()) {
+        return (1..=24).contains(&n).then_some(0x70 + n - 1);
+    }
+    match upper.as_str() {
+        "SPACE" => Some(0x20),
+        "ENTER" => Some(0x0D),
+        "TAB" => Some(0x09),
+        "ESC" | "ESCAPE" => Some(0x1B),
+        _ => None,
+    }
+}
+
+impl FromStr for KeyChord {
+    type Err = KeyChordError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut modifiers = Modifiers::default();
+        let mut vk = None;
+        for part in s.split('+').map(str::trim) {
+            match part.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => modifiers.insert(Modifiers::CTRL),
+                "alt" => modifiers.insert(Modifiers::ALT),
+                "shift" => modifiers.insert(Modifiers::SHIFT),
+                "win" | "super" | "meta" => modifiers.insert(Modifiers::WIN),
+                _ => {
+                    if vk.is_some() {
+                        return Err(KeyChordError(format!(
+                            "key chord `{}` has more than one non-modifier key",
+                            s
+                        )));
+                    }
+                    vk = Some(parse_key(part).ok_or_else(|| {
+                        KeyChordError(format!("unknown key `{}` in key chord `{}`", part, s))
+                    })?);
+                }
+            }
+        }
+        match vk {
+            Some(vk) => Ok(Self { modifiers, vk }),
+            None => Err(KeyChordError(format!("key chord `{}` has no key", s))),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyChord {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+type Handler = Arc<dyn Fn() + Send + Sync>;
+
+pub struct KeyBindings {
+    handlers: Mutex<HashMap<String, (KeyChord, Handler)>>,
+}
+
+impl KeyBindings {
+    pub fn new() -> Self {
+        Self {
+            handlers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 第一个绑定出现时才安装键盘钩子
+    pub fn bind<F: Fn() + Send + Sync + 'static>(&self, id: String, chord: KeyChord, handler: F) {
+        let mut handlers = self.handlers.lock().unwrap();
+        handlers.insert(id, (chord, Arc::new(handler)));
+        // 持锁安装/卸载，避免和并发的 unbind 交错导致钩子状态与绑定不一致
+        set_keyboard_hook();
+    }
+
+    /// 移除以 `prefix` 开头的所有绑定，没有绑定时卸载键盘钩子
+    pub fn unbind_prefix(&self, prefix: &str) {
+        let mut handlers = self.handlers.lock().unwrap();
+        handlers.retain(|id, _| !id.starts_with(prefix));
+        if handlers.is_empty() {
+            unset_keyboard_hook();
+        }
+    }
+
+    pub fn emit(&self, chord: KeyChord) {
+        // 先释放锁再调用，处理函数里可能会重新绑定
+        let handlers: Vec<Handler> = self
+            .handlers
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|(bound, _)| *bound == chord)
+            .map(|(_, handler)| handler.clone())
+            .collect();
+        for handler in handlers {
+            (handler)();
+        }
+    }
+}
+
+pub static KEY_BINDINGS: LazyLock<KeyBindings> = LazyLock::new(KeyBindings::new);
+
+pub fn init_key_event_channel() {
+    std::thread::spawn(|| {
+        let (tx, rx) = crossbeam::channel::bounded(8);
+        unsafe {
+            KEY_DOWN_TX = Some(tx);
+        }
+        while let Ok(chord) = rx.recv() {
+            KEY_BINDINGS.emit(chord);
+        }
+    });
+}
+
+pub unsafe extern "system" fn hook_proc(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    if code >= 0 && matches!(wparam.0 as u32, WM_KEYDOWN | WM_SYSKEYDOWN) {
+        let info = &*(lparam.0 as *const KBDLLHOOKSTRUCT);
+        if let Some(tx) = &KEY_DOWN_TX {
+            let _ = tx.try_send(KeyChord {
+                modifiers: Modifiers::current(),
+                vk: info.vkCode as u16,
+            });
+        }
+    }
+    CallNextHookEx(HHOOK::default(), code, wparam, lparam)
+}
+
+/// 低级钩子回调在安装钩子的线程上执行，所以单独起一个带消息循环的线程。
+/// 没有任何绑定时不安装，免得每次按键都经过本进程
+pub fn set_keyboard_hook() {
+    let mut thread = KEYBOARD_HOOK_THREAD.lock().unwrap();
+    if thread.is_some() {
+        return;
+    }
+    let (tx, rx) = crossbeam::channel::bounded(1);
+    let handle = std::thread::spawn(move || unsafe {
+        // 先建立消息队列，之后 PostThreadMessageW 才不会失败
+        let mut msg = MSG::default();
+        let _ = PeekMessageW(&mut msg, HWND::default(), 0, 0, PM_NOREMOVE);
+        let hinstance: HINSTANCE = HINSTANCE::default();
+        let hhook = match SetWindowsHookExW(WH_KEYBOARD_LL, Some(hook_proc), hinstance, 0) {
+            Ok(hhook) => hhook,
+            Err(err) => {
+                tracing::error!("SetWindowsHookExW(WH_KEYBOARD_LL) error: {}", err);
+                let _ = tx.send(None);
+                return;
+            }
+        };
+        let _ = tx.send(Some(GetCurrentThreadId()));
+        // 收到 WM_QUIT 时返回 0，出错时返回 -1
+        while GetMessageW(&mut msg, HWND::default(), 0, 0).0 > 0 {}
+        if let Err(err) = UnhookWindowsHookEx(hhook) {
+            tracing::error!("UnhookWindowsHookEx error: {}", err);
+        }
+    });
+    match rx.recv() {
+        Ok(Some(thread_id)) => *thread = Some((thread_id, handle)),
+        _ => {
+            let _ = handle.join();
+        }
+    }
+}
+
+pub fn unset_keyboard_hook() {
+    let Some((thread_id, handle)) = KEYBOARD_HOOK_THREAD.lock().unwrap().take() else {
+        return;
+    };
+    unsafe {
+        if let Err(err) = PostThreadMessageW(thread_id, WM_QUIT, WPARAM(0), LPARAM(0)) {
+            tracing::error!("PostThreadMessageW(WM_QUIT) error: {}", err);
+            return;
+        }
+    }
+    let _ = handle.join();
+}
@@ -1,24 +1,53 @@
 #![allow(static_mut_refs)]
 
-use std::{ffi::OsString, os::windows::ffi::OsStringExt};
+use std::{
+    ffi::OsString,
+    os::windows::ffi::OsStringExt,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
-use mouse_event::{set_mouse_hook, unset_mouse_hook, MOUSE_EVENT, MOUSE_MOVE_TX};
-use tauri::Manager;
+use keyboard_event::init_key_event_channel;
+use mouse_event::{is_mouse_hook_set, set_mouse_hook, MOUSE_EVENT, MOUSE_MOVE_TX};
+use passthrough::{
+    ChangeReason, EventMask, ForwardStrategy, HitTestMode, MetricsSnapshot, PassthroughRequest,
+    PassthroughState, FORWARD_CALLS, METRICS, PASSTHROUGH, STYLE_CALLS,
+};
+use passthrough_core::hit_test::{HitTester, Miss};
+use tauri::{Manager, RunEvent};
 use windows::{
     core::PWSTR,
     Win32::{
-        Foundation::{GetLastError, HWND, LPARAM, RECT, WPARAM},
+        Foundation::{GetLastError, HWND, LPARAM, POINT, RECT, WPARAM},
         Graphics::Gdi::{PtInRect, ScreenToClient},
         System::Diagnostics::Debug::{FormatMessageW, FORMAT_MESSAGE_FROM_SYSTEM},
         UI::WindowsAndMessaging::{
             GetClientRect, GetWindow, GetWindowLongW, SendMessageW, SetWindowLongA, GWL_EXSTYLE,
-            GW_CHILD, MSLLHOOKSTRUCT, WINDOW_EX_STYLE, WM_MOUSEMOVE, WS_EX_LAYERED,
+            GW_CHILD, WINDOW_EX_STYLE, WM_MOUSEMOVE, WM_MOUSEWHEEL, WS_EX_LAYERED,
             WS_EX_TRANSPARENT,
         },
     },
 };
+mod auto_restore;
+mod bug_report;
+mod coalesce;
+mod config;
+mod error;
 mod hook_sub;
+mod inject;
+mod inspect;
+mod keyboard_event;
+mod logging;
+mod message_filter;
 mod mouse_event;
+mod nc_hit_test;
+mod passthrough;
+mod persist;
+mod preflight;
+mod region_table;
+mod remote;
+mod shared_memory;
+mod shutdown;
+mod telemetry;
 
 // Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
 #[tauri::command]
@@ -63,36 +92,166 @@ fn init_mouse_event_channel() {
             MOUSE_MOVE_TX = Some(tx);
         }
         while let Ok(event) = rx.recv() {
-            MOUSE_EVENT.emit("mousemove", event);
+            let event_name = if event.wparam.0 as u32 == WM_MOUSEWHEEL {
+                "mousewheel"
+            } else {
+                "mousemove"
+            };
+            MOUSE_EVENT.emit(event_name, event);
         }
     });
 }
 
-fn ignore_cursor_events(hwnd: HWND, ignore: bool) {
+/// 返回是否真的修改了窗口样式
+fn ignore_cursor_events(hwnd: HWND, ignore: bool) -> bool {
     unsafe {
-        if ignore {
-            let nindex = GWL_EXSTYLE;
-            let ex_style = WINDOW_EX_STYLE(GetWindowLongW(hwnd, nindex) as u32);
-            let style = ex_style | WS_EX_LAYERED | WS_EX_TRANSPARENT;
-            SetWindowLongA(hwnd, nindex, style.0 as i32);
+        let nindex = GWL_EXSTYLE;
+        let ex_style = WINDOW_EX_STYLE(GetWindowLongW(hwnd, nindex) as u32);
+        let style = if ignore {
+            ex_style | WS_EX_LAYERED | WS_EX_TRANSPARENT
         } else {
-            let nindex = GWL_EXSTYLE;
-            let ex_style = WINDOW_EX_STYLE(GetWindowLongW(hwnd, nindex) as u32);
-            let style = ex_style & !(WS_EX_LAYERED | WS_EX_TRANSPARENT);
-            SetWindowLongA(hwnd, nindex, style.0 as i32);
+            ex_style & !(WS_EX_LAYERED | WS_EX_TRANSPARENT)
+        };
+        if style == ex_style {
+            return false;
         }
+        SetWindowLongA(hwnd, nindex, style.0 as i32);
+        true
     }
 }
 
 #[tauri::command]
-fn ignore_mouse_events(window: tauri::Window, ignore: bool, forward: Option<bool>) {
+async fn ignore_mouse_events(window: tauri::Window, ignore: bool, forward: Option<bool>) {
+    let request = PASSTHROUGH
+        .get(window.label())
+        .map(|state| state.request())
+        .unwrap_or_default()
+        .with_passthrough(ignore, forward.unwrap_or(false));
+    coalesce::submit(window, request);
+}
+
+#[tauri::command]
+fn get_passthrough_metrics() -> MetricsSnapshot {
+    METRICS.snapshot()
+}
+
+#[tauri::command]
+fn get_passthrough_state(app: tauri::AppHandle, label: String) -> Option<PassthroughState> {
+    PASSTHROUGH.get(&label).or_else(|| {
+        // 窗口存在但还没有设置过穿透
+        app.get_window(&label).map(|_| PassthroughState {
+            hook_installed: is_mouse_hook_set(),
+            ..Default::default()
+        })
+    })
+}
+
+#[tauri::command]
+async fn set_passthrough_profile(
+    window: tauri::Window,
+    label: Option<String>,
+    profile: String,
+) -> error::Result<PassthroughState> {
+    let window = match label {
+        Some(label) => window
+            .get_window(&label)
+            .ok_or(error::Error::UnknownWindow(label))?,
+        None => window,
+    };
+    config::apply_profile(&window, &profile, ChangeReason::Profile)
+}
+
+/// `HitTestMode::Regions` 下根据鼠标位置自动切换穿透
+struct RegionsPolicy {
+    app: tauri::AppHandle,
+    label: String,
+    hwnd: usize,
+    tester: HitTester,
+    ignore: AtomicBool,
+}
+
+impl RegionsPolicy {
+    /// 返回切换之后是否穿透
+    unsafe fn update(&self, p: POINT, in_client: bool) -> bool {
+        let ignore = !(in_client && self.tester.is_interactive(p.x, p.y));
+        if self.ignore.swap(ignore, Ordering::SeqCst) != ignore {
+            ignore_cursor_events(HWND(self.hwnd as *mut std::ffi::c_void), ignore);
+            PASSTHROUGH.update(&self.app, &self.label, ChangeReason::Policy, |state| {
+                state.ignore = ignore;
+            });
+        }
+        ignore
+    }
+}
+
+unsafe fn client_point(hwnd: HWND, screen: POINT) -> (POINT, bool) {
+    let mut client_rect = RECT {
+        left: 0,
+        top: 0,
+        right: 0,
+        bottom: 0,
+    };
+    GetClientRect(hwnd, &mut client_rect).unwrap();
+    let mut p = screen;
+    ScreenToClient(hwnd, &mut p).unwrap();
+    (p, PtInRect(&client_rect, p).as_bool())
+}
+
+fn set_passthrough(
+    window: &tauri::Window,
+    request: &PassthroughRequest,
+    reason: ChangeReason,
+) -> PassthroughState {
+    let request = &request.normalized();
+    let current = PASSTHROUGH.get(window.label());
+    if let Some(current) = current.as_ref().filter(|state| state.request() == *request) {
+        METRICS.unchanged();
+        return current.clone();
+    }
+    METRICS.applied();
+    // 转发的设置没变时不需要重新注入和监听，`Regions` 的监听里记录了穿透状态所以总是重设。
+    // `NcHitTest` 的区域变了要更新子类化用的区域
+    let forwarding_unchanged = current.as_ref().is_some_and(|state| {
+        state.forward == request.forward
+            && state.event_mask == request.event_mask
+            && state.hit_test == HitTestMode::Frontend
+            && request.hit_test == HitTestMode::Frontend
+            && state.strategy == request.strategy
+            && (request.strategy == ForwardStrategy::Inject || state.regions == request.regions)
+    });
+
     let hwnd = window.hwnd().unwrap();
-    let forward = forward.unwrap_or(false);
     unsafe {
-        ignore_cursor_events(hwnd, ignore);
+        if !ignore_cursor_events(hwnd, request.ignore) {
+            METRICS.avoided(STYLE_CALLS);
+        }
 
-        if forward {
-            hook_sub::SUB_CLASS_HWND.reject_dll(hwnd).unwrap();
+        if forwarding_unchanged {
+            METRICS.avoided(FORWARD_CALLS);
+        } else if request.forward {
+            match request.strategy {
+                ForwardStrategy::Inject => {
+                    nc_hit_test::remove(window);
+                    // 先建好共享内存，sub_dll 子类化窗口时打开。只有 `Regions` 模式需要在
+                    // WebView2 进程里做命中测试
+                    let regions = match request.hit_test {
+                        HitTestMode::Regions => request.regions.as_slice(),
+                        HitTestMode::Frontend => &[],
+                    };
+                    region_table::publish(window, regions, nc_hit_test::miss());
+                    // 注入可能很慢，放到后台进行，结果通过事件和状态通知。
+                    // 注入失败时鼠标钩子的转发仍然可用，只是没有子类化
+                    inject::start(window);
+                }
+                ForwardStrategy::NcHitTest => {
+                    region_table::retire(window);
+                    inject::cancel(window.label());
+                    if let Err(err) = hook_sub::SUB_CLASS_HWND.unhook_sub(hwnd) {
+                        tracing::error!("unhook sub_dll error: {}", err);
+                    }
+                    nc_hit_test::install(window, &request.regions);
+                }
+            }
             // FIXME: 因为这个窗口是在不同的进程中创建的，所以设置子类化会失败
             {
                 // let set_window_sub_class = SetWindowSubclass(hwnd, Some(subclass_proc), 1, 0);
@@ -102,6 +261,7 @@ fn ignore_mouse_events(window: tauri::Window, ignore: bool, forward: Option<bool
                 // println!("set_window_sub_class {:?}", set_window_sub_class.as_bool());
             }
 
+            let top_hwnd = hwnd.0 as usize;
             // 获取到需要的子窗口
             let hwnd = GetWindow(hwnd, GW_CHILD).unwrap();
             let hwnd = GetWindow(hwnd, GW_CHILD).unwrap();
@@ -110,52 +270,122 @@ fn ignore_mouse_events(window: tauri::Window, ignore: bool, forward: Option<bool
             let hwnd = hwnd.0 as usize;
 
             // 16进制显示
-            println!("hwnd {:02X}", hwnd);
-
-            MOUSE_EVENT.unlisten("mousemove");
-            MOUSE_EVENT.listen("mousemove", move |event| {
-                let hwnd = HWND(hwnd as *mut std::ffi::c_void);
-                let l_param = event.lparam;
-                let p = l_param.0 as *const MSLLHOOKSTRUCT;
-                let p = (*p).pt;
-                let mut client_rect = RECT {
-                    left: 0,
-                    top: 0,
-                    right: 0,
-                    bottom: 0,
-                };
-                GetClientRect(hwnd, &mut client_rect).unwrap();
-                let mut p = p;
-                ScreenToClient(hwnd, &mut p).unwrap();
-
-                if PtInRect(&client_rect, p).as_bool() {
-                    let l = LPARAM(MAKELPARAM!(p.x, p.y));
-                    let w = WPARAM(0);
-                    // println!("post mouse move x: {}, y: {}", p.x, p.y);
-                    SendMessageW(hwnd, WM_MOUSEMOVE, w, l);
-                }
+            tracing::info!("hwnd {:02X}", hwnd);
+
+            MOUSE_EVENT.unlisten("mousemove", window.label());
+            MOUSE_EVENT.unlisten("mousewheel", window.label());
+
+            let policy = (request.hit_test == HitTestMode::Regions).then(|| RegionsPolicy {
+                app: window.app_handle().clone(),
+                label: window.label().to_string(),
+                hwnd: top_hwnd,
+                tester: HitTester::new(
+                    request
+                        .regions
+                        .iter()
+                        .map(|&region| region.into())
+                        .collect(),
+                    window.scale_factor().unwrap_or(1.0),
+                    Miss::default(),
+                ),
+                ignore: AtomicBool::new(request.ignore),
             });
+            let forward_move = request.event_mask.contains(EventMask::MOVE);
+            if forward_move || policy.is_some() {
+                MOUSE_EVENT.listen("mousemove", window.label(), move |event| {
+                    let hwnd = HWND(hwnd as *mut std::ffi::c_void);
+                    let (p, in_client) = client_point(hwnd, event.pt);
+
+                    let ignore = match &policy {
+                        Some(policy) => policy.update(p, in_client),
+                        None => true,
+                    };
+                    // 不穿透的时候窗口自己能收到消息
+                    if forward_move && ignore && in_client {
+                        let l = LPARAM(MAKELPARAM!(p.x, p.y));
+                        let w = WPARAM(0);
+                        // println!("post mouse move x: {}, y: {}", p.x, p.y);
+                        SendMessageW(hwnd, WM_MOUSEMOVE, w, l);
+                    }
+                });
+            }
+            if request.event_mask.contains(EventMask::WHEEL) {
+                MOUSE_EVENT.listen("mousewheel", window.label(), move |event| {
+                    let hwnd = HWND(hwnd as *mut std::ffi::c_void);
+                    let (_, in_client) = client_point(hwnd, event.pt);
+                    if in_client {
+                        // WM_MOUSEWHEEL 使用屏幕坐标，滚动量在 mouseData 的高位
+                        let w = WPARAM((event.mouse_data & 0xffff0000) as usize);
+                        let l = LPARAM(MAKELPARAM!(event.pt.x, event.pt.y));
+                        SendMessageW(hwnd, WM_MOUSEWHEEL, w, l);
+                    }
+                });
+            }
         } else {
-            hook_sub::SUB_CLASS_HWND.unhook_sub(hwnd).unwrap();
-            MOUSE_EVENT.unlisten("mousemove");
+            nc_hit_test::remove(window);
+            region_table::retire(window);
+            inject::cancel(window.label());
+            if let Err(err) = hook_sub::SUB_CLASS_HWND.unhook_sub(hwnd) {
+                tracing::error!("unhook sub_dll error: {}", err);
+            }
+            MOUSE_EVENT.unlisten("mousemove", window.label());
+            MOUSE_EVENT.unlisten("mousewheel", window.label());
         }
     }
+
+    let state = PASSTHROUGH.update(window.app_handle(), window.label(), reason, |state| {
+        state.apply(request);
+        state.hook_installed = is_mouse_hook_set();
+        state.injected_process = inject::injected_process(hwnd);
+        if !request.forward {
+            state.injection_error = None;
+            state.injection_refusal = None;
+            state.degraded = None;
+        }
+    });
+    if reason.persists() {
+        // 用户重新做了选择，之前的临时穿透不再恢复
+        auto_restore::cancel(window.label());
+        persist::remember(window, &state);
+    }
+    state
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    logging::init();
+    shutdown::install_panic_hook();
     init_mouse_event_channel();
+    init_key_event_channel();
+    telemetry::start();
     set_mouse_hook();
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
-        .invoke_handler(tauri::generate_handler![greet, ignore_mouse_events,])
+        .plugin(config::init())
+        .invoke_handler(tauri::generate_handler![
+            greet,
+            ignore_mouse_events,
+            get_passthrough_state,
+            get_passthrough_metrics,
+            set_passthrough_profile,
+            auto_restore::ignore_mouse_events_until,
+            message_filter::set_message_filter,
+            inject::cancel_passthrough_injection,
+            inspect::passthrough_diagnostics,
+            bug_report::export_passthrough_report,
+        ])
         .setup(|app| {
             let main_window = app.get_webview_window("main").unwrap();
             main_window.open_devtools();
             Ok(())
         })
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
-    println!("tauri application exit");
-    unset_mouse_hook();
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        // 事件循环结束后进程直接退出，`run` 后面的代码不会执行
+        .run(|_app, event| {
+            if let RunEvent::Exit = event {
+                tracing::info!("tauri application exit");
+                shutdown::shutdown();
+            }
+        });
 }
@@ -0,0 +1,101 @@
+use std::{
+    fmt::{self, Write as _},
+    sync::Mutex,
+};
+
+use passthrough_core::bug_report::{LogLine, Recent};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Event, Level, Metadata, Subscriber,
+};
+
+use crate::bug_report::unix_ms;
+
+/// 问题报告里保留的日志行数
+const LOG_CAPACITY: usize = 2000;
+
+static LOG: Mutex<Recent<LogLine>> = Mutex::new(Recent::new(LOG_CAPACITY));
+
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            let _ = write!(self.fields, " {}={}", field.name(), value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            let _ = write!(self.fields, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+/// 把 `tracing` 事件打印到控制台，警告和错误打到 stderr，同时留下最近的日志给问题报告。
+/// 不记录 span
+struct Console;
+
+impl Subscriber for Console {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        *metadata.level() <= Level::INFO
+    }
+
+    fn new_span(&self, _span: &Attributes<'_>) -> Id {
+        Id::from_u64(1)
+    }
+
+    fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        let message = visitor.message + &visitor.fields;
+        let metadata = event.metadata();
+        if *metadata.level() <= Level::WARN {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
+        }
+        let time_ms = unix_ms();
+        // 别的线程拿着锁 panic 之后日志照样记，不能在这里再 panic
+        LOG.lock().unwrap_or_else(|err| err.into_inner()).push(
+            time_ms,
+            LogLine {
+                time_ms,
+                level: metadata.level().to_string(),
+                target: metadata.target().to_string(),
+                message,
+            },
+        );
+    }
+
+    fn enter(&self, _span: &Id) {}
+
+    fn exit(&self, _span: &Id) {}
+}
+
+/// 在输出任何日志之前调用
+pub fn init() {
+    if let Err(err) = tracing::subscriber::set_global_default(Console) {
+        eprintln!("set log subscriber error: {}", err);
+    }
+}
+
+/// `since_ms` 以及之后的日志
+pub fn recent(since_ms: u64) -> Vec<LogLine> {
+    LOG.lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .since(since_ms)
+}
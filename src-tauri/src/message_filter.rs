@@ -0,0 +1,70 @@
+use passthrough_core::filter::{self, Action, Rule};
+use serde::Deserialize;
+
+use crate::{
+    error::{Error, Result},
+    hook_sub::SUB_CLASS_HWND,
+};
+
+/// 前端传过来的过滤规则，`msg` 是消息的数值，例如 `WM_SETCURSOR` 是 `0x20`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "camelCase", deny_unknown_fields)]
+pub enum MessageRule {
+    Pass {
+        msg: u32,
+    },
+    Swallow {
+        msg: u32,
+        #[serde(default)]
+        result: isize,
+    },
+    Rewrite {
+        msg: u32,
+        to: Option<u32>,
+        wparam: Option<usize>,
+        lparam: Option<isize>,
+    },
+}
+
+impl From<&MessageRule> for Rule {
+    fn from(rule: &MessageRule) -> Self {
+        match *rule {
+            MessageRule::Pass { msg } => Rule {
+                msg,
+                action: Action::Pass,
+            },
+            MessageRule::Swallow { msg, result } => Rule {
+                msg,
+                action: Action::Swallow { result },
+            },
+            MessageRule::Rewrite {
+                msg,
+                to,
+                wparam,
+                lparam,
+            } => Rule {
+                msg,
+                action: Action::Rewrite {
+                    msg: to.unwrap_or(msg),
+                    wparam,
+                    lparam,
+                },
+            },
+        }
+    }
+}
+
+/// 替换注入进程里这个窗口的消息过滤规则，需要先开启 `forward`
+#[tauri::command]
+pub async fn set_message_filter(window: tauri::Window, rules: Vec<MessageRule>) -> Result<()> {
+    if rules.len() > filter::MAX_RULES {
+        return Err(Error::InvalidArgument(format!(
+            "at most {} message rules are supported",
+            filter::MAX_RULES
+        )));
+    }
+    let rules: Vec<Rule> = rules.iter().map(Rule::from).collect();
+    let hwnd = window.hwnd()?;
+    unsafe { SUB_CLASS_HWND.set_message_filter(hwnd, &rules)? };
+    Ok(())
+}
@@ -5,41 +5,51 @@ use std::{
 };
 
 use crossbeam::channel::Sender;
+use passthrough_core::bug_report::PointerKind;
 use windows::Win32::{
-    Foundation::{HINSTANCE, LPARAM, LRESULT, WPARAM},
+    Foundation::{HINSTANCE, LPARAM, LRESULT, POINT, WPARAM},
     UI::{
         Controls::WM_MOUSELEAVE,
         WindowsAndMessaging::{
-            CallNextHookEx, SetWindowsHookExW, UnhookWindowsHookEx, HHOOK, WH_MOUSE_LL,
-            WM_MOUSEMOVE,
+            CallNextHookEx, SetWindowsHookExW, UnhookWindowsHookEx, HHOOK, MSLLHOOKSTRUCT,
+            WH_MOUSE_LL, WM_MOUSEMOVE, WM_MOUSEWHEEL,
         },
     },
 };
 
+use crate::bug_report::record_pointer;
+
 static mut MOUSE_HHOOK: Option<HHOOK> = None;
 
 pub static mut MOUSE_MOVE_TX: Option<Sender<Event>> = None;
 
 type EventName = &'static str;
 
+/// 钩子返回后 `MSLLHOOKSTRUCT` 就失效了，只能复制需要的字段发出去
 #[derive(Clone)]
 pub struct Event {
-    #[allow(unused)]
     pub wparam: WPARAM,
-    pub lparam: LPARAM,
+    /// 屏幕坐标
+    pub pt: POINT,
+    pub mouse_data: u32,
 }
 
 impl Event {
-    pub fn new(wparam: WPARAM, lparam: LPARAM) -> Self {
-        Self { wparam, lparam }
+    pub fn new(wparam: WPARAM, info: &MSLLHOOKSTRUCT) -> Self {
+        Self {
+            wparam,
+            pt: info.pt,
+            mouse_data: info.mouseData,
+        }
     }
 }
 
 type Handler = Box<dyn Fn(Event) + Send>;
 
+/// 每个窗口各自监听，`(事件名, 窗口 label)` 作为键
 #[derive(Clone)]
 pub struct MouseEvent {
-    handlers: Arc<Mutex<HashMap<EventName, Handler>>>,
+    handlers: Arc<Mutex<HashMap<(EventName, String), Handler>>>,
 }
 
 impl MouseEvent {
@@ -49,32 +59,49 @@ impl MouseEvent {
         }
     }
 
-    pub fn unlisten<S: AsRef<str>>(&self, event_name: S) {
-        self.handlers.lock().unwrap().remove(event_name.as_ref());
+    pub fn unlisten(&self, event_name: EventName, label: &str) {
+        self.handlers
+            .lock()
+            .unwrap()
+            .remove(&(event_name, label.to_string()));
     }
 
     #[allow(unused)]
-    pub fn once<F: FnOnce(Event) + Send + 'static>(&self, event_name: EventName, handler: F) {
+    pub fn once<F: FnOnce(Event) + Send + 'static>(
+        &self,
+        event_name: EventName,
+        label: &str,
+        handler: F,
+    ) {
         let self_ = self.clone();
         let handler = Cell::new(Some(handler));
+        let label_ = label.to_string();
 
-        self.listen(event_name, move |event| {
+        self.listen(event_name, label, move |event| {
             let handler = handler.take().unwrap();
             handler(event);
-            self_.unlisten(event_name);
+            self_.unlisten(event_name, &label_);
         });
     }
 
-    pub fn listen<F: Fn(Event) + Send + 'static>(&self, event_name: EventName, callback: F) {
+    pub fn listen<F: Fn(Event) + Send + 'static>(
+        &self,
+        event_name: EventName,
+        label: &str,
+        callback: F,
+    ) {
         self.handlers
             .lock()
             .unwrap()
-            .insert(event_name, Box::new(callback));
+            .insert((event_name, label.to_string()), Box::new(callback));
     }
 
+    /// 发给所有窗口的监听
     pub fn emit(&self, event_name: &str, event: Event) {
-        if let Some(handler) = self.handlers.lock().unwrap().get(event_name) {
-            (handler)(event);
+        for ((name, _), handler) in self.handlers.lock().unwrap().iter() {
+            if *name == event_name {
+                (handler)(event.clone());
+            }
         }
     }
 }
@@ -85,13 +112,22 @@ pub unsafe extern "system" fn hook_proc(code: i32, wparam: WPARAM, lparam: LPARA
     if code >= 0 {
         match wparam.0 as u32 {
             WM_MOUSEMOVE => {
-                // MOUSE_EVENT.emit("mousemove", Event::new(wparam, lparam));
+                let info = &*(lparam.0 as *const MSLLHOOKSTRUCT);
+                record_pointer(PointerKind::Move, info.pt, 0);
+                if let Some(tx) = &MOUSE_MOVE_TX {
+                    let _ = tx.send(Event::new(wparam, info));
+                }
+            }
+            WM_MOUSEWHEEL => {
+                let info = &*(lparam.0 as *const MSLLHOOKSTRUCT);
+                // 滚动量在 mouseData 的高位
+                record_pointer(PointerKind::Wheel, info.pt, (info.mouseData >> 16) as i16);
                 if let Some(tx) = &MOUSE_MOVE_TX {
-                    let _ = tx.send(Event::new(wparam, lparam));
+                    let _ = tx.send(Event::new(wparam, info));
                 }
             }
             WM_MOUSELEAVE => {
-                println!("mouse leave...");
+                tracing::info!("mouse leave...");
             }
             _ => {}
         }
@@ -127,15 +163,19 @@ pub fn set_mouse_hook() {
     }
 }
 
+pub fn is_mouse_hook_set() -> bool {
+    unsafe { MOUSE_HHOOK.is_some() }
+}
+
 pub fn unset_mouse_hook() {
     unsafe {
         if let Some(h) = MOUSE_HHOOK.take() {
             match UnhookWindowsHookEx(h) {
                 Ok(()) => {
-                    println!("UnhookWindowsHookEx success");
+                    tracing::info!("UnhookWindowsHookEx success");
                 }
                 Err(err) => {
-                    eprintln!("UnhookWindowsHookEx error: {}", err);
+                    tracing::error!("UnhookWindowsHookEx error: {}", err);
                 }
             }
         }
@@ -0,0 +1,115 @@
+use std::{
+    collections::HashMap,
+    ffi::c_void,
+    sync::{LazyLock, Mutex},
+};
+
+use passthrough_core::hit_test::{HitTester, Miss};
+use windows::Win32::{
+    Foundation::{HWND, LPARAM, LRESULT, POINT, RECT, WPARAM},
+    Graphics::Gdi::ScreenToClient,
+    UI::{
+        Shell::{DefSubclassProc, RemoveWindowSubclass, SetWindowSubclass},
+        WindowsAndMessaging::{GetClientRect, WM_NCDESTROY, WM_NCHITTEST},
+    },
+};
+
+use crate::{get_last_error_message, passthrough::Region};
+
+/// `SetWindowSubclass` 的 id，同一个窗口上和别的子类化区分开
+const SUBCLASS_ID: usize = 0x5054_4854;
+
+static MISS: Mutex<Miss> = Mutex::new(Miss::Transparent);
+
+// 顶层窗口 hwnd -> 命中测试
+static TESTERS: LazyLock<Mutex<HashMap<usize, HitTester>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub fn set_miss(miss: Miss) {
+    *MISS.lock().unwrap() = miss;
+}
+
+pub fn miss() -> Miss {
+    *MISS.lock().unwrap()
+}
+
+/// 子类化自己的顶层窗口，`regions` 以外的点不再命中这个窗口。
+/// 已经子类化过时只更新区域
+pub fn install(window: &tauri::Window, regions: &[Region]) {
+    let hwnd = window.hwnd().unwrap().0 as usize;
+    let tester = HitTester::new(
+        regions.iter().map(|&region| region.into()).collect(),
+        window.scale_factor().unwrap_or(1.0),
+        miss(),
+    );
+    TESTERS.lock().unwrap().insert(hwnd, tester);
+    // 子类化只能在创建窗口的线程里做
+    let result = window.run_on_main_thread(move || unsafe {
+        let hwnd_ = HWND(hwnd as *mut c_void);
+        if !SetWindowSubclass(hwnd_, Some(subclass_proc), SUBCLASS_ID, 0).as_bool() {
+            tracing::error!("subclass window error: {}", get_last_error_message());
+            TESTERS.lock().unwrap().remove(&hwnd);
+        }
+    });
+    if let Err(err) = result {
+        tracing::error!("subclass window error: {}", err);
+        TESTERS.lock().unwrap().remove(&hwnd);
+    }
+}
+
+pub fn remove(window: &tauri::Window) {
+    let hwnd = window.hwnd().unwrap().0 as usize;
+    if TESTERS.lock().unwrap().remove(&hwnd).is_none() {
+        return;
+    }
+    let result = window.run_on_main_thread(move || unsafe {
+        let _ = RemoveWindowSubclass(HWND(hwnd as *mut c_void), Some(subclass_proc), SUBCLASS_ID);
+    });
+    if let Err(err) = result {
+        tracing::error!("remove window subclass error: {}", err);
+    }
+}
+
+unsafe fn hit_test(hwnd: HWND, lparam: LPARAM) -> Option<isize> {
+    let testers = TESTERS.lock().ok()?;
+    let tester = testers.get(&(hwnd.0 as usize))?;
+    // 屏幕坐标，多显示器时可能是负数
+    let mut p = POINT {
+        x: (lparam.0 & 0xffff) as i16 as i32,
+        y: ((lparam.0 >> 16) & 0xffff) as i16 as i32,
+    };
+    if !ScreenToClient(hwnd, &mut p).as_bool() {
+        return None;
+    }
+    let mut client_rect = RECT::default();
+    GetClientRect(hwnd, &mut client_rect).ok()?;
+    tester
+        .hit_test(p.x, p.y, client_rect.right, client_rect.bottom)
+        .code()
+}
+
+unsafe extern "system" fn subclass_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+    _subclass_id: usize,
+    _ref_data: usize,
+) -> LRESULT {
+    match msg {
+        WM_NCHITTEST => {
+            if let Some(code) = hit_test(hwnd, lparam) {
+                return LRESULT(code);
+            }
+        }
+        // 窗口销毁前必须撤销子类化
+        WM_NCDESTROY => {
+            if let Ok(mut testers) = TESTERS.lock() {
+                testers.remove(&(hwnd.0 as usize));
+            }
+            let _ = RemoveWindowSubclass(hwnd, Some(subclass_proc), SUBCLASS_ID);
+        }
+        _ => {}
+    }
+    DefSubclassProc(hwnd, msg, wparam, lparam)
+}
@@ -0,0 +1,527 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        LazyLock, Mutex,
+    },
+};
+
+use passthrough_core::{
+    abi::{CAP_MESSAGE_FILTER, CAP_REGION_TABLE, CAP_SUBCLASS, CAP_TELEMETRY},
+    hit_test::Rect,
+    preflight::PreflightError,
+    resources::ResourceSnapshot,
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tauri::{AppHandle, Emitter};
+
+use crate::telemetry::TelemetrySnapshot;
+
+pub const STATE_CHANGED_EVENT: &str = "passthrough-state-changed";
+
+/// 转发给 WebView 的鼠标事件集合
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EventMask(u32);
+
+impl EventMask {
+    pub const NONE: Self = Self(0);
+    pub const MOVE: Self = Self(1);
+    pub const WHEEL: Self = Self(1 << 1);
+
+    const NAMES: [(&'static str, Self); 2] = [("move", Self::MOVE), ("wheel", Self::WHEEL)];
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl std::ops::BitOr for EventMask {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl Serialize for EventMask {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(
+            Self::NAMES
+                .iter()
+                .filter(|(_, mask)| self.contains(*mask))
+                .map(|(name, _)| *name),
+        )
+    }
+}
+
+impl<'de> Deserialize<'de> for EventMask {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let names = Vec::<String>::deserialize(deserializer)?;
+        let mut mask = Self::NONE;
+        for name in names {
+            let (_, bit) = Self::NAMES
+                .iter()
+                .find(|(known, _)| *known == name)
+                .ok_or_else(|| {
+                    serde::de::Error::custom(format!(
+                        "unknown mouse event `{}`, expected one of `move`, `wheel`",
+                        name
+                    ))
+                })?;
+            mask = mask | *bit;
+        }
+        Ok(mask)
+    }
+}
+
+/// 窗口客户区内可交互的区域，单位为逻辑像素
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Region {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl From<Region> for Rect {
+    fn from(region: Region) -> Self {
+        Rect {
+            x: region.x,
+            y: region.y,
+            width: region.width,
+            height: region.height,
+        }
+    }
+}
+
+/// 由谁决定鼠标是否穿透
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum HitTestMode {
+    /// 前端通过 `ignore_mouse_events` 自己切换
+    #[default]
+    Frontend,
+    /// 鼠标进入 `regions` 时自动取消穿透，离开后恢复
+    Regions,
+}
+
+/// 转发时怎么让 WebView 正常收到鼠标消息
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ForwardStrategy {
+    /// 注入 sub_dll，子类化 WebView2 进程里的窗口
+    #[default]
+    Inject,
+    /// 不注入，只子类化自己的顶层窗口，`regions` 以外的 `WM_NCHITTEST` 返回透明
+    NcHitTest,
+}
+
+/// 一次完整的穿透设置
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PassthroughRequest {
+    pub ignore: bool,
+    pub forward: bool,
+    pub event_mask: EventMask,
+    pub regions: Vec<Region>,
+    pub hit_test: HitTestMode,
+    pub strategy: ForwardStrategy,
+    pub profile: Option<String>,
+}
+
+impl PassthroughRequest {
+    /// 只改穿透和转发，profile 设置的区域、事件集合、命中测试和转发方式保持不变。
+    /// 打开转发但没有选事件时默认转发移动
+    pub fn with_passthrough(&self, ignore: bool, forward: bool) -> Self {
+        let mut request = self.clone();
+        request.ignore = ignore;
+        request.forward = forward;
+        if forward && request.event_mask.is_empty() {
+            request.event_mask = EventMask::MOVE;
+        }
+        request
+    }
+
+    /// 不转发时事件集合和转发方式没有意义，统一清空方便比较
+    pub fn normalized(&self) -> Self {
+        let mut request = self.clone();
+        if !request.forward {
+            request.event_mask = EventMask::NONE;
+            request.strategy = ForwardStrategy::default();
+        }
+        request
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InjectedProcess {
+    pub pid: u32,
+    /// 握手得到的 sub_dll 接口版本
+    pub abi_version: u16,
+    pub capabilities: Vec<&'static str>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InjectionRefusal {
+    pub check: &'static str,
+    pub reason: String,
+}
+
+impl From<&PreflightError> for InjectionRefusal {
+    fn from(err: &PreflightError) -> Self {
+        Self {
+            check: err.check.name(),
+            reason: err.reason.clone(),
+        }
+    }
+}
+
+/// 没有 sub_dll 时用户能感觉到的功能缺失
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Feature {
+    /// WebView2 窗口被子类化，转发的鼠标消息和真实输入一样处理
+    NativeForwarding,
+    /// `set_message_filter`
+    MessageFilter,
+    /// `Regions` 模式在 WebView2 进程里做命中测试，没有时只靠鼠标钩子切换穿透，会慢一拍
+    RegionHitTest,
+    /// sub_dll 记录的遥测事件，诊断信息和问题报告里会缺这部分
+    Telemetry,
+}
+
+impl Feature {
+    /// 缺少 sub_dll 的 `missing` 这些能力时不可用的功能。`marshal` 和 `commands`
+    /// 只是 host 调用 sub_dll 的方式，用户感觉不到
+    pub fn unavailable(
+        missing: u16,
+        hit_test: HitTestMode,
+        fallback: Option<ForwardStrategy>,
+    ) -> Vec<Self> {
+        // `NcHitTest` 在自己的窗口里按区域命中测试，不需要 WebView2 进程配合
+        let replaced = fallback == Some(ForwardStrategy::NcHitTest);
+        let mut features = Vec::new();
+        if missing & CAP_SUBCLASS != 0 && !replaced {
+            features.push(Self::NativeForwarding);
+        }
+        if missing & CAP_MESSAGE_FILTER != 0 {
+            features.push(Self::MessageFilter);
+        }
+        if missing & CAP_REGION_TABLE != 0 && hit_test == HitTestMode::Regions && !replaced {
+            features.push(Self::RegionHitTest);
+        }
+        if missing & CAP_TELEMETRY != 0 {
+            features.push(Self::Telemetry);
+        }
+        features
+    }
+}
+
+/// 注入 sub_dll 失败后降级运行，这些功能不可用
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Degradation {
+    pub reason: String,
+    pub unavailable: Vec<Feature>,
+    /// 代替注入的转发方式，没有时只用鼠标钩子转发
+    pub fallback: Option<ForwardStrategy>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PassthroughState {
+    pub ignore: bool,
+    pub forward: bool,
+    pub hook_installed: bool,
+    pub injected_process: Option<InjectedProcess>,
+    /// 最近一次注入失败的原因，比如 WebView2 进程里留着不兼容的旧 DLL
+    pub injection_error: Option<String>,
+    /// 注入前检查没通过时是哪一项
+    pub injection_refusal: Option<InjectionRefusal>,
+    /// 降级运行时不可用的能力，注入成功后清空
+    pub degraded: Option<Degradation>,
+    pub event_mask: EventMask,
+    pub profile: Option<String>,
+    pub hit_test: HitTestMode,
+    pub strategy: ForwardStrategy,
+    pub regions: Vec<Region>,
+}
+
+impl PassthroughState {
+    pub fn request(&self) -> PassthroughRequest {
+        PassthroughRequest {
+            ignore: self.ignore,
+            forward: self.forward,
+            event_mask: self.event_mask,
+            regions: self.regions.clone(),
+            hit_test: self.hit_test,
+            strategy: self.strategy,
+            profile: self.profile.clone(),
+        }
+    }
+
+    /// 记录生效的设置，`request()` 能原样取回
+    pub fn apply(&mut self, request: &PassthroughRequest) {
+        self.ignore = request.ignore;
+        self.forward = request.forward;
+        self.event_mask = request.event_mask;
+        self.profile = request.profile.clone();
+        self.hit_test = request.hit_test;
+        self.strategy = request.strategy;
+        self.regions = request.regions.clone();
+    }
+}
+
+/// 触发状态变化的来源
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ChangeReason {
+    Command,
+    Profile,
+    Hotkey,
+    /// 自动策略，例如 `HitTestMode::Regions`
+    Policy,
+    /// 窗口创建时恢复上次保存的设置
+    Restore,
+    /// `ignore_mouse_events_until` 设置的临时穿透
+    Timed,
+    /// 临时穿透的条件满足后自动恢复
+    Released,
+    /// 后台注入 sub_dll 完成或失败
+    Injection,
+}
+
+impl ChangeReason {
+    /// 是否是用户的选择，需要保存下来
+    pub fn persists(self) -> bool {
+        matches!(self, Self::Command | Self::Profile | Self::Hotkey)
+    }
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StateChanged<'a> {
+    label: &'a str,
+    reason: ChangeReason,
+    state: &'a PassthroughState,
+}
+
+pub struct PassthroughStore {
+    windows: Mutex<HashMap<String, PassthroughState>>,
+}
+
+impl PassthroughStore {
+    pub fn new() -> Self {
+        Self {
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, label: &str) -> Option<PassthroughState> {
+        self.windows.lock().unwrap().get(label).cloned()
+    }
+
+    /// 修改窗口状态，只有状态真正变化时才会通知前端
+    pub fn update<F: FnOnce(&mut PassthroughState)>(
+        &self,
+        app: &AppHandle,
+        label: &str,
+        reason: ChangeReason,
+        f: F,
+    ) -> PassthroughState {
+        let (state, changed) = {
+            let mut windows = self.windows.lock().unwrap();
+            let state = windows.entry(label.to_string()).or_default();
+            let before = state.clone();
+            f(state);
+            (state.clone(), before != *state)
+        };
+        if changed {
+            let payload = StateChanged {
+                label,
+                reason,
+                state: &state,
+            };
+            if let Err(err) = app.emit(STATE_CHANGED_EVENT, payload) {
+                tracing::error!("emit {} error: {}", STATE_CHANGED_EVENT, err);
+            }
+        }
+        state
+    }
+}
+
+pub static PASSTHROUGH: LazyLock<PassthroughStore> = LazyLock::new(PassthroughStore::new);
+
+/// 设置窗口样式的原生调用
+pub const STYLE_CALLS: u64 = 1;
+/// 设置转发的原生调用：注入或卸载子类、重设鼠标监听
+pub const FORWARD_CALLS: u64 = 2;
+/// 一次完整设置涉及的原生调用
+const NATIVE_CALLS_PER_APPLY: u64 = STYLE_CALLS + FORWARD_CALLS;
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetricsSnapshot {
+    pub requests: u64,
+    pub applied: u64,
+    pub coalesced: u64,
+    pub unchanged: u64,
+    pub native_calls_avoided: u64,
+    pub resources: ResourceUsage,
+    /// sub_dll 报告的计数器和最近的事件
+    pub telemetry: TelemetrySnapshot,
+}
+
+/// 注入用到的远程资源，空闲时除了进程句柄都应该是 0，不是 0 说明有泄漏
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceUsage {
+    pub allocations: usize,
+    pub threads: usize,
+    pub processes: usize,
+    pub modules: usize,
+    pub abandoned_allocations: usize,
+}
+
+impl From<ResourceSnapshot> for ResourceUsage {
+    fn from(snapshot: ResourceSnapshot) -> Self {
+        Self {
+            allocations: snapshot.allocations,
+            threads: snapshot.threads,
+            processes: snapshot.processes,
+            modules: snapshot.modules,
+            abandoned_allocations: snapshot.abandoned_allocations,
+        }
+    }
+}
+
+pub struct PassthroughMetrics {
+    requests: AtomicU64,
+    applied: AtomicU64,
+    coalesced: AtomicU64,
+    unchanged: AtomicU64,
+    native_calls_avoided: AtomicU64,
+}
+
+impl PassthroughMetrics {
+    const fn new() -> Self {
+        Self {
+            requests: AtomicU64::new(0),
+            applied: AtomicU64::new(0),
+            coalesced: AtomicU64::new(0),
+            unchanged: AtomicU64::new(0),
+            native_calls_avoided: AtomicU64::new(0),
+        }
+    }
+
+    pub fn requested(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn applied(&self) {
+        self.applied.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 被后来的请求替换掉，没有生效
+    pub fn coalesced(&self) {
+        self.coalesced.fetch_add(1, Ordering::Relaxed);
+        self.avoided(NATIVE_CALLS_PER_APPLY);
+    }
+
+    /// 和当前状态相同，直接跳过
+    pub fn unchanged(&self) {
+        self.unchanged.fetch_add(1, Ordering::Relaxed);
+        self.avoided(NATIVE_CALLS_PER_APPLY);
+    }
+
+    pub fn avoided(&self, calls: u64) {
+        self.native_calls_avoided
+            .fetch_add(calls, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            requests: self.requests.load(Ordering::Relaxed),
+            applied: self.applied.load(Ordering::Relaxed),
+            coalesced: self.coalesced.load(Ordering::Relaxed),
+            unchanged: self.unchanged.load(Ordering::Relaxed),
+            native_calls_avoided: self.native_calls_avoided.load(Ordering::Relaxed),
+            resources: crate::remote::resources().into(),
+            telemetry: crate::telemetry::snapshot(),
+        }
+    }
+}
+
+pub static METRICS: PassthroughMetrics = PassthroughMetrics::new();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile_state() -> PassthroughState {
+        PassthroughState {
+            ignore: false,
+            forward: true,
+            event_mask: EventMask::MOVE | EventMask::WHEEL,
+            regions: vec![Region {
+                x: 10.0,
+                y: 20.0,
+                width: 30.0,
+                height: 40.0,
+            }],
+            hit_test: HitTestMode::Regions,
+            strategy: ForwardStrategy::NcHitTest,
+            profile: Some("overlay".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn with_passthrough_keeps_the_profile_settings() {
+        let state = profile_state();
+        let timed = state.request().with_passthrough(true, false);
+        assert!(timed.ignore);
+        assert!(!timed.forward);
+        assert_eq!(timed.regions, state.regions);
+        assert_eq!(timed.hit_test, HitTestMode::Regions);
+        assert_eq!(timed.strategy, ForwardStrategy::NcHitTest);
+        assert_eq!(timed.event_mask, state.event_mask);
+        assert_eq!(timed.profile, state.profile);
+    }
+
+    #[test]
+    fn with_passthrough_defaults_forwarding_to_move() {
+        let request = PassthroughRequest::default().with_passthrough(true, true);
+        assert_eq!(request.event_mask, EventMask::MOVE);
+        let request = PassthroughRequest {
+            event_mask: EventMask::WHEEL,
+            ..Default::default()
+        };
+        assert_eq!(
+            request.with_passthrough(true, true).event_mask,
+            EventMask::WHEEL
+        );
+    }
+
+    /// 定时穿透结束后恢复到之前的完整设置
+    #[test]
+    fn timed_ignore_then_restore_round_trips() {
+        let mut state = profile_state();
+        let previous = state.request();
+
+        state.apply(&previous.with_passthrough(true, false));
+        assert!(state.ignore);
+        assert_eq!(state.regions, previous.regions);
+        assert_eq!(state.strategy, ForwardStrategy::NcHitTest);
+
+        state.apply(&previous);
+        assert_eq!(state.request(), previous);
+        assert_eq!(state, profile_state());
+    }
+}
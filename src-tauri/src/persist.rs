@@ -0,0 +1,213 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crossbeam::channel::{Receiver, Sender};
+
+use passthrough_core::document::{self, ReadError, Schema};
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+
+use crate::{
+    config::{bind_hotkeys, PassthroughConfig},
+    passthrough::{ChangeReason, EventMask, PassthroughRequest, PassthroughState},
+    set_passthrough,
+};
+
+pub const FILE_NAME: &str = "passthrough-state.json";
+
+/// 第 1 版就是第一个写入文件的版本，还没有需要升级的旧版本
+const SCHEMA: Schema = Schema {
+    version: 1,
+    migrations: &[],
+};
+
+/// 合并这段时间内的修改再写入
+const WRITE_DELAY: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WindowPreference {
+    pub ignore: bool,
+    pub forward: bool,
+    #[serde(default)]
+    pub events: EventMask,
+    #[serde(default)]
+    pub profile: Option<String>,
+}
+
+impl From<&PassthroughState> for WindowPreference {
+    fn from(state: &PassthroughState) -> Self {
+        Self {
+            ignore: state.ignore,
+            forward: state.forward,
+            events: state.event_mask,
+            profile: state.profile.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct PreferenceFile {
+    version: u64,
+    windows: HashMap<String, WindowPreference>,
+}
+
+#[derive(Default)]
+struct Windows {
+    preferences: HashMap<String, WindowPreference>,
+    /// 有还没写入文件的修改
+    dirty: bool,
+}
+
+struct Shared {
+    path: PathBuf,
+    windows: Mutex<Windows>,
+    /// 写入线程和退出时的 `flush` 不能同时写临时文件
+    writing: Mutex<()>,
+}
+
+impl Shared {
+    fn write_if_dirty(&self) {
+        let _writing = self.writing.lock().unwrap();
+        let file = {
+            let mut windows = self.windows.lock().unwrap();
+            if !windows.dirty {
+                return;
+            }
+            windows.dirty = false;
+            PreferenceFile {
+                version: SCHEMA.version,
+                windows: windows.preferences.clone(),
+            }
+        };
+        if let Err(err) = document::write(&self.path, &file) {
+            tracing::error!("save {} error: {}", self.path.display(), err);
+        }
+    }
+}
+
+/// 收到修改后等一会儿再写，这段时间内的修改只写一次
+fn run_writer(shared: Arc<Shared>, rx: Receiver<()>) {
+    while rx.recv().is_ok() {
+        thread::sleep(WRITE_DELAY);
+        while rx.try_recv().is_ok() {}
+        shared.write_if_dirty();
+    }
+    shared.write_if_dirty();
+}
+
+/// 按窗口 label 保存用户的穿透设置。修改在后台线程里合并写入，
+/// 鼠标悬停触发的频繁切换不会每次都写磁盘
+pub struct PreferenceStore {
+    shared: Arc<Shared>,
+    /// 文件是更新版本的程序写的时候是 `None`，只在内存里记录
+    write_tx: Option<Sender<()>>,
+}
+
+impl PreferenceStore {
+    pub fn load(path: PathBuf) -> Self {
+        let mut writable = true;
+        let preferences = match SCHEMA.load::<PreferenceFile>(&path) {
+            Ok(file) => file.map(|file| file.windows).unwrap_or_default(),
+            Err(ReadError::Newer(version)) => {
+                tracing::warn!(
+                    "{} is version {}, newer than {}; it will not be overwritten",
+                    path.display(),
+                    version,
+                    SCHEMA.version
+                );
+                writable = false;
+                HashMap::new()
+            }
+            Err(ReadError::Corrupt(err)) => {
+                // 文件已经备份成 .json.corrupt，从空状态开始
+                tracing::error!("load {} error: {}", path.display(), err);
+                HashMap::new()
+            }
+        };
+        let shared = Arc::new(Shared {
+            path,
+            windows: Mutex::new(Windows {
+                preferences,
+                dirty: false,
+            }),
+            writing: Mutex::new(()),
+        });
+        let write_tx = writable.then(|| {
+            let (tx, rx) = crossbeam::channel::unbounded();
+            let shared = shared.clone();
+            thread::spawn(move || run_writer(shared, rx));
+            tx
+        });
+        Self { shared, write_tx }
+    }
+
+    pub fn get(&self, label: &str) -> Option<WindowPreference> {
+        self.shared
+            .windows
+            .lock()
+            .unwrap()
+            .preferences
+            .get(label)
+            .cloned()
+    }
+
+    pub fn save(&self, label: &str, preference: WindowPreference) {
+        let mut windows = self.shared.windows.lock().unwrap();
+        if windows.preferences.get(label) == Some(&preference) {
+            return;
+        }
+        windows.preferences.insert(label.to_string(), preference);
+        if let Some(tx) = &self.write_tx {
+            windows.dirty = true;
+            let _ = tx.send(());
+        }
+    }
+
+    /// 立即写入还没保存的修改，程序退出时调用
+    pub fn flush(&self) {
+        if self.write_tx.is_some() {
+            self.shared.write_if_dirty();
+        }
+    }
+}
+
+pub fn remember(window: &tauri::Window, state: &PassthroughState) {
+    if let Some(store) = window.try_state::<PreferenceStore>() {
+        store.save(window.label(), state.into());
+    }
+}
+
+/// 窗口创建时恢复上次保存的设置，返回是否有保存的设置
+pub fn restore(window: &tauri::Window) -> bool {
+    let Some(preference) = window
+        .try_state::<PreferenceStore>()
+        .and_then(|store| store.get(window.label()))
+    else {
+        return false;
+    };
+
+    let config = window.state::<PassthroughConfig>();
+    let profile = preference
+        .profile
+        .as_ref()
+        .and_then(|name| config.profiles.get(name).map(|profile| (name, profile)));
+    let mut request = match profile {
+        Some((name, profile)) => {
+            let request = profile.request(name);
+            bind_hotkeys(window, profile, request.clone());
+            request
+        }
+        None => PassthroughRequest::default(),
+    };
+    request.ignore = preference.ignore;
+    request.forward = preference.forward;
+    request.event_mask = preference.events;
+    set_passthrough(window, &request, ChangeReason::Restore);
+    true
+}
@@ -0,0 +1,117 @@
+use std::{collections::HashMap, ffi::c_void, path::Path};
+
+use passthrough_core::{
+    pe::Machine,
+    preflight::{self, Host, IntegrityLevel, Target},
+};
+use windows::{
+    core::{Error as WindowsError, PWSTR},
+    Win32::{
+        Foundation::{CloseHandle, HANDLE},
+        Security::{
+            GetSidSubAuthority, GetSidSubAuthorityCount, GetTokenInformation, TokenIntegrityLevel,
+            TOKEN_MANDATORY_LABEL, TOKEN_QUERY,
+        },
+        System::{
+            Diagnostics::ToolHelp::{
+                CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W,
+                TH32CS_SNAPPROCESS,
+            },
+            SystemInformation::{IMAGE_FILE_MACHINE, IMAGE_FILE_MACHINE_UNKNOWN},
+            Threading::{
+                GetCurrentProcess, GetCurrentProcessId, IsWow64Process2, OpenProcessToken,
+                QueryFullProcessImageNameW, PROCESS_NAME_WIN32, PROCESS_QUERY_LIMITED_INFORMATION,
+            },
+        },
+    },
+};
+
+use crate::{error::Error, hook_sub, remote::OwnedProcess};
+
+/// 注入前检查目标进程，任何一项不通过都不会写入目标进程的内存。
+/// 检查不通过返回 `Error::Refused`，查询信息失败（比如没有权限）返回 `Error::Windows`
+pub unsafe fn run(pid: u32, dll_path: &Path) -> crate::error::Result<()> {
+    // 只要查询权限，检查通过之前不打开可以写内存的句柄
+    let process = OwnedProcess::open(PROCESS_QUERY_LIMITED_INFORMATION, pid)?;
+    let target = Target {
+        pid,
+        image: image_path(process.handle())?,
+        machine: process_machine(process.handle())?,
+        integrity: integrity_level(process.handle())?,
+    };
+    let host = Host {
+        pid: GetCurrentProcessId(),
+        integrity: integrity_level(GetCurrentProcess())?,
+    };
+    let parents = parent_processes()?;
+    let dll_machine = hook_sub::module_image(dll_path)?.machine;
+
+    preflight::check(&host, &target, &parents, dll_machine).map_err(Error::Refused)
+}
+
+unsafe fn image_path(h_process: HANDLE) -> Result<String, WindowsError> {
+    let mut buffer = [0u16; 1024];
+    let mut len = buffer.len() as u32;
+    QueryFullProcessImageNameW(
+        h_process,
+        PROCESS_NAME_WIN32,
+        PWSTR(buffer.as_mut_ptr()),
+        &mut len,
+    )?;
+    Ok(String::from_utf16_lossy(&buffer[..len as usize]))
+}
+
+/// WOW64 进程返回模拟的机器类型，否则就是系统的机器类型
+unsafe fn process_machine(h_process: HANDLE) -> Result<Machine, WindowsError> {
+    let mut process = IMAGE_FILE_MACHINE::default();
+    let mut native = IMAGE_FILE_MACHINE::default();
+    IsWow64Process2(h_process, &mut process, Some(&mut native))?;
+    if process == IMAGE_FILE_MACHINE_UNKNOWN {
+        Ok(Machine(native.0))
+    } else {
+        Ok(Machine(process.0))
+    }
+}
+
+unsafe fn integrity_level(h_process: HANDLE) -> Result<IntegrityLevel, WindowsError> {
+    let mut token = HANDLE::default();
+    OpenProcessToken(h_process, TOKEN_QUERY, &mut token)?;
+    let result = (|| {
+        let mut len = 0;
+        // 第一次调用只是为了拿到需要的长度，一定会失败
+        let _ = GetTokenInformation(token, TokenIntegrityLevel, None, 0, &mut len);
+        let mut buffer = vec![0u64; (len as usize).div_ceil(8)];
+        GetTokenInformation(
+            token,
+            TokenIntegrityLevel,
+            Some(buffer.as_mut_ptr() as *mut c_void),
+            len,
+            &mut len,
+        )?;
+        let label = &*(buffer.as_ptr() as *const TOKEN_MANDATORY_LABEL);
+        let count = *GetSidSubAuthorityCount(label.Label.Sid);
+        Ok(IntegrityLevel(*GetSidSubAuthority(
+            label.Label.Sid,
+            u32::from(count) - 1,
+        )))
+    })();
+    CloseHandle(token)?;
+    result
+}
+
+/// 所有进程的 pid -> 父进程 pid
+unsafe fn parent_processes() -> Result<HashMap<u32, u32>, WindowsError> {
+    let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0)?;
+    let mut parents = HashMap::new();
+    let mut entry = PROCESSENTRY32W {
+        dwSize: std::mem::size_of::<PROCESSENTRY32W>() as u32,
+        ..Default::default()
+    };
+    let mut next = Process32FirstW(snapshot, &mut entry);
+    while next.is_ok() {
+        parents.insert(entry.th32ProcessID, entry.th32ParentProcessID);
+        next = Process32NextW(snapshot, &mut entry);
+    }
+    CloseHandle(snapshot)?;
+    Ok(parents)
+}
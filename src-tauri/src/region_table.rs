@@ -0,0 +1,53 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+};
+
+use passthrough_core::{
+    hit_test::Miss,
+    region_table::{self, RegionTable, TABLE_SIZE},
+};
+use windows::Win32::System::Threading::GetCurrentProcessId;
+
+use crate::{passthrough::Region, shared_memory::SharedMemory};
+
+fn table(memory: &SharedMemory) -> &RegionTable {
+    unsafe { RegionTable::from_ptr(memory.as_ptr()) }
+}
+
+// 顶层窗口 hwnd -> 共享的区域表
+static TABLES: LazyLock<Mutex<HashMap<usize, SharedMemory>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// 把窗口的可交互区域写到共享内存，sub_dll 子类化这个窗口时会打开它。
+/// `regions` 为空时 sub_dll 不做命中测试
+pub fn publish(window: &tauri::Window, regions: &[Region], miss: Miss) {
+    let hwnd = window.hwnd().unwrap().0 as usize;
+    let mut tables = TABLES.lock().unwrap();
+    if !tables.contains_key(&hwnd) {
+        let name = region_table::segment_name(unsafe { GetCurrentProcessId() }, hwnd);
+        match unsafe { SharedMemory::create(&name, TABLE_SIZE) } {
+            Ok(memory) => {
+                table(&memory).init();
+                tables.insert(hwnd, memory);
+            }
+            Err(err) => {
+                tracing::error!("create region table {} error: {}", name, err);
+                return;
+            }
+        }
+    }
+    let rects: Vec<_> = regions.iter().map(|&region| region.into()).collect();
+    let scale = window.scale_factor().unwrap_or(1.0);
+    if let Err(err) = table(&tables[&hwnd]).write(&rects, scale, miss) {
+        tracing::error!("write region table error: {}", err);
+    }
+}
+
+/// 清空区域并关闭 host 的映射，sub_dll 还开着时读到的是空表
+pub fn retire(window: &tauri::Window) {
+    let hwnd = window.hwnd().unwrap().0 as usize;
+    if let Some(memory) = TABLES.lock().unwrap().remove(&hwnd) {
+        let _ = table(&memory).write(&[], 1.0, Miss::default());
+    }
+}
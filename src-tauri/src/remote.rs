@@ -0,0 +1,167 @@
+use std::{ffi::c_void, sync::Mutex};
+
+use passthrough_core::{
+    resources::{ResourceCounters, ResourceKind, ResourceSnapshot},
+    win32::ProcessHandle,
+};
+use windows::{
+    core::Error as WindowsError,
+    Win32::{
+        Foundation::{CloseHandle, DuplicateHandle, BOOL, DUPLICATE_SAME_ACCESS, HANDLE},
+        System::{
+            Memory::{
+                VirtualAllocEx, VirtualFreeEx, MEM_COMMIT, MEM_RELEASE, PAGE_PROTECTION_FLAGS,
+            },
+            Threading::{GetCurrentProcess, PROCESS_ACCESS_RIGHTS},
+        },
+    },
+};
+
+pub static RESOURCES: ResourceCounters = ResourceCounters::new();
+
+pub fn resources() -> ResourceSnapshot {
+    RESOURCES.snapshot()
+}
+
+/// `OpenProcess` 打开的进程句柄，drop 时关闭，打开的句柄数记在 `RESOURCES` 里
+pub struct OwnedProcess(ProcessHandle);
+
+impl OwnedProcess {
+    pub unsafe fn open(access: PROCESS_ACCESS_RIGHTS, pid: u32) -> Result<Self, WindowsError> {
+        let process = ProcessHandle::open(access, pid)?;
+        RESOURCES.acquired(ResourceKind::Process);
+        Ok(Self(process))
+    }
+
+    /// 复制一个独立的句柄，原来的关闭后这个仍然有效
+    unsafe fn duplicate(handle: HANDLE) -> Result<Self, WindowsError> {
+        let current = GetCurrentProcess();
+        let mut copy = HANDLE::default();
+        DuplicateHandle(
+            current,
+            handle,
+            current,
+            &mut copy,
+            0,
+            BOOL(0),
+            DUPLICATE_SAME_ACCESS,
+        )?;
+        RESOURCES.acquired(ResourceKind::Process);
+        Ok(Self(ProcessHandle::from_raw(copy)))
+    }
+
+    pub fn handle(&self) -> HANDLE {
+        self.0.handle()
+    }
+}
+
+impl Drop for OwnedProcess {
+    fn drop(&mut self) {
+        RESOURCES.released(ResourceKind::Process);
+    }
+}
+
+/// `CreateRemoteThread` 返回的线程句柄，drop 时关闭（不会结束线程）
+pub struct RemoteThread(HANDLE);
+
+impl RemoteThread {
+    pub fn new(handle: HANDLE) -> Self {
+        RESOURCES.acquired(ResourceKind::Thread);
+        Self(handle)
+    }
+
+    pub fn handle(&self) -> HANDLE {
+        self.0
+    }
+}
+
+impl Drop for RemoteThread {
+    fn drop(&mut self) {
+        RESOURCES.released(ResourceKind::Thread);
+        if let Err(err) = unsafe { CloseHandle(self.0) } {
+            tracing::error!("close thread handle error: {}", err);
+        }
+    }
+}
+
+/// `VirtualAllocEx` 分配的远程内存，drop 时释放
+pub struct RemoteAlloc {
+    process: HANDLE,
+    ptr: *mut c_void,
+}
+
+impl RemoteAlloc {
+    pub unsafe fn new(
+        process: HANDLE,
+        size: usize,
+        protect: PAGE_PROTECTION_FLAGS,
+    ) -> Result<Self, WindowsError> {
+        let ptr = VirtualAllocEx(process, None, size, MEM_COMMIT, protect);
+        if ptr.is_null() {
+            return Err(WindowsError::from_win32());
+        }
+        RESOURCES.acquired(ResourceKind::Allocation);
+        Ok(Self { process, ptr })
+    }
+
+    pub fn ptr(&self) -> *mut c_void {
+        self.ptr
+    }
+
+    /// 释放并返回错误，drop 时的错误只能打印出来
+    pub unsafe fn free(self) -> Result<(), WindowsError> {
+        let this = std::mem::ManuallyDrop::new(self);
+        RESOURCES.released(ResourceKind::Allocation);
+        VirtualFreeEx(this.process, this.ptr, 0, MEM_RELEASE)
+    }
+
+    /// 远程线程没有结束时还可能在用这块内存，先放着，退出时由 `free_abandoned` 释放
+    pub fn abandon(self) {
+        let this = std::mem::ManuallyDrop::new(self);
+        RESOURCES.released(ResourceKind::Allocation);
+        match unsafe { OwnedProcess::duplicate(this.process) } {
+            Ok(process) => {
+                RESOURCES.abandoned();
+                ABANDONED.lock().unwrap().push((process, this.ptr as usize));
+            }
+            Err(err) => tracing::error!(
+                "keep remote allocation {:#x} error: {}, it will leak",
+                this.ptr as usize,
+                err
+            ),
+        }
+    }
+}
+
+impl Drop for RemoteAlloc {
+    fn drop(&mut self) {
+        RESOURCES.released(ResourceKind::Allocation);
+        if let Err(err) = unsafe { VirtualFreeEx(self.process, self.ptr, 0, MEM_RELEASE) } {
+            tracing::error!(
+                "free remote allocation {:#x} error: {}",
+                self.ptr as usize,
+                err
+            );
+        }
+    }
+}
+
+// 没等到远程线程结束的内存，连同一个独立的进程句柄
+static ABANDONED: Mutex<Vec<(OwnedProcess, usize)>> = Mutex::new(Vec::new());
+
+/// 释放之前放着的远程内存，返回释放的数量。panic hook 里也会调用，锁被占用时跳过
+pub fn free_abandoned(failures: &mut Vec<String>) -> usize {
+    let Ok(mut abandoned) = ABANDONED.try_lock() else {
+        failures.push("abandoned allocation table is locked".to_string());
+        return 0;
+    };
+    let mut freed = 0;
+    for (process, ptr) in abandoned.drain(..) {
+        RESOURCES.reclaimed();
+        match unsafe { VirtualFreeEx(process.handle(), ptr as *mut c_void, 0, MEM_RELEASE) } {
+            Ok(()) => freed += 1,
+            Err(err) => failures.push(format!("free remote allocation {:#x}: {}", ptr, err)),
+        }
+    }
+    freed
+}
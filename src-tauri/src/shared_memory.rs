@@ -0,0 +1,55 @@
+use windows::{
+    core::{Error as WindowsError, HSTRING},
+    Win32::{
+        Foundation::{CloseHandle, HANDLE, INVALID_HANDLE_VALUE},
+        System::Memory::{
+            CreateFileMappingW, MapViewOfFile, UnmapViewOfFile, FILE_MAP_ALL_ACCESS,
+            MEMORY_MAPPED_VIEW_ADDRESS, PAGE_READWRITE,
+        },
+    },
+};
+
+/// host 建的命名共享内存，drop 时解除映射。sub_dll 还开着时内存不会释放
+pub struct SharedMemory {
+    mapping: HANDLE,
+    view: MEMORY_MAPPED_VIEW_ADDRESS,
+}
+
+unsafe impl Send for SharedMemory {}
+unsafe impl Sync for SharedMemory {}
+
+impl SharedMemory {
+    /// 同名的已经存在时（sub_dll 还开着上一次的）打开的是同一块内存。新建的内存全是 0
+    pub unsafe fn create(name: &str, size: usize) -> Result<Self, WindowsError> {
+        let mapping = CreateFileMappingW(
+            INVALID_HANDLE_VALUE,
+            None,
+            PAGE_READWRITE,
+            0,
+            size as u32,
+            &HSTRING::from(name),
+        )?;
+        let view = MapViewOfFile(mapping, FILE_MAP_ALL_ACCESS, 0, 0, size);
+        if view.Value.is_null() {
+            let err = WindowsError::from_win32();
+            let _ = CloseHandle(mapping);
+            return Err(err);
+        }
+        Ok(Self { mapping, view })
+    }
+
+    pub fn as_ptr(&self) -> *const u8 {
+        self.view.Value as *const u8
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        unsafe {
+            if let Err(err) = UnmapViewOfFile(self.view) {
+                tracing::error!("unmap shared memory error: {}", err);
+            }
+            let _ = CloseHandle(self.mapping);
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{
+    hook_sub::SUB_CLASS_HWND, keyboard_event::unset_keyboard_hook, mouse_event::unset_mouse_hook,
+};
+
+static SHUT_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// 卸载全局钩子，恢复子类化的窗口并从 WebView2 进程里卸载 sub_dll，只会执行一次
+pub fn shutdown() {
+    if SHUT_DOWN.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    unset_mouse_hook();
+    unset_keyboard_hook();
+    let report = unsafe { SUB_CLASS_HWND.eject_all() };
+    if report.failures.is_empty() {
+        tracing::info!("passthrough shutdown: {}", report);
+    } else {
+        tracing::error!("passthrough shutdown: {}", report);
+    }
+}
+
+/// 主线程 panic 时进程马上就会退出，先把注入的东西清理掉。
+/// 其他线程的 panic（比如某个命令）不会结束程序，不能在这里卸载
+pub fn install_panic_hook() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if std::thread::current().name() == Some("main") {
+            shutdown();
+        }
+        previous(info);
+    }));
+}
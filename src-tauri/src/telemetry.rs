@@ -0,0 +1,224 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+
+use passthrough_core::telemetry::{self, Counter, EventKind, Reader, TelemetryRing, RING_SIZE};
+use serde::Serialize;
+use windows::{
+    core::Error as WindowsError,
+    Win32::{
+        Foundation::WAIT_OBJECT_0,
+        System::Threading::{WaitForSingleObject, PROCESS_SYNCHRONIZE},
+    },
+};
+
+use crate::{remote::OwnedProcess, shared_memory::SharedMemory};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// 诊断信息里保留的最近事件数
+const RECENT_EVENTS: usize = 256;
+
+/// 一个 WebView2 进程的遥测通道，host 是唯一的读者
+struct Channel {
+    memory: SharedMemory,
+    reader: Reader,
+    lost: u64,
+    /// 用来发现进程退出，pid 可能被新进程复用，不能只看 pid
+    process: OwnedProcess,
+}
+
+impl Channel {
+    fn ring(&self) -> &TelemetryRing {
+        unsafe { TelemetryRing::from_ptr(self.memory.as_ptr()) }
+    }
+
+    fn exited(&self) -> bool {
+        unsafe { WaitForSingleObject(self.process.handle(), 0) == WAIT_OBJECT_0 }
+    }
+}
+
+// WebView2 进程 pid -> 通道
+static CHANNELS: LazyLock<Mutex<HashMap<u32, Channel>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+static RECENT: Mutex<VecDeque<TelemetryEvent>> = Mutex::new(VecDeque::new());
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TelemetryEvent {
+    pub pid: u32,
+    pub sequence: u64,
+    pub kind: &'static str,
+    pub hwnd: u64,
+    pub detail: u64,
+    /// WebView2 进程里的 `GetTickCount64`
+    pub time_ms: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessTelemetry {
+    pub pid: u32,
+    pub messages: u64,
+    pub swallowed: u64,
+    pub rewritten: u64,
+    pub hit_tests: u64,
+    /// sub_dll 没抢到槽丢掉的事件
+    pub dropped: u64,
+    /// host 读得太慢被覆盖的事件
+    pub lost: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TelemetrySnapshot {
+    pub processes: Vec<ProcessTelemetry>,
+    pub recent_events: Vec<TelemetryEvent>,
+}
+
+/// 注入之前给 WebView2 进程建好通道，sub_dll 第一次记录时打开。已经有了就不再建
+pub unsafe fn open(pid: u32) -> Result<(), WindowsError> {
+    let mut channels = CHANNELS.lock().unwrap();
+    if channels.contains_key(&pid) {
+        return Ok(());
+    }
+    let process = OwnedProcess::open(PROCESS_SYNCHRONIZE, pid)?;
+    let memory = SharedMemory::create(&telemetry::channel_name(pid), RING_SIZE)?;
+    let ring = TelemetryRing::from_ptr(memory.as_ptr());
+    ring.init(pid);
+    // sub_dll 还开着上次的共享内存时，之前的事件已经读过了
+    let reader = Reader::attach(ring);
+    channels.insert(
+        pid,
+        Channel {
+            memory,
+            reader,
+            lost: 0,
+            process,
+        },
+    );
+    Ok(())
+}
+
+/// 后台定时读出所有通道的事件，转成 `tracing` 事件
+pub fn start() {
+    std::thread::spawn(|| loop {
+        std::thread::sleep(POLL_INTERVAL);
+        poll();
+    });
+}
+
+/// 读完最后的事件再关闭，sub_dll 之后再记录的事件不会有人读
+pub fn close(pid: u32) {
+    let mut channels = CHANNELS.lock().unwrap();
+    if let Some(mut channel) = channels.remove(&pid) {
+        drain(pid, &mut channel, &mut RECENT.lock().unwrap());
+        tracing::info!(target: "passthrough::telemetry", pid, "telemetry channel closed");
+    }
+}
+
+/// 退出时关闭所有通道。panic hook 里也会调用，锁被占用时跳过
+pub fn close_all(failures: &mut Vec<String>) {
+    let (Ok(mut channels), Ok(mut recent)) = (CHANNELS.try_lock(), RECENT.try_lock()) else {
+        failures.push("telemetry channels are locked, left open".to_string());
+        return;
+    };
+    for (pid, mut channel) in channels.drain() {
+        drain(pid, &mut channel, &mut recent);
+    }
+}
+
+fn poll() {
+    let mut channels = CHANNELS.lock().unwrap();
+    let mut recent = RECENT.lock().unwrap();
+    let mut exited = Vec::new();
+    for (&pid, channel) in channels.iter_mut() {
+        // 先判断再读，退出前写下的事件也能读到
+        if channel.exited() {
+            exited.push(pid);
+        }
+        drain(pid, channel, &mut recent);
+    }
+    for pid in exited {
+        channels.remove(&pid);
+        tracing::info!(
+            target: "passthrough::telemetry",
+            pid,
+            "WebView2 process exited, telemetry channel closed"
+        );
+    }
+}
+
+fn drain(pid: u32, channel: &mut Channel, recent: &mut VecDeque<TelemetryEvent>) {
+    // 只借用 `memory`，`reader` 要可变借用
+    let ring = unsafe { TelemetryRing::from_ptr(channel.memory.as_ptr()) };
+    let drained = channel.reader.drain(ring);
+    if drained.lost > 0 {
+        channel.lost += drained.lost;
+        tracing::warn!(
+            target: "passthrough::telemetry",
+            pid,
+            lost = drained.lost,
+            "sub_dll telemetry events lost"
+        );
+    }
+    for event in drained.events {
+        let hwnd = format!("{:#x}", event.hwnd);
+        match event.kind {
+            EventKind::ProcReplaced => tracing::warn!(
+                target: "passthrough::telemetry",
+                pid,
+                hwnd,
+                proc = format!("{:#x}", event.detail),
+                "window procedure was replaced by someone else"
+            ),
+            kind => tracing::info!(
+                target: "passthrough::telemetry",
+                pid,
+                hwnd,
+                detail = event.detail,
+                "sub_dll {}",
+                kind
+            ),
+        }
+        if recent.len() == RECENT_EVENTS {
+            recent.pop_front();
+        }
+        recent.push_back(TelemetryEvent {
+            pid,
+            sequence: event.sequence,
+            kind: event.kind.name(),
+            hwnd: event.hwnd,
+            detail: event.detail,
+            time_ms: event.time_ms,
+        });
+    }
+}
+
+/// 计数器和最近的事件，先读一次保证是最新的
+pub fn snapshot() -> TelemetrySnapshot {
+    poll();
+    let channels = CHANNELS.lock().unwrap();
+    let mut processes: Vec<_> = channels
+        .iter()
+        .map(|(&pid, channel)| {
+            let counters = channel.ring().counters();
+            ProcessTelemetry {
+                pid,
+                messages: counters[Counter::Messages as usize],
+                swallowed: counters[Counter::Swallowed as usize],
+                rewritten: counters[Counter::Rewritten as usize],
+                hit_tests: counters[Counter::HitTests as usize],
+                dropped: counters[Counter::Dropped as usize],
+                lost: channel.lost,
+            }
+        })
+        .collect();
+    processes.sort_by_key(|process| process.pid);
+    TelemetrySnapshot {
+        processes,
+        recent_events: RECENT.lock().unwrap().iter().cloned().collect(),
+    }
+}
@@ -1,13 +1,48 @@
-#![allow(static_mut_refs)]
-use std::ffi::c_void;
-
-use windows::Win32::{
-    Foundation::{BOOL, HINSTANCE, HWND, LPARAM, LRESULT, WPARAM},
-    UI::{
-        Controls::WM_MOUSELEAVE,
-        WindowsAndMessaging::{
-            CallWindowProcW, DefWindowProcW, GetWindowLongPtrW, SetWindowLongPtrW, GWLP_WNDPROC,
-            GWL_WNDPROC, WNDPROC,
+use std::{
+    collections::HashMap,
+    ffi::c_void,
+    sync::{
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        LazyLock, Mutex,
+    },
+};
+
+use passthrough_core::{
+    abi::{
+        AbiInfo, COMMAND_HOOK_FAILED, COMMAND_HOOK_OK, COMMAND_INVALID, COMMAND_UNAUTHORIZED,
+        FILTER_INVALID, FILTER_OK, MARSHAL_INVALID, MARSHAL_OK, MARSHAL_RESULT_TOO_LARGE,
+        SUBCLASS_ALREADY_SET, SUBCLASS_NOT_SET, SUBCLASS_NO_WND_PROC, SUBCLASS_OK,
+        SUBCLASS_SET_FAILED,
+    },
+    command::{self, Command, CommandError},
+    command_args::{self, CommandArgs, SECTION_SIZE},
+    filter::{self, Decision, Rule},
+    marshal::{self, Decode, Encode, MarshalError},
+    region_table::{self, RegionTable, TABLE_SIZE},
+    telemetry::{self, Counter, EventKind, TelemetryRing, RING_SIZE},
+};
+use windows::{
+    core::HSTRING,
+    Win32::{
+        Foundation::{
+            CloseHandle, BOOL, HANDLE, HINSTANCE, HWND, LPARAM, LRESULT, POINT, RECT, WPARAM,
+        },
+        Graphics::Gdi::ScreenToClient,
+        System::{
+            Memory::{
+                MapViewOfFile, OpenFileMappingW, UnmapViewOfFile, FILE_MAP, FILE_MAP_READ,
+                FILE_MAP_WRITE, MEMORY_MAPPED_VIEW_ADDRESS,
+            },
+            SystemInformation::GetTickCount64,
+            SystemServices::DLL_PROCESS_ATTACH,
+            Threading::GetCurrentProcessId,
+        },
+        UI::WindowsAndMessaging::{
+            CallNextHookEx, CallWindowProcW, DefWindowProcW, GetAncestor, GetClientRect,
+            GetWindowLongPtrW, GetWindowThreadProcessId, RegisterWindowMessageW, ReplyMessage,
+            SetWindowLongPtrW, SetWindowsHookExW, UnhookWindowsHookEx, CWPSTRUCT, GA_ROOT,
+            GWLP_WNDPROC, GWL_WNDPROC, HC_ACTION, HHOOK, WH_CALLWNDPROC, WM_NCDESTROY,
+            WM_NCHITTEST, WNDPROC,
         },
     },
 };
@@ -23,43 +58,363 @@ pub extern "system" fn dll_add(left: *const u32) -> u32 {
 
 #[no_mangle]
 pub unsafe extern "system" fn remove_subclass(hwnd_value: *const HWND) -> u32 {
-    let hwnd = *hwnd_value;
-    if let Some(original_proc) = ORIGINAL_WND_PROC.take() {
-        let result = SetWindowLongPtrW(hwnd, GWL_WNDPROC, original_proc.ptr);
-        result as u32
-    } else {
-        0
+    unsubclass(*hwnd_value)
+}
+
+unsafe fn unsubclass(hwnd: HWND) -> u32 {
+    // 只恢复这个窗口自己的原始过程
+    let Some(original_proc) = ORIGINAL_WND_PROCS
+        .lock()
+        .unwrap()
+        .remove(&(hwnd.0 as isize))
+    else {
+        return SUBCLASS_NOT_SET;
+    };
+    MESSAGE_FILTERS.lock().unwrap().remove(&(hwnd.0 as isize));
+    REGION_TABLES.lock().unwrap().remove(&(hwnd.0 as isize));
+    // 别人在我们之后又子类化了这个窗口，恢复会把它的过程也去掉
+    let current_proc = GetWindowLongPtrW(hwnd, GWLP_WNDPROC);
+    if current_proc != subclass_proc as isize {
+        record(EventKind::ProcReplaced, hwnd, current_proc);
     }
+    SetWindowLongPtrW(hwnd, GWL_WNDPROC, original_proc);
+    record(EventKind::Removed, hwnd, original_proc);
+    SUBCLASS_OK
 }
 
-#[derive(Clone, Copy)]
-struct OriginalWndProc {
-    proc: WNDPROC,
-    ptr: isize,
+/// 窗口销毁时子类化随之失效，忘掉这个窗口的状态，hwnd 之后可能被复用
+fn forget_window(hwnd: HWND) {
+    let key = hwnd.0 as isize;
+    if ORIGINAL_WND_PROCS.lock().unwrap().remove(&key).is_none() {
+        return;
+    }
+    MESSAGE_FILTERS.lock().unwrap().remove(&key);
+    REGION_TABLES.lock().unwrap().remove(&key);
+    record(EventKind::WindowDestroyed, hwnd, 0);
 }
 
-// 存储原始窗口过程的静态变量
-static mut ORIGINAL_WND_PROC: Option<OriginalWndProc> = None;
+// 每个窗口的原始窗口过程，key 是 HWND 的值
+static ORIGINAL_WND_PROCS: LazyLock<Mutex<HashMap<isize, isize>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
 
+/// 远程线程里调用，不在窗口自己的线程上。支持命令时 host 改用 `Command::Subclass`
 #[no_mangle]
 pub unsafe extern "system" fn set_subclass(hwnd_value: *const HWND) -> u32 {
-    let hwnd = *hwnd_value;
-    // FIXME: 不知道为啥在同一个进程里面用这种方式设置窗口的子类化还是失败，还需要在同一个线程？
-    // let set_window_sub_class = SetWindowSubclass(hwnd, Some(subclass_proc), 1, 0);
+    subclass(*hwnd_value)
+}
 
-    // 只能使用这中原始的方式
+unsafe fn subclass(hwnd: HWND) -> u32 {
+    // `SetWindowSubclass` 只能在窗口自己的线程里用，远程线程调用时会失败，
+    // 所以两条路径都用原始的 `SetWindowLongPtrW`
+    let mut procs = ORIGINAL_WND_PROCS.lock().unwrap();
+    if procs.contains_key(&(hwnd.0 as isize)) {
+        return SUBCLASS_ALREADY_SET;
+    }
     // 获取窗口的原始过程
     let original_proc = GetWindowLongPtrW(hwnd, GWLP_WNDPROC);
     if original_proc == 0 {
-        return 102;
+        return SUBCLASS_NO_WND_PROC;
+    }
+    if original_proc == subclass_proc as isize {
+        return SUBCLASS_ALREADY_SET;
     }
     // 保存原始窗口过程
-    ORIGINAL_WND_PROC = Some(OriginalWndProc {
-        proc: std::mem::transmute(original_proc),
-        ptr: original_proc,
-    });
-    let result = SetWindowLongPtrW(hwnd, GWL_WNDPROC, subclass_proc as isize);
-    result as u32
+    procs.insert(hwnd.0 as isize, original_proc);
+    if SetWindowLongPtrW(hwnd, GWL_WNDPROC, subclass_proc as isize) == 0 {
+        procs.remove(&(hwnd.0 as isize));
+        return SUBCLASS_SET_FAILED;
+    }
+    drop(procs);
+    record(EventKind::Installed, hwnd, original_proc);
+    // host 没有建区域表时（不是 `Regions` 模式）不做命中测试
+    if let Some(view) = open_region_table(hwnd) {
+        REGION_TABLES.lock().unwrap().insert(hwnd.0 as isize, view);
+    }
+    SUBCLASS_OK
+}
+
+/// host 创建的命名共享内存的映射
+struct SharedView {
+    mapping: HANDLE,
+    view: MEMORY_MAPPED_VIEW_ADDRESS,
+}
+
+unsafe impl Send for SharedView {}
+unsafe impl Sync for SharedView {}
+
+impl SharedView {
+    unsafe fn open(name: &str, access: FILE_MAP, size: usize) -> Option<Self> {
+        let mapping = OpenFileMappingW(access.0, BOOL(0), &HSTRING::from(name)).ok()?;
+        let view = MapViewOfFile(mapping, access, 0, 0, size);
+        if view.Value.is_null() {
+            let _ = CloseHandle(mapping);
+            return None;
+        }
+        Some(Self { mapping, view })
+    }
+
+    fn as_ptr(&self) -> *const u8 {
+        self.view.Value as *const u8
+    }
+}
+
+impl Drop for SharedView {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = UnmapViewOfFile(self.view);
+            let _ = CloseHandle(self.mapping);
+        }
+    }
+}
+
+/// 区域表的名字由顶层窗口和它所在的 host 进程决定，见 `passthrough_core::region_table`
+unsafe fn open_region_table(hwnd: HWND) -> Option<SharedView> {
+    let root = GetAncestor(hwnd, GA_ROOT);
+    let mut host_pid = 0;
+    GetWindowThreadProcessId(root, Some(&mut host_pid));
+    let name = region_table::segment_name(host_pid, root.0 as usize);
+    SharedView::open(&name, FILE_MAP_READ, TABLE_SIZE)
+}
+
+// 每个子类化的窗口对应的区域表
+static REGION_TABLES: LazyLock<Mutex<HashMap<isize, SharedView>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// host 注入前建好的遥测通道，见 `passthrough_core::telemetry`。没有时什么都不记
+static TELEMETRY: LazyLock<Option<SharedView>> = LazyLock::new(|| unsafe {
+    let name = telemetry::channel_name(GetCurrentProcessId());
+    SharedView::open(&name, FILE_MAP_WRITE, RING_SIZE)
+});
+
+fn telemetry() -> Option<&'static TelemetryRing> {
+    let view = TELEMETRY.as_ref()?;
+    let ring = unsafe { TelemetryRing::from_ptr(view.as_ptr()) };
+    ring.is_ready().then_some(ring)
+}
+
+fn record(kind: EventKind, hwnd: HWND, detail: isize) {
+    if let Some(ring) = telemetry() {
+        let now = unsafe { GetTickCount64() };
+        ring.publish(kind, hwnd.0 as u64, detail as u64, now);
+    }
+}
+
+fn count(counter: Counter) {
+    if let Some(ring) = telemetry() {
+        ring.add(counter, 1);
+    }
+}
+
+/// 按 host 写的可交互区域回答 `WM_NCHITTEST`，区域相对于顶层窗口的客户区。
+/// 没有区域表、表是空的或者读不到时返回 `None`，交给原始过程
+unsafe fn region_hit_test(hwnd: HWND, lparam: LPARAM) -> Option<isize> {
+    let snapshot = {
+        let tables = REGION_TABLES.lock().unwrap();
+        let view = tables.get(&(hwnd.0 as isize))?;
+        RegionTable::from_ptr(view.as_ptr()).read().ok()?
+    };
+    if snapshot.regions().is_empty() {
+        return None;
+    }
+    let root = GetAncestor(hwnd, GA_ROOT);
+    let mut p = POINT {
+        x: (lparam.0 & 0xffff) as i16 as i32,
+        y: ((lparam.0 >> 16) & 0xffff) as i16 as i32,
+    };
+    if !ScreenToClient(root, &mut p).as_bool() {
+        return None;
+    }
+    let mut client_rect = RECT::default();
+    GetClientRect(root, &mut client_rect).ok()?;
+    snapshot
+        .hit_test((p.x, p.y), (client_rect.right, client_rect.bottom))
+        .code()
+}
+
+/// 接口版本和能力，见 `passthrough_core::abi::AbiInfo`
+#[no_mangle]
+pub extern "system" fn sub_dll_abi(_: *const c_void) -> u32 {
+    AbiInfo::current().pack()
+}
+
+/// 当前子类化了多少个窗口
+#[no_mangle]
+pub extern "system" fn subclass_count(_: *const c_void) -> u32 {
+    ORIGINAL_WND_PROCS.lock().unwrap().len() as u32
+}
+
+/// 当前子类化的窗口，参数和结果布局见 `passthrough_core::marshal`
+#[no_mangle]
+pub unsafe extern "system" fn subclassed_windows(frame: *mut u8) -> u32 {
+    marshalled(frame, |()| {
+        let mut windows: Vec<u64> = ORIGINAL_WND_PROCS
+            .lock()
+            .unwrap()
+            .keys()
+            .map(|hwnd| *hwnd as u64)
+            .collect();
+        windows.sort_unstable();
+        windows
+    })
+}
+
+/// 解码 `frame` 里的参数调用 `f`，把结果写回 `frame`
+unsafe fn marshalled<A: Decode, R: Encode>(frame: *mut u8, f: impl FnOnce(A) -> R) -> u32 {
+    let header = std::slice::from_raw_parts(frame, marshal::FRAME_HEADER_SIZE);
+    let Ok(len) = marshal::frame_len(header) else {
+        return MARSHAL_INVALID;
+    };
+    let frame = std::slice::from_raw_parts_mut(frame, len);
+    let Ok(args) = marshal::frame_args(frame) else {
+        return MARSHAL_INVALID;
+    };
+    match marshal::write_result(frame, &f(args)) {
+        Ok(()) => MARSHAL_OK,
+        Err(MarshalError::ResultTooLarge { .. }) => MARSHAL_RESULT_TOO_LARGE,
+        Err(_) => MARSHAL_INVALID,
+    }
+}
+
+/// 设置窗口的消息过滤规则，参数布局见 `passthrough_core::filter`
+#[no_mangle]
+pub unsafe extern "system" fn set_message_filter(args: *const u8) -> u32 {
+    update_filter(args)
+}
+
+unsafe fn update_filter(args: *const u8) -> u32 {
+    let header = std::slice::from_raw_parts(args, filter::HEADER_SIZE);
+    let Ok(len) = filter::encoded_len(header) else {
+        return FILTER_INVALID;
+    };
+    let Ok((hwnd, rules)) = filter::decode(std::slice::from_raw_parts(args, len)) else {
+        return FILTER_INVALID;
+    };
+    MESSAGE_FILTERS.lock().unwrap().insert(hwnd as isize, rules);
+    FILTER_OK
+}
+
+/// `UpdateFilter` 命令的参数从 host 写的 `command_args` 共享内存里读，不接受 `lParam` 里的地址。
+/// 参数里的窗口必须是收到命令的窗口：host 等上一个命令超时后可能已经写了别的窗口的参数
+unsafe fn update_filter_from_args(hwnd: HWND) -> u32 {
+    let Some(args) = read_command_args() else {
+        return FILTER_INVALID;
+    };
+    match filter::decode(&args) {
+        Ok((target, rules)) if target == hwnd.0 as u64 => {
+            MESSAGE_FILTERS
+                .lock()
+                .unwrap()
+                .insert(hwnd.0 as isize, rules);
+            FILTER_OK
+        }
+        _ => FILTER_INVALID,
+    }
+}
+
+// host 第一次发 `UpdateFilter` 之前才建，第一次用到时再打开
+static COMMAND_ARGS: Mutex<Option<SharedView>> = Mutex::new(None);
+
+unsafe fn read_command_args() -> Option<Vec<u8>> {
+    let mut view = COMMAND_ARGS.lock().unwrap();
+    if view.is_none() {
+        let name = command_args::section_name(GetCurrentProcessId());
+        *view = SharedView::open(&name, FILE_MAP_READ, SECTION_SIZE);
+    }
+    let view = view.as_ref()?;
+    CommandArgs::from_ptr(view.as_ptr()).read().ok()
+}
+
+// 每个窗口的消息过滤规则，没有的使用 `filter::DEFAULT_RULES`
+static MESSAGE_FILTERS: LazyLock<Mutex<HashMap<isize, Vec<Rule>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// sub_dll 自己的模块句柄，装钩子时要用
+static MODULE: AtomicUsize = AtomicUsize::new(0);
+
+static COMMAND_MESSAGE: LazyLock<u32> =
+    LazyLock::new(|| unsafe { RegisterWindowMessageW(&HSTRING::from(command::MESSAGE_NAME)) });
+
+// host 握手时设置的令牌，0 表示还没设置，这时不执行任何命令
+static COMMAND_TOKEN: AtomicU32 = AtomicU32::new(0);
+
+/// 握手时由 host 用远程线程调用，只有能在这个进程里建线程的调用者才能设置。
+/// host 重启后重新注入时换成新的令牌，旧的随之失效
+#[no_mangle]
+pub unsafe extern "system" fn set_command_token(token: *const u32) -> u32 {
+    COMMAND_TOKEN.store(*token, Ordering::Relaxed);
+    COMMAND_HOOK_OK
+}
+
+// 线程 id -> 命令钩子，每个 UI 线程只装一个
+static COMMAND_HOOKS: LazyLock<Mutex<HashMap<u32, isize>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// 在窗口所在的线程上装命令钩子，之后 host 用 `command` 里的窗口消息发命令。
+/// 同一个线程已经装过时直接返回成功
+#[no_mangle]
+pub unsafe extern "system" fn install_command_hook(hwnd_value: *const HWND) -> u32 {
+    let thread_id = GetWindowThreadProcessId(*hwnd_value, None);
+    if thread_id == 0 {
+        return COMMAND_HOOK_FAILED;
+    }
+    let mut hooks = COMMAND_HOOKS.lock().unwrap();
+    if hooks.contains_key(&thread_id) {
+        return COMMAND_HOOK_OK;
+    }
+    // 在这个线程里注册一次，钩子里就不用再注册
+    LazyLock::force(&COMMAND_MESSAGE);
+    let module = HINSTANCE(MODULE.load(Ordering::Relaxed) as *mut c_void);
+    match SetWindowsHookExW(WH_CALLWNDPROC, Some(command_hook), module, thread_id) {
+        Ok(hook) => {
+            hooks.insert(thread_id, hook.0 as isize);
+            COMMAND_HOOK_OK
+        }
+        Err(_) => COMMAND_HOOK_FAILED,
+    }
+}
+
+/// 卸载 DLL 之前去掉所有命令钩子，返回去掉了多少个
+#[no_mangle]
+pub unsafe extern "system" fn remove_command_hooks(_: *const c_void) -> u32 {
+    let mut removed = 0;
+    for (_, hook) in COMMAND_HOOKS.lock().unwrap().drain() {
+        if UnhookWindowsHookEx(HHOOK(hook as *mut c_void)).is_ok() {
+            removed += 1;
+        }
+    }
+    removed
+}
+
+/// 在窗口自己的线程里执行命令，返回结果码
+unsafe fn execute(hwnd: HWND, command: Command) -> u16 {
+    let code = match command {
+        Command::Ping => COMMAND_HOOK_OK,
+        Command::Subclass => subclass(hwnd),
+        Command::Unsubclass => unsubclass(hwnd),
+        Command::UpdateFilter => update_filter_from_args(hwnd),
+    };
+    code as u16
+}
+
+unsafe extern "system" fn command_hook(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    if code == HC_ACTION as i32 {
+        let message = &*(lparam.0 as *const CWPSTRUCT);
+        if message.message == *COMMAND_MESSAGE {
+            let token = COMMAND_TOKEN.load(Ordering::Relaxed);
+            let reply = match Command::decode(message.wParam.0, message.lParam.0, token) {
+                Ok(command) => Some(command.reply(execute(message.hwnd, command))),
+                Err(err @ CommandError::Unauthorized { .. }) => {
+                    err.reply(COMMAND_UNAUTHORIZED as u16)
+                }
+                Err(err) => err.reply(COMMAND_INVALID as u16),
+            };
+            // 钩子改不了窗口过程的返回值，先把结果交给发送者，窗口过程之后的返回值会被忽略
+            if let Some(reply) = reply {
+                let _ = ReplyMessage(LRESULT(reply));
+            }
+        }
+    }
+    CallNextHookEx(None, code, wparam, lparam)
 }
 
 pub unsafe extern "system" fn subclass_proc(
@@ -68,16 +423,57 @@ pub unsafe extern "system" fn subclass_proc(
     wparam: WPARAM,
     lparam: LPARAM,
 ) -> LRESULT {
-    match msg {
-        WM_MOUSELEAVE => LRESULT(0),
-        _ => {
-            if let Some(original_proc) = ORIGINAL_WND_PROC {
-                CallWindowProcW(original_proc.proc, hwnd, msg, wparam, lparam)
-            } else {
-                DefWindowProcW(hwnd, msg, wparam, lparam)
+    // 命令已经在钩子里处理过了
+    if msg == *COMMAND_MESSAGE {
+        return LRESULT(0);
+    }
+    count(Counter::Messages);
+    let decision = {
+        let filters = MESSAGE_FILTERS.lock().unwrap();
+        let rules = filters
+            .get(&(hwnd.0 as isize))
+            .map_or(filter::DEFAULT_RULES, Vec::as_slice);
+        filter::evaluate(rules, msg, wparam.0, lparam.0)
+    };
+    let original = (msg, wparam.0, lparam.0);
+    let (msg, wparam, lparam) = match decision {
+        Decision::Return(result) => {
+            count(Counter::Swallowed);
+            return LRESULT(result);
+        }
+        Decision::Forward {
+            msg,
+            wparam,
+            lparam,
+        } => {
+            if (msg, wparam, lparam) != original {
+                count(Counter::Rewritten);
             }
+            (msg, WPARAM(wparam), LPARAM(lparam))
+        }
+    };
+    if msg == WM_NCHITTEST {
+        if let Some(code) = region_hit_test(hwnd, lparam) {
+            count(Counter::HitTests);
+            return LRESULT(code);
         }
     }
+    // 先拷贝出来再调用，原始过程里可能会再进来
+    let original_proc = ORIGINAL_WND_PROCS
+        .lock()
+        .unwrap()
+        .get(&(hwnd.0 as isize))
+        .copied();
+    let result = if let Some(original_proc) = original_proc {
+        let original_proc: WNDPROC = std::mem::transmute(original_proc);
+        CallWindowProcW(original_proc, hwnd, msg, wparam, lparam)
+    } else {
+        DefWindowProcW(hwnd, msg, wparam, lparam)
+    };
+    if msg == WM_NCDESTROY {
+        forget_window(hwnd);
+    }
+    result
 }
 
 // pub unsafe extern "system" fn subclass_proc(
@@ -100,10 +496,13 @@ pub unsafe extern "system" fn subclass_proc(
 
 #[no_mangle]
 pub extern "system" fn DllMain(
-    _hinst_dll: HINSTANCE,
-    _fdw_reason: u32,
+    hinst_dll: HINSTANCE,
+    fdw_reason: u32,
     _lp_reserved: *mut c_void,
 ) -> BOOL {
     println!("DllMain called...");
+    if fdw_reason == DLL_PROCESS_ATTACH {
+        MODULE.store(hinst_dll.0 as usize, Ordering::Relaxed);
+    }
     BOOL::from(true)
 }
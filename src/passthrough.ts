@@ -0,0 +1,291 @@
+import { invoke } from "@tauri-apps/api/core";
+import { listen, type UnlistenFn } from "@tauri-apps/api/event";
+
+export type ForwardedEvent = "move" | "wheel";
+
+export type HitTestMode = "frontend" | "regions";
+
+export type ForwardStrategy = "inject" | "ncHitTest";
+
+export interface Region {
+  x: number;
+  y: number;
+  width: number;
+  height: number;
+}
+
+export type DllCapability =
+  | "subclass"
+  | "messageFilter"
+  | "marshal"
+  | "regionTable"
+  | "telemetry"
+  | "commands";
+
+export interface InjectedProcess {
+  pid: number;
+  abiVersion: number;
+  capabilities: DllCapability[];
+}
+
+export type PreflightCheck =
+  | "webview2Process"
+  | "processTree"
+  | "architecture"
+  | "integrityLevel"
+  | "dllIntegrity";
+
+export interface InjectionRefusal {
+  check: PreflightCheck;
+  reason: string;
+}
+
+export type DegradedFeature =
+  | "nativeForwarding"
+  | "messageFilter"
+  | "regionHitTest"
+  | "telemetry";
+
+export interface Degradation {
+  reason: string;
+  unavailable: DegradedFeature[];
+  fallback: ForwardStrategy | null;
+}
+
+export interface PassthroughState {
+  ignore: boolean;
+  forward: boolean;
+  hookInstalled: boolean;
+  injectedProcess: InjectedProcess | null;
+  injectionError: string | null;
+  injectionRefusal: InjectionRefusal | null;
+  degraded: Degradation | null;
+  eventMask: ForwardedEvent[];
+  profile: string | null;
+  hitTest: HitTestMode;
+  strategy: ForwardStrategy;
+  regions: Region[];
+}
+
+export type ChangeReason =
+  | "command"
+  | "profile"
+  | "hotkey"
+  | "policy"
+  | "restore"
+  | "timed"
+  | "released"
+  | "injection";
+
+export interface PassthroughStateChanged {
+  label: string;
+  reason: ChangeReason;
+  state: PassthroughState;
+}
+
+export function getPassthroughState(
+  label: string,
+): Promise<PassthroughState | null> {
+  return invoke("get_passthrough_state", { label });
+}
+
+export function onPassthroughStateChanged(
+  handler: (event: PassthroughStateChanged) => void,
+): Promise<UnlistenFn> {
+  return listen<PassthroughStateChanged>("passthrough-state-changed", (event) =>
+    handler(event.payload),
+  );
+}
+
+export interface PassthroughMetrics {
+  requests: number;
+  applied: number;
+  coalesced: number;
+  unchanged: number;
+  nativeCallsAvoided: number;
+  resources: ResourceUsage;
+  telemetry: TelemetrySnapshot;
+}
+
+export type TelemetryEventKind =
+  | "installed"
+  | "removed"
+  | "windowDestroyed"
+  | "procReplaced";
+
+export interface TelemetryEvent {
+  pid: number;
+  sequence: number;
+  kind: TelemetryEventKind;
+  hwnd: number;
+  detail: number;
+  timeMs: number;
+}
+
+export interface ProcessTelemetry {
+  pid: number;
+  messages: number;
+  swallowed: number;
+  rewritten: number;
+  hitTests: number;
+  dropped: number;
+  lost: number;
+}
+
+export interface TelemetrySnapshot {
+  processes: ProcessTelemetry[];
+  recentEvents: TelemetryEvent[];
+}
+
+export interface ResourceUsage {
+  allocations: number;
+  threads: number;
+  processes: number;
+  modules: number;
+  abandonedAllocations: number;
+}
+
+export function getPassthroughMetrics(): Promise<PassthroughMetrics> {
+  return invoke("get_passthrough_metrics");
+}
+
+export function setPassthroughProfile(
+  profile: string,
+  label?: string,
+): Promise<PassthroughState> {
+  return invoke("set_passthrough_profile", { profile, label });
+}
+
+export type ReleaseCondition =
+  | { type: "duration"; ms: number }
+  | { type: "focus" }
+  | { type: "foregroundChange" }
+  | { type: "keyChord"; chord: string };
+
+export type ReleaseCause = "timeout" | "focus" | "foregroundChange" | "keyChord";
+
+export interface PassthroughReleased {
+  label: string;
+  cause: ReleaseCause;
+}
+
+export function ignoreMouseEventsUntil(
+  until: ReleaseCondition[],
+  forward?: boolean,
+): Promise<PassthroughState> {
+  return invoke("ignore_mouse_events_until", { until, forward });
+}
+
+export function onPassthroughReleased(
+  handler: (event: PassthroughReleased) => void,
+): Promise<UnlistenFn> {
+  return listen<PassthroughReleased>("passthrough-released", (event) =>
+    handler(event.payload),
+  );
+}
+
+export type MessageRule =
+  | { action: "pass"; msg: number }
+  | { action: "swallow"; msg: number; result?: number }
+  | {
+      action: "rewrite";
+      msg: number;
+      to?: number;
+      wparam?: number;
+      lparam?: number;
+    };
+
+export function setMessageFilter(rules: MessageRule[]): Promise<void> {
+  return invoke("set_message_filter", { rules });
+}
+
+export type InjectionStage =
+  | "started"
+  | "subclassed"
+  | "failed"
+  | "timedOut"
+  | "cancelled"
+  | "refused"
+  | "degraded";
+
+export interface PassthroughInjection {
+  label: string;
+  stage: InjectionStage;
+  error: string | null;
+  refusal: InjectionRefusal | null;
+}
+
+export function onPassthroughInjection(
+  handler: (event: PassthroughInjection) => void,
+): Promise<UnlistenFn> {
+  return listen<PassthroughInjection>("passthrough-injection", (event) =>
+    handler(event.payload),
+  );
+}
+
+export function cancelPassthroughInjection(): Promise<void> {
+  return invoke("cancel_passthrough_injection");
+}
+
+export interface WindowNode {
+  hwnd: number;
+  pid: number;
+  threadId: number;
+  className: string;
+  title: string;
+  visible: boolean;
+  rect: [number, number, number, number];
+  wndProc: number;
+  subclassed: boolean;
+  children: WindowNode[];
+}
+
+export interface ModuleInfo {
+  name: string;
+  path: string;
+  base: number;
+  size: number;
+}
+
+export interface SubDllStatus {
+  module: ModuleInfo;
+  abi: { version: number; capabilities: DllCapability[] } | null;
+  subclassedWindows: number[];
+}
+
+export interface ProcessInfo {
+  pid: number;
+  parentPid: number;
+  name: string;
+  path: string | null;
+  modules: ModuleInfo[];
+  windows: WindowNode[];
+  subDll: SubDllStatus | null;
+  error: string | null;
+}
+
+export interface HostWindow {
+  label: string;
+  hwnd: number;
+  webview: number | null;
+  injectedPid: number | null;
+  tree: WindowNode;
+}
+
+export interface PassthroughDiagnostics {
+  hostPid: number;
+  subDllVersion: string;
+  windows: HostWindow[];
+  processes: ProcessInfo[];
+}
+
+export function getPassthroughDiagnostics(): Promise<PassthroughDiagnostics> {
+  return invoke("passthrough_diagnostics");
+}
+
+export function exportPassthroughReport(
+  seconds?: number,
+  redactTitles?: boolean,
+): Promise<string> {
+  return invoke("export_passthrough_report", { seconds, redactTitles });
+}